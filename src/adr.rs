@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+use reqwest::Client;
+
+use crate::{
+    codeblocks,
+    config::Config,
+    copilot::{self, CopilotManager, Sampling},
+    gh::AuthenticationManager,
+    input, patch, prompts, term,
+};
+
+/// Where `copilot adr` keeps the records, relative to the current directory.
+pub const ADR_DIR: &str = "docs/adr";
+
+/// Runs `copilot adr <title>`: asks the model what it needs to know, interviews the user
+/// and writes the record it produces as the next numbered file in `docs/adr`.
+pub async fn run(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    model: &str,
+    title: &str,
+) -> Result<PathBuf, String> {
+    let config = Config::load();
+    let workspace = patch::Workspace::from_config(&config)?;
+    let auth = auth_manager.cache_auth().await.map_err(|e| e.to_string())?;
+
+    let mut copilot_m = CopilotManager::new(&auth, client, config.system_prompt())
+        .with_endpoint(config.endpoint)
+        .with_sampling(Sampling::from_config(&config))
+        .with_retries(&config);
+    copilot_m.set_model(model);
+
+    println!("Working out what to ask about \"{}\"...", title);
+    let reply = copilot_m.ask_aside(&prompts::adr_questions(title)).await;
+    let questions = match codeblocks::find_json(&reply.content) {
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .filter_map(|q| q.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if questions.is_empty() {
+        return Err("The model didn't come up with questions, try again".to_string());
+    }
+
+    let mut rl = input::new_editor();
+    let mut interview = String::new();
+    for question in &questions {
+        let answer = rl
+            .readline(&format!("{}\n> ", question))
+            .map_err(|e| e.to_string())?;
+        interview.push_str(&format!("Q: {}\nA: {}\n\n", question, answer.trim()));
+    }
+
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    copilot_m.subscribe(Box::new(term::TerminalSink::default()));
+    let record = copilot_m
+        .ask(&prompts::adr_record(title, &date, &interview))
+        .await;
+    if record.finish_reason == copilot::FINISH_ERROR {
+        return Err("Couldn't write the record".to_string());
+    }
+    let record = unfence(&record.content);
+
+    let existing: Vec<String> = std::fs::read_dir(ADR_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    let path = Path::new(ADR_DIR).join(file_name(next_number(&existing), title));
+
+    if !input::confirm(&mut rl, &format!("Write {}?", path.display()), true) {
+        return Err("Not written".to_string());
+    }
+    workspace
+        .write(&path, &format!("{}\n", record.trim_end()))
+        .map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// The record without the code fence models like to wrap whole documents in.
+fn unfence(answer: &str) -> String {
+    let trimmed = answer.trim();
+    match codeblocks::extract(trimmed).first() {
+        Some(block) if trimmed.starts_with("```") && trimmed.ends_with("```") => block.code.clone(),
+        _ => trimmed.to_string(),
+    }
+}
+
+/// One more than the highest `NNNN-` prefix among `names`.
+fn next_number(names: &[String]) -> u32 {
+    names
+        .iter()
+        .filter_map(|name| name.split('-').next()?.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+/// `0007-switch-to-async-streaming.md`
+fn file_name(number: u32, title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("{:04}-{}.md", number, slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbering_and_names() {
+        let names =
+            ["0001-record-decisions.md", "0012-use-tokio.md", "README.md"].map(String::from);
+        assert_eq!(next_number(&names), 13);
+        assert_eq!(next_number(&[]), 1);
+        assert_eq!(
+            file_name(13, "Switch to async streaming!"),
+            "0013-switch-to-async-streaming.md"
+        );
+        assert_eq!(unfence("```markdown\n# ADR\n```"), "# ADR\n");
+    }
+}
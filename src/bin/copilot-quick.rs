@@ -0,0 +1,87 @@
+//! `copilot-quick`: asks one question through a running `copilot daemon` and prints the
+//! answer. Meant to be bound to a key in a window manager, so it starts the daemon on first
+//! use and otherwise does nothing but connect, send a line and copy the answer to stdout.
+//! Keep it free of tokio, reqwest and the config parser, startup must stay under 100ms
+//! (`cargo test --test quick_startup -- --ignored`).
+
+#[cfg(unix)]
+fn main() {
+    use std::{
+        io::{BufRead, Write},
+        os::unix::net::UnixStream,
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    let socket = match std::env::var_os("COPILOT_DAEMON_SOCKET") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let home = std::env::var_os("HOME").unwrap_or_default();
+            PathBuf::from(home).join(".config/copilot/daemon.sock")
+        }
+    };
+
+    let mut question = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+    if question.trim().is_empty() {
+        eprint!("? ");
+        let _ = std::io::stderr().flush();
+        let _ = std::io::stdin().lock().read_line(&mut question);
+    }
+    let question = question.trim();
+    if question.is_empty() {
+        std::process::exit(0);
+    }
+
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(_) => {
+            // start the daemon next to us and wait for it to log in and listen
+            let daemon = std::env::current_exe()
+                .ok()
+                .and_then(|exe| Some(exe.parent()?.join("copilot")))
+                .unwrap_or_else(|| PathBuf::from("copilot"));
+            let started = std::process::Command::new(daemon)
+                .arg("daemon")
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn();
+            if let Err(e) = started {
+                eprintln!(
+                    "No daemon on {} and starting one failed: {}",
+                    socket.display(),
+                    e
+                );
+                std::process::exit(1);
+            }
+
+            let deadline = Instant::now() + Duration::from_secs(15);
+            loop {
+                if let Ok(stream) = UnixStream::connect(&socket) {
+                    break stream;
+                }
+                if Instant::now() > deadline {
+                    eprintln!(
+                        "The daemon didn't start, run `copilot daemon` to see why (log in first with `copilot auth login`)"
+                    );
+                    std::process::exit(1);
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+    };
+
+    let request = serde_json::json!({ "question": question });
+    if writeln!(stream, "{}", request).is_err() {
+        eprintln!("The daemon closed the connection");
+        std::process::exit(1);
+    }
+
+    let _ = std::io::copy(&mut stream, &mut std::io::stdout());
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("copilot-quick needs unix sockets and isn't supported on this platform");
+    std::process::exit(1);
+}
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use futures::{SinkExt, StreamExt};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::{
+    config::{Config, SlackConfig},
+    copilot::{CopilotManager, Sampling},
+    gh::AuthenticationManager,
+};
+
+/// A Socket Mode message. Everything except `hello` carries an `envelope_id` that has to be
+/// acknowledged within three seconds, or Slack delivers the event again.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    envelope_id: Option<String>,
+    payload: Option<Value>,
+}
+
+/// One question and answer in a Slack thread.
+struct Exchange {
+    question: String,
+    answer: String,
+}
+
+/// Runs `copilot bridge slack`: answers every mention of the app with the logged in user's
+/// Copilot subscription. Each Slack thread is its own conversation.
+pub async fn slack(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    config: &SlackConfig,
+) -> Result<(), String> {
+    let mut threads: HashMap<String, Vec<Exchange>> = HashMap::new();
+    // events are redelivered while we're busy answering, answer each one once
+    let mut answered: HashSet<String> = HashSet::new();
+
+    loop {
+        let url = open_connection(client, &config.app_token).await?;
+        let (mut socket, _) = connect_async(url.as_str())
+            .await
+            .map_err(|e| format!("Failed to connect to Slack: {}", e))?;
+        println!("Connected to Slack, answering mentions. Press Ctrl+C to stop.");
+
+        while let Some(message) = socket.next().await {
+            let text = match message {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Ping(data)) => {
+                    let _ = socket.send(Message::Pong(data)).await;
+                    continue;
+                }
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("Slack connection failed: {}", e);
+                    break;
+                }
+            };
+
+            let Ok(envelope) = serde_json::from_str::<Envelope>(&text) else {
+                continue;
+            };
+            if let Some(id) = &envelope.envelope_id {
+                let ack = json!({ "envelope_id": id }).to_string();
+                let _ = socket.send(Message::Text(ack)).await;
+            }
+
+            match envelope.kind.as_str() {
+                // Slack rotates connections every few hours
+                "disconnect" => break,
+                "events_api" => {}
+                _ => continue,
+            }
+
+            let Some(event) = envelope.payload.as_ref().and_then(|p| p.get("event")) else {
+                continue;
+            };
+            if event["type"] != "app_mention" {
+                continue;
+            }
+
+            let field = |name: &str| event[name].as_str().unwrap_or("").to_string();
+            let (channel, ts) = (field("channel"), field("ts"));
+            let thread = event["thread_ts"]
+                .as_str()
+                .map(|t| t.to_string())
+                .unwrap_or(ts.clone());
+            if !answered.insert(format!("{}/{}", channel, ts)) {
+                continue;
+            }
+
+            let question = strip_mentions(&field("text"));
+            if question.is_empty() {
+                continue;
+            }
+
+            let history = threads
+                .entry(format!("{}/{}", channel, thread))
+                .or_default();
+            let reply = match answer(auth_manager, client, history, &question).await {
+                Ok(answer) => {
+                    history.push(Exchange {
+                        question,
+                        answer: answer.clone(),
+                    });
+                    answer
+                }
+                Err(e) => format!("Sorry, I couldn't answer that: {}", e),
+            };
+
+            if let Err(e) = post_message(client, &config.bot_token, &channel, &thread, &reply).await
+            {
+                eprintln!("Failed to reply in {}: {}", channel, e);
+            }
+        }
+    }
+}
+
+/// Asks Copilot `question` with the thread's earlier exchanges as history. The Copilot token
+/// is refreshed as needed, the bridge runs much longer than a token lives.
+async fn answer(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    history: &[Exchange],
+    question: &str,
+) -> Result<String, String> {
+    let auth = auth_manager.cache_auth().await.map_err(|e| e.to_string())?;
+    let config = Config::load();
+    let mut copilot_m = CopilotManager::new(&auth, client, config.system_prompt())
+        .with_endpoint(config.endpoint)
+        .with_glossary(&config.glossary)
+        .with_sampling(Sampling::from_config(&config))
+        .with_retries(&config)
+        .with_history_budget(config.history_token_budget)
+        .with_history_limit(config.history_max_messages)
+        .with_metrics(config.metrics.clone());
+
+    for exchange in history {
+        copilot_m.replay(&exchange.question, &exchange.answer, None, None);
+    }
+
+    Ok(copilot_m.ask(question).await.content)
+}
+
+/// `<@U0123> how do I ...` -> `how do I ...`
+fn strip_mentions(text: &str) -> String {
+    text.split_whitespace()
+        .filter(|word| !(word.starts_with("<@") && word.ends_with('>')))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Deserialize)]
+struct SlackResponse {
+    ok: bool,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+}
+
+impl SlackResponse {
+    fn check(self) -> Result<SlackResponse, String> {
+        if self.ok {
+            Ok(self)
+        } else {
+            Err(format!(
+                "Slack API error: {}",
+                self.error.as_deref().unwrap_or("unknown")
+            ))
+        }
+    }
+}
+
+/// Gets a Socket Mode WebSocket URL with the app-level (`xapp-`) token.
+async fn open_connection(client: &Client, app_token: &str) -> Result<String, String> {
+    let response = client
+        .post("https://slack.com/api/apps.connections.open")
+        .bearer_auth(app_token)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<SlackResponse>()
+        .await
+        .map_err(|e| e.to_string())?
+        .check()?;
+
+    response
+        .url
+        .ok_or("Slack didn't return a Socket Mode URL".to_string())
+}
+
+/// Replies in the thread with the bot (`xoxb-`) token.
+async fn post_message(
+    client: &Client,
+    bot_token: &str,
+    channel: &str,
+    thread_ts: &str,
+    text: &str,
+) -> Result<(), String> {
+    client
+        .post("https://slack.com/api/chat.postMessage")
+        .bearer_auth(bot_token)
+        .json(&json!({
+            "channel": channel,
+            "thread_ts": thread_ts,
+            "text": text,
+        }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<SlackResponse>()
+        .await
+        .map_err(|e| e.to_string())?
+        .check()
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_are_removed_from_questions() {
+        assert_eq!(
+            strip_mentions("<@U024BE7LH> why does  <@U1> this fail?"),
+            "why does this fail?"
+        );
+    }
+}
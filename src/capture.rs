@@ -0,0 +1,108 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::io::AsyncReadExt;
+
+/// The output a command produced while it was captured.
+#[derive(Debug, PartialEq)]
+pub struct Capture {
+    pub output: String,
+    /// Bytes dropped from the start to stay within the size cap.
+    pub dropped: usize,
+    /// Whether the command ended on its own before the time was up.
+    pub exited: bool,
+}
+
+/// Runs `command` through the shell for at most `duration`, or until Ctrl+C, keeping the last
+/// `max_bytes` of what it wrote to stdout and stderr. Meant for commands that never end on
+/// their own like `journalctl -f`, which are killed when the time is up.
+pub async fn capture(
+    command: &str,
+    duration: Duration,
+    max_bytes: usize,
+) -> Result<Capture, String> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", command, e))?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+
+    let mut output = Vec::new();
+    let mut dropped = 0;
+    let (mut out_buf, mut err_buf) = ([0u8; 4096], [0u8; 4096]);
+    let (mut out_open, mut err_open) = (true, true);
+    let mut exited = false;
+
+    loop {
+        if !out_open && !err_open {
+            exited = true;
+            break;
+        }
+        let read = tokio::select! {
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+            read = stdout.read(&mut out_buf), if out_open => match read {
+                Ok(0) | Err(_) => {
+                    out_open = false;
+                    continue;
+                }
+                Ok(n) => &out_buf[..n],
+            },
+            read = stderr.read(&mut err_buf), if err_open => match read {
+                Ok(0) | Err(_) => {
+                    err_open = false;
+                    continue;
+                }
+                Ok(n) => &err_buf[..n],
+            },
+        };
+        output.extend_from_slice(read);
+        dropped += keep_tail(&mut output, max_bytes);
+    }
+
+    if !exited {
+        let _ = child.kill().await;
+    }
+
+    Ok(Capture {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        dropped,
+        exited,
+    })
+}
+
+/// Drops the start of `output` so at most `max_bytes` remain, cut after a newline where there
+/// is one so the first line isn't half a line. Returns how many bytes were dropped.
+fn keep_tail(output: &mut Vec<u8>, max_bytes: usize) -> usize {
+    if output.len() <= max_bytes {
+        return 0;
+    }
+    let mut cut = output.len() - max_bytes;
+    if let Some(newline) = output[cut..].iter().position(|&b| b == b'\n') {
+        cut += newline + 1;
+    }
+    output.drain(..cut);
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_whole_lines_at_the_end() {
+        let mut output = b"first line\nsecond line\nthird\n".to_vec();
+        assert_eq!(keep_tail(&mut output, 100), 0);
+
+        assert_eq!(keep_tail(&mut output, 15), 23);
+        assert_eq!(output, b"third\n");
+    }
+}
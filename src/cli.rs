@@ -0,0 +1,501 @@
+use std::time::Duration;
+
+use crate::{config::MAX_STOP_SEQUENCES, filter::Filter, postprocess, sessions};
+
+/// More candidate answers than this are too many to read and pick from.
+const MAX_CHOICES: u32 = 8;
+
+/// Command line arguments.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// The named profile to authenticate with (`--profile work`).
+    pub profile: Option<String>,
+    /// The GitHub host used when creating a new profile (`--host github.example.com`).
+    pub host: Option<String>,
+    /// A command that prints the GitHub token (`--token-cmd "pass show github/copilot"`).
+    pub token_cmd: Option<String>,
+    /// A file descriptor to read the GitHub token from (`--token-fd 3`).
+    pub token_fd: Option<i32>,
+    /// Log in through the browser instead of the device flow (`auth login --web`).
+    pub web: bool,
+    /// Log in with a personal access token read from stdin (`auth login --with-token`).
+    pub with_token: bool,
+    /// Keep tokens and keys in the archive (`backup create --include-credentials`).
+    pub include_credentials: bool,
+    /// What `export` writes (`--format html`), Markdown unless given.
+    pub format: Option<ExportFormat>,
+    /// Where `complete` fills in code (`--file src/gh.rs --line 42 --col 7`), 1-based.
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+    /// The chat model to start with (`--model gpt-4o`), `/model` switches it later.
+    pub model: Option<String>,
+    /// Override the sampling settings from the config (`--temperature 0.7`, `--top-p 0.9`,
+    /// `--max-tokens 500`, `--stop '###'` given up to 4 times).
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub stop: Vec<String>,
+    /// Ask for this many candidate answers and pick the one to keep (`--choices 3`).
+    pub choices: Option<u32>,
+    /// Replaces the system prompt (`--system-prompt "..."`, or read from a file with
+    /// `--system-prompt-file prompt.md`).
+    pub system_prompt: Option<String>,
+    /// Short answers for the whole session, like `/brief` (`--brief`).
+    pub brief: bool,
+    /// Continue the most recent conversation (`--resume`).
+    pub resume: bool,
+    /// Continue or start the named conversation (`--session rust-refactor`).
+    pub session: Option<String>,
+    /// Wait for whole answers instead of streaming them (`--no-stream`).
+    pub no_stream: bool,
+    /// Ask for answers that are a JSON object, like `/json` (`--json-response`).
+    pub json_response: bool,
+    /// Rewrite each answer before it's shown and kept (`--post code-only,lf`, `--code-only`).
+    pub post: Vec<postprocess::Builtin>,
+    /// Print where the time of each request went after its answer (`--timings`).
+    pub timings: bool,
+    /// Stop answers after this long, keeping what arrived (`--max-time 30s`).
+    pub max_time: Option<Duration>,
+    /// Print what this jq-style path selects from JSON answers (`--filter '.items[].name'`).
+    pub filter: Option<Filter>,
+    /// Also write every answer to this file (`--out answer.md`).
+    pub out: Option<String>,
+    /// Stream answers as JSON lines to this file, `-` for stdout (`--json-stream -`).
+    pub json_stream: Option<String>,
+    /// A subcommand to run instead of the interactive chat.
+    pub command: Option<Subcommand>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Subcommand {
+    Auth(AuthCommand),
+    Edits(EditsCommand),
+    Backup(BackupCommand),
+    /// Print the account and Copilot plan the token belongs to.
+    Whoami,
+    /// List the models `--model` accepts.
+    Models,
+    /// Summarize the `/good` and `/bad` ratings.
+    Feedback,
+    /// Answer mentions in Slack (`bridge slack`, needs the `slack` feature).
+    BridgeSlack,
+    /// Keep the login warm and answer `copilot-quick` over a unix socket.
+    Daemon,
+    /// Embed the current repository's files so questions get relevant code attached.
+    Index,
+    /// Package the last request, its response and the environment for a bug report
+    /// (`bundle-last-request [file.tar.gz]`). Requests are only kept with `COPILOT_DEBUG` set.
+    BundleLastRequest {
+        path: Option<String>,
+    },
+    /// Turn transcripts exported by other assistants into sessions
+    /// (`import conversations.json [name]`).
+    Import {
+        path: String,
+        name: Option<String>,
+    },
+    /// Interview the user and write an Architecture Decision Record (`adr "use tokio"`).
+    Adr {
+        title: String,
+    },
+    /// Plan a change across files and apply it file by file
+    /// (`refactor "rename CopilotManager to ChatClient"`).
+    Refactor {
+        instruction: String,
+    },
+    /// Print the code Copilot suggests at a position in a file, for editor integrations
+    /// (`complete --file src/gh.rs --line 42 --col 7`, `--file -` reads the buffer from stdin).
+    Complete {
+        file: String,
+        line: usize,
+        col: usize,
+    },
+    /// Write a stored conversation to a file, the latest unless `--session` names one
+    /// (`export --format html [file]`).
+    Export {
+        path: Option<String>,
+        format: ExportFormat,
+    },
+    /// Find the stored conversations that mention something (`history search "device flow"`).
+    HistorySearch {
+        query: String,
+    },
+    /// Ask one question without the chat and print the answer to stdout, for shell aliases
+    /// and scripts (`ask "how do I statically link openssl"`). Piped input is attached to
+    /// the question (`cat error.log | copilot ask "what is causing this panic?"`). Exits with
+    /// 0 for a complete answer, 1 when the request or the login failed and 3 when the answer
+    /// was cut off.
+    Ask {
+        question: String,
+    },
+    /// Ask a template from the config directory once (`run tests src/gh.rs`).
+    Run {
+        template: String,
+        args: String,
+    },
+}
+
+/// The file formats conversations are exported in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    /// One self-contained page with highlighted code.
+    Html,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Result<ExportFormat, String> {
+        match name {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(format!(
+                "Unknown export format: {}, expected markdown or html",
+                name
+            )),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthCommand {
+    /// Log in and replace any stored token.
+    Login(LoginMethod),
+    /// Delete the stored token.
+    Logout,
+    /// Show the user, token scopes and Copilot plan.
+    Status,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BackupCommand {
+    /// Pack the config, sessions and templates into an archive, without credentials unless
+    /// asked to.
+    Create {
+        path: Option<String>,
+        include_credentials: bool,
+    },
+    /// Put a backup's config and data in place, keeping what exists here.
+    Restore { path: String },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EditsCommand {
+    /// List every file modification, newest first.
+    Log,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginMethod {
+    DeviceFlow,
+    /// `--web`: open the browser and receive the redirect on localhost.
+    Web,
+    /// `--with-token`: read a personal access token from stdin.
+    WithToken,
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                positional.push(arg);
+                continue;
+            }
+
+            // support both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("Missing value for {}", flag))
+            };
+
+            match flag.as_str() {
+                "--profile" | "-p" => parsed.profile = Some(value()?),
+                "--host" => parsed.host = Some(value()?),
+                "--token-cmd" => parsed.token_cmd = Some(value()?),
+                "--web" => parsed.web = true,
+                "--with-token" => parsed.with_token = true,
+                "--include-credentials" => parsed.include_credentials = true,
+                "--brief" => parsed.brief = true,
+                "--resume" => parsed.resume = true,
+                "--no-stream" => parsed.no_stream = true,
+                "--timings" => parsed.timings = true,
+                "--json-response" => parsed.json_response = true,
+                "--code-only" => parsed.add_post(postprocess::Builtin::CodeOnly),
+                "--post" => {
+                    for name in value()?.split(',') {
+                        parsed.add_post(postprocess::Builtin::parse(name.trim())?);
+                    }
+                }
+                "--session" => {
+                    let name = value()?;
+                    sessions::check_name(&name)?;
+                    parsed.session = Some(name);
+                }
+                "--system-prompt" => parsed.system_prompt = Some(value()?),
+                "--system-prompt-file" => {
+                    let path = value()?;
+                    parsed.system_prompt = Some(
+                        std::fs::read_to_string(&path)
+                            .map_err(|e| format!("Can't read {}: {}", path, e))?,
+                    );
+                }
+                "--model" | "-m" => parsed.model = Some(value()?),
+                "--temperature" => {
+                    let temperature = value()?;
+                    parsed.temperature = Some(
+                        temperature
+                            .parse()
+                            .ok()
+                            .filter(|t| (0.0..=2.0).contains(t))
+                            .ok_or(format!(
+                                "Invalid temperature: {}, expected 0 to 2",
+                                temperature
+                            ))?,
+                    );
+                }
+                "--top-p" => {
+                    let top_p = value()?;
+                    parsed.top_p = Some(
+                        top_p
+                            .parse()
+                            .ok()
+                            .filter(|p| (0.0..=1.0).contains(p))
+                            .ok_or(format!("Invalid top_p: {}, expected 0 to 1", top_p))?,
+                    );
+                }
+                "--max-tokens" => {
+                    let max_tokens = value()?;
+                    parsed.max_tokens = Some(
+                        max_tokens
+                            .parse()
+                            .ok()
+                            .filter(|n| *n > 0)
+                            .ok_or(format!("Invalid max tokens: {}", max_tokens))?,
+                    );
+                }
+                "--stop" => {
+                    let stop = value()?;
+                    if stop.is_empty() {
+                        return Err("--stop needs a non-empty sequence".to_string());
+                    }
+                    if parsed.stop.len() == MAX_STOP_SEQUENCES {
+                        return Err(format!(
+                            "At most {} --stop sequences can be given",
+                            MAX_STOP_SEQUENCES
+                        ));
+                    }
+                    parsed.stop.push(stop);
+                }
+                "--choices" | "-n" => {
+                    let choices = value()?;
+                    parsed.choices = Some(
+                        choices
+                            .parse()
+                            .ok()
+                            .filter(|n| (1..=MAX_CHOICES).contains(n))
+                            .ok_or(format!(
+                                "Invalid number of choices: {}, expected 1 to {}",
+                                choices, MAX_CHOICES
+                            ))?,
+                    );
+                }
+                "--max-time" => {
+                    let max_time = value()?;
+                    parsed.max_time = Some(
+                        parse_duration(&max_time)
+                            .ok_or(format!("Invalid duration: {}, e.g. 30s or 2m", max_time))?,
+                    );
+                }
+                "--format" => parsed.format = Some(ExportFormat::parse(&value()?)?),
+                "--filter" => parsed.filter = Some(Filter::parse(&value()?)?),
+                "--out" | "-o" => parsed.out = Some(value()?),
+                "--json-stream" => parsed.json_stream = Some(value()?),
+                "--file" => parsed.file = Some(value()?),
+                "--line" | "--col" => {
+                    let n = value()?;
+                    let n = Some(
+                        n.parse()
+                            .ok()
+                            .filter(|n| *n > 0)
+                            .ok_or(format!("Invalid {}: {}, expected a number from 1", flag, n))?,
+                    );
+                    if flag == "--line" {
+                        parsed.line = n;
+                    } else {
+                        parsed.col = n;
+                    }
+                }
+                "--token-fd" => {
+                    let fd = value()?;
+                    parsed.token_fd = Some(
+                        fd.parse()
+                            .map_err(|_| format!("Invalid file descriptor: {}", fd))?,
+                    );
+                }
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        parsed.command = Subcommand::parse(&positional, &parsed)?;
+
+        Ok(parsed)
+    }
+
+    /// Adds a post-processor once, in the order they were first given.
+    fn add_post(&mut self, post: postprocess::Builtin) {
+        if !self.post.contains(&post) {
+            self.post.push(post);
+        }
+    }
+}
+
+/// `500ms`, `30s`, `2m` or a bare number of seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|d| !d.is_zero())
+}
+
+impl Subcommand {
+    fn parse(positional: &[String], flags: &Args) -> Result<Option<Subcommand>, String> {
+        let words: Vec<&str> = positional.iter().map(|s| s.as_str()).collect();
+
+        let command = match words.as_slice() {
+            [] => return Ok(None),
+            ["auth", "login"] => {
+                let method = match (flags.web, flags.with_token) {
+                    (true, true) => {
+                        return Err("--web and --with-token can't be combined".to_string())
+                    }
+                    (true, false) => LoginMethod::Web,
+                    (false, true) => LoginMethod::WithToken,
+                    (false, false) => LoginMethod::DeviceFlow,
+                };
+                Subcommand::Auth(AuthCommand::Login(method))
+            }
+            ["auth", "logout"] => Subcommand::Auth(AuthCommand::Logout),
+            ["auth", "status"] | ["auth"] => Subcommand::Auth(AuthCommand::Status),
+            ["auth", other, ..] => return Err(format!("Unknown auth command: {}", other)),
+            ["whoami"] => Subcommand::Whoami,
+            ["models"] => Subcommand::Models,
+            ["feedback"] => Subcommand::Feedback,
+            ["bridge", "slack"] => Subcommand::BridgeSlack,
+            ["daemon"] => Subcommand::Daemon,
+            ["index"] => Subcommand::Index,
+            ["bundle-last-request"] => Subcommand::BundleLastRequest { path: None },
+            ["bundle-last-request", path] => Subcommand::BundleLastRequest {
+                path: Some(path.to_string()),
+            },
+            ["import"] => return Err("Usage: copilot import <file> [name]".to_string()),
+            ["import", path] => Subcommand::Import {
+                path: path.to_string(),
+                name: None,
+            },
+            ["import", path, name] => Subcommand::Import {
+                path: path.to_string(),
+                name: Some(name.to_string()),
+            },
+            ["adr"] => return Err("Usage: copilot adr <title>".to_string()),
+            ["adr", title @ ..] => Subcommand::Adr {
+                title: title.join(" "),
+            },
+            ["refactor"] => return Err("Usage: copilot refactor <instruction>".to_string()),
+            ["refactor", instruction @ ..] => Subcommand::Refactor {
+                instruction: instruction.join(" "),
+            },
+            ["complete"] => match (&flags.file, flags.line, flags.col) {
+                (Some(file), Some(line), Some(col)) => Subcommand::Complete {
+                    file: file.clone(),
+                    line,
+                    col,
+                },
+                _ => {
+                    return Err(
+                        "Usage: copilot complete --file <path> --line <n> --col <n>".to_string(),
+                    )
+                }
+            },
+            ["export"] => Subcommand::Export {
+                path: None,
+                format: flags.format.unwrap_or_default(),
+            },
+            ["export", path] => Subcommand::Export {
+                path: Some(path.to_string()),
+                format: flags.format.unwrap_or_default(),
+            },
+            ["history", "search", query @ ..] if !query.is_empty() => Subcommand::HistorySearch {
+                query: query.join(" "),
+            },
+            ["history", ..] => return Err("Usage: copilot history search <text>".to_string()),
+            ["ask", question @ ..] => Subcommand::Ask {
+                question: question.join(" "),
+            },
+            ["run"] => return Err("Usage: copilot run <template> [args]".to_string()),
+            ["run", template, args @ ..] => Subcommand::Run {
+                template: template.to_string(),
+                args: args.join(" "),
+            },
+            ["bridge", other, ..] => return Err(format!("Unknown bridge: {}", other)),
+            ["backup", "create"] => Subcommand::Backup(BackupCommand::Create {
+                path: None,
+                include_credentials: flags.include_credentials,
+            }),
+            ["backup", "create", path] => Subcommand::Backup(BackupCommand::Create {
+                path: Some(path.to_string()),
+                include_credentials: flags.include_credentials,
+            }),
+            ["backup", "restore", path] => Subcommand::Backup(BackupCommand::Restore {
+                path: path.to_string(),
+            }),
+            ["backup", ..] => {
+                return Err(
+                    "Usage: copilot backup create [archive] [--include-credentials] | restore <archive>"
+                        .to_string(),
+                )
+            }
+            ["edits", "log"] | ["edits"] => Subcommand::Edits(EditsCommand::Log),
+            ["edits", other, ..] => return Err(format!("Unknown edits command: {}", other)),
+            _ => return Err(format!("Unknown command: {}", words.join(" "))),
+        };
+
+        Ok(Some(command))
+    }
+}
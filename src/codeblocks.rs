@@ -0,0 +1,225 @@
+/// A fenced code block from a markdown answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    /// The language tag after the opening fence, lowercased. Empty if there is none.
+    pub lang: String,
+    pub code: String,
+}
+
+/// Extracts every fenced (```) code block from `markdown`, in order.
+pub fn extract(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+
+        match current.take() {
+            None => {
+                if let Some(info) = trimmed.strip_prefix("```") {
+                    current = Some(CodeBlock {
+                        lang: info.split_whitespace().next().unwrap_or("").to_lowercase(),
+                        code: String::new(),
+                    });
+                }
+            }
+            Some(block) if trimmed.starts_with("```") => blocks.push(block),
+            Some(mut block) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+                current = Some(block);
+            }
+        }
+    }
+
+    // an unterminated block at the end of the answer still counts
+    if let Some(block) = current {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+/// The JSON in an answer: the whole answer, or the first code block that parses.
+pub fn find_json(markdown: &str) -> Option<serde_json::Value> {
+    if let Ok(value) = serde_json::from_str(markdown.trim()) {
+        return Some(value);
+    }
+    extract(markdown)
+        .iter()
+        .find_map(|block| serde_json::from_str(block.code.trim()).ok())
+}
+
+/// Suggests a file name for a code block based on its language and content,
+/// e.g. `main.rs` for Rust with a `fn main`, or `Dockerfile` for a block starting with `FROM`.
+pub fn infer_filename(block: &CodeBlock) -> String {
+    if let Some(path) = path_from_header_comment(&block.code) {
+        return path;
+    }
+
+    let code = &block.code;
+    let first_line = code
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("")
+        .trim();
+
+    if block.lang == "dockerfile" || first_line.starts_with("FROM ") {
+        return "Dockerfile".to_string();
+    }
+    if block.lang == "makefile" || block.lang == "make" {
+        return "Makefile".to_string();
+    }
+
+    let name = match block.lang.as_str() {
+        "rust" | "rs" => {
+            if code.contains("fn main(") {
+                "main.rs"
+            } else {
+                "lib.rs"
+            }
+        }
+        "python" | "py" => {
+            if code.contains("__main__") {
+                "main.py"
+            } else {
+                "script.py"
+            }
+        }
+        "javascript" | "js" => "index.js",
+        "jsx" => "App.jsx",
+        "typescript" | "ts" => "index.ts",
+        "tsx" => "App.tsx",
+        "go" | "golang" => "main.go",
+        "c" => "main.c",
+        "cpp" | "c++" | "cc" => "main.cpp",
+        "java" => return java_filename(code),
+        "kotlin" | "kt" => "Main.kt",
+        "ruby" | "rb" => "main.rb",
+        "php" => "index.php",
+        "swift" => "main.swift",
+        "sh" | "bash" | "shell" | "zsh" => "script.sh",
+        "powershell" | "ps1" => "script.ps1",
+        "toml" => {
+            if code.contains("[package]") || code.contains("[workspace]") {
+                "Cargo.toml"
+            } else {
+                "config.toml"
+            }
+        }
+        "json" => {
+            if code.contains("\"dependencies\"") || code.contains("\"scripts\"") {
+                "package.json"
+            } else if code.contains("\"compilerOptions\"") {
+                "tsconfig.json"
+            } else {
+                "data.json"
+            }
+        }
+        "yaml" | "yml" => {
+            if code.contains("services:") {
+                "docker-compose.yml"
+            } else if code.contains("jobs:") && code.contains("runs-on:") {
+                ".github/workflows/ci.yml"
+            } else {
+                "config.yml"
+            }
+        }
+        "html" => "index.html",
+        "css" => "style.css",
+        "sql" => "query.sql",
+        "markdown" | "md" => "README.md",
+        "lua" => "init.lua",
+        "vim" => "init.vim",
+        _ => "snippet.txt",
+    };
+
+    name.to_string()
+}
+
+/// The fence language for a file, from its name or extension, e.g. `rust` for `src/gh.rs`.
+/// Empty when unknown, which still makes a valid fence.
+pub fn lang_for_path(path: &std::path::Path) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    match name {
+        "Dockerfile" => return "dockerfile",
+        "Makefile" | "makefile" => return "makefile",
+        _ => {}
+    }
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match ext.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "sh" | "bash" | "zsh" => "sh",
+        "ps1" => "powershell",
+        "toml" => "toml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "sql" => "sql",
+        "md" => "markdown",
+        "lua" => "lua",
+        "vim" => "vim",
+        _ => "",
+    }
+}
+
+/// Picks up a path the model put in a leading comment, like `// src/gh.rs` or `# file: app.py`.
+fn path_from_header_comment(code: &str) -> Option<String> {
+    let first_line = code.lines().next()?.trim();
+    let comment = ["//", "#", "--", "/*", "<!--"]
+        .iter()
+        .find_map(|prefix| first_line.strip_prefix(prefix))?;
+
+    let candidate = comment
+        .trim()
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim();
+    let candidate = candidate
+        .strip_prefix("file:")
+        .or_else(|| candidate.strip_prefix("File:"))
+        .unwrap_or(candidate)
+        .trim();
+
+    let looks_like_path = !candidate.contains(' ')
+        && candidate
+            .rsplit_once('.')
+            .is_some_and(|(stem, ext)| !stem.is_empty() && (1..=5).contains(&ext.len()));
+
+    looks_like_path.then(|| candidate.to_string())
+}
+
+fn java_filename(code: &str) -> String {
+    code.lines()
+        .find_map(|line| {
+            let rest = line.trim().strip_prefix("public class ")?;
+            let name: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            (!name.is_empty()).then_some(name)
+        })
+        .map(|name| format!("{}.java", name))
+        .unwrap_or("Main.java".to_string())
+}
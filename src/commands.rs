@@ -0,0 +1,404 @@
+use crate::{config::InfraHelper, feedback::Rating};
+
+/// Slash commands that can be typed at the `You: ` prompt instead of a question.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Exit,
+    /// `/profile` lists the profiles, `/profile <name>` switches to one.
+    Profile(Option<String>),
+    /// `/web <query>` searches the web and answers with citations.
+    Web(Option<String>),
+    /// `/apply [n] [path]` saves code block n (default: the last one) of the last answer to a file.
+    Apply(Option<String>),
+    /// `/open [n]` opens code block n (default: the last one) of the last answer in an editor
+    /// and offers to attach the edited code to the next question.
+    Open(Option<String>),
+    /// `/session [list]`, `/session new|switch|delete <name>` manage named conversations,
+    /// `/session prompt [text|reset]` shows or changes the current one's system prompt.
+    Session(Option<String>),
+    /// `/scratch` opens the session's scratchpad in an editor, `@scratch` in a question
+    /// attaches it.
+    Scratch,
+    /// `/undo-edit` restores the file changed by the last `/apply`.
+    UndoEdit,
+    /// `/export [html] <file> [profile]` writes the conversation as Markdown, or as a
+    /// standalone HTML page, redacted with a profile from `redaction_profiles`.
+    Export(Option<String>),
+    /// `/system` shows the system prompt, `/system <text>` replaces it and `/system reset`
+    /// goes back to the one from the config file, or the default. Both start a new conversation.
+    System(Option<String>),
+    /// `/model` shows the chat model, `/model <name>` switches to another one.
+    Model(Option<String>),
+    /// `/brief` asks for short answers, `/verbose` for detailed ones. Repeating either goes
+    /// back to the default.
+    Brief,
+    Verbose,
+    /// `/json` asks for answers that are a JSON object, repeating it goes back to prose.
+    Json,
+    /// `/models` lists the models `/model` can switch to.
+    Models,
+    /// `/check [cargo_check|npm_test|pytest]` runs the project's checks and attaches a
+    /// summary of the result to the next question.
+    Check(Option<String>),
+    /// `/file <path>` attaches a file, fenced with its path and language, to the next question.
+    File(Option<String>),
+    /// `/capture [duration] <command>` runs a command like `kubectl logs -f` for a while
+    /// (default 10s) and attaches what it printed to the next question.
+    Capture(Option<String>),
+    /// `/k8s pods`, `/docker ps` and other read-only views attached as context, when enabled
+    /// in `infra_helpers`.
+    Infra(InfraHelper, Option<String>),
+    /// `/tasks` shows the checklist made from an answer with `/tasks new`, `/tasks <n>` checks
+    /// off item n (or unchecks it) and `/tasks export <file>` writes it as a Markdown task list.
+    Tasks(Option<String>),
+    /// `/to-issue [owner/repo]` drafts a GitHub issue from the conversation and files it
+    /// after a preview.
+    ToIssue(Option<String>),
+    /// `/tpl` lists the prompt templates, `/tpl <name> [args]` asks one.
+    Template(Option<String>),
+    /// `/whoami` shows the account and Copilot plan in use.
+    Whoami,
+    /// `/good` and `/bad [reason]` rate the last answer.
+    Rate(Rating, Option<String>),
+    /// `/feedback` shows how answers were rated so far.
+    Feedback,
+    /// `/search <text>` finds the stored conversations that mention something and opens one
+    /// of the matching exchanges.
+    Search(Option<String>),
+    /// `/fork` lists the messages by number, `/fork <n>` continues in a new session branched
+    /// at message n, leaving the original as it is.
+    Fork(Option<String>),
+    /// `/edit` puts the last question back on the prompt to change it, and asks the edited
+    /// version in place of the original.
+    Edit,
+    /// `/regenerate [temperature]` replaces the last answer with a new one to the same
+    /// question, e.g. `/regenerate 0.8` for a more varied take.
+    Regenerate(Option<String>),
+    /// `/retry` tries to reconnect when running offline.
+    Retry,
+    /// `/cache` shows the HTTP cache size, `/cache clear` empties it.
+    Cache(Option<String>),
+    /// `/remember <fact>` keeps a fact about the user for future sessions, when `memory`
+    /// is on.
+    Remember(Option<String>),
+    /// `/memories` lists what's remembered, `/memories forget <n>` and `/memories clear`
+    /// remove it, `/memories extract` finds facts worth keeping in the conversation.
+    Memories(Option<String>),
+    /// `/usage` shows the tokens of the last answer and of the whole session.
+    Usage,
+    /// `/help`, or `?` on its own, lists the commands, keybindings and current settings.
+    Help,
+    Unknown(String),
+}
+
+/// How a command is listed by `/help`.
+pub struct CommandHelp {
+    /// The names it's typed as, without the slash.
+    pub names: &'static [&'static str],
+    pub usage: &'static str,
+    pub summary: &'static str,
+}
+
+/// Every command `Command::parse` knows. A name missing here parses as `Unknown`, so the
+/// list `/help` shows can't fall behind what works.
+pub const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        names: &["help"],
+        usage: "/help, ?",
+        summary: "Show this help",
+    },
+    CommandHelp {
+        names: &["exit", "quit"],
+        usage: "/exit, /quit",
+        summary: "Leave the chat",
+    },
+    CommandHelp {
+        names: &["model"],
+        usage: "/model [name]",
+        summary: "Show or switch the chat model",
+    },
+    CommandHelp {
+        names: &["models"],
+        usage: "/models",
+        summary: "List the models /model can switch to",
+    },
+    CommandHelp {
+        names: &["system"],
+        usage: "/system [text|reset]",
+        summary: "Show or replace the system prompt",
+    },
+    CommandHelp {
+        names: &["brief"],
+        usage: "/brief",
+        summary: "Ask for short answers, again to undo",
+    },
+    CommandHelp {
+        names: &["verbose"],
+        usage: "/verbose",
+        summary: "Ask for detailed answers, again to undo",
+    },
+    CommandHelp {
+        names: &["json"],
+        usage: "/json",
+        summary: "Ask for JSON answers, again to undo",
+    },
+    CommandHelp {
+        names: &["profile"],
+        usage: "/profile [name]",
+        summary: "List the profiles or switch to one",
+    },
+    CommandHelp {
+        names: &["whoami"],
+        usage: "/whoami",
+        summary: "Show the account and Copilot plan in use",
+    },
+    CommandHelp {
+        names: &["session"],
+        usage: "/session [list|new|switch|delete|prompt]",
+        summary: "Manage named conversations",
+    },
+    CommandHelp {
+        names: &["search"],
+        usage: "/search <text>",
+        summary: "Find earlier conversations that mention something",
+    },
+    CommandHelp {
+        names: &["fork"],
+        usage: "/fork [n]",
+        summary: "Continue in a new session from message n",
+    },
+    CommandHelp {
+        names: &["edit"],
+        usage: "/edit",
+        summary: "Change the last question and ask it again",
+    },
+    CommandHelp {
+        names: &["regenerate"],
+        usage: "/regenerate [temperature]",
+        summary: "Get another answer to the last question",
+    },
+    CommandHelp {
+        names: &["web"],
+        usage: "/web <query>",
+        summary: "Search the web and answer with citations",
+    },
+    CommandHelp {
+        names: &["file"],
+        usage: "/file <path>",
+        summary: "Attach a file to the next question",
+    },
+    CommandHelp {
+        names: &["check"],
+        usage: "/check [cargo_check|npm_test|pytest]",
+        summary: "Run the project's checks and attach the result",
+    },
+    CommandHelp {
+        names: &["capture"],
+        usage: "/capture [duration] <command>",
+        summary: "Attach what a command prints for a while",
+    },
+    CommandHelp {
+        names: &["k8s", "docker"],
+        usage: "/k8s, /docker <view>",
+        summary: "Attach read-only cluster or container views",
+    },
+    CommandHelp {
+        names: &["scratch"],
+        usage: "/scratch",
+        summary: "Edit the session's scratchpad, @scratch attaches it",
+    },
+    CommandHelp {
+        names: &["apply"],
+        usage: "/apply [n] [path]",
+        summary: "Save a code block of the last answer to a file",
+    },
+    CommandHelp {
+        names: &["undo-edit"],
+        usage: "/undo-edit",
+        summary: "Restore the file changed by the last /apply",
+    },
+    CommandHelp {
+        names: &["open"],
+        usage: "/open [n]",
+        summary: "Open a code block of the last answer in an editor",
+    },
+    CommandHelp {
+        names: &["tasks"],
+        usage: "/tasks [new|<n>|export <file>]",
+        summary: "Track a checklist made from an answer",
+    },
+    CommandHelp {
+        names: &["tpl"],
+        usage: "/tpl [name] [args]",
+        summary: "List the prompt templates or ask one",
+    },
+    CommandHelp {
+        names: &["export"],
+        usage: "/export [html] <file> [profile]",
+        summary: "Write the conversation as Markdown or HTML",
+    },
+    CommandHelp {
+        names: &["to-issue"],
+        usage: "/to-issue [owner/repo]",
+        summary: "Draft a GitHub issue from the conversation",
+    },
+    CommandHelp {
+        names: &["good", "bad"],
+        usage: "/good, /bad [reason]",
+        summary: "Rate the last answer",
+    },
+    CommandHelp {
+        names: &["feedback"],
+        usage: "/feedback",
+        summary: "Show how answers were rated so far",
+    },
+    CommandHelp {
+        names: &["remember"],
+        usage: "/remember <fact>",
+        summary: "Keep a fact about you for future sessions",
+    },
+    CommandHelp {
+        names: &["memories"],
+        usage: "/memories [forget <n>|clear|extract]",
+        summary: "Manage the remembered facts",
+    },
+    CommandHelp {
+        names: &["usage"],
+        usage: "/usage",
+        summary: "Show the tokens of the last answer and the session",
+    },
+    CommandHelp {
+        names: &["cache"],
+        usage: "/cache [clear]",
+        summary: "Show or empty the HTTP cache",
+    },
+    CommandHelp {
+        names: &["retry"],
+        usage: "/retry",
+        summary: "Try to reconnect when running offline",
+    },
+];
+
+/// `/help`: the commands, the keybindings of the prompt and `settings` as (name, value).
+pub fn help(settings: &[(&str, String)]) -> String {
+    let bold = crate::term::style("\x1b[1m");
+    let reset = crate::term::style("\x1b[0m");
+    let mut out = format!("{}Commands{}\n", bold, reset);
+    let width = COMMANDS.iter().map(|c| c.usage.len()).max().unwrap_or(0);
+    for command in COMMANDS {
+        out += &format!("  {:width$}  {}\n", command.usage, command.summary);
+    }
+
+    out += &format!("\n{}Keys{}\n", bold, reset);
+    let width = crate::input::KEYBINDINGS
+        .iter()
+        .map(|(keys, _)| keys.len())
+        .max()
+        .unwrap_or(0);
+    for (keys, action) in crate::input::KEYBINDINGS {
+        out += &format!("  {:width$}  {}\n", keys, action);
+    }
+
+    out += &format!("\n{}Settings{}\n", bold, reset);
+    let width = settings
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, value) in settings {
+        out += &format!("  {:width$}  {}\n", name, value);
+    }
+    out
+}
+
+impl Command {
+    /// Parses a line of input, returning `None` if it should be sent to Copilot as-is.
+    pub fn parse(input: &str) -> Option<Command> {
+        let input = input.trim();
+
+        if input == "exit" {
+            return Some(Command::Exit);
+        }
+        if input == "?" {
+            return Some(Command::Help);
+        }
+
+        let rest = input.strip_prefix('/')?;
+        let (name, arg) = match rest.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim().to_string())),
+            None => (rest, None),
+        };
+        let arg = arg.filter(|a| !a.is_empty());
+
+        if !COMMANDS.iter().any(|c| c.names.contains(&name)) {
+            return Some(Command::Unknown(name.to_string()));
+        }
+
+        let command = match name {
+            "exit" | "quit" => Command::Exit,
+            "profile" => Command::Profile(arg),
+            "web" => Command::Web(arg),
+            "cache" => Command::Cache(arg),
+            "apply" => Command::Apply(arg),
+            "open" => Command::Open(arg),
+            "session" => Command::Session(arg),
+            "scratch" => Command::Scratch,
+            "undo-edit" => Command::UndoEdit,
+            "retry" => Command::Retry,
+            "regenerate" => Command::Regenerate(arg),
+            "edit" => Command::Edit,
+            "fork" => Command::Fork(arg),
+            "search" => Command::Search(arg),
+            "good" => Command::Rate(Rating::Good, arg),
+            "bad" => Command::Rate(Rating::Bad, arg),
+            "feedback" => Command::Feedback,
+            "whoami" => Command::Whoami,
+            "tasks" => Command::Tasks(arg),
+            "to-issue" => Command::ToIssue(arg),
+            "tpl" => Command::Template(arg),
+            "export" => Command::Export(arg),
+            "model" => Command::Model(arg),
+            "system" => Command::System(arg),
+            "models" => Command::Models,
+            "check" => Command::Check(arg),
+            "file" => Command::File(arg),
+            "capture" => Command::Capture(arg),
+            "k8s" => Command::Infra(InfraHelper::K8s, arg),
+            "docker" => Command::Infra(InfraHelper::Docker, arg),
+            "brief" => Command::Brief,
+            "verbose" => Command::Verbose,
+            "json" => Command::Json,
+            "remember" => Command::Remember(arg),
+            "memories" => Command::Memories(arg),
+            "usage" => Command::Usage,
+            "help" => Command::Help,
+            _ => Command::Unknown(name.to_string()),
+        };
+
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_listed_command_parses() {
+        for command in COMMANDS {
+            for name in command.names {
+                let parsed = Command::parse(&format!("/{}", name));
+                assert!(
+                    !matches!(parsed, Some(Command::Unknown(_))),
+                    "/{} is listed but not handled",
+                    name
+                );
+            }
+        }
+        assert_eq!(Command::parse("?"), Some(Command::Help));
+        assert_eq!(
+            Command::parse("/nope"),
+            Some(Command::Unknown("nope".to_string()))
+        );
+    }
+}
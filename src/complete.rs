@@ -0,0 +1,133 @@
+use std::{io::Read, path::Path};
+
+use reqwest::Client;
+
+use crate::{
+    codeblocks, config::Config, copilot::CopilotManager, gh::AuthenticationManager, license, utils,
+};
+
+/// How much of the file before and after the cursor is sent, the nearest part.
+const MAX_PREFIX_BYTES: usize = 8_000;
+const MAX_SUFFIX_BYTES: usize = 2_000;
+
+/// Completions are kept to a few lines, like ghost text in an editor.
+const MAX_TOKENS: u32 = 200;
+
+/// Splits `text` at a 1-based `line` and `col`, counting the column in characters. A column
+/// past the end of the line puts the cursor at its end.
+fn split_at(text: &str, line: usize, col: usize) -> Result<(&str, &str), String> {
+    if line == 0 || col == 0 {
+        return Err("--line and --col start at 1".to_string());
+    }
+    let mut offset = 0;
+    let mut lines = text.split_inclusive('\n');
+    for _ in 1..line {
+        offset += lines
+            .next()
+            .ok_or(format!("The file has fewer than {} lines", line))?
+            .len();
+    }
+    let current = lines.next().unwrap_or_default();
+    let current = current.strip_suffix('\n').unwrap_or(current);
+    let current = current.strip_suffix('\r').unwrap_or(current);
+    offset += current
+        .char_indices()
+        .nth(col - 1)
+        .map_or(current.len(), |(i, _)| i);
+    Ok(text.split_at(offset))
+}
+
+/// The end of `text` starting at a line boundary, at most `max` bytes.
+fn tail(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut start = text.len() - max;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    match text[start..].find('\n') {
+        Some(newline) => &text[start + newline + 1..],
+        None => &text[start..],
+    }
+}
+
+/// The start of `text` ending at a line boundary, at most `max` bytes.
+fn head(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let cut = utils::truncate_bytes(text, max);
+    match cut.rfind('\n') {
+        Some(newline) => &cut[..=newline],
+        None => cut,
+    }
+}
+
+/// The prompt the model sees: the file's path in a comment, as Copilot's editor plugins
+/// send it, then the code before the cursor.
+fn prompt(path: &str, prefix: &str) -> String {
+    let comment = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(license::comment_prefix);
+    match comment {
+        Some(comment) => format!("{} Path: {}\n{}", comment, path, prefix),
+        None => prefix.to_string(),
+    }
+}
+
+/// Runs `copilot complete --file <path> --line <n> --col <n>`: prints the code Copilot
+/// suggests at the cursor, and nothing else, for editor integrations. With `--file -` the
+/// buffer is read from stdin.
+pub async fn run(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    path: &str,
+    line: usize,
+    col: usize,
+) -> Result<String, String> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        text
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?
+    };
+    let (prefix, suffix) = split_at(&text, line, col)?;
+
+    let config = Config::load();
+    let auth = auth_manager.cache_auth().await.map_err(|e| e.to_string())?;
+    let copilot_m =
+        CopilotManager::new(&auth, client, config.system_prompt()).with_endpoint(config.endpoint);
+
+    let language = codeblocks::lang_for_path(Path::new(path));
+    copilot_m
+        .fill_in_middle(
+            &prompt(path, tail(prefix, MAX_PREFIX_BYTES)),
+            head(suffix, MAX_SUFFIX_BYTES),
+            language,
+            MAX_TOKENS,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_the_cursor() {
+        let text = "fn main() {\n    let é = 1;\n}\n";
+        assert_eq!(split_at(text, 2, 10).unwrap().0, "fn main() {\n    let é");
+        assert_eq!(split_at(text, 2, 99).unwrap().1, "\n}\n");
+        assert_eq!(split_at(text, 4, 1).unwrap().1, "");
+        assert!(split_at(text, 9, 1).is_err());
+
+        assert_eq!(tail("one\ntwo\nthree", 9), "three");
+        assert_eq!(head("one\ntwo\nthree", 9), "one\ntwo\n");
+        assert_eq!(prompt("src/a.rs", "fn"), "// Path: src/a.rs\nfn");
+    }
+}
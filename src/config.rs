@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{gh::GithubCopilotAuth, prompts, tools, urls, utils};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The API accepts no more than this many stop sequences.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// A single set of GitHub credentials, e.g. a personal and a work Copilot seat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub token: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// The OAuth scopes granted to `token`, as reported when it was created.
+    #[serde(default)]
+    pub scopes: Option<String>,
+    /// Unix timestamps of when the token was stored and last accepted by GitHub.
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub last_verified_at: Option<i64>,
+    /// The last Copilot session token, reused at startup until it expires.
+    #[serde(default)]
+    pub copilot: Option<GithubCopilotAuth>,
+}
+
+impl Profile {
+    pub fn new(token: String, host: String) -> Profile {
+        Profile {
+            token,
+            user: None,
+            host,
+            scopes: None,
+            created_at: Some(chrono::Utc::now().timestamp()),
+            last_verified_at: None,
+            copilot: None,
+        }
+    }
+
+    /// The cached Copilot session token, if it's still valid for at least a minute.
+    pub fn valid_copilot_auth(&self) -> Option<&GithubCopilotAuth> {
+        let now = chrono::Utc::now().timestamp() as u64;
+        self.copilot
+            .as_ref()
+            .filter(|copilot| copilot.expires_at > now + 60)
+    }
+}
+
+fn default_host() -> String {
+    urls::GITHUB_HOST.to_string()
+}
+
+/// The on-disk configuration stored in `~/.config/copilot/config.json`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub active_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Prompts estimated above this many tokens need confirmation before sending.
+    pub prompt_token_limit: usize,
+    /// Requests above this many bytes, history and attachments included, need confirmation.
+    pub large_request_bytes: usize,
+    /// The size of the model's context window in tokens.
+    pub context_window: usize,
+    /// Older messages are left out of requests once the conversation is estimated above this
+    /// many tokens, so long sessions stay within the context window.
+    pub history_token_budget: usize,
+    /// Only the newest this many messages of a chat are kept, older exchanges are forgotten.
+    /// Unlimited when unset.
+    pub history_max_messages: Option<usize>,
+    /// Replaces the built-in system prompt. `--system-prompt` and `/system` override it.
+    pub system_prompt: Option<String>,
+    /// Lets `/remember` and `/memories extract` keep facts about you, which go into the
+    /// system prompt of every chat. Off unless set, and stored only in `memories.json`.
+    pub memory: bool,
+    /// Team jargon explained to the model, e.g. `{"LGTM": "looks good to me", "PDB": "the payments database"}`.
+    pub glossary: BTreeMap<String, String>,
+    /// Sampling temperature, lower is more deterministic. `--temperature` overrides it.
+    pub temperature: f64,
+    /// Nucleus sampling cutoff. `--top-p` overrides it.
+    pub top_p: f64,
+    /// Caps the length of answers in tokens, unlimited when unset. `--max-tokens` overrides it.
+    pub max_tokens: Option<u32>,
+    /// Sequences that end an answer where they'd appear, e.g. `["\n---\n"]`, at most 4.
+    /// `--stop` overrides them.
+    pub stop: Vec<String>,
+    /// The `max_tokens` used when answers are piped to another program, and with `--brief`.
+    pub pipe_max_tokens: u32,
+    pub brief_max_tokens: u32,
+    /// Streamed pieces of an answer are drawn together at most this often, in milliseconds.
+    /// 0 draws every piece as soon as it arrives.
+    pub render_interval_ms: u64,
+    /// Which Copilot API chat goes through. `auto` picks it from the account's plan.
+    pub endpoint: CopilotEndpoint,
+    /// Connects to the Copilot API while the first question is typed, so its answer doesn't
+    /// wait for the TLS handshake. With `COPILOT_DEBUG` the time saved goes to `debug.txt`.
+    pub prewarm: bool,
+    /// How attached context is picked when a question is sent.
+    pub context_selection: ContextSelection,
+    /// With `"context_selection": "scored"`, at most this many tokens of context are sent.
+    pub context_budget: usize,
+    /// How many pieces of the repository's code, indexed with `copilot index`, are attached
+    /// to each question as context. 0 turns retrieval off.
+    pub retrieval_chunks: usize,
+    /// The model `copilot index` and retrieval embed code and questions with.
+    pub embedding_model: String,
+    /// Enables the `/web` search command.
+    pub web_search: Option<WebSearchProvider>,
+    /// Proxy for every request, e.g. `http://proxy:3128` or `socks5://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+    /// A PEM file with extra root certificates, e.g. for a TLS intercepting proxy.
+    pub ca_cert: Option<String>,
+    /// Disables certificate verification entirely. Only meant for debugging.
+    pub tls_insecure: bool,
+    /// The `/k8s` and `/docker` helpers, off unless listed here, e.g. `["k8s", "docker"]`.
+    pub infra_helpers: Vec<InfraHelper>,
+    /// Local tools the model may call in the chat, each run only after confirmation, e.g.
+    /// `["read_file", "shell"]`. Off unless listed.
+    pub tools: Vec<BuiltinTool>,
+    /// An answer stops after this many rounds of tool calls, so a model that keeps calling
+    /// them doesn't spend tokens forever.
+    pub max_tool_rounds: usize,
+    /// At most this much of a `/capture`d command's output is attached, the newest part.
+    pub capture_max_bytes: usize,
+    /// Files attached with `/file` are cut to this many bytes, keeping the beginning.
+    pub file_max_bytes: usize,
+    /// Input piped into `copilot ask` is cut to this many bytes, keeping the beginning.
+    pub stdin_max_bytes: usize,
+    /// Globs of files that /apply and other edits must never modify, e.g. `["**/prod/**", "Cargo.lock"]`.
+    pub protect: Vec<String>,
+    /// What happens when a write would add something that looks like a secret: `block`
+    /// refuses it, `warn` writes it anyway with a warning, `off` skips the check.
+    pub secret_scan: SecretScan,
+    /// Checks shell commands in answers against this machine: `path` warns about programs
+    /// that aren't installed, `flags` also about long flags their `--help` doesn't list
+    /// (running `<program> --help` for it), `off` skips the check.
+    pub shell_check: ShellCheck,
+    /// Put on top of new files written from answers: the header text, commented for the
+    /// file's language, or `"auto"` for the license comment the project's files of the same
+    /// type start with.
+    pub license_header: Option<String>,
+    /// The editor `/open` uses instead of `$VISUAL`/`$EDITOR`, e.g. `code --wait`. GUI editors
+    /// need their wait flag so edits can be offered back as context.
+    pub editor: Option<String>,
+    /// A shell command run after every answer, with the answer on stdin.
+    pub notify_command: Option<String>,
+    /// `/good` and `/bad` also POST the feedback as JSON to this URL.
+    pub feedback_webhook: Option<String>,
+    /// URLs that get a JSON POST when something happens, e.g. to route activity into Slack.
+    pub webhooks: Vec<Webhook>,
+    /// Named sets of redactions for `/export`, e.g. `{"public": ["secrets", "paths", "usernames"]}`.
+    /// `default` is used when no profile is given and applies all of them unless overridden.
+    pub redaction_profiles: BTreeMap<String, Vec<Redaction>>,
+    /// The Slack app `copilot bridge slack` connects as.
+    pub slack: Option<SlackConfig>,
+    /// The OAuth app used by `auth login --web`. Its callback URL must be `http://127.0.0.1/callback`.
+    pub oauth_client_id: Option<String>,
+    pub oauth_client_secret: Option<String>,
+    /// Where request counts, latencies and outcomes are reported, e.g.
+    /// `{"exporter": "textfile", "path": "/var/lib/node_exporter/copilot.prom"}` for
+    /// node_exporter's textfile collector or `{"exporter": "statsd", "address": "127.0.0.1:8125"}`.
+    /// Nothing is collected unless this is set.
+    pub metrics: Option<MetricsExporter>,
+    /// How many times a rate limited (429) question is retried, waiting as long as
+    /// `Retry-After` says.
+    pub rate_limit_retries: u32,
+    /// How many times requests to GitHub are attempted before giving up.
+    pub retry_attempts: u32,
+    /// The delay before the first retry, doubled for every further attempt.
+    pub retry_base_delay_ms: u64,
+    /// Set when config.json couldn't be parsed, so the defaults used instead are never
+    /// saved over it.
+    #[serde(skip)]
+    pub(crate) unparsed: bool,
+}
+
+/// A web search API, configured like `{"provider": "brave", "api_key": "..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum WebSearchProvider {
+    Searxng { url: String },
+    Brave { api_key: String },
+    Bing { api_key: String },
+}
+
+/// A local metrics exporter, configured like `{"exporter": "statsd", "address": "..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "exporter", rename_all = "lowercase")]
+pub enum MetricsExporter {
+    /// Rewrites a Prometheus text file after every request.
+    Textfile { path: String },
+    /// Sends counters and timings over UDP, named `<prefix>.requests.<outcome>` and
+    /// `<prefix>.request_duration`.
+    Statsd {
+        address: String,
+        #[serde(default = "default_statsd_prefix")]
+        prefix: String,
+    },
+}
+
+fn default_statsd_prefix() -> String {
+    "copilot".to_string()
+}
+
+/// `{"url": "...", "secret": "...", "events": ["edit_applied"]}`. With a `secret`, deliveries
+/// carry an `X-Copilot-Signature-256` HMAC header; without `events` every event is sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CopilotEndpoint {
+    #[default]
+    Auto,
+    Individual,
+    Business,
+    Enterprise,
+}
+
+/// A Slack app with Socket Mode enabled and the `app_mentions:read` and `chat:write` scopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlackConfig {
+    /// The app-level token (`xapp-...`) with `connections:write`.
+    pub app_token: String,
+    /// The bot token (`xoxb-...`) used to post answers.
+    pub bot_token: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    AnswerCompleted,
+    /// A prompt went over `prompt_token_limit`, or context was left out to fit `context_budget`.
+    BudgetExceeded,
+    /// A file was written by `/apply` or restored by `/undo-edit`.
+    EditApplied,
+}
+
+/// A CLI whose read-only views can be attached as context, see `infra_helpers`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfraHelper {
+    /// `/k8s`, runs `kubectl`.
+    K8s,
+    /// `/docker`.
+    Docker,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretScan {
+    #[default]
+    Block,
+    Warn,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellCheck {
+    Off,
+    #[default]
+    Path,
+    Flags,
+}
+
+/// The tools in `tools`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinTool {
+    /// Runs a command through `sh -c`, stopped after a minute.
+    Shell,
+    ReadFile,
+    /// GETs a URL through the HTTP cache.
+    WebFetch,
+    /// Searches with the `web_search` provider, like `/web`.
+    WebSearch,
+    /// The project checks `/check` runs, summarized.
+    CargoCheck,
+    NpmTest,
+    Pytest,
+}
+
+/// What an export redaction profile replaces.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// Tokens, keys and `password=` style values.
+    Secrets,
+    /// File paths, keeping the file name.
+    Paths,
+    /// The GitHub login and the local user name.
+    Usernames,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextSelection {
+    /// Send everything that was attached.
+    #[default]
+    All,
+    /// Rank the attachments by relevance per token and send what fits in `context_budget`.
+    Scored,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            active_profile: None,
+            profiles: BTreeMap::new(),
+            prompt_token_limit: 2000,
+            context_window: 8192,
+            history_token_budget: 6000,
+            history_max_messages: None,
+            large_request_bytes: 100_000,
+            system_prompt: None,
+            memory: false,
+            glossary: BTreeMap::new(),
+            temperature: 0.1,
+            top_p: 1.0,
+            max_tokens: None,
+            stop: Vec::new(),
+            pipe_max_tokens: 800,
+            brief_max_tokens: 300,
+            render_interval_ms: 16,
+            endpoint: CopilotEndpoint::Auto,
+            prewarm: false,
+            context_selection: ContextSelection::All,
+            context_budget: 4000,
+            retrieval_chunks: 4,
+            embedding_model: "text-embedding-3-small".to_string(),
+            web_search: None,
+            proxy: None,
+            ca_cert: None,
+            tls_insecure: false,
+            infra_helpers: Vec::new(),
+            tools: Vec::new(),
+            max_tool_rounds: tools::DEFAULT_MAX_ROUNDS,
+            capture_max_bytes: 16_000,
+            file_max_bytes: 32_000,
+            stdin_max_bytes: 32_000,
+            protect: Vec::new(),
+            secret_scan: SecretScan::Block,
+            shell_check: ShellCheck::Path,
+            license_header: None,
+            editor: None,
+            notify_command: None,
+            feedback_webhook: None,
+            webhooks: Vec::new(),
+            redaction_profiles: BTreeMap::new(),
+            slack: None,
+            metrics: None,
+            oauth_client_id: None,
+            oauth_client_secret: None,
+            rate_limit_retries: 3,
+            retry_attempts: 3,
+            retry_base_delay_ms: 500,
+            unparsed: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, migrating the old format (a bare token string)
+    /// into the `default` profile. A config.json that doesn't parse is reported and the
+    /// defaults are used for this run, without ever being saved over it.
+    pub fn load() -> Config {
+        let raw = utils::read_config_file();
+
+        match Config::parse(&raw) {
+            Ok(mut config) => {
+                config.validate();
+                config
+            }
+            Err(e) => {
+                eprintln!("Cannot read config.json, using the defaults: {}", e);
+                Config {
+                    unparsed: true,
+                    ..Config::default()
+                }
+            }
+        }
+    }
+
+    fn parse(raw: &str) -> Result<Config, serde_json::Error> {
+        let raw = raw.trim();
+
+        if raw.is_empty() {
+            return Ok(Config::default());
+        }
+
+        // only the old format isn't a JSON object
+        if !raw.starts_with('{') {
+            let mut config = Config::default();
+            config.profiles.insert(
+                DEFAULT_PROFILE.to_string(),
+                Profile::new(raw.to_string(), default_host()),
+            );
+            return Ok(config);
+        }
+
+        serde_json::from_str(raw)
+    }
+
+    /// Drops what the API would reject the requests for, saying so once per run.
+    fn validate(&mut self) {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+
+        let stop = self.stop.len();
+        if stop > MAX_STOP_SEQUENCES {
+            WARNED.call_once(|| {
+                eprintln!(
+                    "The config has {} stop sequences, only the first {} are used",
+                    stop, MAX_STOP_SEQUENCES
+                )
+            });
+            self.stop.truncate(MAX_STOP_SEQUENCES);
+        }
+    }
+
+    pub fn save(&self) {
+        if self.unparsed {
+            eprintln!("Not saving the config, fix config.json first");
+            return;
+        }
+        let json = serde_json::to_string_pretty(self).unwrap();
+        utils::write_config_file(&json);
+    }
+
+    /// `system_prompt`, or the built-in one.
+    pub fn system_prompt(&self) -> &str {
+        self.system_prompt
+            .as_deref()
+            .unwrap_or(prompts::COPILOT_INSTRUCTIONS)
+    }
+
+    /// The profile to use when none is given on the command line.
+    pub fn active_profile_name(&self) -> &str {
+        self.active_profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_bare_token_is_migrated() {
+        let config = Config::parse("gho_abc\n").unwrap();
+        assert_eq!(config.profiles[DEFAULT_PROFILE].token, "gho_abc");
+
+        let e = Config::parse("{\n  \"prompt_token_limit\": \"lots\"\n}").unwrap_err();
+        assert_eq!(e.line(), 2);
+        assert!(Config::parse("{ \"memory\": true, }").is_err());
+    }
+}
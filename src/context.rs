@@ -0,0 +1,281 @@
+use std::{collections::BTreeSet, io::Read, path::Path};
+
+use crate::{codeblocks, tokens, utils};
+
+/// A block of context (a pasted log, search results, a file, ...) waiting to be sent
+/// with the next question.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextItem {
+    pub label: String,
+    pub lang: String,
+    pub content: String,
+}
+
+impl ContextItem {
+    /// The fenced block that's sent to the model.
+    pub fn render(&self) -> String {
+        format!(
+            "{}:\n```{}\n{}\n```",
+            self.label,
+            self.lang,
+            self.content.trim_end()
+        )
+    }
+}
+
+/// A file read for `/file` or the input piped into `copilot ask`, cut to the size limit.
+#[derive(Debug)]
+pub struct AttachedFile {
+    pub item: ContextItem,
+    pub lines: usize,
+    /// How many bytes past the limit were left out, of those that were read.
+    pub dropped: usize,
+}
+
+/// Reads `path` as a context item labelled with the path and fenced in its language. Files
+/// longer than `max_bytes` keep their beginning, with a note telling the model the rest is
+/// missing.
+pub fn read_file(path: &Path, max_bytes: usize) -> Result<AttachedFile, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    if bytes.contains(&0) {
+        return Err(format!("{} looks like a binary file", path.display()));
+    }
+    let content =
+        String::from_utf8(bytes).map_err(|_| format!("{} isn't UTF-8 text", path.display()))?;
+
+    Ok(attach(
+        format!("File {}", path.display()),
+        codeblocks::lang_for_path(path),
+        content,
+        max_bytes,
+        false,
+    ))
+}
+
+/// Reads what was piped into `copilot ask` as a context item. Like files, input longer than
+/// `max_bytes` keeps its beginning with a note. Reading stops there, so an endless stream
+/// doesn't keep the question from being sent.
+pub fn read_piped(input: impl Read, max_bytes: usize) -> Result<AttachedFile, String> {
+    let mut bytes = Vec::new();
+    // one byte over the limit tells whether there's more, and lets the cut land at a line
+    // end like for files
+    let limit = u64::try_from(max_bytes).map_or(u64::MAX, |max| max.saturating_add(1));
+    input
+        .take(limit)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Can't read stdin: {}", e))?;
+    if bytes.contains(&0) {
+        return Err("The input on stdin looks like binary data".to_string());
+    }
+
+    // logs aren't always valid UTF-8, and the limit can split a character
+    let unread = bytes.len() > max_bytes;
+    let content = String::from_utf8_lossy(&bytes).into_owned();
+    Ok(attach(
+        "Input".to_string(),
+        "text",
+        content,
+        max_bytes,
+        unread,
+    ))
+}
+
+/// Cuts `content` to `max_bytes` and notes what's missing. `unread` means there was more
+/// after `content` that wasn't read, so how much is unknown.
+fn attach(
+    label: String,
+    lang: &str,
+    mut content: String,
+    max_bytes: usize,
+    unread: bool,
+) -> AttachedFile {
+    let dropped = truncate(&mut content, max_bytes);
+    let lines = content.lines().count();
+    if unread {
+        content.push_str("\n[... more input not read]");
+    } else if dropped > 0 {
+        content.push_str(&format!("\n[... {} more bytes not included]", dropped));
+    }
+    AttachedFile {
+        item: ContextItem {
+            label,
+            lang: lang.to_string(),
+            content,
+        },
+        lines,
+        dropped,
+    }
+}
+
+/// Cuts `text` to at most `max_bytes` at a line end when there's one, returning how many
+/// bytes were removed.
+fn truncate(text: &mut String, max_bytes: usize) -> usize {
+    if text.len() <= max_bytes {
+        return 0;
+    }
+    let mut end = utils::truncate_bytes(text, max_bytes).len();
+    if let Some(newline) = text[..end].rfind('\n') {
+        end = newline + 1;
+    }
+    let dropped = text.len() - end;
+    text.truncate(end);
+    dropped
+}
+
+#[derive(Debug)]
+pub struct Scored {
+    pub item: ContextItem,
+    pub tokens: usize,
+    /// How many distinct words of the question appear in the item.
+    pub relevance: usize,
+    /// Position in the order the items were attached in.
+    index: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub included: Vec<Scored>,
+    pub excluded: Vec<Scored>,
+}
+
+/// Words that say nothing about what the question is about.
+const STOPWORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "can", "this", "that", "with", "have",
+    "from", "what", "why", "how", "does", "when", "where", "which", "will", "would", "should",
+    "could", "there", "their", "them", "then", "than", "into", "about", "just",
+];
+
+fn terms(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| w.len() >= 3)
+        .map(|w| w.to_lowercase())
+        .filter(|w| !STOPWORDS.contains(&w.as_str()))
+        .collect()
+}
+
+/// Ranks `items` by relevance to `question` per token and fills `budget` tokens greedily,
+/// best first. Items that share no words with the question still fill leftover room, the
+/// smallest first. The included items keep the order they were attached in.
+pub fn select(items: Vec<ContextItem>, question: &str, budget: usize) -> Selection {
+    let wanted = terms(question);
+
+    let mut scored: Vec<Scored> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let have = terms(&format!("{} {}", item.label, item.content));
+            Scored {
+                tokens: tokens::estimate(&item.render()),
+                relevance: wanted.intersection(&have).count(),
+                item,
+                index,
+            }
+        })
+        .collect();
+
+    // relevance per token, compared as a.relevance / a.tokens > b.relevance / b.tokens
+    scored.sort_by(|a, b| {
+        (b.relevance * a.tokens.max(1))
+            .cmp(&(a.relevance * b.tokens.max(1)))
+            .then(a.tokens.cmp(&b.tokens))
+            .then(a.index.cmp(&b.index))
+    });
+
+    let mut selection = Selection::default();
+    let mut used = 0;
+    for item in scored {
+        if used + item.tokens <= budget {
+            used += item.tokens;
+            selection.included.push(item);
+        } else {
+            selection.excluded.push(item);
+        }
+    }
+    selection.included.sort_by_key(|item| item.index);
+
+    selection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(label: &str, content: &str) -> ContextItem {
+        ContextItem {
+            label: label.to_string(),
+            lang: String::new(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn fills_budget_by_relevance_per_token() {
+        let items = vec![
+            item("Pasted logs", &"unrelated noise line\n".repeat(40)),
+            item("Pasted code", "fn parse_config() { todo!() }"),
+            item("Web search results", "serde config parsing with defaults"),
+        ];
+
+        let selection = select(
+            items,
+            "Why does parse_config panic on the serde defaults?",
+            60,
+        );
+
+        let included: Vec<&str> = selection
+            .included
+            .iter()
+            .map(|s| s.item.label.as_str())
+            .collect();
+        assert_eq!(included, ["Pasted code", "Web search results"]);
+        assert_eq!(selection.excluded.len(), 1);
+        assert_eq!(selection.excluded[0].relevance, 0);
+    }
+
+    #[test]
+    fn long_files_are_cut_at_a_line() {
+        let mut text = "first line\nsecond line\nthird".to_string();
+        assert_eq!(truncate(&mut text, 15), 17);
+        assert_eq!(text, "first line\n");
+
+        let mut text = "ééé".to_string();
+        assert_eq!(truncate(&mut text, 3), 4);
+        assert_eq!(text, "é");
+        assert_eq!(
+            codeblocks::lang_for_path(Path::new("src/copilot.rs")),
+            "rust"
+        );
+    }
+
+    #[test]
+    fn piped_input_is_capped_with_a_note() {
+        let input = "panicked at src/main.rs\nstack backtrace:\n0: main\n";
+        let mut rest = input.as_bytes();
+        let attached = read_piped(&mut rest, 30).unwrap();
+        assert_eq!(attached.dropped, 31 - 24);
+        assert_eq!(
+            attached.item.content,
+            "panicked at src/main.rs\n\n[... more input not read]"
+        );
+        // the rest of the stream is left alone
+        assert_eq!(rest.len(), input.len() - 31);
+
+        let attached = read_piped(input.as_bytes(), 1000).unwrap();
+        assert_eq!(attached.dropped, 0);
+        assert_eq!(attached.item.content, input);
+        assert!(read_piped(&b"\x7fELF\0\0"[..], 1000).is_err());
+    }
+
+    #[test]
+    fn irrelevant_items_fill_leftover_room() {
+        let items = vec![
+            item("Pasted text", "hello there"),
+            item("Pasted logs", "boom"),
+        ];
+
+        let selection = select(items, "explain", 1000);
+
+        assert_eq!(selection.included.len(), 2);
+        assert_eq!(selection.included[0].item.label, "Pasted text");
+    }
+}
@@ -1,49 +1,112 @@
-use std::io::Write;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use crate::{
+    bundle::LastRequest,
+    config::{Config, CopilotEndpoint, MetricsExporter},
+    context::{self, ContextItem},
     gh,
-    headers::{CopilotCompletionHeaders, Headers},
-    utils,
-    term
+    headers::{CopilotCompletionHeaders, CopilotGhostHeaders, Headers},
+    http, input, metrics,
+    postprocess::PostProcessor,
+    prompts,
+    sinks::{DeltaSink, Fanout},
+    sse,
+    timings::Timings,
+    tokens,
+    tools::{self, ToolCall},
+    urls, utils,
 };
 
 use futures::StreamExt;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 
 // crossterm for writing
 
+// Every response type defaults missing fields and collects unknown ones in `extra`,
+// so new fields in the API never break parsing of the stream.
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct ContentFilterResult {
     filtered: bool,
     severity: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct ContentFilterOffsets {
     check_offset: i32,
     start_offset: i32,
     end_offset: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct Delta {
     content: Option<String>,
     role: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
+}
+
+/// A piece of a tool call. Streamed calls arrive in parts that share an `index`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct ToolCallDelta {
+    index: Option<usize>,
+    id: Option<String>,
+    function: FunctionDelta,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+impl Delta {
+    /// Adds the tool call pieces in this delta to `calls`.
+    fn collect_tool_calls(&self, calls: &mut Vec<ToolCall>) {
+        for (position, delta) in self.tool_calls.iter().flatten().enumerate() {
+            let index = delta.index.unwrap_or(position);
+            if calls.len() <= index {
+                calls.resize(index + 1, ToolCall::default());
+            }
+            calls[index].extend(
+                delta.id.as_deref(),
+                delta.function.name.as_deref(),
+                delta.function.arguments.as_deref(),
+            );
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct Choice {
     index: i32,
     content_filter_offsets: ContentFilterOffsets,
     content_filter_results: Option<ContentFilterResults>,
     delta: Delta,
+    /// The whole answer, in place of `delta` when the request wasn't streamed.
+    message: Delta,
     finish_reason: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct ContentFilterResults {
     hate: ContentFilterResult,
     self_harm: ContentFilterResult,
@@ -51,214 +114,1787 @@ struct ContentFilterResults {
     violence: ContentFilterResult,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ContentFilterResults {
+    /// What the filter found in a question it refused, from the error response's body.
+    fn from_error(body: &str) -> Option<ContentFilterResults> {
+        let mut error = serde_json::from_str::<Value>(body).ok()?;
+        let results = error["error"]["innererror"]["content_filter_result"].take();
+        serde_json::from_value(results).ok()
+    }
+
+    /// Adds the categories that filtered something to `verdicts`, each once.
+    fn collect_verdicts(&self, verdicts: &mut Vec<FilterVerdict>) {
+        let categories = [
+            ("hate", &self.hate),
+            ("self-harm", &self.self_harm),
+            ("sexual", &self.sexual),
+            ("violence", &self.violence),
+        ];
+        for (category, result) in categories {
+            if result.filtered && !verdicts.iter().any(|v| v.category == category) {
+                verdicts.push(FilterVerdict {
+                    category,
+                    severity: result.severity.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A content filter category that removed or cut off part of an answer, or refused the
+/// question.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FilterVerdict {
+    pub category: &'static str,
+    /// `safe`, `low`, `medium` or `high`.
+    pub severity: String,
+}
+
+impl std::fmt::Display for FilterVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.category, self.severity)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 struct GhCopilotResponse {
     choices: Vec<Choice>,
     created: i64,
     id: String,
+    /// Sent with the last chunk of a stream, or with the whole answer.
+    usage: Option<Usage>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
+}
+
+impl GhCopilotResponse {
+    fn log_unknown_fields(&self) {
+        utils::log_unknown_fields("GhCopilotResponse", &self.extra);
+        for choice in &self.choices {
+            utils::log_unknown_fields("Choice", &choice.extra);
+            utils::log_unknown_fields("Delta", &choice.delta.extra);
+            utils::log_unknown_fields("Message", &choice.message.extra);
+        }
+    }
+
+    /// Adds what every choice but the first says to `candidates`, by `choice.index`: the
+    /// deltas of a streamed response, the whole messages otherwise.
+    fn collect_candidates(&self, candidates: &mut BTreeMap<i32, String>, streamed: bool) {
+        for choice in self.choices.iter().filter(|c| c.index != 0) {
+            let part = if streamed {
+                &choice.delta
+            } else {
+                &choice.message
+            };
+            if let Some(content) = &part.content {
+                candidates
+                    .entry(choice.index)
+                    .or_default()
+                    .push_str(content);
+            }
+        }
+    }
+}
+
+/// A model from the Copilot API's `/models` listing.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: String,
+    pub capabilities: ModelCapabilities,
+    #[serde(flatten)]
+    extra: BTreeMap<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ModelCapabilities {
+    /// `chat` or `embeddings`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub limits: ModelLimits,
+    pub supports: ModelSupports,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ModelLimits {
+    pub max_context_window_tokens: Option<u64>,
+    pub max_output_tokens: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ModelSupports {
+    pub streaming: bool,
+    pub vision: bool,
+    pub tool_calls: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingList {
+    data: Vec<Embedding>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// How the model samples its answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    pub temperature: f64,
+    pub top_p: f64,
+    pub max_tokens: Option<u32>,
+    /// The answer ends right before the first of these, which isn't included.
+    pub stop: Vec<String>,
+    /// How many candidate answers to ask for, see `Completion::candidates`.
+    pub choices: u32,
+}
+
+impl Sampling {
+    pub fn from_config(config: &Config) -> Sampling {
+        Sampling {
+            temperature: config.temperature,
+            top_p: config.top_p,
+            max_tokens: config.max_tokens,
+            stop: config.stop.clone(),
+            choices: 1,
+        }
+    }
 }
 
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::from_config(&Config::default())
+    }
+}
+
+/// The `finish_reason` of an answer stopped with Esc or Ctrl+C.
+pub const FINISH_CANCELLED: &str = "cancelled";
+/// The `finish_reason` of an answer cut off by `--max-time`.
+pub const FINISH_MAX_TIME: &str = "max_time";
+/// The `finish_reason` of an answer the content filter cut off, see `Completion::filtered`.
+pub const FINISH_CONTENT_FILTER: &str = "content_filter";
+/// The `finish_reason` of an answer that ran into `max_tokens`.
+pub const FINISH_LENGTH: &str = "length";
+/// The `finish_reason` of an answer stopped after `max_tool_rounds` rounds of tool calls.
+pub const FINISH_TOOL_ROUNDS: &str = "tool_rounds";
+/// The `finish_reason` when the API refused the question, e.g. still rate limited after
+/// `rate_limit_retries`.
+pub const FINISH_ERROR: &str = "error";
+
+/// The `finish_reason` of an answer that wants tools run before it continues.
+const FINISH_TOOL_CALLS: &str = "tool_calls";
+
+/// How often the sinks are ticked while an answer streams, see `DeltaSink::on_tick`.
+const TICK_INTERVAL: Duration = Duration::from_millis(25);
+
+/// The chat model questions are sent to unless `--model` or `/model` picks another.
+pub const DEFAULT_MODEL: &str = "gpt-4";
+
 #[derive(Deserialize, Serialize, Clone)]
-pub struct Message<'alloc> {
-    content: &'alloc str,
-    role: &'alloc str,
+pub struct Message {
+    content: String,
+    role: String,
+    /// The model that wrote an answer. Only kept locally, never sent.
+    #[serde(skip)]
+    model: Option<String>,
+    /// The tokens an answer took, also only kept locally.
+    #[serde(skip)]
+    usage: Option<Usage>,
+}
+
+impl Message {
+    fn new(role: &str, content: &str) -> Message {
+        Message {
+            content: content.to_string(),
+            role: role.to_string(),
+            model: None,
+            usage: None,
+        }
+    }
+}
+
+/// A message of the conversation, see `CopilotManager::transcript`.
+pub struct Turn<'t> {
+    pub role: &'t str,
+    pub content: &'t str,
+    pub model: Option<&'t str>,
+    pub usage: Option<Usage>,
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct Completion {
     pub content: String,
     pub finish_reason: String,
+    pub usage: Usage,
+    /// Why the content filter stepped in, empty if it didn't.
+    pub filtered: Vec<FilterVerdict>,
+    /// How long sending, receiving and drawing it took, for `--timings`.
+    pub timings: Timings,
+    /// Every candidate answer in `choice.index` order when more than one was asked for, the
+    /// first being `content`. Only that one streams to the sinks and goes into the history.
+    pub candidates: Vec<String>,
 }
 
-pub struct CopilotManager<'a, 'alloc> {
+/// The tokens an answer took, as the API reported them.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Counted locally with `tokens::estimate`, because the API didn't report it.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+}
+
+impl Usage {
+    /// Estimates a request sending `messages` that was answered with `answer`.
+    fn estimate(messages: &Value, answer: &str) -> Usage {
+        let prompt: usize = messages
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|m| tokens::estimate(m["content"].as_str().unwrap_or_default()))
+            .sum();
+        Usage {
+            prompt_tokens: prompt as u64,
+            completion_tokens: tokens::estimate(answer) as u64,
+            estimated: true,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated |= other.estimated;
+    }
+}
+
+/// The Copilot API chat requests go to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiEndpoint {
+    /// Never `Auto`, that's resolved to one of the others.
+    pub kind: CopilotEndpoint,
+    pub base_url: String,
+}
+
+impl ApiEndpoint {
+    /// Resolves the `endpoint` setting. With `auto`, the API GitHub lists in the Copilot
+    /// token's `endpoints` wins, then the plan from `sku`.
+    pub fn resolve(setting: CopilotEndpoint, copilot_auth: &gh::GithubCopilotAuth) -> ApiEndpoint {
+        let kind = match setting {
+            CopilotEndpoint::Auto => {
+                let listed = copilot_auth
+                    .extra
+                    .get("endpoints")
+                    .and_then(|endpoints| endpoints.get("api"))
+                    .and_then(|api| api.as_str());
+                if let Some(url) = listed {
+                    return ApiEndpoint {
+                        kind: Self::kind_of(url),
+                        base_url: url.to_string(),
+                    };
+                }
+
+                match copilot_auth.sku.as_str() {
+                    sku if sku.starts_with("copilot_enterprise") => CopilotEndpoint::Enterprise,
+                    sku if sku.starts_with("copilot_for_business") => CopilotEndpoint::Business,
+                    _ => CopilotEndpoint::Individual,
+                }
+            }
+            explicit => explicit,
+        };
+
+        let base_url = match kind {
+            CopilotEndpoint::Business => urls::COPILOT_API_BUSINESS,
+            CopilotEndpoint::Enterprise => urls::COPILOT_API_ENTERPRISE,
+            _ => urls::COPILOT_API_INDIVIDUAL,
+        };
+        ApiEndpoint {
+            kind,
+            base_url: base_url.to_string(),
+        }
+    }
+
+    /// Guesses the kind from a listed URL. Anything that isn't one of the public
+    /// individual or business hosts, e.g. a GHE.com tenant, gets enterprise treatment.
+    fn kind_of(url: &str) -> CopilotEndpoint {
+        if url == urls::COPILOT_API_INDIVIDUAL || url.contains(".individual.") {
+            CopilotEndpoint::Individual
+        } else if url.contains(".business.") {
+            CopilotEndpoint::Business
+        } else {
+            CopilotEndpoint::Enterprise
+        }
+    }
+}
+
+/// How long answers should be, set with `/brief` and `/verbose`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AnswerStyle {
+    #[default]
+    Default,
+    Brief,
+    Verbose,
+}
+
+impl AnswerStyle {
+    fn directive(self) -> Option<&'static str> {
+        match self {
+            AnswerStyle::Default => None,
+            AnswerStyle::Brief => Some(prompts::BRIEF_DIRECTIVE),
+            AnswerStyle::Verbose => Some(prompts::VERBOSE_DIRECTIVE),
+        }
+    }
+}
+
+pub struct CopilotManager<'a> {
     vscode_sid: String,
     device_id: String,
     auth: &'a gh::GithubAuth,
     client: &'a Client,
-    allocator: &'alloc oxc_allocator::Allocator,
-    history: Vec<Message<'alloc>>,
-    full_message: String,
-}
-
-impl<'a, 'alloc> CopilotManager<'a, 'alloc> {
-    pub fn new(
-        auth: &'a gh::GithubAuth,
-        client: &'a Client,
-        allocator: &'a oxc_allocator::Allocator,
-        prompt: &'static str
-    ) -> CopilotManager<'a, 'alloc>
-    where
-        'a: 'alloc,
-    {
+    history: Vec<Message>,
+    /// The system prompt before the glossary and answer style are added.
+    prompt: String,
+    glossary: Option<String>,
+    /// The facts from `/remember`, when `memory` is on.
+    memories: Option<String>,
+    style: AnswerStyle,
+    /// Asks for answers that are a JSON object, and once more when one doesn't parse.
+    json_response: bool,
+    endpoint: ApiEndpoint,
+    model: String,
+    sampling: Sampling,
+    cancel_on_ctrl_c: bool,
+    /// Asks for the answer token by token rather than as one response.
+    streaming: bool,
+    rate_limit_retries: u32,
+    /// How connection errors and 5xx responses are retried.
+    retry: http::RetryPolicy,
+    max_time: Option<Duration>,
+    /// Leaves the oldest messages out of requests beyond this many tokens.
+    history_budget: Option<usize>,
+    /// Forgets the oldest exchanges once the history holds more messages than this.
+    history_limit: Option<usize>,
+    /// Renews the Copilot token when the API rejects it.
+    auth_manager: Option<&'a gh::AuthenticationManager>,
+    /// The renewed Copilot token, which replaces `auth.copilot_auth`.
+    refreshed: Option<gh::GithubCopilotAuth>,
+    /// Local tools the model may call while it answers.
+    tools: Option<tools::Registry>,
+    max_tool_rounds: usize,
+    /// Where each request's outcome and latency are reported.
+    metrics: Option<MetricsExporter>,
+    /// How long the warm-up request from `prewarm` took, once it's done. Taken by the first
+    /// question.
+    warmup: Option<Arc<OnceLock<Duration>>>,
+    /// Rewrite each answer, in order, before it's kept and shown.
+    post_processors: Vec<Box<dyn PostProcessor>>,
+    /// Everyone listening to the streamed answer.
+    sinks: Fanout,
+    /// Context blocks that get sent along with the next question.
+    context: Vec<ContextItem>,
+}
+
+impl<'a> CopilotManager<'a> {
+    pub fn new(auth: &'a gh::GithubAuth, client: &'a Client, prompt: &str) -> CopilotManager<'a> {
         let vscode_sid = crate::utils::generate_vscode_session_id();
         let device_id = crate::utils::random_hex_string(6);
 
-        let mut history = Vec::new();
-
-        history.push(Message {
-            content: allocator.alloc_str(prompt),
-            role: allocator.alloc_str("system"),
-        });
+        let history = vec![Message::new("system", prompt)];
 
         CopilotManager {
             vscode_sid,
             device_id,
             auth,
             client,
-            allocator,
             history,
-            full_message: String::new(),
+            prompt: prompt.to_string(),
+            glossary: None,
+            memories: None,
+            style: AnswerStyle::Default,
+            json_response: false,
+            endpoint: ApiEndpoint::resolve(CopilotEndpoint::Auto, &auth.copilot_auth),
+            model: DEFAULT_MODEL.to_string(),
+            sampling: Sampling::default(),
+            cancel_on_ctrl_c: false,
+            streaming: true,
+            rate_limit_retries: 0,
+            retry: http::RetryPolicy {
+                attempts: 1,
+                base_delay: Duration::ZERO,
+            },
+            max_time: None,
+            history_budget: None,
+            history_limit: None,
+            auth_manager: None,
+            refreshed: None,
+            tools: None,
+            max_tool_rounds: tools::DEFAULT_MAX_ROUNDS,
+            metrics: None,
+            warmup: None,
+            post_processors: Vec::new(),
+            sinks: Fanout::default(),
+            context: Vec::new(),
         }
     }
 
-    #[allow(unused_assignments)]
-    pub async fn ask(&mut self, prompt: &String, log: bool) -> Completion {
-        let url = "https://api.githubcopilot.com/chat/completions";
-        let headers = CopilotCompletionHeaders {
-            token: &self.auth.copilot_auth.token,
+    /// Uses the `endpoint` setting instead of picking the API from the account's plan.
+    pub fn with_endpoint(mut self, setting: CopilotEndpoint) -> Self {
+        self.endpoint = ApiEndpoint::resolve(setting, &self.auth.copilot_auth);
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Sends every following question to `model`, the history carries over.
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    /// The system prompt without the glossary, memories and answer style.
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    /// Replaces the system prompt given to `new`, keeping the glossary and answer style.
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_string();
+        self.update_system_prompt();
+    }
+
+    pub fn style(&self) -> AnswerStyle {
+        self.style
+    }
+
+    pub fn sampling(&self) -> &Sampling {
+        &self.sampling
+    }
+
+    /// Rewrites the system prompt with the directive for `style`, which applies to the whole
+    /// conversation including what was asked before.
+    pub fn set_style(&mut self, style: AnswerStyle) {
+        self.style = style;
+        self.update_system_prompt();
+    }
+
+    pub fn json_response(&self) -> bool {
+        self.json_response
+    }
+
+    /// Requests JSON mode for the following answers. One that doesn't parse is asked for
+    /// again once, with the parse error.
+    pub fn set_json_response(&mut self, json_response: bool) {
+        self.json_response = json_response;
+        self.update_system_prompt();
+    }
+
+    /// Lets Esc or Ctrl+C stop an answer while it's sent and streams in, which then finishes
+    /// with `FINISH_CANCELLED`. When stdin isn't a terminal only Ctrl+C does, caught as a
+    /// signal, and once it's been listened for it no longer ends the process.
+    pub fn cancel_on_ctrl_c(mut self) -> Self {
+        self.cancel_on_ctrl_c = true;
+        self
+    }
+
+    /// Renews the Copilot token through `auth_manager` and retries once when a question is
+    /// rejected with 401.
+    pub fn with_auth_manager(mut self, auth_manager: &'a gh::AuthenticationManager) -> Self {
+        self.auth_manager = Some(auth_manager);
+        self
+    }
+
+    /// Stops every answer `max_time` after the question was sent, keeping what arrived.
+    pub fn with_max_time(mut self, max_time: Option<Duration>) -> Self {
+        self.max_time = max_time;
+        self
+    }
+
+    /// With `false`, answers are requested in one piece and handed to the sinks once they're
+    /// complete, for models and scripts that don't want a stream.
+    pub fn with_streaming(mut self, streaming: bool) -> Self {
+        self.streaming = streaming;
+        self
+    }
+
+    /// Offers the `tools` to the model. Its calls run after confirmation and their results
+    /// are sent back until it answers; the history only keeps the question and that answer.
+    pub fn with_tools(mut self, tools: tools::Registry) -> Self {
+        self.tools = (!tools.is_empty()).then_some(tools);
+        self
+    }
+
+    /// Stops an answer after `rounds` rounds of tool calls with `FINISH_TOOL_ROUNDS`.
+    pub fn with_max_tool_rounds(mut self, rounds: usize) -> Self {
+        self.max_tool_rounds = rounds;
+        self
+    }
+
+    /// Runs every answer through `processor` after the ones added before it. The sinks then
+    /// get the answer in one piece once it's complete, as what streams in isn't final.
+    pub fn with_post_processor(mut self, processor: Box<dyn PostProcessor>) -> Self {
+        self.post_processors.push(processor);
+        self
+    }
+
+    /// Reports every request to the `metrics` exporter from the config, if one is set.
+    pub fn with_metrics(mut self, metrics: Option<MetricsExporter>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Retries rate limited questions up to `rate_limit_retries` times, and connection
+    /// errors and 5xx responses by `retry_attempts` and `retry_base_delay_ms`.
+    pub fn with_retries(mut self, config: &Config) -> Self {
+        self.rate_limit_retries = config.rate_limit_retries;
+        self.retry = http::RetryPolicy::from_config(config);
+        self
+    }
+
+    /// Keeps requests within `budget` estimated tokens by leaving out the oldest messages.
+    /// They stay in the history, only what is sent gets shorter.
+    pub fn with_history_budget(mut self, budget: usize) -> Self {
+        self.history_budget = Some(budget);
+        self
+    }
+
+    /// Keeps at most `limit` messages besides the system prompt, forgetting the oldest
+    /// exchanges after each answer. Unlike the budget, what is forgotten is gone for good.
+    pub fn with_history_limit(mut self, limit: Option<usize>) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Explains the team's `glossary` terms to the model in the system prompt.
+    pub fn with_glossary(mut self, glossary: &BTreeMap<String, String>) -> Self {
+        self.glossary = prompts::glossary(glossary);
+        self.update_system_prompt();
+        self
+    }
+
+    /// Tells the model the remembered `facts` about the user in the system prompt.
+    pub fn set_memories(&mut self, facts: &[String]) {
+        self.memories = prompts::memories(facts);
+        self.update_system_prompt();
+    }
+
+    fn update_system_prompt(&mut self) {
+        let mut prompt = self.prompt.trim_end().to_string();
+        let extras = [
+            self.glossary.as_deref(),
+            self.memories.as_deref(),
+            self.style.directive(),
+            self.json_response.then_some(prompts::JSON_DIRECTIVE),
+        ];
+        for extra in extras.into_iter().flatten() {
+            prompt.push('\n');
+            prompt.push_str(extra);
+        }
+        self.history[0].content = prompt;
+    }
+
+    /// Streams every future answer to `sink` as well.
+    pub fn subscribe(&mut self, sink: Box<dyn DeltaSink>) {
+        self.sinks.subscribe(sink);
+    }
+
+    /// Attaches a fenced block of context (a pasted log, a file, ...) to the next question.
+    pub fn attach_context(&mut self, label: &str, lang: &str, content: &str) {
+        self.context.push(ContextItem {
+            label: label.to_string(),
+            lang: lang.to_string(),
+            content: content.to_string(),
+        });
+    }
+
+    pub fn pending_context(&self) -> usize {
+        self.context.len()
+    }
+
+    /// Drops the context attached since the last question.
+    pub fn clear_context(&mut self) {
+        self.context.clear();
+    }
+
+    /// What the request for `question` would consist of, as `(source, bytes)`.
+    pub fn request_breakdown(&self, question: &str) -> Vec<(String, usize)> {
+        let mut parts = vec![("System prompt".to_string(), self.history[0].content.len())];
+        let earlier: usize = self.history[1..].iter().map(|m| m.content.len()).sum();
+        if earlier > 0 {
+            parts.push((
+                format!("Conversation history ({} messages)", self.history.len() - 1),
+                earlier,
+            ));
+        }
+        for item in &self.context {
+            parts.push((item.label.clone(), item.render().len()));
+        }
+        parts.push(("Question".to_string(), question.len()));
+        parts
+    }
+
+    /// Adds an earlier question and its answer to the history without sending anything,
+    /// e.g. to continue a conversation that was stored elsewhere. `model` wrote the answer.
+    pub fn replay(
+        &mut self,
+        question: &str,
+        answer: &str,
+        model: Option<&str>,
+        usage: Option<Usage>,
+    ) {
+        self.history.push(Message::new("user", question));
+        self.history.push(Message {
+            model: model.map(str::to_string),
+            usage,
+            ..Message::new("assistant", answer)
+        });
+    }
+
+    /// The conversation so far, without the system prompt.
+    pub fn transcript(&self) -> impl Iterator<Item = Turn<'_>> {
+        self.history.iter().skip(1).map(|message| Turn {
+            role: &message.role,
+            content: &message.content,
+            model: message.model.as_deref(),
+            usage: message.usage,
+        })
+    }
+
+    /// The tokens of the last answer, and of every answer in the conversation together.
+    pub fn usage(&self) -> (Option<Usage>, Usage) {
+        let mut total = Usage::default();
+        for usage in self.history.iter().filter_map(|m| m.usage) {
+            total += usage;
+        }
+        let last = self.history.last().and_then(|m| m.usage);
+        (last, total)
+    }
+
+    /// The model the conversation started on, i.e. the one that wrote the first answer.
+    pub fn conversation_model(&self) -> Option<&str> {
+        self.history
+            .iter()
+            .find_map(|message| message.model.as_deref())
+    }
+
+    /// Keeps only the pending context that fits in `budget` tokens, ranked by relevance
+    /// to `question`, and returns what was kept and dropped.
+    pub fn select_context(&mut self, question: &str, budget: usize) -> context::Selection {
+        let selection = context::select(std::mem::take(&mut self.context), question, budget);
+        self.context = selection.included.iter().map(|s| s.item.clone()).collect();
+        selection
+    }
+
+    fn headers(&self) -> reqwest::header::HeaderMap {
+        let copilot_auth = self.refreshed.as_ref().unwrap_or(&self.auth.copilot_auth);
+        CopilotCompletionHeaders {
+            token: &copilot_auth.token,
             vscode_sid: &self.vscode_sid,
             device_id: &self.device_id,
+            integration_id: match self.endpoint.kind {
+                CopilotEndpoint::Individual => None,
+                _ => Some("vscode-chat"),
+            },
         }
-        .to_headers();
+        .to_headers()
+    }
 
-        let mut transport_history = Vec::new();
+    /// Lists the models in the background, which opens the connection to the API and has
+    /// the token checked before the first question needs either. The pooled connection is
+    /// then reused by the first request.
+    pub fn prewarm(&mut self) {
+        let request = self
+            .client
+            .get(urls::copilot_models_url(&self.endpoint.base_url))
+            .headers(self.headers());
+        let warmup = Arc::new(OnceLock::new());
+        self.warmup = Some(warmup.clone());
 
-        {
-            let history = &mut self.history;
+        tokio::spawn(async move {
+            let started = tokio::time::Instant::now();
+            match request.send().await {
+                Ok(response) => {
+                    // read to the end so the connection goes back to the pool
+                    let _ = response.bytes().await;
+                    let _ = warmup.set(started.elapsed());
+                }
+                Err(e) => utils::debug_log(&format!("warm-up request failed: {}", e)),
+            }
+        });
+    }
 
-            history.push(Message {
-                content: self.allocator.alloc_str(prompt),
-                role: self.allocator.alloc_str("user"),
-            });
+    /// The models the account can use, as listed by the Copilot API.
+    pub async fn models(&self) -> Result<Vec<ModelInfo>, String> {
+        let response = self
+            .client
+            .get(urls::copilot_models_url(&self.endpoint.base_url))
+            .headers(self.headers())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to list models: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Failed to list models: {}", response.status()));
+        }
 
-            transport_history = history.clone();
+        let list = response
+            .json::<ModelList>()
+            .await
+            .map_err(|e| format!("Failed to list models: {}", e))?;
+        for model in &list.data {
+            utils::log_unknown_fields("ModelInfo", &model.extra);
         }
+        Ok(list.data)
+    }
 
-        // no chat history for this
-        let data = json!({
-            "intent": true,
-            "model": "gpt-4",
-            "n": 1,
-            "stream": true,
-            "temperature": 0.1,
-            "top_p": 1,
-            "messages": transport_history
-        });
+    /// Asks for the code between `prompt` and `suffix` from the inline completions model,
+    /// the way editors fill in ghost text. Returns the completion without the surrounding code.
+    pub async fn fill_in_middle(
+        &self,
+        prompt: &str,
+        suffix: &str,
+        language: &str,
+        max_tokens: u32,
+    ) -> Result<String, String> {
+        let copilot_auth = self.refreshed.as_ref().unwrap_or(&self.auth.copilot_auth);
+        let proxy = copilot_auth
+            .extra
+            .get("endpoints")
+            .and_then(|endpoints| endpoints.get("proxy"))
+            .and_then(|proxy| proxy.as_str())
+            .unwrap_or(urls::COPILOT_PROXY);
+        let headers = CopilotGhostHeaders {
+            token: &copilot_auth.token,
+            vscode_sid: &self.vscode_sid,
+            device_id: &self.device_id,
+        }
+        .to_headers();
 
-        // we need to stream the response
-        let mut response = self
+        let response = self
             .client
-            .post(url)
+            .post(urls::copilot_code_completions_url(proxy))
             .headers(headers)
-            .json(&data)
+            .json(&json!({
+                "prompt": prompt,
+                "suffix": suffix,
+                "max_tokens": max_tokens,
+                "temperature": 0,
+                "top_p": 1,
+                "n": 1,
+                "stop": ["\n\n\n"],
+                "stream": true,
+                "extra": {
+                    "language": language,
+                    "trim_by_indentation": true,
+                },
+            }))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get a completion: {}", e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!(
+                "Failed to get a completion: {} {}",
+                status,
+                body.trim()
+            ));
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to get a completion: {}", e))?;
+        Ok(completion_text(&body))
+    }
+
+    /// Embeds each of `inputs` with the embedding `model`, in the same order.
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let response = self
+            .client
+            .post(urls::copilot_embeddings_url(&self.endpoint.base_url))
+            .headers(self.headers())
+            .json(&json!({ "model": model, "input": inputs }))
             .send()
             .await
-            .unwrap()
-            .bytes_stream();
+            .map_err(|e| format!("Failed to get embeddings: {}", e))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!(
+                "Failed to get embeddings: {} {}",
+                status,
+                body.trim()
+            ));
+        }
+
+        let mut list = response
+            .json::<EmbeddingList>()
+            .await
+            .map_err(|e| format!("Failed to get embeddings: {}", e))?;
+        list.data.sort_by_key(|e| e.index);
+        Ok(list.data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    /// Asks `prompt` and hands the answer to every subscribed sink as it streams in. In JSON
+    /// mode an answer that doesn't parse is followed by a second one that fixes it.
+    pub async fn ask(&mut self, prompt: &str) -> Completion {
+        let completion = self.ask_sinks(prompt).await;
+        if !self.json_response || !completion_finished(&completion.finish_reason) {
+            return completion;
+        }
+        match check_json(&completion.content) {
+            Ok(()) => completion,
+            Err(e) => {
+                eprintln!("[the answer isn't valid JSON ({}), asking again]", e);
+                self.ask_sinks(&prompts::json_retry(&e)).await
+            }
+        }
+    }
+
+    async fn ask_sinks(&mut self, prompt: &str) -> Completion {
+        let mut sinks = std::mem::take(&mut self.sinks);
+        // what streams in isn't final when it's post-processed
+        let hold_back = !self.post_processors.is_empty();
+        let mut completion = if hold_back {
+            self.ask_stream(prompt, &mut Fanout::default()).await
+        } else {
+            self.ask_stream(prompt, &mut sinks).await
+        };
+        let drawing = tokio::time::Instant::now();
+        if hold_back {
+            sinks.delta(&completion.content);
+        }
+        sinks.finish(&completion);
+        completion.timings.rendering += drawing.elapsed();
+        self.sinks = sinks;
+
+        completion
+    }
+
+    /// Removes the last question and its answer from the history, e.g. to ask an edited
+    /// version instead. Returns false if nothing has been answered yet.
+    pub fn forget_last_exchange(&mut self) -> bool {
+        let len = self.history.len();
+        if len < 3 || self.history[len - 1].role == "user" || self.history[len - 2].role != "user" {
+            return false;
+        }
+        self.history.truncate(len - 2);
+        true
+    }
+
+    /// Forgets the oldest exchanges until at most `keep` messages follow the system prompt,
+    /// returning how many were forgotten. A question is never kept without its answer.
+    pub fn forget_oldest(&mut self, keep: usize) -> usize {
+        forget_oldest(&mut self.history, keep)
+    }
+
+    /// Replaces the last answer with `content`, e.g. another of its candidates. Returns false
+    /// if nothing has been answered yet.
+    pub fn replace_last_answer(&mut self, content: &str) -> bool {
+        replace_last_answer(&mut self.history, content)
+    }
+
+    /// Replaces the last answer by asking its question again, at `temperature` if given, for
+    /// another take. The previous answer stays when the new one fails or is cancelled. `None`
+    /// if nothing has been answered yet.
+    pub async fn regenerate(&mut self, temperature: Option<f64>) -> Option<Completion> {
+        let len = self.history.len();
+        if len < 3 || self.history[len - 1].role == "user" || self.history[len - 2].role != "user" {
+            return None;
+        }
+        let previous = self.history.clone();
+        self.history.pop();
+        // the question already carries the context it was asked with
+        let question = self.history.pop()?.content;
+
+        let context = std::mem::take(&mut self.context);
+        let sampling = self.sampling.clone();
+        if let Some(temperature) = temperature {
+            self.sampling.temperature = temperature;
+        }
+        let completion = self.ask(&question).await;
+        self.sampling = sampling;
+        self.context = context;
+        if [FINISH_ERROR, FINISH_CANCELLED].contains(&completion.finish_reason.as_str()) {
+            self.history = previous;
+        }
+
+        Some(completion)
+    }
+
+    /// Asks `prompt` with the conversation so far, but keeps neither the question nor the
+    /// answer in the history. Attached context is left for the next real question.
+    pub async fn ask_aside(&mut self, prompt: &str) -> Completion {
+        let len = self.history.len();
+        let context = std::mem::take(&mut self.context);
+        // these prompts say themselves what shape they want
+        let json_response = std::mem::take(&mut self.json_response);
+        if json_response {
+            self.update_system_prompt();
+        }
+        let post_processors = std::mem::take(&mut self.post_processors);
+        let completion = self.ask_stream(prompt, &mut Fanout::default()).await;
+        self.history.truncate(len);
+        self.context = context;
+        self.post_processors = post_processors;
+        if json_response {
+            self.set_json_response(true);
+        }
+        completion
+    }
+
+    /// Asks `prompt` and hands each piece of the answer to `sinks` as it arrives, ticking
+    /// them while it streams so held back pieces get drawn during a stall too. The complete
+    /// answer is returned and kept in the history, after the post-processors.
+    async fn ask_stream(&mut self, prompt: &str, sinks: &mut Fanout) -> Completion {
+        let url = urls::copilot_chat_completions_url(&self.endpoint.base_url);
+        let mut headers = self.headers();
+        let started = tokio::time::Instant::now();
+        let mut timings = Timings::default();
+        let deliver = |sinks: &mut Fanout, content: &str, timings: &mut Timings| {
+            if !content.is_empty() {
+                timings.first_token.get_or_insert(started.elapsed());
+            }
+            let drawing = tokio::time::Instant::now();
+            sinks.delta(content);
+            timings.rendering += drawing.elapsed();
+        };
+
+        let content = if self.context.is_empty() {
+            prompt.to_string()
+        } else {
+            let mut blocks: Vec<String> =
+                self.context.drain(..).map(|item| item.render()).collect();
+            blocks.push(prompt.to_string());
+            blocks.join("\n\n")
+        };
+        self.history.push(Message::new("user", &content));
+        let mut transport_history = self.history.clone();
+
+        if let Some(budget) = self.history_budget {
+            let dropped = trim_history(&mut transport_history, budget);
+            if dropped > 0 {
+                eprintln!(
+                    "[left out the {} oldest messages to stay within {} tokens]",
+                    dropped, budget
+                );
+            }
+        }
+
+        // no chat history for this
+        let mut data = json!({
+            "intent": true,
+            "model": self.model,
+            "n": self.sampling.choices.max(1),
+            "stream": self.streaming,
+            "temperature": self.sampling.temperature,
+            "top_p": self.sampling.top_p,
+            "messages": transport_history
+        });
+        if let Some(max_tokens) = self.sampling.max_tokens {
+            data["max_tokens"] = json!(max_tokens);
+        }
+        if !self.sampling.stop.is_empty() {
+            data["stop"] = json!(self.sampling.stop);
+        }
+        if self.streaming {
+            data["stream_options"] = json!({ "include_usage": true });
+        }
+        if let Some(tools) = &self.tools {
+            data["tools"] = tools.schemas();
+        }
+        if self.json_response {
+            data["response_format"] = json!({ "type": "json_object" });
+        }
 
         let mut message = String::new();
-        let mut buffer = String::new();
         let mut finish_reason = String::new();
+        let mut usage = Usage::default();
+        let mut filtered = Vec::new();
+        // the pieces of every choice but the first, by `choice.index`
+        let mut candidates: BTreeMap<i32, String> = BTreeMap::new();
+        let mut tool_rounds = 0;
 
-        'outerloop: while let Some(chunk) = response.next().await {
-            let body = chunk.unwrap();
-            let body_str = String::from_utf8_lossy(&body);
+        loop {
+            // what this round adds to the answer, after any earlier tool calls
+            let round_start = message.len();
+            let mut reported = None;
+            // kept for `copilot bundle-last-request`
+            let mut last_request = LastRequest::new(&url, &headers, &data);
 
-            buffer.push_str(&body_str);
+            // dropped before any tool runs, those ask for confirmation on the terminal
+            let mut interrupt = Box::pin(interrupted(self.cancel_on_ctrl_c));
 
-            // the data may be split into multiple chunks, BUT it's always dilimited by \n\ndata:
-            let lines = buffer
-                .split("\n\ndata: ")
-                .map(|s| s.to_string())
-                .map(|s| s.replacen("data:", "", 1))
-                .collect::<Vec<String>>();
+            // we need to stream the response
+            let sent = tokio::time::Instant::now();
+            let max_time = self.max_time;
+            let sending = self.send(&url, &mut headers, &data);
+            // the retries and rate limit waits count towards `max_time` as well
+            let sending = async move {
+                match max_time {
+                    Some(max_time) => tokio::time::timeout_at(started + max_time, sending)
+                        .await
+                        .ok(),
+                    None => Some(sending.await),
+                }
+            };
+            let response = tokio::select! {
+                response = sending => response.ok_or(FINISH_MAX_TIME),
+                _ = &mut interrupt => Err(FINISH_CANCELLED),
+            };
+            timings.send += sent.elapsed();
+            let response = match response {
+                Ok(response) => response,
+                Err(reason) => {
+                    finish_reason = reason.to_string();
+                    last_request.finish_reason = finish_reason.clone();
+                    let _ = last_request.save();
+                    break;
+                }
+            };
+            let receiving = tokio::time::Instant::now();
 
-            let mut processed_buffer = String::new();
-            for line in lines {
-                utils::append_to_file("resp.txt", &format!("{}\n", line));
-                if line.is_empty() {
-                    continue;
+            if let Some(warmup) = self.warmup.take() {
+                utils::debug_log(&match warmup.get() {
+                    Some(saved) => format!(
+                        "first response after {} ms, the warm-up saved up to {} ms",
+                        started.elapsed().as_millis(),
+                        saved.as_millis()
+                    ),
+                    None => format!(
+                        "first response after {} ms, the warm-up hadn't finished",
+                        started.elapsed().as_millis()
+                    ),
+                });
+            }
+
+            let response = match response {
+                Ok(response) if response.status().is_success() => response,
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    eprintln!("Copilot responded with {}: {}", status, body.trim());
+                    // a question the content filter refused says why in the error
+                    if let Some(results) = ContentFilterResults::from_error(&body) {
+                        results.collect_verdicts(&mut filtered);
+                    }
+                    last_request.response = format!("{}\n{}", status, body);
+                    return self.fail(last_request, started, usage, filtered, timings);
+                }
+                Err(e) => {
+                    eprintln!("Failed to reach Copilot: {}", e);
+                    last_request.response = e.to_string();
+                    return self.fail(last_request, started, usage, filtered, timings);
                 }
+            };
+            let mut calls: Vec<ToolCall> = Vec::new();
 
-                let parsed = serde_json::from_str::<GhCopilotResponse>(&line);
+            let mut out_of_time = std::pin::pin!(async move {
+                match max_time {
+                    Some(max_time) => tokio::time::sleep_until(started + max_time).await,
+                    None => std::future::pending::<()>().await,
+                }
+            });
+
+            if !self.streaming {
+                let body = tokio::select! {
+                    body = response.text() => body.unwrap_or_default(),
+                    _ = &mut interrupt => {
+                        finish_reason = FINISH_CANCELLED.to_string();
+                        String::new()
+                    }
+                    _ = &mut out_of_time => {
+                        finish_reason = FINISH_MAX_TIME.to_string();
+                        String::new()
+                    }
+                };
+                utils::debug_log(&format!("response: {}", body));
+                last_request.response.push_str(&body);
+                if finish_reason.is_empty() {
+                    match serde_json::from_str::<GhCopilotResponse>(&body) {
+                        Ok(parsed) => {
+                            parsed.log_unknown_fields();
+                            reported = parsed.usage;
+                            parsed.collect_candidates(&mut candidates, false);
+                            if let Some(choice) = parsed.choices.iter().find(|c| c.index == 0) {
+                                if let Some(results) = &choice.content_filter_results {
+                                    results.collect_verdicts(&mut filtered);
+                                }
+                                let content = choice.message.content.as_deref().unwrap_or_default();
+                                deliver(sinks, content, &mut timings);
+                                message.push_str(content);
+                                choice.message.collect_tool_calls(&mut calls);
+                                finish_reason = choice.finish_reason.clone().unwrap_or_default();
+                            }
+                        }
+                        Err(e) => {
+                            utils::debug_log(&format!("unparsed response: {}", body));
+                            eprintln!("Failed to parse the answer: {}", e);
+                            finish_reason = FINISH_ERROR.to_string();
+                        }
+                    }
+                }
+            } else {
+                let mut response = response.bytes_stream();
+                let mut decoder = sse::Decoder::default();
+                let mut ticks = tokio::time::interval(TICK_INTERVAL);
+                ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+                'outerloop: loop {
+                    let chunk = tokio::select! {
+                        chunk = response.next() => chunk,
+                        _ = ticks.tick() => {
+                            let drawing = tokio::time::Instant::now();
+                            sinks.tick();
+                            timings.rendering += drawing.elapsed();
+                            continue;
+                        }
+                        // dropping the stream aborts the request, what arrived so far is kept
+                        _ = &mut interrupt => {
+                            finish_reason = FINISH_CANCELLED.to_string();
+                            break;
+                        }
+                        _ = &mut out_of_time => {
+                            finish_reason = FINISH_MAX_TIME.to_string();
+                            break;
+                        }
+                    };
+                    let body = match chunk {
+                        Some(Ok(body)) => body,
+                        Some(Err(e)) => {
+                            eprintln!("The answer broke off: {}", e);
+                            finish_reason = FINISH_ERROR.to_string();
+                            break;
+                        }
+                        None => break,
+                    };
+
+                    for event in decoder.push(&body) {
+                        utils::debug_log(&format!("event: {}", event.data));
+                        last_request.response.push_str(&event.data);
+                        last_request.response.push('\n');
+                        if event.data == "[DONE]" {
+                            break 'outerloop;
+                        }
+
+                        let parsed = match serde_json::from_str::<GhCopilotResponse>(&event.data) {
+                            Ok(parsed) => parsed,
+                            Err(_) => {
+                                utils::debug_log(&format!("unparsed event: {}", event.data));
+                                continue;
+                            }
+                        };
+                        parsed.log_unknown_fields();
+                        if parsed.usage.is_some() {
+                            reported = parsed.usage;
+                        }
+
+                        // the other candidates are only collected, the first one streams
+                        parsed.collect_candidates(&mut candidates, true);
 
-                match parsed {
-                    Ok(parsed) => {
                         // If the choice actually exists
-                        if parsed.choices.len() > 0 {
-                            let choice = &parsed.choices[0];
-                            // If there is a finish reason in the choice, we break the loop
+                        if let Some(choice) = parsed.choices.iter().find(|c| c.index == 0) {
+                            if let Some(results) = &choice.content_filter_results {
+                                results.collect_verdicts(&mut filtered);
+                            }
+                            choice.delta.collect_tool_calls(&mut calls);
+                            // the usage may still follow the finish reason, up to [DONE]
                             if let Some(freason) = &choice.finish_reason {
-                                finish_reason = freason.clone().to_string();
-                                break 'outerloop;
+                                finish_reason = freason.clone();
+                                continue;
                             }
                             // There might be content in the delta, let's handle it
-                            let delta = &choice.delta;
-                            if let Some(content) = &delta.content {
-                                if log {
-                                    self.handle_content(content).await;
-                                }//std::io::stdout().flush().unwrap();
+                            if let Some(content) = &choice.delta.content {
+                                deliver(sinks, content, &mut timings);
                                 message.push_str(content);
                             }
                         }
                     }
-                    Err(_) => {
-                        utils::append_to_file("debug.txt", &format!("{}\n", line));
-                        processed_buffer.push_str(&line);
-                    }
                 }
-                // Add the incomplete line to the buffer to be processed in the next iteration
-                buffer = processed_buffer.clone();
             }
-        }
 
-        if log {
-            print!("\n");
-            std::io::stdout().flush().unwrap();
+            timings.streaming += receiving.elapsed();
+            drop(interrupt);
+            last_request.finish_reason = finish_reason.clone();
+            let _ = last_request.save();
+            usage += reported
+                .unwrap_or_else(|| Usage::estimate(&data["messages"], &message[round_start..]));
+
+            let Some(tools) = &self.tools else {
+                break;
+            };
+            if finish_reason != FINISH_TOOL_CALLS || calls.is_empty() {
+                break;
+            }
+            if tool_rounds == self.max_tool_rounds {
+                finish_reason = FINISH_TOOL_ROUNDS.to_string();
+                break;
+            }
+            tool_rounds += 1;
+            // the calls and their results are only sent for this question
+            let messages = data["messages"].as_array_mut().unwrap();
+            messages.push(tools::call_message(&message[round_start..], &calls));
+            for call in &calls {
+                messages.push(tools.call(call, self.client).await);
+            }
+            finish_reason.clear();
         }
 
-        // add the response to the history
-        {
-            let history = &mut self.history;
+        // an answer that failed or was cut off stays as it came, the processors expect a
+        // whole one and code-only would drop a half-written code block
+        let complete = completion_finished(&finish_reason) && finish_reason != FINISH_LENGTH;
+        let message = self.post_process(message, complete);
+        let candidates: BTreeMap<i32, String> = candidates
+            .into_iter()
+            .map(|(index, candidate)| (index, self.post_process(candidate, complete)))
+            .collect();
 
-            history.push(Message {
-                content: self.allocator.alloc_str(&message),
-                role: self.allocator.alloc_str("system"),
-            });
+        // add the response to the history
+        self.history.push(Message {
+            model: Some(self.model.clone()),
+            usage: Some(usage),
+            ..Message::new("assistant", &message)
+        });
+        if let Some(limit) = self.history_limit {
+            self.forget_oldest(limit);
         }
 
-        self.full_message = String::new();
-
+        self.record_metrics(&finish_reason, started.elapsed());
+        // the answer was drawn while it streamed in
+        timings.streaming = timings.streaming.saturating_sub(timings.rendering);
+        let candidates = candidate_list(&message, candidates);
         Completion {
             content: message,
             finish_reason,
+            usage,
+            filtered,
+            timings,
+            candidates,
         }
     }
 
-    async fn handle_content(&mut self, content: &String) {
-        // tokio sleep for 10 ms
-        // tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    /// Posts `data` to `url`, renewing the Copilot token once when it's rejected and
+    /// waiting out rate limits. Connection errors and 5xx responses are retried by `retry`.
+    async fn send(
+        &mut self,
+        url: &str,
+        headers: &mut reqwest::header::HeaderMap,
+        data: &Value,
+    ) -> Result<reqwest::Response, http::SendError> {
+        let mut retries = 0;
+        let mut reauthenticated = false;
 
-        self.full_message.push_str(content);
-        let line_count = self.full_message.split("\n").count();
+        loop {
+            let request = self.client.post(url).headers(headers.clone()).json(data);
+            let response = http::send_with_retry(request, &self.retry).await?;
 
-        if self.full_message.ends_with("\n") {
-            let highlighted = term::highlight_line(&self.full_message);
-            let escaped: Vec<String> = term::to_terminal_escaped(&highlighted)
-                .split("\n")
-                .filter(|s| !s.is_empty())
-                .map(|s| s.to_string())
-                .collect();
+            // the Copilot token only lives for half an hour, a long chat outlives it
+            if response.status() == StatusCode::UNAUTHORIZED && !reauthenticated {
+                let Some(auth_manager) = self.auth_manager else {
+                    return Ok(response);
+                };
+                reauthenticated = true;
+                match auth_manager.refresh_copilot(&self.auth.token).await {
+                    Ok(copilot_auth) => {
+                        self.refreshed = Some(copilot_auth);
+                        *headers = self.headers();
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to renew the Copilot token: {}", e);
+                        return Ok(response);
+                    }
+                }
+            }
 
-            let mut escaped_len = escaped.len();
-            while line_count > escaped_len {
-                print!("\n");
-                escaped_len += 1;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS
+                || retries >= self.rate_limit_retries
+            {
+                return Ok(response);
             }
 
-            print!("{}", escaped.last().unwrap());
-            std::io::stdout().flush().unwrap();
-            // self.full_message = String::new();
+            retries += 1;
+            let wait =
+                http::retry_after(&response).unwrap_or(Duration::from_secs(2u64.pow(retries)));
+            eprintln!(
+                "Rate limited, retrying in {}s ({}/{})",
+                wait.as_secs_f64().ceil(),
+                retries,
+                self.rate_limit_retries
+            );
+            tokio::time::sleep(wait).await;
         }
     }
-}
\ No newline at end of file
+
+    /// Gives up on the question without an answer. It's taken back out of the history, which
+    /// has to keep alternating between questions and answers for sessions to pair them up.
+    fn fail(
+        &mut self,
+        mut last_request: LastRequest,
+        started: tokio::time::Instant,
+        usage: Usage,
+        filtered: Vec<FilterVerdict>,
+        timings: Timings,
+    ) -> Completion {
+        last_request.finish_reason = FINISH_ERROR.to_string();
+        let _ = last_request.save();
+        self.history.pop();
+        self.record_metrics(FINISH_ERROR, started.elapsed());
+        Completion {
+            content: String::new(),
+            finish_reason: FINISH_ERROR.to_string(),
+            usage,
+            filtered,
+            timings,
+            candidates: Vec::new(),
+        }
+    }
+
+    fn post_process(&self, answer: String, complete: bool) -> String {
+        if !complete {
+            return answer;
+        }
+        self.post_processors
+            .iter()
+            .fold(answer, |answer, processor| processor.process(&answer))
+    }
+
+    fn record_metrics(&self, finish_reason: &str, latency: Duration) {
+        let Some(exporter) = &self.metrics else {
+            return;
+        };
+        let outcome = match finish_reason {
+            FINISH_ERROR => "error",
+            FINISH_CANCELLED => "cancelled",
+            FINISH_MAX_TIME => "timeout",
+            _ => "ok",
+        };
+        metrics::record(exporter, &self.model, outcome, latency);
+    }
+}
+
+/// Replaces the last answer in `history` with `content`, unless the last message is the
+/// system prompt or a question still waiting for one.
+fn replace_last_answer(history: &mut [Message], content: &str) -> bool {
+    let len = history.len();
+    match history.last_mut() {
+        Some(answer) if len > 1 && answer.role != "user" => {
+            answer.content = content.to_string();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// `Completion::candidates`: nothing when only `first` was asked for, else every candidate
+/// in `choice.index` order.
+fn candidate_list(first: &str, others: BTreeMap<i32, String>) -> Vec<String> {
+    if others.is_empty() {
+        return Vec::new();
+    }
+    std::iter::once(first.to_string())
+        .chain(others.into_values())
+        .collect()
+}
+
+/// Whether the answer ended on its own, rather than being cut off or refused.
+/// Resolves once the user stops the answer: Esc or Ctrl+C read from the terminal, or Ctrl+C
+/// as a signal when stdin isn't one. Never unless `cancellable`.
+async fn interrupted(cancellable: bool) {
+    if !cancellable {
+        return std::future::pending().await;
+    }
+    match input::CancelKeys::watch() {
+        Some(mut keys) => keys.pressed().await,
+        None => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+fn completion_finished(finish_reason: &str) -> bool {
+    ![
+        FINISH_ERROR,
+        FINISH_CANCELLED,
+        FINISH_MAX_TIME,
+        FINISH_CONTENT_FILTER,
+        FINISH_TOOL_ROUNDS,
+    ]
+    .contains(&finish_reason)
+}
+
+/// Checks that a JSON mode answer is a JSON document and nothing else.
+pub fn check_json(answer: &str) -> Result<(), String> {
+    serde_json::from_str::<Value>(answer.trim())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// The text of the first choice in a streamed `/completions` response.
+fn completion_text(body: &[u8]) -> String {
+    let mut text = String::new();
+    for event in sse::Decoder::default().push(body) {
+        if event.data == "[DONE]" {
+            break;
+        }
+        if let Ok(chunk) = serde_json::from_str::<Value>(&event.data) {
+            let first = chunk["choices"].as_array().and_then(|choices| {
+                choices
+                    .iter()
+                    .find(|c| c["index"].as_u64().unwrap_or(0) == 0)
+            });
+            if let Some(piece) = first.and_then(|c| c["text"].as_str()) {
+                text.push_str(piece);
+            }
+        }
+    }
+    text
+}
+
+/// Drops the oldest messages after the system prompt until `messages` are estimated to fit in
+/// `budget` tokens, returning how many were dropped. The system prompt and the question are
+/// always kept, and so is every message after them, so an answer never loses its question.
+fn trim_history(messages: &mut Vec<Message>, budget: usize) -> usize {
+    // roles, separators and the like cost a few tokens per message
+    let cost = |message: &Message| tokens::estimate(&message.content) + 4;
+    let mut total: usize = messages.iter().map(cost).sum();
+    let mut dropped = 0;
+
+    while messages.len() > 2 && total > budget {
+        total -= cost(&messages.remove(1));
+        dropped += 1;
+        // an answer whose question is gone goes with it
+        while messages.len() > 2 && messages[1].role != "user" {
+            total -= cost(&messages.remove(1));
+            dropped += 1;
+        }
+    }
+
+    dropped
+}
+
+/// Removes the oldest messages after the system prompt until at most `keep` are left,
+/// returning how many were removed. Leftover answers of a removed question go as well.
+fn forget_oldest(messages: &mut Vec<Message>, keep: usize) -> usize {
+    let len = messages.len();
+    let mut start = 1;
+    while len - start > keep {
+        start += 1;
+        while start < len && messages[start].role != "user" {
+            start += 1;
+        }
+    }
+    messages.drain(1..start);
+    start - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_oldest_exchanges_first() {
+        let message = |role, content: &str| Message::new(role, content);
+        let long = "word ".repeat(100);
+        let mut messages = vec![
+            message("system", "You are a helpful assistant"),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", "short question"),
+            message("assistant", "short answer"),
+            message("user", "the question"),
+        ];
+
+        assert_eq!(trim_history(&mut messages.clone(), 10_000), 0);
+
+        assert_eq!(trim_history(&mut messages, 100), 2);
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "You are a helpful assistant",
+                "short question",
+                "short answer",
+                "the question"
+            ]
+        );
+
+        assert_eq!(trim_history(&mut messages, 1), 2);
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn forgets_whole_exchanges() {
+        let mut messages = vec![
+            Message::new("system", "You are a helpful assistant"),
+            Message::new("user", "first question"),
+            Message::new("assistant", "first answer"),
+            Message::new("user", "second question"),
+            Message::new("assistant", "second answer"),
+        ];
+
+        assert_eq!(forget_oldest(&mut messages, 4), 0);
+        assert_eq!(forget_oldest(&mut messages, 3), 2);
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "You are a helpful assistant",
+                "second question",
+                "second answer"
+            ]
+        );
+
+        assert_eq!(forget_oldest(&mut messages, 0), 2);
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn collects_every_candidate_but_the_first() {
+        let mut candidates = BTreeMap::new();
+        for chunk in [
+            r#"{"choices":[{"index":0,"delta":{"content":"A"}},{"index":2,"delta":{"content":"C"}}]}"#,
+            r#"{"choices":[{"index":1,"delta":{"content":"B1"}},{"index":2,"delta":{"content":"2"}}]}"#,
+            r#"{"choices":[{"index":1,"delta":{"content":"!"}}]}"#,
+        ] {
+            let parsed: GhCopilotResponse = serde_json::from_str(chunk).unwrap();
+            parsed.collect_candidates(&mut candidates, true);
+        }
+        assert_eq!(candidate_list("A", candidates), ["A", "B1!", "C2"]);
+
+        let whole: GhCopilotResponse = serde_json::from_str(
+            r#"{"choices":[{"index":0,"message":{"content":"one"}},{"index":1,"message":{"content":"two"}}]}"#,
+        )
+        .unwrap();
+        let mut candidates = BTreeMap::new();
+        whole.collect_candidates(&mut candidates, false);
+        assert_eq!(candidate_list("one", candidates), ["one", "two"]);
+
+        assert!(candidate_list("only", BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn replaces_only_an_answer() {
+        let mut history = vec![Message::new("system", "You are a helpful assistant")];
+        assert!(!replace_last_answer(&mut history, "other"));
+
+        history.push(Message::new("user", "question"));
+        assert!(!replace_last_answer(&mut history, "other"));
+
+        history.push(Message::new("assistant", "first candidate"));
+        assert!(replace_last_answer(&mut history, "second candidate"));
+        assert_eq!(history[2].content, "second candidate");
+        assert_eq!(history[0].content, "You are a helpful assistant");
+    }
+
+    #[test]
+    fn json_answers_must_parse_whole() {
+        assert!(check_json("{\"files\": [\"a.rs\"]}\n").is_ok());
+        assert!(check_json("```json\n{}\n```").is_err());
+        assert!(check_json("{\"files\": [").is_err());
+    }
+
+    #[test]
+    fn joins_streamed_completions() {
+        let body = b"data: {\"choices\":[{\"index\":0,\"text\":\"let x\"}]}\n\n\
+                     data: {\"choices\":[{\"index\":0,\"text\":\" = 1;\"}]}\n\n\
+                     data: [DONE]\n\n";
+        assert_eq!(completion_text(body), "let x = 1;");
+    }
+
+    #[test]
+    fn parses_whole_answers() {
+        let body = r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"Hi there"},"finish_reason":"stop"}],"created":1,"id":"x"}"#;
+        let parsed: GhCopilotResponse = serde_json::from_str(body).unwrap();
+        let choice = &parsed.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("Hi there"));
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+        assert!(choice.delta.content.is_none());
+    }
+
+    #[test]
+    fn names_what_the_content_filter_caught() {
+        let chunk = r#"{"choices":[{"index":0,"delta":{},"finish_reason":"content_filter","content_filter_results":{"hate":{"filtered":false,"severity":"safe"},"violence":{"filtered":true,"severity":"medium"}}}]}"#;
+        let parsed: GhCopilotResponse = serde_json::from_str(chunk).unwrap();
+        let mut verdicts = Vec::new();
+        let results = parsed.choices[0].content_filter_results.as_ref().unwrap();
+        results.collect_verdicts(&mut verdicts);
+        results.collect_verdicts(&mut verdicts);
+        assert_eq!(verdicts.len(), 1);
+        assert_eq!(verdicts[0].to_string(), "violence (medium)");
+    }
+
+    #[test]
+    fn collects_streamed_tool_calls() {
+        let events = [
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"shell","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"command\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"ls\"}"}}]}}]}"#,
+        ];
+        let mut calls = Vec::new();
+        for event in events {
+            let parsed: GhCopilotResponse = serde_json::from_str(event).unwrap();
+            parsed.choices[0].delta.collect_tool_calls(&mut calls);
+        }
+        assert_eq!(
+            calls,
+            [ToolCall {
+                id: "call_1".to_string(),
+                name: "shell".to_string(),
+                arguments: "{\"command\":\"ls\"}".to_string(),
+            }]
+        );
+    }
+
+    fn copilot_auth(json: &str) -> gh::GithubCopilotAuth {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// An account whose questions go to `base_url`.
+    fn auth(base_url: &str) -> gh::GithubAuth {
+        gh::GithubAuth {
+            user: serde_json::from_str(r#"{"login": "octocat"}"#).unwrap(),
+            token: serde_json::from_str(
+                r#"{"access_token": "gho_test", "token_type": "bearer", "scope": ""}"#,
+            )
+            .unwrap(),
+            copilot_auth: copilot_auth(
+                &json!({ "token": "tid=test", "endpoints": { "api": base_url } }).to_string(),
+            ),
+        }
+    }
+
+    /// Answers every connection to a local port with `response`, or never when it's `None`,
+    /// returning the port's URL.
+    async fn serve(response: Option<&'static str>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let Some(response) = response else {
+                        std::future::pending::<()>().await;
+                        return;
+                    };
+                    // the whole request is read first, the client may not like it otherwise
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request);
+                        let Some(end) = text.find("\r\n\r\n") else {
+                            continue;
+                        };
+                        let length = text[..end]
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        url
+    }
+
+    fn client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn max_time_covers_a_stalled_request() {
+        let client = client();
+        let auth = auth(&serve(None).await);
+        let mut copilot_m = CopilotManager::new(&auth, &client, "You help.")
+            .with_max_time(Some(Duration::from_millis(200)));
+
+        let started = std::time::Instant::now();
+        let completion = copilot_m.ask("hi").await;
+        assert_eq!(completion.finish_reason, FINISH_MAX_TIME);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn failed_questions_leave_the_history() {
+        let client = client();
+        let url = serve(Some(
+            "HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        ))
+        .await;
+        let auth = auth(&url);
+        let mut copilot_m = CopilotManager::new(&auth, &client, "You help.");
+        assert_eq!(copilot_m.ask("hi").await.finish_reason, FINISH_ERROR);
+        assert_eq!(copilot_m.history.len(), 1);
+
+        // nothing listens on a port that was just given back
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let auth = auth(&format!("http://{}", closed.local_addr().unwrap()));
+        drop(closed);
+        let mut copilot_m = CopilotManager::new(&auth, &client, "You help.");
+        assert_eq!(copilot_m.ask("hi").await.finish_reason, FINISH_ERROR);
+        assert_eq!(copilot_m.history.len(), 1);
+        assert!(!copilot_m.forget_last_exchange());
+    }
+
+    #[test]
+    fn endpoint_from_plan_or_token() {
+        let business = copilot_auth(r#"{"sku": "copilot_for_business_seat"}"#);
+        assert_eq!(
+            ApiEndpoint::resolve(CopilotEndpoint::Auto, &business).base_url,
+            urls::COPILOT_API_BUSINESS
+        );
+
+        let listed = copilot_auth(
+            r#"{"sku": "copilot_enterprise_seat", "endpoints": {"api": "https://api.octo.ghe.com"}}"#,
+        );
+        let endpoint = ApiEndpoint::resolve(CopilotEndpoint::Auto, &listed);
+        assert_eq!(endpoint.base_url, "https://api.octo.ghe.com");
+        assert_eq!(endpoint.kind, CopilotEndpoint::Enterprise);
+
+        let individual = copilot_auth(r#"{"sku": "free_limited_copilot"}"#);
+        assert_eq!(
+            ApiEndpoint::resolve(CopilotEndpoint::Auto, &individual).kind,
+            CopilotEndpoint::Individual
+        );
+        assert_eq!(
+            ApiEndpoint::resolve(CopilotEndpoint::Enterprise, &individual).base_url,
+            urls::COPILOT_API_ENTERPRISE
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{os::unix::net::UnixStream, path::PathBuf, time::Duration};
+
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::unix::OwnedWriteHalf,
+    sync::mpsc,
+};
+
+use crate::{
+    config::Config,
+    copilot::{Completion, CopilotManager, Sampling},
+    gh::AuthenticationManager,
+    sinks::DeltaSink,
+    utils,
+};
+
+/// Where `copilot daemon` listens. `copilot-quick` computes the same path, so keep the two
+/// in sync: `$COPILOT_DAEMON_SOCKET`, or `daemon.sock` next to the config file.
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("COPILOT_DAEMON_SOCKET") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(utils::get_config_path()).join("daemon.sock"),
+    }
+}
+
+/// How long a client gets to send its question, and to take each piece of the answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One line of JSON sent by `copilot-quick`.
+#[derive(Deserialize)]
+struct Request {
+    question: String,
+}
+
+/// Hands the answer to the connection's writer as it streams in, see `write_answer`.
+struct SocketSink {
+    pieces: mpsc::UnboundedSender<String>,
+}
+
+impl DeltaSink for SocketSink {
+    fn on_delta(&mut self, content: &str) {
+        let _ = self.pieces.send(content.to_string());
+    }
+
+    fn on_finish(&mut self, _completion: &Completion) {
+        let _ = self.pieces.send("\n".to_string());
+    }
+}
+
+/// Runs `copilot daemon`: keeps the login warm and answers one question per connection on
+/// the socket, so `copilot-quick` doesn't pay for authentication on every start. The daemon
+/// never logs in itself, `copilot-quick` starts it without a terminal to show a code on.
+pub async fn run(auth_manager: &AuthenticationManager, client: &Client) -> Result<(), String> {
+    auth_manager
+        .stored_auth()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        return Err(format!(
+            "A daemon is already listening on {}",
+            path.display()
+        ));
+    }
+    // a stale socket from a daemon that didn't exit cleanly
+    let _ = std::fs::remove_file(&path);
+
+    let listener = tokio::net::UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+    println!("Listening on {}", path.display());
+
+    // every connection is answered on its own task, so a slow answer or a client that
+    // stops reading holds up nobody else. Answers aren't `Send`, the tasks stay on this thread.
+    let connections = tokio::task::LocalSet::new();
+    connections
+        .run_until(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        utils::debug_log(&format!("daemon accept failed: {}", e));
+                        continue;
+                    }
+                };
+
+                let auth_manager = auth_manager.clone();
+                let client = client.clone();
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = answer(&auth_manager, &client, stream).await {
+                        utils::debug_log(&format!("daemon request failed: {}", e));
+                    }
+                });
+            }
+        })
+        .await;
+    Ok(())
+}
+
+async fn answer(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    stream: tokio::net::UnixStream,
+) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = String::new();
+    tokio::time::timeout(CLIENT_TIMEOUT, BufReader::new(reader).read_line(&mut line))
+        .await
+        .map_err(|_| "The client sent no question".to_string())?
+        .map_err(|e| e.to_string())?;
+    let request = serde_json::from_str::<Request>(&line).map_err(|e| e.to_string())?;
+
+    // the Copilot token expires every half hour, this refreshes it when needed
+    let auth = match auth_manager.stored_auth().await {
+        Ok(auth) => auth,
+        Err(e) => {
+            let error = format!("error: {}\n", e);
+            let _ = tokio::time::timeout(CLIENT_TIMEOUT, writer.write_all(error.as_bytes())).await;
+            return Err(e.to_string());
+        }
+    };
+
+    let config = Config::load();
+    let mut copilot_m = CopilotManager::new(&auth, client, config.system_prompt())
+        .with_endpoint(config.endpoint)
+        .with_glossary(&config.glossary)
+        .with_sampling(Sampling::from_config(&config))
+        .with_retries(&config)
+        .with_history_budget(config.history_token_budget)
+        .with_history_limit(config.history_max_messages)
+        .with_metrics(config.metrics.clone());
+    let (pieces, answer) = mpsc::unbounded_channel();
+    copilot_m.subscribe(Box::new(SocketSink { pieces }));
+    let asking = async move {
+        copilot_m.ask(&request.question).await;
+        // which ends the writer once it has sent everything
+        drop(copilot_m);
+    };
+    tokio::join!(asking, write_answer(writer, answer));
+
+    Ok(())
+}
+
+/// Writes the answer to the client as it arrives, giving up on a client that stops reading.
+async fn write_answer(mut writer: OwnedWriteHalf, mut answer: mpsc::UnboundedReceiver<String>) {
+    while let Some(piece) = answer.recv().await {
+        let written =
+            tokio::time::timeout(CLIENT_TIMEOUT, writer.write_all(piece.as_bytes())).await;
+        if !matches!(written, Ok(Ok(()))) {
+            return;
+        }
+    }
+}
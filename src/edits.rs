@@ -0,0 +1,124 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// One file modification, newest last in `log.jsonl`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Edit {
+    pub time: i64,
+    /// Absolute path of the modified file.
+    pub path: String,
+    /// Snapshot of the file before the edit, `None` if the edit created it.
+    pub before: Option<String>,
+    /// Snapshot of what the edit wrote.
+    pub after: String,
+    /// Set once `/undo-edit` has restored `before`.
+    #[serde(default)]
+    pub undone: bool,
+}
+
+fn edits_dir() -> PathBuf {
+    PathBuf::from(utils::get_config_path()).join("edits")
+}
+
+fn log_path() -> PathBuf {
+    edits_dir().join("log.jsonl")
+}
+
+fn object_path(id: &str) -> PathBuf {
+    edits_dir().join("objects").join(id)
+}
+
+/// The id `snapshot` stores `contents` under, a 64-bit FNV-1a hash. It names files that
+/// outlive the binary, so it can't be `DefaultHasher`, which may change between Rust releases.
+pub fn content_id(contents: &str) -> String {
+    let hash = contents.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Stores `contents` under its hash and returns the snapshot id. Identical contents
+/// share one file, so re-applying the same change doesn't grow the store.
+pub fn snapshot(contents: &str) -> std::io::Result<String> {
+    let id = content_id(contents);
+
+    let path = object_path(&id);
+    if !path.exists() {
+        std::fs::create_dir_all(edits_dir().join("objects"))?;
+        std::fs::write(&path, contents)?;
+    }
+
+    Ok(id)
+}
+
+pub fn load_snapshot(id: &str) -> std::io::Result<String> {
+    std::fs::read_to_string(object_path(id))
+}
+
+/// Appends an edit to the history.
+pub fn record(path: &Path, before: Option<String>, after: String) -> std::io::Result<()> {
+    let edit = Edit {
+        time: chrono::Utc::now().timestamp(),
+        path: path.display().to_string(),
+        before,
+        after,
+        undone: false,
+    };
+
+    std::fs::create_dir_all(edits_dir())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+    writeln!(file, "{}", serde_json::to_string(&edit)?)
+}
+
+/// The whole history, oldest first. Lines that don't parse are skipped.
+pub fn log() -> Vec<Edit> {
+    std::fs::read_to_string(log_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// The most recent edit that hasn't been undone yet, with its index in `log()`.
+pub fn last_undoable() -> Option<(usize, Edit)> {
+    log()
+        .into_iter()
+        .enumerate()
+        .rev()
+        .find(|(_, edit)| !edit.undone)
+}
+
+/// Marks the edit at `index` as undone.
+pub fn mark_undone(index: usize) -> std::io::Result<()> {
+    let mut edits = log();
+    if let Some(edit) = edits.get_mut(index) {
+        edit.undone = true;
+    }
+
+    let mut contents = String::new();
+    for edit in &edits {
+        contents.push_str(&serde_json::to_string(edit)?);
+        contents.push('\n');
+    }
+    std::fs::write(log_path(), contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_ids_are_stable() {
+        assert_eq!(content_id(""), "cbf29ce484222325");
+        assert_eq!(content_id("a"), "af63dc4c8601ec8c");
+    }
+}
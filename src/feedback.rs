@@ -0,0 +1,136 @@
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad,
+}
+
+/// A `/good` or `/bad` verdict on one question and its answer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Feedback {
+    pub time: i64,
+    pub rating: Rating,
+    pub reason: Option<String>,
+    pub model: String,
+    pub profile: String,
+    pub question: String,
+    pub answer: String,
+}
+
+fn feedback_path() -> PathBuf {
+    PathBuf::from(utils::get_config_path()).join("feedback.jsonl")
+}
+
+pub fn record(feedback: &Feedback) -> std::io::Result<()> {
+    std::fs::create_dir_all(utils::get_config_path())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(feedback_path())?;
+    writeln!(file, "{}", serde_json::to_string(feedback)?)
+}
+
+/// Every recorded verdict, oldest first. Lines that don't parse are skipped.
+pub fn load() -> Vec<Feedback> {
+    std::fs::read_to_string(feedback_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Tally {
+    pub good: usize,
+    pub bad: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub total: Tally,
+    pub by_model: BTreeMap<String, Tally>,
+    /// How often each `/bad` reason was given, most common first.
+    pub bad_reasons: Vec<(String, usize)>,
+}
+
+pub fn stats(feedback: &[Feedback]) -> Stats {
+    let mut stats = Stats::default();
+    let mut reasons: BTreeMap<String, usize> = BTreeMap::new();
+
+    for entry in feedback {
+        let model = stats.by_model.entry(entry.model.clone()).or_default();
+        match entry.rating {
+            Rating::Good => {
+                stats.total.good += 1;
+                model.good += 1;
+            }
+            Rating::Bad => {
+                stats.total.bad += 1;
+                model.bad += 1;
+                if let Some(reason) = &entry.reason {
+                    *reasons.entry(reason.trim().to_lowercase()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    stats.bad_reasons = reasons.into_iter().collect();
+    stats
+        .bad_reasons
+        .sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    stats
+}
+
+/// Posts the verdict as JSON to `url`, for teams that collect feedback centrally.
+pub async fn send_webhook(client: &Client, url: &str, feedback: &Feedback) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .json(feedback)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("Webhook responded with {}", response.status()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(rating: Rating, model: &str, reason: Option<&str>) -> Feedback {
+        Feedback {
+            time: 0,
+            rating,
+            reason: reason.map(|r| r.to_string()),
+            model: model.to_string(),
+            profile: "default".to_string(),
+            question: String::new(),
+            answer: String::new(),
+        }
+    }
+
+    #[test]
+    fn aggregate_stats() {
+        let stats = stats(&[
+            feedback(Rating::Good, "gpt-4", None),
+            feedback(Rating::Bad, "gpt-4", Some("wrong API")),
+            feedback(Rating::Bad, "gpt-4o", Some("Wrong API ")),
+            feedback(Rating::Bad, "gpt-4o", Some("too long")),
+        ]);
+
+        assert_eq!(stats.total, Tally { good: 1, bad: 3 });
+        assert_eq!(stats.by_model["gpt-4o"], Tally { good: 0, bad: 2 });
+        assert_eq!(stats.bad_reasons[0], ("wrong api".to_string(), 2));
+    }
+}
@@ -0,0 +1,154 @@
+use serde_json::Value;
+
+/// A jq-style path like `.items[].name`, enough to pull fields out of an answer without
+/// piping it into jq: `.key`, `."quoted key"`, `[n]` (negative counts from the end) and
+/// `[]` to iterate arrays and objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(i64),
+    Iterate,
+}
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Filter, String> {
+        let invalid = |why: &str| format!("Invalid filter {}: {}", expr, why);
+
+        let mut rest = expr
+            .trim()
+            .strip_prefix('.')
+            .ok_or_else(|| invalid("it has to start with ."))?;
+        let mut steps = Vec::new();
+        // the `.` that starts the filter may be followed by a key directly, `.items`
+        let mut after_dot = true;
+
+        while !rest.is_empty() {
+            if let Some(bracketed) = rest.strip_prefix('[') {
+                let (inner, after) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("missing ]"))?;
+                steps.push(match inner.trim() {
+                    "" => Step::Iterate,
+                    quoted if quoted.starts_with('"') => Step::Key(
+                        serde_json::from_str(quoted).map_err(|_| invalid("bad quoted key"))?,
+                    ),
+                    index => Step::Index(index.parse().map_err(|_| invalid("bad index"))?),
+                });
+                rest = after;
+                after_dot = false;
+            } else if after_dot {
+                let (key, after) = if let Some(quoted) = rest.strip_prefix('"') {
+                    let (key, after) = quoted
+                        .split_once('"')
+                        .ok_or_else(|| invalid("missing closing quote"))?;
+                    (key.to_string(), after)
+                } else {
+                    let end = rest
+                        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    if end == 0 {
+                        return Err(invalid("expected a key after ."));
+                    }
+                    (rest[..end].to_string(), &rest[end..])
+                };
+                steps.push(Step::Key(key));
+                rest = after;
+                after_dot = false;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                rest = after;
+                after_dot = true;
+            } else {
+                return Err(invalid(&format!("unexpected {}", rest)));
+            }
+        }
+
+        Ok(Filter { steps })
+    }
+
+    /// Every value the path leads to. Missing keys and indexes give `null` like in jq,
+    /// indexing into something that isn't a container is an error.
+    pub fn apply(&self, value: &Value) -> Result<Vec<Value>, String> {
+        let mut values = vec![value.clone()];
+
+        for step in &self.steps {
+            let mut next = Vec::new();
+            for value in values {
+                match (step, value) {
+                    (Step::Key(_) | Step::Index(_), Value::Null) => next.push(Value::Null),
+                    (Step::Key(key), Value::Object(mut object)) => {
+                        next.push(object.remove(key).unwrap_or(Value::Null))
+                    }
+                    (Step::Index(index), Value::Array(array)) => {
+                        let index = if *index < 0 {
+                            array.len() as i64 + index
+                        } else {
+                            *index
+                        };
+                        let item = usize::try_from(index).ok().and_then(|i| array.get(i));
+                        next.push(item.cloned().unwrap_or(Value::Null));
+                    }
+                    (Step::Iterate, Value::Array(array)) => next.extend(array),
+                    (Step::Iterate, Value::Object(object)) => next.extend(object.into_values()),
+                    (step, value) => {
+                        return Err(match step {
+                            Step::Key(key) => {
+                                format!("Cannot index {} with \"{}\"", kind(&value), key)
+                            }
+                            Step::Index(index) => {
+                                format!("Cannot index {} with {}", kind(&value), index)
+                            }
+                            Step::Iterate => format!("Cannot iterate over {}", kind(&value)),
+                        });
+                    }
+                }
+            }
+            values = next;
+        }
+
+        Ok(values)
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn paths() {
+        let value = json!({
+            "items": [{"name": "a", "tags": ["x"]}, {"name": "b"}],
+            "odd key": 1,
+        });
+        let apply = |expr: &str| Filter::parse(expr).unwrap().apply(&value);
+
+        assert_eq!(apply(".").unwrap(), std::slice::from_ref(&value));
+        assert_eq!(apply(".items[].name").unwrap(), [json!("a"), json!("b")]);
+        assert_eq!(apply(".items[-1].name").unwrap(), [json!("b")]);
+        assert_eq!(apply(".items[0].tags[0]").unwrap(), [json!("x")]);
+        assert_eq!(apply(".\"odd key\"").unwrap(), [json!(1)]);
+        assert_eq!(apply(".[\"odd key\"]").unwrap(), [json!(1)]);
+        assert_eq!(apply(".missing.deeper").unwrap(), [Value::Null]);
+        assert!(apply(".items.name").is_err());
+
+        assert!(Filter::parse("items").is_err());
+        assert!(Filter::parse(".items[").is_err());
+        assert!(Filter::parse(".items..name").is_err());
+    }
+}
@@ -1,69 +1,254 @@
 #![allow(dead_code)]
 
-use reqwest::{self};
+use std::collections::BTreeMap;
+
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json;
 
 use crate::{
+    config::{self, Config, Profile},
     headers::{self, Headers},
-    urls, utils,
+    http, oauth, term, urls, utils,
 };
 
+/// Everything that can go wrong while authenticating with GitHub and Copilot.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The request never got a response, e.g. no connection or a timeout.
+    Network(String),
+    /// The host couldn't be reached at all, as opposed to answering with an error.
+    Unreachable(String),
+    /// GitHub answered with an unexpected status code.
+    HttpStatus(reqwest::StatusCode),
+    /// The response body wasn't the JSON we expected.
+    Json(String),
+    /// The user hasn't entered the device code yet.
+    Pending,
+    /// The device code expired before the user entered it.
+    TokenExpired,
+    /// GitHub rejected the token (`401 Unauthorized`), it was probably revoked.
+    Unauthorized,
+    /// GitHub accepted the token but refused the request (`403 Forbidden`).
+    Forbidden,
+    /// GitHub's API rate limit is used up. `reset` is when it resets, as a Unix timestamp,
+    /// when GitHub says.
+    RateLimited { reset: Option<i64> },
+    /// An organization enforces SAML SSO and the token hasn't been authorized for it yet.
+    /// `url` is where the user can authorize it, when GitHub sends one.
+    SsoRequired { url: Option<String> },
+    /// The token works but lacks scopes we need, e.g. after they were edited on github.com.
+    MissingScopes(Vec<String>),
+    /// The account is signed in but has no Copilot subscription. `message` and `url` come
+    /// from GitHub's response when it explains why (e.g. a signup link).
+    NoCopilotSeat {
+        message: Option<String>,
+        url: Option<String>,
+    },
+    /// The account has Copilot, but chat is turned off, usually by an organization policy.
+    ChatDisabled { plan: String },
+    /// The browser login couldn't be completed.
+    Callback(String),
+    /// The user pressed Ctrl+C while we were waiting for the login.
+    Cancelled,
+    /// There's no token and logging in wasn't an option, see `stored_auth`.
+    NotLoggedIn,
+    /// We poll too often. `interval` is the new polling interval, if GitHub sent one.
+    SlowDown { interval: Option<u64> },
+    /// The user clicked "Cancel" on the authorization page.
+    AccessDenied,
+    /// Any other OAuth error, e.g. `unsupported_grant_type`.
+    OAuth {
+        error: String,
+        description: Option<String>,
+    },
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Network(e) => write!(f, "Network error: {}", e),
+            AuthError::Unreachable(e) => write!(f, "Cannot reach the server: {}", e),
+            AuthError::HttpStatus(status) => write!(f, "GitHub responded with {}", status),
+            AuthError::Json(e) => write!(f, "Unexpected response from GitHub: {}", e),
+            AuthError::Pending => write!(f, "Authorization is still pending"),
+            AuthError::TokenExpired => write!(f, "The device code expired"),
+            AuthError::Cancelled => write!(f, "Login cancelled"),
+            AuthError::NotLoggedIn => write!(f, "Not logged in, run copilot auth login first"),
+            AuthError::SlowDown { .. } => write!(f, "Polling too fast, GitHub asked to slow down"),
+            AuthError::AccessDenied => write!(f, "The authorization request was denied"),
+            AuthError::OAuth { error, description } => match description {
+                Some(description) => write!(f, "GitHub refused the login ({}): {}", error, description),
+                None => write!(f, "GitHub refused the login ({})", error),
+            },
+            AuthError::Unauthorized => write!(f, "GitHub rejected the token (401 Unauthorized)"),
+            AuthError::Forbidden => write!(f, "GitHub refused access with this token (403 Forbidden)"),
+            AuthError::RateLimited { reset } => {
+                write!(f, "GitHub's API rate limit is used up")?;
+                match reset.and_then(|reset| chrono::DateTime::from_timestamp(reset, 0)) {
+                    Some(reset) => write!(
+                        f,
+                        ", try again after {}",
+                        reset.with_timezone(&chrono::Local).format("%H:%M")
+                    ),
+                    None => write!(f, ", try again later"),
+                }
+            }
+            AuthError::MissingScopes(scopes) => {
+                write!(f, "The token is missing the {} scope(s)", scopes.join(", "))
+            }
+            AuthError::SsoRequired { url } => {
+                writeln!(
+                    f,
+                    "An organization enforces SAML single sign-on and this token isn't authorized for it yet."
+                )?;
+                match url {
+                    Some(url) => write!(
+                        f,
+                        "Open {} to authorize the token, then run copilot again.",
+                        url
+                    ),
+                    None => write!(
+                        f,
+                        "Authorize it under https://github.com/settings/applications (or \"Configure SSO\" for a personal access token), then run copilot again."
+                    ),
+                }
+            }
+            AuthError::NoCopilotSeat { message, url } => {
+                write!(f, "This GitHub account doesn't have a Copilot seat.")?;
+                if let Some(message) = message {
+                    write!(f, " GitHub says: {}", message)?;
+                }
+                write!(
+                    f,
+                    "\nSubscribe at {}, or ask your organization admin to assign you a seat.",
+                    url.as_deref()
+                        .unwrap_or("https://github.com/features/copilot")
+                )
+            }
+            AuthError::ChatDisabled { plan } => write!(
+                f,
+                "Copilot Chat is disabled for this account (plan: {}).\n\
+                 If the seat comes from an organization, an admin has to enable Copilot Chat in its Copilot policies.",
+                plan
+            ),
+            AuthError::Callback(e) => write!(f, "Browser login failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<reqwest::Error> for AuthError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            AuthError::Json(e.to_string())
+        } else if e.is_connect() || e.is_timeout() {
+            AuthError::Unreachable(e.to_string())
+        } else {
+            AuthError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<http::SendError> for AuthError {
+    fn from(e: http::SendError) -> Self {
+        match e {
+            http::SendError::Unreachable(e) => AuthError::Unreachable(e),
+            http::SendError::Failed(e) => AuthError::Network(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AuthError {
+    fn from(e: serde_json::Error) -> Self {
+        AuthError::Json(e.to_string())
+    }
+}
+
+/// The `error` field GitHub's OAuth endpoints return instead of a token.
+#[derive(Debug, Deserialize)]
+struct OAuthErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+    /// Sent with `slow_down`, the polling interval to use from now on.
+    #[serde(default)]
+    interval: Option<u64>,
+}
+
+/// The body of a GitHub API error, e.g. `{"message": "Not Found"}`. The Copilot token
+/// endpoint adds `error_details` with a human readable reason and a signup link.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GithubErrorResponse {
+    message: Option<String>,
+    error_details: Option<GithubErrorDetails>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GithubErrorDetails {
+    message: Option<String>,
+    url: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GitHubDeviceLoginResponse {
     interval: u64,
     user_code: String,
     expires_in: u64,
     verification_uri: String,
+    /// The verification URL with the code already filled in, when the server provides one.
+    #[serde(default)]
+    verification_uri_complete: Option<String>,
     device_code: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GitHubDeviceTokenResponse {
     access_token: String,
     token_type: String,
     scope: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
+/// The parts of `GET /user` the crate uses. GitHub returns `null` for most profile
+/// fields the user hasn't filled in, so everything but the login is optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GithubUserData {
     pub login: String,
+    #[serde(default)]
     pub id: u64,
-    pub node_id: String,
-    pub avatar_url: String,
-    pub gravatar_id: String,
-    pub url: String,
-    pub html_url: String,
-    pub followers_url: String,
-    pub following_url: String,
-    pub gists_url: String,
-    pub starred_url: String,
-    pub subscriptions_url: String,
-    pub organizations_url: String,
-    pub repos_url: String,
-    pub events_url: String,
-    pub received_events_url: String,
-    #[serde(rename = "type")]
-    pub type_: String,
-    pub site_admin: bool,
-    pub name: String,
-    pub company: Option<String>,
-    pub blog: String,
-    pub location: String,
-    pub email: Option<String>,
-    pub hireable: Option<bool>,
-    pub bio: String,
-    pub twitter_username: Option<String>,
-    pub public_repos: u64,
-    pub public_gists: u64,
-    pub followers: u64,
-    pub following: u64,
-    pub created_at: String,
-    pub updated_at: String,
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl GithubUserData {
+    /// Up to two initials from the display name (or the login), standing in for the avatar.
+    pub fn initials(&self) -> String {
+        let name = match &self.name {
+            Some(name) if !name.trim().is_empty() => name,
+            _ => &self.login,
+        };
+
+        let words: Vec<&str> = name.split_whitespace().collect();
+        let initials: String = if words.len() > 1 {
+            words
+                .iter()
+                .filter_map(|w| w.chars().next())
+                .take(2)
+                .collect()
+        } else {
+            name.chars().take(2).collect()
+        };
+
+        initials.to_uppercase()
+    }
+}
+
+/// The Copilot session token and feature flags. GitHub adds flags regularly, so missing
+/// fields fall back to their defaults and unknown ones end up in `extra`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GithubCopilotAuth {
     pub annotations_enabled: bool,
     pub chat_enabled: bool,
@@ -83,6 +268,39 @@ pub struct GithubCopilotAuth {
     pub tracking_id: String,
     pub vsc_electron_fetcher: bool,
     pub vsc_panel_v2: bool,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl GithubCopilotAuth {
+    /// A readable name for the `sku`, falling back to the raw value for plans we don't know.
+    pub fn plan_name(&self) -> String {
+        let name = match self.sku.as_str() {
+            "free_limited_copilot" => "Copilot Free",
+            "copilot_for_individual_subscriber" | "monthly_subscriber" | "yearly_subscriber" => {
+                "Copilot Individual"
+            }
+            "free_educational" | "free_engaged_oss" => "Copilot Individual (free)",
+            "copilot_for_business_seat" | "copilot_for_business_seat_quota" => "Copilot Business",
+            "copilot_enterprise_seat" | "copilot_enterprise_seat_quota" => "Copilot Enterprise",
+            "" => "unknown plan",
+            other => other,
+        };
+
+        name.to_string()
+    }
+
+    /// The names of the feature flags that are on, including ones we don't know yet.
+    pub fn enabled_features(&self) -> Vec<String> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(fields)) => fields
+                .into_iter()
+                .filter(|(_, value)| value == &serde_json::Value::Bool(true))
+                .map(|(name, _)| name)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -93,11 +311,59 @@ pub struct GithubAuth {
 }
 
 /// A struct that represents the authentication manager for Github Copilot
-pub struct AuthenticationManager {}
+#[derive(Clone)]
+pub struct AuthenticationManager {
+    profile: String,
+    host: String,
+    client: Client,
+    /// A token supplied by `--token-cmd`/`--token-fd`, never written to the config file.
+    explicit_token: Option<String>,
+    retry: http::RetryPolicy,
+}
 
 impl AuthenticationManager {
-    pub fn new() -> Self {
-        AuthenticationManager {}
+    pub fn new(client: Client) -> Self {
+        Self::for_profile(config::DEFAULT_PROFILE, client)
+    }
+
+    /// Creates an authentication manager for the named profile, using the host
+    /// stored in the profile (or github.com for a profile that doesn't exist yet).
+    /// `client` (and its proxy settings) is used for every request, see `http::client`.
+    pub fn for_profile(profile: &str, client: Client) -> Self {
+        let config = Config::load();
+        let host = config
+            .profiles
+            .get(profile)
+            .map(|p| p.host.clone())
+            .unwrap_or(urls::GITHUB_HOST.to_string());
+
+        AuthenticationManager {
+            profile: profile.to_string(),
+            host,
+            client,
+            explicit_token: None,
+            retry: http::RetryPolicy::from_config(&config),
+        }
+    }
+
+    /// Overrides the GitHub host, e.g. for a GitHub Enterprise profile.
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Authenticates with `token` instead of the environment or the config file.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.explicit_token = Some(token);
+        self
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
     }
 
     /// `request_github_auth` is an asynchronous function that requests GitHub authentication.
@@ -105,7 +371,7 @@ impl AuthenticationManager {
     /// # Returns
     ///
     /// This function returns a `Result` which is `Ok` if the authentication request is successful,
-    /// containing a `GitHubDeviceLoginResponse`. If the request fails, it returns an `Err` with a description of the error.
+    /// containing a `GitHubDeviceLoginResponse`. If the request fails, it returns an `AuthError`.
     ///
     /// # Example
     ///
@@ -119,21 +385,24 @@ impl AuthenticationManager {
     /// # Errors
     ///
     /// This function will return an error if the authentication request fails.
-    pub async fn request_github_auth(&self) -> Result<GitHubDeviceLoginResponse, String> {
+    pub async fn request_github_auth(&self) -> Result<GitHubDeviceLoginResponse, AuthError> {
         let headers = headers::LoginHeaders().to_headers();
 
-        let req = reqwest::Client::new()
-            .post(urls::DEVICE_CODE_LOGIN_URL)
+        let req = self
+            .client
+            .post(urls::device_code_login_url(&self.host))
             .json(&serde_json::json!({
                 "client_id": "Iv1.b507a08c87ecfe98",
                 "scope": "read:user"
             }))
-            .headers(headers)
-            .send()
-            .await
-            .unwrap();
+            .headers(headers);
+        let req = http::send_with_retry(req, &self.retry).await?;
+
+        if !req.status().is_success() {
+            return Err(AuthError::HttpStatus(req.status()));
+        }
 
-        let json = req.json::<GitHubDeviceLoginResponse>().await.unwrap();
+        let json = req.json::<GitHubDeviceLoginResponse>().await?;
         Ok(json)
     }
 
@@ -147,7 +416,7 @@ impl AuthenticationManager {
     ///
     /// This function returns a `Result` which is `Ok` if the authentication is successful,
     /// containing a `GitHubDeviceTokenResponse`. If the authentication is still pending,
-    /// it returns an `Err`.
+    /// it returns `AuthError::Pending`, and `AuthError::TokenExpired` once the device code ran out.
     ///
     /// # Example
     ///
@@ -155,22 +424,24 @@ impl AuthenticationManager {
     /// let device_code = String::from("your_device_code");
     /// match auth_manager.check_github_auth(&device_code).await {
     ///     Ok(response) => println!("Authentication successful: {:?}", response),
-    ///     Err(_) => println!("Authentication is still pending"),
+    ///     Err(AuthError::Pending) => println!("Authentication is still pending"),
+    ///     Err(e) => println!("Authentication failed: {}", e),
     /// }
     /// ```
     ///
     /// # Errors
     ///
-    /// This function will return an error if the authentication is still pending.
+    /// This function will return an error if the authentication is still pending, or if the request fails.
     pub async fn check_github_auth(
         &self,
         device_code: &String,
-    ) -> Result<GitHubDeviceTokenResponse, ()> {
+    ) -> Result<GitHubDeviceTokenResponse, AuthError> {
         // let headers = DEFAULT_LOGIN_HEADERS.to_headers();
         let headers = headers::LoginHeaders().to_headers();
 
-        let req = reqwest::Client::new()
-            .post(urls::DEVICE_CODE_TOKEN_CHECK_URL)
+        let req = self
+            .client
+            .post(urls::device_code_token_check_url(&self.host))
             .json(&serde_json::json!({
                 "client_id": "Iv1.b507a08c87ecfe98",
                 "device_code": device_code,
@@ -178,16 +449,15 @@ impl AuthenticationManager {
             }))
             .headers(headers)
             .send()
-            .await
-            .unwrap();
+            .await?;
 
         // we have to use text here because there are two possible responses
-        let text = req.text().await.unwrap();
-        if text.contains("authorization_pending") {
-            return Err(());
+        let text = req.text().await?;
+        if let Some(error) = oauth_error(&text) {
+            return Err(error);
         }
 
-        let json = serde_json::from_str::<GitHubDeviceTokenResponse>(&text).unwrap();
+        let json = serde_json::from_str::<GitHubDeviceTokenResponse>(&text)?;
 
         Ok(json)
     }
@@ -203,7 +473,7 @@ impl AuthenticationManager {
     ///
     /// This function returns a `Result` type. On successful execution, it returns `Ok(GithubUserData)`,
     /// where `GithubUserData` is the user data retrieved from GitHub. If there is an error during execution,
-    /// it returns `Err(String)`, where `String` is the error message.
+    /// it returns an `AuthError`, `AuthError::Unauthorized` if GitHub rejected the token.
     ///
     /// # Example
     ///
@@ -223,50 +493,138 @@ impl AuthenticationManager {
     pub async fn gh_get_user(
         &self,
         auth: &GitHubDeviceTokenResponse,
-    ) -> Result<GithubUserData, String> {
+    ) -> Result<GithubUserData, AuthError> {
+        let headers = headers::GithubUserHeaders {
+            token: &auth.access_token,
+            token_type: &auth.token_type,
+        }
+        .to_headers();
+
+        let req = self
+            .client
+            .get(urls::gh_auth_token_url(&self.host))
+            .headers(headers);
+        let req = http::send_with_retry(req, &self.retry).await?;
+
+        if let Some(limited) = rate_limited(&req) {
+            return Err(limited);
+        }
+        match req.status() {
+            reqwest::StatusCode::UNAUTHORIZED => return Err(AuthError::Unauthorized),
+            reqwest::StatusCode::FORBIDDEN => {
+                let sso = sso_required(req.headers());
+                let body = req.json::<GithubErrorResponse>().await.unwrap_or_default();
+                return Err(sso
+                    .or_else(|| sso_required_by_message(&body))
+                    .unwrap_or(AuthError::Forbidden));
+            }
+            status if !status.is_success() => return Err(AuthError::HttpStatus(status)),
+            _ => {}
+        }
+
+        // only OAuth tokens and classic PATs report their scopes, fine-grained tokens have
+        // no header and get checked by the Copilot endpoint instead
+        if let Some(scopes) = req
+            .headers()
+            .get("x-oauth-scopes")
+            .and_then(|v| v.to_str().ok())
+        {
+            let missing = missing_scopes(scopes);
+            if !missing.is_empty() {
+                return Err(AuthError::MissingScopes(missing));
+            }
+        }
+
+        Ok(req.json::<GithubUserData>().await?)
+    }
+
+    /// Opens an issue in `repo` (`owner/name`) and returns its URL. Not retried, so a flaky
+    /// connection can't file it twice.
+    pub async fn create_issue(
+        &self,
+        auth: &GitHubDeviceTokenResponse,
+        repo: &str,
+        title: &str,
+        body: &str,
+    ) -> Result<String, AuthError> {
         let headers = headers::GithubUserHeaders {
             token: &auth.access_token,
             token_type: &auth.token_type,
         }
         .to_headers();
 
-        let req = reqwest::Client::new()
-            .get(urls::GH_AUTH_TOKEN_URL)
+        let res = self
+            .client
+            .post(urls::gh_issues_url(&self.host, repo))
             .headers(headers)
+            .json(&serde_json::json!({ "title": title, "body": body }))
             .send()
-            .await
-            .unwrap();
+            .await?;
 
-        if req.status().is_success() {
-            let json = req.json::<GithubUserData>().await.unwrap();
-            Ok(json)
-        } else {
-            Err("Failed to authenticate with Github".to_string())
+        match res.status() {
+            reqwest::StatusCode::UNAUTHORIZED => return Err(AuthError::Unauthorized),
+            // GitHub hides repositories the token can't write to behind 404
+            reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::NOT_FOUND => {
+                return Err(AuthError::Forbidden)
+            }
+            status if !status.is_success() => return Err(AuthError::HttpStatus(status)),
+            _ => {}
         }
+
+        #[derive(Deserialize)]
+        struct CreatedIssue {
+            html_url: String,
+        }
+        Ok(res.json::<CreatedIssue>().await?.html_url)
     }
 
     pub async fn gh_copilot_authenticate(
         &self,
         auth: &GitHubDeviceTokenResponse,
-    ) -> Result<GithubCopilotAuth, String> {
+    ) -> Result<GithubCopilotAuth, AuthError> {
         let headers = headers::GithubInternalHeaders {
             token: &auth.access_token,
         }
         .to_headers();
 
-        let req = reqwest::Client::new()
-            .get(urls::GH_COPILOT_INTERNAL_AUTH_URL)
-            .headers(headers)
-            .send()
-            .await
-            .unwrap();
+        let req = self
+            .client
+            .get(urls::gh_copilot_internal_auth_url(&self.host))
+            .headers(headers);
+        let req = http::send_with_retry(req, &self.retry).await?;
 
-        if req.status().is_success() {
-            let json = req.json::<GithubCopilotAuth>().await.unwrap();
-            return Ok(json);
+        if let Some(limited) = rate_limited(&req) {
+            return Err(limited);
         }
+        match req.status() {
+            reqwest::StatusCode::UNAUTHORIZED => Err(AuthError::Unauthorized),
+            // the token endpoint doesn't exist for accounts without a seat
+            reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::NOT_FOUND => {
+                let sso = sso_required(req.headers());
+                let body = req.json::<GithubErrorResponse>().await.unwrap_or_default();
+                if let Some(sso) = sso.or_else(|| sso_required_by_message(&body)) {
+                    return Err(sso);
+                }
+                let details = body.error_details.unwrap_or_default();
 
-        Err("Failed to authenticate with Github Copilot".to_string())
+                Err(AuthError::NoCopilotSeat {
+                    message: details.message.or(body.message),
+                    url: details.url,
+                })
+            }
+            status if status.is_success() => {
+                let copilot = req.json::<GithubCopilotAuth>().await?;
+                utils::log_unknown_fields("GithubCopilotAuth", &copilot.extra);
+
+                if !copilot.chat_enabled {
+                    return Err(AuthError::ChatDisabled {
+                        plan: copilot.plan_name(),
+                    });
+                }
+                Ok(copilot)
+            }
+            status => Err(AuthError::HttpStatus(status)),
+        }
     }
 
     /// `auth` is an asynchronous function that handles the entire authentication process with GitHub and GitHub Copilot.
@@ -290,28 +648,64 @@ impl AuthenticationManager {
     /// This function will return an error if the authentication request fails,
     /// if the check for GitHub authentication fails,
     /// or if the authentication with GitHub Copilot fails.
-    pub async fn auth(&self) -> Result<GithubAuth, String> {
-        let response = self.request_github_auth().await?;
+    pub async fn auth(&self) -> Result<GithubAuth, AuthError> {
+        'device_flow: loop {
+            let response = self.request_github_auth().await?;
 
-        println!(
-            "Please visit {} and enter the code {}",
-            response.verification_uri, response.user_code
-        );
+            println!(
+                "Please visit {} and enter the code {}",
+                response.verification_uri, response.user_code
+            );
 
-        loop {
-            let auth = self.check_github_auth(&response.device_code).await;
-            match auth {
-                Ok(auth) => {
-                    let user = self.gh_get_user(&auth).await.unwrap();
-                    let copilot = self.gh_copilot_authenticate(&auth).await.unwrap();
-                    return Ok(GithubAuth {
-                        user,
-                        token: auth,
-                        copilot_auth: copilot,
-                    });
-                }
-                Err(_) => {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(response.interval)).await;
+            // scanning is much easier than typing the URL on a phone when logged in over SSH
+            let qr_target = response
+                .verification_uri_complete
+                .as_ref()
+                .unwrap_or(&response.verification_uri);
+            if let Some(qr) = term::render_qr_code(qr_target) {
+                println!("{}", qr);
+            }
+
+            println!("Waiting for the code to be entered, press Ctrl+C to cancel.");
+            let deadline =
+                tokio::time::Instant::now() + tokio::time::Duration::from_secs(response.expires_in);
+            let mut interval = response.interval;
+
+            loop {
+                let auth = self.check_github_auth(&response.device_code).await;
+                match auth {
+                    Ok(auth) => {
+                        let user = self.gh_get_user(&auth).await?;
+                        let copilot = self.gh_copilot_authenticate(&auth).await?;
+                        return Ok(GithubAuth {
+                            user,
+                            token: auth,
+                            copilot_auth: copilot,
+                        });
+                    }
+                    Err(e @ (AuthError::Pending | AuthError::SlowDown { .. })) => {
+                        if let AuthError::SlowDown { interval: new } = e {
+                            // RFC 8628: without a new interval, add 5 seconds
+                            interval = new.unwrap_or(interval + 5);
+                        }
+
+                        let next_poll = tokio::time::Instant::now()
+                            + tokio::time::Duration::from_secs(interval);
+                        // nobody entered the code in time, give up instead of polling forever
+                        if next_poll >= deadline {
+                            return Err(AuthError::TokenExpired);
+                        }
+
+                        tokio::select! {
+                            _ = tokio::time::sleep_until(next_poll) => {}
+                            _ = tokio::signal::ctrl_c() => return Err(AuthError::Cancelled),
+                        }
+                    }
+                    Err(AuthError::TokenExpired) => {
+                        println!("The code expired, requesting a new one.");
+                        continue 'device_flow;
+                    }
+                    Err(e) => return Err(e),
                 }
             }
         }
@@ -320,12 +714,14 @@ impl AuthenticationManager {
     /// This asynchronous function is responsible for caching the GitHub authentication.
     ///
     /// # Functionality
-    /// It first reads the configuration file to check if the token is already present.
+    /// It first checks the `GH_TOKEN`/`GITHUB_TOKEN` environment variables, then reads the configuration file
+    /// to check if the token for this manager's profile is already present.
     /// If the token is found, it proceeds to authenticate the user and the copilot with GitHub.
+    /// Otherwise it runs the device flow and stores the new token, user and host under the profile.
     /// It then returns a `GithubAuth` object which includes the user, the token, and the copilot authentication.
     ///
     /// # Returns
-    /// This function returns a `Result` that contains a `GithubAuth` object on success, or a `String` error message on failure.
+    /// This function returns a `Result` that contains a `GithubAuth` object on success, or an `AuthError` on failure.
     ///
     /// # Errors
     /// This function will return an error if the GitHub authentication fails, or if there's an issue with reading the configuration file.
@@ -338,34 +734,738 @@ impl AuthenticationManager {
     ///     Err(e) => println!("Error during authentication: {}", e),
     /// }
     /// ```
-    pub async fn cache_auth(&self) -> Result<GithubAuth, String> {
+    pub async fn cache_auth(&self) -> Result<GithubAuth, AuthError> {
         // read the config file, and see if the token is already there
         // if it is, then we just need to do the copilot auth
 
-        let gh_token = utils::read_config_file();
+        if let Some(token) = self.token() {
+            match self.authenticate_token(token).await {
+                Ok(auth) => return Ok(auth),
+                // a token from the environment or a secrets manager isn't ours to replace, and
+                // rate limits or a missing Copilot seat won't go away with a new login
+                Err(
+                    e @ (AuthError::Unauthorized
+                    | AuthError::Forbidden
+                    | AuthError::MissingScopes(_)),
+                ) if !self.has_external_token() => {
+                    match e {
+                        AuthError::Unauthorized => println!(
+                            "The stored GitHub token for profile {} was rejected, it has probably been revoked.",
+                            self.profile
+                        ),
+                        e => println!(
+                            "The stored GitHub token for profile {} can't be used anymore: {}.",
+                            self.profile, e
+                        ),
+                    }
+                    println!("Removing it and starting a new login.");
+                    self.remove_token();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let auth = self.auth().await?;
+        self.save_profile(&auth);
+
+        Ok(auth)
+    }
+
+    /// Like `cache_auth`, but only checks the token there is: a rejected one is reported and
+    /// kept, and no login is started. For commands that only look, like `auth status`.
+    pub async fn stored_auth(&self) -> Result<GithubAuth, AuthError> {
+        let token = self.token().ok_or(AuthError::NotLoggedIn)?;
+        self.authenticate_token(token).await
+    }
 
-        if gh_token != "" {
-            let auth = GitHubDeviceTokenResponse {
-                access_token: gh_token,
-                token_type: "bearer".to_string(),
-                scope: "".to_string(),
-            };
+    /// Mints a new Copilot session token for `auth`, e.g. when the old one expired during a
+    /// long chat, and keeps it for the next start.
+    pub async fn refresh_copilot(
+        &self,
+        auth: &GitHubDeviceTokenResponse,
+    ) -> Result<GithubCopilotAuth, AuthError> {
+        let copilot = self.gh_copilot_authenticate(auth).await?;
+        self.update_profile(&auth.access_token, |profile| {
+            profile.copilot = Some(copilot.clone());
+        });
+        Ok(copilot)
+    }
 
-            let user = self.gh_get_user(&auth).await.unwrap();
-            let copilot = self.gh_copilot_authenticate(&auth).await.unwrap();
+    /// Exchanges an existing GitHub token for the user data and a Copilot session token.
+    /// `gh_get_user`, keeping the result for the next start.
+    async fn fetch_user(
+        &self,
+        auth: &GitHubDeviceTokenResponse,
+    ) -> Result<GithubUserData, AuthError> {
+        let user = self.gh_get_user(auth).await?;
+        self.cache_user(&auth.access_token, &user);
+        Ok(user)
+    }
 
-            let auth = GithubAuth {
-                user,
-                token: auth,
-                copilot_auth: copilot,
-            };
+    async fn authenticate_token(&self, token: String) -> Result<GithubAuth, AuthError> {
+        let auth = GitHubDeviceTokenResponse {
+            access_token: token,
+            token_type: "bearer".to_string(),
+            scope: "".to_string(),
+        };
 
-            return Ok(auth);
+        // a Copilot session token lives for about half an hour, so a restart can
+        // often reuse the one from the last run
+        let cached = self
+            .stored_profile()
+            .filter(|profile| profile.token == auth.access_token)
+            .and_then(|profile| profile.valid_copilot_auth().cloned());
+        let (copilot, user) = match cached {
+            // the user endpoint only feeds the display, so a cached copy is good enough
+            // while the session is still valid and gets refreshed in the background
+            Some(copilot) => match self.cached_user() {
+                Some(user) => {
+                    self.refresh_user_in_background(&auth);
+                    (copilot, user)
+                }
+                None => (copilot, self.fetch_user(&auth).await?),
+            },
+            // otherwise check the token itself first, so a revoked token or one that lost
+            // its scopes is reported as such instead of as a Copilot failure
+            None => {
+                let user = self.fetch_user(&auth).await?;
+                let copilot = self.gh_copilot_authenticate(&auth).await?;
+                self.update_profile(&auth.access_token, |profile| {
+                    profile.last_verified_at = Some(chrono::Utc::now().timestamp());
+                    profile.copilot = Some(copilot.clone());
+                });
+                (copilot, user)
+            }
+        };
+
+        Ok(GithubAuth {
+            user,
+            token: auth,
+            copilot_auth: copilot,
+        })
+    }
+
+    /// `auth_web` authenticates through the browser instead of a device code.
+    ///
+    /// It listens on a random localhost port, opens the authorization page with the system
+    /// browser and exchanges the code GitHub redirects back with for a token. The OAuth app
+    /// configured with `oauth_client_id`/`oauth_client_secret` must allow
+    /// `http://127.0.0.1/callback` as a redirect URL.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the OAuth app isn't configured, if the user denies
+    /// the request in the browser, or if exchanging the code fails.
+    pub async fn auth_web(&self) -> Result<GithubAuth, AuthError> {
+        let config = Config::load();
+        let (Some(client_id), Some(client_secret)) =
+            (config.oauth_client_id, config.oauth_client_secret)
+        else {
+            return Err(AuthError::Callback(
+                "set oauth_client_id and oauth_client_secret in the config file to use --web"
+                    .to_string(),
+            ));
+        };
+
+        let listener = oauth::RedirectListener::bind().await?;
+        let redirect_uri = listener.redirect_uri();
+        let state = utils::random_hex_string(16);
+
+        let mut authorize_url = reqwest::Url::parse(&urls::oauth_authorize_url(&self.host))
+            .map_err(|e| AuthError::Callback(e.to_string()))?;
+        authorize_url
+            .query_pairs_mut()
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", &redirect_uri)
+            .append_pair("scope", "read:user")
+            .append_pair("state", &state);
+
+        println!("Opening {} in your browser", authorize_url);
+        if utils::open_in_browser(authorize_url.as_str()).is_err() {
+            println!("Couldn't open a browser, please open the link above manually");
+        }
+
+        let code = listener.wait_for_code(&state).await?;
+
+        let req = self
+            .client
+            .post(urls::device_code_token_check_url(&self.host))
+            .json(&serde_json::json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "code": code,
+                "redirect_uri": redirect_uri,
+            }))
+            .headers(headers::LoginHeaders().to_headers());
+        let text = http::send_with_retry(req, &self.retry)
+            .await?
+            .text()
+            .await?;
+
+        if let Ok(error) = serde_json::from_str::<OAuthErrorResponse>(&text) {
+            return Err(AuthError::Callback(error.error));
         }
+        let auth = serde_json::from_str::<GitHubDeviceTokenResponse>(&text)?;
+
+        let user = self.gh_get_user(&auth).await?;
+        let copilot = self.gh_copilot_authenticate(&auth).await?;
 
-        let auth = self.auth().await.unwrap();
-        utils::write_token_to_config_file(&auth.token.access_token);
+        Ok(GithubAuth {
+            user,
+            token: auth,
+            copilot_auth: copilot,
+        })
+    }
+
+    /// Like `login`, but authenticates through the browser with `auth_web`.
+    pub async fn login_web(&self) -> Result<GithubAuth, AuthError> {
+        let auth = self.auth_web().await?;
+        self.save_profile(&auth);
+
+        Ok(auth)
+    }
+
+    /// Stores a personal access token for users who can't complete the device flow. The token
+    /// is validated with `gh_get_user` and exchanged for a Copilot token before it's saved.
+    pub async fn login_with_token(&self, token: &str) -> Result<GithubAuth, AuthError> {
+        let mut auth = GitHubDeviceTokenResponse {
+            access_token: token.trim().to_string(),
+            token_type: "bearer".to_string(),
+            scope: "".to_string(),
+        };
+
+        let user = self.gh_get_user(&auth).await?;
+        let copilot = self.gh_copilot_authenticate(&auth).await?;
+        auth.scope = self.gh_token_scopes(&auth).await.unwrap_or_default();
+
+        let auth = GithubAuth {
+            user,
+            token: auth,
+            copilot_auth: copilot,
+        };
+        self.save_profile(&auth);
+
+        Ok(auth)
+    }
+
+    /// Runs the device flow and stores the new token in the profile, replacing any existing one.
+    pub async fn login(&self) -> Result<GithubAuth, AuthError> {
+        let auth = self.auth().await?;
+        self.save_profile(&auth);
 
         Ok(auth)
     }
+
+    /// Removes the stored token for this profile. Returns `false` if there was nothing to remove.
+    pub fn logout(&self) -> bool {
+        let removed = self.remove_token();
+
+        let mut config = Config::load();
+        if config.active_profile.as_deref() == Some(self.profile.as_str()) {
+            config.active_profile = None;
+            config.save();
+        }
+
+        removed
+    }
+
+    fn remove_token(&self) -> bool {
+        let _ = std::fs::remove_dir_all(self.user_cache_dir());
+
+        let mut config = Config::load();
+        let removed = config.profiles.remove(&self.profile).is_some();
+        config.save();
+
+        removed
+    }
+
+    /// Whether a token is available, either from the environment or stored for this profile.
+    pub fn is_logged_in(&self) -> bool {
+        self.token().is_some()
+    }
+
+    /// The token to authenticate with. A `--token-cmd`/`--token-fd` token comes first, then
+    /// `GH_TOKEN` and `GITHUB_TOKEN`, so containers and CI can skip the device flow, and
+    /// finally the config file.
+    fn token(&self) -> Option<String> {
+        self.explicit_token.clone().or_else(env_token).or_else(|| {
+            Config::load()
+                .profiles
+                .get(&self.profile)
+                .map(|p| p.token.clone())
+        })
+    }
+
+    /// Whether the token comes from somewhere other than the config file.
+    pub fn has_external_token(&self) -> bool {
+        self.explicit_token.is_some() || env_token().is_some()
+    }
+
+    /// Returns the OAuth scopes GitHub reports for the token in the `X-OAuth-Scopes` header.
+    pub async fn gh_token_scopes(
+        &self,
+        auth: &GitHubDeviceTokenResponse,
+    ) -> Result<String, AuthError> {
+        let headers = headers::GithubUserHeaders {
+            token: &auth.access_token,
+            token_type: &auth.token_type,
+        }
+        .to_headers();
+
+        let req = self
+            .client
+            .get(urls::gh_auth_token_url(&self.host))
+            .headers(headers)
+            .send()
+            .await?;
+
+        let scopes = req
+            .headers()
+            .get("x-oauth-scopes")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        Ok(scopes)
+    }
+
+    fn user_cache_dir(&self) -> std::path::PathBuf {
+        std::path::PathBuf::from(utils::get_config_path())
+            .join("users")
+            .join(&self.profile)
+    }
+
+    /// One file per host, the same profile name can be logged in to github.com and to a
+    /// GitHub Enterprise server with different accounts.
+    fn user_cache_path(&self) -> std::path::PathBuf {
+        let host: String = self
+            .host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.user_cache_dir().join(format!("{}.json", host))
+    }
+
+    /// The user data saved by the last successful login or refresh of this profile on this
+    /// host.
+    pub fn cached_user(&self) -> Option<GithubUserData> {
+        let text = std::fs::read_to_string(self.user_cache_path()).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Keeps `user` for the next start, but only while `token` is the one stored for the
+    /// profile, so a token from the environment can't leave its account behind.
+    fn cache_user(&self, token: &str, user: &GithubUserData) {
+        if !self
+            .stored_profile()
+            .is_some_and(|profile| profile.token == token)
+        {
+            return;
+        }
+        let path = self.user_cache_path();
+        let _ = std::fs::create_dir_all(path.parent().unwrap());
+        if let Ok(json) = serde_json::to_string_pretty(user) {
+            let _ = std::fs::write(path, json);
+        }
+    }
+
+    fn refresh_user_in_background(&self, auth: &GitHubDeviceTokenResponse) {
+        let manager = self.clone();
+        let auth = auth.clone();
+
+        tokio::spawn(async move {
+            if let Ok(user) = manager.gh_get_user(&auth).await {
+                manager.cache_user(&auth.access_token, &user);
+            }
+        });
+    }
+
+    fn save_profile(&self, auth: &GithubAuth) {
+        // the users cached for the old token may belong to another account
+        let _ = std::fs::remove_dir_all(self.user_cache_dir());
+
+        let mut profile = Profile::new(auth.token.access_token.clone(), self.host.clone());
+        profile.user = Some(auth.user.login.clone());
+        profile.scopes = Some(auth.token.scope.clone());
+        profile.last_verified_at = profile.created_at;
+        profile.copilot = Some(auth.copilot_auth.clone());
+
+        let mut config = Config::load();
+        config.profiles.insert(self.profile.clone(), profile);
+        config.save();
+
+        self.cache_user(&auth.token.access_token, &auth.user);
+    }
+
+    /// The profile as stored in the config file.
+    pub fn stored_profile(&self) -> Option<Profile> {
+        Config::load().profiles.remove(&self.profile)
+    }
+
+    /// Updates the stored profile, but only while it still holds `token`, so a token from
+    /// the environment never overwrites the metadata of the one in the config file.
+    fn update_profile(&self, token: &str, update: impl FnOnce(&mut Profile)) {
+        let mut config = Config::load();
+        if let Some(profile) = config.profiles.get_mut(&self.profile) {
+            if profile.token == token {
+                update(profile);
+                config.save();
+            }
+        }
+    }
+}
+
+/// Reads the `X-GitHub-SSO: required; url=https://github.com/orgs/.../sso?...` header GitHub
+/// sends when SAML enforcement blocks a token.
+fn sso_required(headers: &reqwest::header::HeaderMap) -> Option<AuthError> {
+    let value = headers.get("x-github-sso")?.to_str().ok()?;
+    sso_from_header(value)
+}
+
+fn sso_from_header(value: &str) -> Option<AuthError> {
+    // `partial-results; organizations=...` only means some results were hidden
+    if !value.trim_start().starts_with("required") {
+        return None;
+    }
+
+    let url = value
+        .split(';')
+        .filter_map(|part| part.trim().strip_prefix("url="))
+        .next()
+        .map(|url| url.to_string());
+    Some(AuthError::SsoRequired { url })
+}
+
+/// `RateLimited` when a `403`/`429` comes from GitHub's rate limit rather than the token:
+/// `x-ratelimit-remaining: 0` for the primary limit, `retry-after` for the secondary one.
+fn rate_limited(res: &reqwest::Response) -> Option<AuthError> {
+    if !matches!(
+        res.status(),
+        reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+    rate_limit_from_headers(res.headers())
+}
+
+fn rate_limit_from_headers(headers: &reqwest::header::HeaderMap) -> Option<AuthError> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+
+    if let Some(after) = header("retry-after").and_then(|after| after.parse::<i64>().ok()) {
+        return Some(AuthError::RateLimited {
+            reset: Some(chrono::Utc::now().timestamp() + after),
+        });
+    }
+    if header("x-ratelimit-remaining") == Some("0") {
+        return Some(AuthError::RateLimited {
+            reset: header("x-ratelimit-reset").and_then(|reset| reset.parse().ok()),
+        });
+    }
+    None
+}
+
+/// Some endpoints only say it in the body: "Resource protected by organization SAML enforcement..."
+fn sso_required_by_message(body: &GithubErrorResponse) -> Option<AuthError> {
+    body.message
+        .as_deref()
+        .filter(|message| message.contains("SAML enforcement"))
+        .map(|_| AuthError::SsoRequired { url: None })
+}
+
+/// The scopes the device flow asks for.
+const REQUIRED_SCOPES: &[&str] = &["read:user"];
+
+/// The `REQUIRED_SCOPES` missing from an `x-oauth-scopes` header like `"gist, read:user"`.
+/// A parent scope counts for its children, `user` grants `read:user`.
+fn missing_scopes(granted: &str) -> Vec<String> {
+    let granted: Vec<&str> = granted.split(',').map(|s| s.trim()).collect();
+
+    REQUIRED_SCOPES
+        .iter()
+        .filter(|required| {
+            let parent = required.split_once(':').map(|(_, parent)| parent);
+            !granted.iter().any(|g| g == *required || Some(*g) == parent)
+        })
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Maps an OAuth error body from the device flow token endpoint to an `AuthError`,
+/// or `None` if `body` isn't an error.
+fn oauth_error(body: &str) -> Option<AuthError> {
+    let error = serde_json::from_str::<OAuthErrorResponse>(body).ok()?;
+
+    Some(match error.error.as_str() {
+        "authorization_pending" => AuthError::Pending,
+        "slow_down" => AuthError::SlowDown {
+            interval: error.interval,
+        },
+        "expired_token" => AuthError::TokenExpired,
+        "access_denied" => AuthError::AccessDenied,
+        _ => AuthError::OAuth {
+            error: error.error,
+            description: error.error_description,
+        },
+    })
+}
+
+/// Reads a token from `GH_TOKEN` or `GITHUB_TOKEN`, in the same order the `gh` CLI uses.
+pub fn env_token() -> Option<String> {
+    ["GH_TOKEN", "GITHUB_TOKEN"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty())
+}
+
+/// Runs a secrets manager command like `pass show github/copilot` and uses the
+/// first line of its output as the token.
+pub fn token_from_command(command: &str) -> Result<String, String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Failed to run token command: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("Token command exited with {}", output.status));
+    }
+
+    first_line_token(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Reads the token from an inherited file descriptor, e.g. `--token-fd 3 3<token.txt`, or
+/// stdin with `--token-fd 0`. The descriptor is left open.
+#[cfg(unix)]
+pub fn token_from_fd(fd: i32) -> Result<String, String> {
+    use std::{io::Read, mem::ManuallyDrop, os::fd::FromRawFd};
+
+    if fd == 1 || fd == 2 {
+        return Err(format!(
+            "--token-fd {} is stdout or stderr, not a token",
+            fd
+        ));
+    }
+    // SAFETY: F_GETFD only looks the descriptor up
+    if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(format!("--token-fd {} isn't an open file descriptor", fd));
+    }
+
+    // SAFETY: the descriptor is open, and as the file is never dropped it stays open for
+    // whatever else might use it
+    let mut file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|e| format!("Failed to read token from fd {}: {}", fd, e))?;
+
+    first_line_token(&contents)
+}
+
+#[cfg(not(unix))]
+pub fn token_from_fd(_fd: i32) -> Result<String, String> {
+    Err("--token-fd is only supported on unix".to_string())
+}
+
+fn first_line_token(output: &str) -> Result<String, String> {
+    output
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .ok_or("The token source returned an empty token".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_data_with_null_profile_fields() {
+        let json = r#"{
+            "login": "octocat",
+            "id": 1,
+            "node_id": "MDQ6VXNlcjE=",
+            "avatar_url": "https://github.com/images/error/octocat_happy.gif",
+            "gravatar_id": "",
+            "type": "User",
+            "site_admin": false,
+            "name": null,
+            "company": null,
+            "blog": "",
+            "location": null,
+            "email": null,
+            "hireable": null,
+            "bio": null,
+            "twitter_username": null,
+            "public_repos": 2,
+            "followers": 20,
+            "created_at": "2008-01-14T04:33:35Z"
+        }"#;
+
+        let user = serde_json::from_str::<GithubUserData>(json).unwrap();
+        assert_eq!(user.login, "octocat");
+        assert_eq!(user.id, 1);
+        assert_eq!(user.name, None);
+        assert_eq!(user.initials(), "OC");
+    }
+
+    #[test]
+    fn user_data_with_full_profile() {
+        let json = r#"{
+            "login": "monalisa",
+            "id": 583231,
+            "name": "Mona Lisa Octocat",
+            "company": "@github",
+            "blog": "https://github.blog",
+            "location": "San Francisco",
+            "email": "octocat@github.com",
+            "hireable": true,
+            "bio": "There once was...",
+            "plan": { "name": "pro", "space": 976562499, "private_repos": 9999 }
+        }"#;
+
+        let user = serde_json::from_str::<GithubUserData>(json).unwrap();
+        assert_eq!(user.name.as_deref(), Some("Mona Lisa Octocat"));
+        assert_eq!(user.initials(), "ML");
+    }
+
+    #[test]
+    fn user_data_with_only_a_login() {
+        let user = serde_json::from_str::<GithubUserData>(r#"{"login": "ghost"}"#).unwrap();
+        assert_eq!(user.login, "ghost");
+        assert_eq!(user.id, 0);
+        assert_eq!(user.initials(), "GH");
+    }
+
+    #[test]
+    fn user_data_with_blank_name_uses_login() {
+        let json = r#"{"login": "hubot", "id": 2, "name": "  "}"#;
+
+        let user = serde_json::from_str::<GithubUserData>(json).unwrap();
+        assert_eq!(user.initials(), "HU");
+    }
+
+    #[test]
+    fn copilot_auth_with_missing_and_unknown_fields() {
+        let json = r#"{
+            "token": "tid=abc;exp=1700000000",
+            "expires_at": 1700000000,
+            "sku": "copilot_for_business_seat",
+            "chat_enabled": true,
+            "endpoints": { "api": "https://api.business.githubcopilot.com" }
+        }"#;
+
+        let copilot = serde_json::from_str::<GithubCopilotAuth>(json).unwrap();
+        assert_eq!(copilot.sku, "copilot_for_business_seat");
+        assert!(copilot.chat_enabled);
+        assert!(!copilot.prompt_8k);
+        assert!(copilot.extra.contains_key("endpoints"));
+        assert_eq!(copilot.enabled_features(), ["chat_enabled"]);
+    }
+
+    #[test]
+    fn saml_sso_header() {
+        let error = sso_from_header(
+            "required; url=https://github.com/orgs/octo-org/sso?authorization_request=AZSCKtL4U8yX1H3sCQIVnVgmjmon5fWxks5YrqhJgah0b2tlbl9pZM4EuMz4",
+        );
+        assert!(matches!(
+            error,
+            Some(AuthError::SsoRequired { url: Some(url) }) if url.starts_with("https://github.com/orgs/octo-org/sso?")
+        ));
+        assert!(sso_from_header("partial-results; organizations=21955855,20582480").is_none());
+    }
+
+    #[test]
+    fn rate_limit_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert!(rate_limit_from_headers(&headers).is_none());
+
+        headers.insert("x-ratelimit-remaining", "12".parse().unwrap());
+        assert!(rate_limit_from_headers(&headers).is_none());
+
+        headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1700000000".parse().unwrap());
+        assert!(matches!(
+            rate_limit_from_headers(&headers),
+            Some(AuthError::RateLimited {
+                reset: Some(1700000000)
+            })
+        ));
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("retry-after", "60".parse().unwrap());
+        assert!(matches!(
+            rate_limit_from_headers(&headers),
+            Some(AuthError::RateLimited { reset: Some(_) })
+        ));
+    }
+
+    #[test]
+    fn required_scopes() {
+        assert!(missing_scopes("gist, read:user, repo").is_empty());
+        assert!(missing_scopes("user").is_empty());
+        assert_eq!(missing_scopes("repo"), ["read:user"]);
+        assert_eq!(missing_scopes(""), ["read:user"]);
+    }
+
+    #[test]
+    fn device_flow_oauth_errors() {
+        assert!(matches!(
+            oauth_error(r#"{"error": "authorization_pending"}"#),
+            Some(AuthError::Pending)
+        ));
+        assert!(matches!(
+            oauth_error(r#"{"error": "slow_down", "interval": 10}"#),
+            Some(AuthError::SlowDown { interval: Some(10) })
+        ));
+        assert!(matches!(
+            oauth_error(
+                r#"{"error": "access_denied", "error_description": "The user has denied your application access."}"#
+            ),
+            Some(AuthError::AccessDenied)
+        ));
+        assert!(matches!(
+            oauth_error(r#"{"error": "incorrect_client_credentials"}"#),
+            Some(AuthError::OAuth { .. })
+        ));
+        assert!(
+            oauth_error(r#"{"access_token": "gho_x", "token_type": "bearer", "scope": ""}"#)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn copilot_error_body_with_details() {
+        let json = r#"{
+            "error_details": {
+                "url": "https://github.com/github-copilot/signup",
+                "message": "You do not have access to GitHub Copilot.",
+                "title": "Copilot access"
+            },
+            "message": "Resource not accessible by integration"
+        }"#;
+
+        let body = serde_json::from_str::<GithubErrorResponse>(json).unwrap();
+        let details = body.error_details.unwrap();
+        assert_eq!(
+            details.url.as_deref(),
+            Some("https://github.com/github-copilot/signup")
+        );
+        assert_eq!(
+            details.message.as_deref(),
+            Some("You do not have access to GitHub Copilot.")
+        );
+    }
 }
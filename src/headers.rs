@@ -84,11 +84,13 @@ pub(crate) struct CopilotCompletionHeaders<'a> {
     pub token: &'a String,
     pub vscode_sid: &'a String,
     pub device_id: &'a String,
+    /// The business and enterprise APIs reject requests without `Copilot-Integration-Id`.
+    pub integration_id: Option<&'a str>,
 }
 
 impl<'a> Headers for CopilotCompletionHeaders<'a> {
     fn to_headers(&self) -> HeaderMap {
-        headers! {
+        let mut headers = headers! {
             "Authorization" => format!("Bearer {}", self.token),
             "vscode-sessionid" => self.vscode_sid,
             "machineid" => self.device_id,
@@ -98,6 +100,36 @@ impl<'a> Headers for CopilotCompletionHeaders<'a> {
             "openai-intent" => "conversation-panel",
             "Content-Type" => "application/json",
             "User-Agent" => "GitHubCopilotChat/0.12.2023120701"
+        };
+        if let Some(id) = self.integration_id {
+            headers.insert(
+                "Copilot-Integration-Id",
+                reqwest::header::HeaderValue::from_str(id).unwrap(),
+            );
+        }
+        headers
+    }
+}
+
+/// Inline (ghost text) completions, sent as the copilot.lua plugin does.
+pub(crate) struct CopilotGhostHeaders<'a> {
+    pub token: &'a String,
+    pub vscode_sid: &'a String,
+    pub device_id: &'a String,
+}
+
+impl<'a> Headers for CopilotGhostHeaders<'a> {
+    fn to_headers(&self) -> HeaderMap {
+        headers! {
+            "Authorization" => format!("Bearer {}", self.token),
+            "vscode-sessionid" => self.vscode_sid,
+            "machineid" => self.device_id,
+            "editor-version" => "Neovim/0.9.2",
+            "editor-plugin-version" => "copilot.lua/1.11.4",
+            "openai-organization" => "github-copilot",
+            "openai-intent" => "copilot-ghost",
+            "Content-Type" => "application/json",
+            "User-Agent" => "GithubCopilot/1.133.0"
         }
     }
 }
@@ -0,0 +1,191 @@
+use crate::term;
+
+const STYLE: &str = "body{max-width:50rem;margin:2rem auto;padding:0 1rem;\
+font-family:system-ui,sans-serif;line-height:1.5;color:#222}\
+h1{font-size:1.4rem}.message{border-top:1px solid #ddd;padding:.5rem 0}\
+.who{font-weight:bold;color:#555}\
+pre{padding:.75rem;border-radius:4px;overflow-x:auto}\
+code{font-family:ui-monospace,monospace;font-size:.9em}\
+:not(pre)>code{background:#f0f0f0;padding:0 .2em;border-radius:3px}";
+
+/// Escapes the characters HTML gives a meaning to.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A whole conversation as one HTML file that needs nothing else to be viewed: the styles are
+/// inline and code blocks are highlighted like in the terminal. `messages` are
+/// (who, markdown) pairs.
+pub fn render(title: &str, messages: &[(String, String)]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(title),
+        STYLE,
+        escape(title)
+    );
+    for (who, content) in messages {
+        html.push_str(&format!(
+            "<div class=\"message\">\n<div class=\"who\">{}</div>\n{}</div>\n",
+            escape(who),
+            markdown(content)
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// The markdown answers use: fenced code, headings, lists and paragraphs with `code` and
+/// **bold** inside. Anything else comes out as text.
+fn markdown(text: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&str> = None;
+    // the language and code of a fenced block that's still open
+    let mut fence: Option<(String, String)> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+
+        if let Some((lang, code)) = &mut fence {
+            if trimmed.starts_with("```") {
+                html.push_str(&term::highlight_html(code, lang));
+                fence = None;
+            } else {
+                code.push_str(line);
+                code.push('\n');
+            }
+            continue;
+        }
+
+        if let Some(info) = trimmed.strip_prefix("```") {
+            close_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+            let lang = info.split_whitespace().next().unwrap_or("").to_lowercase();
+            fence = Some((lang, String::new()));
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            close_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+            continue;
+        }
+
+        let heading = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&heading) && trimmed[heading..].starts_with(' ') {
+            close_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+            html.push_str(&format!(
+                "<h{}>{}</h{}>\n",
+                heading + 1,
+                inline(trimmed[heading..].trim()),
+                heading + 1
+            ));
+            continue;
+        }
+
+        let item = match trimmed.split_once(' ') {
+            Some(("-" | "*", rest)) => Some(("ul", rest)),
+            Some((number, rest))
+                if number.ends_with('.')
+                    && number.len() > 1
+                    && number[..number.len() - 1]
+                        .chars()
+                        .all(|c| c.is_ascii_digit()) =>
+            {
+                Some(("ol", rest))
+            }
+            _ => None,
+        };
+        if let Some((tag, rest)) = item {
+            close_paragraph(&mut html, &mut paragraph);
+            if list != Some(tag) {
+                close_list(&mut html, &mut list);
+                html.push_str(&format!("<{}>\n", tag));
+                list = Some(tag);
+            }
+            html.push_str(&format!("<li>{}</li>\n", inline(rest)));
+            continue;
+        }
+
+        close_list(&mut html, &mut list);
+        paragraph.push(trimmed);
+    }
+
+    // an unterminated block at the end of the answer is still code
+    if let Some((lang, code)) = &fence {
+        html.push_str(&term::highlight_html(code, lang));
+    }
+    close_paragraph(&mut html, &mut paragraph);
+    close_list(&mut html, &mut list);
+    html
+}
+
+fn close_paragraph(html: &mut String, paragraph: &mut Vec<&str>) {
+    if !paragraph.is_empty() {
+        html.push_str(&format!("<p>{}</p>\n", inline(&paragraph.join("\n"))));
+        paragraph.clear();
+    }
+}
+
+fn close_list(html: &mut String, list: &mut Option<&str>) {
+    if let Some(tag) = list.take() {
+        html.push_str(&format!("</{}>\n", tag));
+    }
+}
+
+/// `code` spans and **bold** within a line, everything else escaped.
+fn inline(text: &str) -> String {
+    let mut html = String::new();
+    for (i, part) in text.split('`').enumerate() {
+        // odd parts are between backticks
+        if i % 2 == 1 {
+            html.push_str(&format!("<code>{}</code>", escape(part)));
+            continue;
+        }
+        for (j, piece) in escape(part).split("**").enumerate() {
+            if j % 2 == 1 {
+                html.push_str(&format!("<strong>{}</strong>", piece));
+            } else {
+                html.push_str(piece);
+            }
+        }
+    }
+    html.replace('\n', "<br>\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_answer_markdown() {
+        let html = markdown("## Fix\nUse `Vec<u8>` **here**:\n\n- one\n- two\n\n1. first");
+        assert_eq!(
+            html,
+            "<h3>Fix</h3>\n\
+             <p>Use <code>Vec&lt;u8&gt;</code> <strong>here</strong>:</p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <ol>\n<li>first</li>\n</ol>\n"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_highlighted_and_escaped() {
+        let html = markdown("```rust\nlet x: Vec<u8> = vec![];\n```");
+        assert!(html.starts_with("<pre style="));
+        assert!(html.contains("&lt;"));
+        assert!(!html.contains("<u8>"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::{Certificate, Client, NoProxy, Proxy, RequestBuilder, Response};
+
+use crate::config::Config;
+
+/// Builds the HTTP client shared by authentication, completions and tools.
+///
+/// Without a `proxy` key in the config, reqwest already picks up `HTTPS_PROXY`,
+/// `HTTP_PROXY`, `ALL_PROXY` and `NO_PROXY` from the environment. `socks5://`
+/// URLs work in both places.
+///
+/// `ca_cert` adds the certificates of a PEM bundle to the trusted roots and
+/// `tls_insecure` turns verification off.
+pub fn client(config: &Config) -> Result<Client, String> {
+    let mut builder = Client::builder();
+
+    if let Some(proxy) = &config.proxy {
+        let proxy = Proxy::all(proxy)
+            .map_err(|e| format!("Invalid proxy {}: {}", proxy, e))?
+            .no_proxy(NoProxy::from_env());
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(path) = &config.ca_cert {
+        let pem = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("Invalid certificate in {}: {}", path, e))?;
+
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if config.tls_insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// How often and how patiently to retry a request that failed for transient reasons.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        RetryPolicy {
+            attempts: config.retry_attempts.max(1),
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+        }
+    }
+
+    /// Exponential backoff with +/-50% jitter, so parallel clients don't retry in lockstep.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay * 2u32.saturating_pow(attempt);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The connection couldn't be made or timed out, most likely there's no network.
+    Unreachable(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Unreachable(e) | SendError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Sends the request, retrying connection errors and 5xx responses according to `policy`.
+/// Any other response, successful or not, is returned to the caller as-is.
+pub async fn send_with_retry(
+    request: RequestBuilder,
+    policy: &RetryPolicy,
+) -> Result<Response, SendError> {
+    let mut attempt = 0;
+
+    loop {
+        let this_try = request.try_clone().ok_or(SendError::Failed(
+            "Request body can't be retried".to_string(),
+        ))?;
+
+        let error = match this_try.send().await {
+            Ok(response) if !response.status().is_server_error() => return Ok(response),
+            Ok(response) => {
+                SendError::Failed(format!("Server responded with {}", response.status()))
+            }
+            Err(e) if e.is_connect() || e.is_timeout() => SendError::Unreachable(e.to_string()),
+            Err(e) => SendError::Failed(e.to_string()),
+        };
+
+        attempt += 1;
+        if attempt >= policy.attempts {
+            return Err(error);
+        }
+
+        tokio::time::sleep(policy.delay(attempt - 1)).await;
+    }
+}
+
+/// How long a 429 or 503 response asks to wait, from `Retry-After` in seconds or as a date.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.to_str().ok()?.trim();
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds_or_date() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // a date in the past means right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use reqwest::{header, Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// Responses without an ETag or Last-Modified header are reused for this long.
+const FRESH_FOR_SECS: i64 = 10 * 60;
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: i64,
+    body: String,
+}
+
+fn cache_dir() -> PathBuf {
+    PathBuf::from(utils::get_config_path())
+        .join("cache")
+        .join("http")
+}
+
+fn entry_path(url: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    cache_dir().join(format!("{:016x}.json", hasher.finish()))
+}
+
+fn load(url: &str) -> Option<CacheEntry> {
+    let text = std::fs::read_to_string(entry_path(url)).ok()?;
+    serde_json::from_str::<CacheEntry>(&text)
+        .ok()
+        .filter(|entry| entry.url == url)
+}
+
+fn store(entry: &CacheEntry) {
+    // the cache is best effort, failing to write it shouldn't fail the request
+    let _ = std::fs::create_dir_all(cache_dir());
+    if let Ok(json) = serde_json::to_string(entry) {
+        let _ = std::fs::write(entry_path(&entry.url), json);
+    }
+}
+
+/// Sends a GET request through the on-disk cache and returns the response body.
+///
+/// Cached entries are revalidated with `If-None-Match`/`If-Modified-Since`, so a
+/// `304 Not Modified` answer is served from disk instead of downloading the page again.
+pub async fn get(client: &Client, request: RequestBuilder) -> Result<String, String> {
+    let mut request = request.build().map_err(|e| e.to_string())?;
+    let url = request.url().to_string();
+    let now = chrono::Utc::now().timestamp();
+
+    let cached = load(&url);
+    if let Some(entry) = &cached {
+        let has_validators = entry.etag.is_some() || entry.last_modified.is_some();
+        if !has_validators && now - entry.fetched_at < FRESH_FOR_SECS {
+            return Ok(entry.body.clone());
+        }
+
+        let headers = request.headers_mut();
+        if let Some(etag) = entry.etag.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(modified) = entry.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(header::IF_MODIFIED_SINCE, modified);
+        }
+    }
+
+    let response = client.execute(request).await.map_err(|e| e.to_string())?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(mut entry) = cached {
+            entry.fetched_at = now;
+            store(&entry);
+            return Ok(entry.body);
+        }
+    }
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Request to {} failed with status {}",
+            url,
+            response.status()
+        ));
+    }
+
+    let header_value = |name: header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let etag = header_value(header::ETAG);
+    let last_modified = header_value(header::LAST_MODIFIED);
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    store(&CacheEntry {
+        url,
+        etag,
+        last_modified,
+        fetched_at: now,
+        body: body.clone(),
+    });
+
+    Ok(body)
+}
+
+/// Returns the number of cached responses and their total size in bytes.
+pub fn stats() -> (usize, u64) {
+    std::fs::read_dir(cache_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok()?.metadata().ok())
+                .fold((0, 0), |(count, size), meta| (count + 1, size + meta.len()))
+        })
+        .unwrap_or((0, 0))
+}
+
+/// Deletes every cached response.
+pub fn clear() -> Result<(), String> {
+    match std::fs::remove_dir_all(cache_dir()) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
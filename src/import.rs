@@ -0,0 +1,403 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::sessions::{self, Session, StoredMessage};
+
+/// A conversation read from another tool, before it becomes a session.
+#[derive(Debug, Default, PartialEq)]
+struct Conversation {
+    title: Option<String>,
+    /// The first system message, kept as the session's prompt.
+    prompt: Option<String>,
+    messages: Vec<StoredMessage>,
+}
+
+impl Conversation {
+    fn push(&mut self, role: &str, content: String, time: Option<i64>, model: Option<String>) {
+        let content = content.trim().to_string();
+        if content.is_empty() {
+            return;
+        }
+        let role = match role {
+            "user" | "human" => "user",
+            "assistant" | "model" | "bot" => "assistant",
+            "system" | "developer" => {
+                self.prompt.get_or_insert(content);
+                return;
+            }
+            // tool results and function calls have no place in a session
+            _ => return,
+        };
+        let time = time.unwrap_or_else(|| chrono::Utc::now().timestamp());
+
+        // sessions alternate questions and answers, so runs of one role are joined
+        match self.messages.last_mut() {
+            Some(last) if last.role == role => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&content);
+            }
+            None if role == "assistant" => {}
+            _ => self.messages.push(StoredMessage {
+                role: role.to_string(),
+                content,
+                time,
+                model: model.filter(|_| role == "assistant"),
+                usage: None,
+            }),
+        }
+    }
+
+    /// A session named `name`, or `None` if no question got an answer.
+    fn into_session(mut self, name: String) -> Option<Session> {
+        // a question without an answer can't be replayed
+        if self.messages.len() % 2 == 1 {
+            self.messages.pop();
+        }
+        let first = self.messages.first()?;
+        Some(Session {
+            id: name.clone(),
+            name: Some(name),
+            prompt: self.prompt,
+            started: first.time,
+            messages: self.messages,
+            ..Session::new()
+        })
+    }
+}
+
+/// Runs `copilot import <file> [name]`: reads transcripts exported by other assistants and
+/// stores each conversation as a named session. Returns the sessions that were saved.
+///
+/// Understood are OpenAI chat messages (`{"messages": [...]}` or a bare array, which is also
+/// what aichat's YAML sessions become with `yq -o json`), ChatGPT's `conversations.json`,
+/// aichat's `messages.md` log, `llm logs --json` and VS Code Copilot Chat's "Export Chat".
+pub fn run(path: &str, name: Option<&str>) -> Result<Vec<Session>, String> {
+    if let Some(name) = name {
+        sessions::check_name(name)?;
+    }
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let conversations = parse(&text)?;
+    let stem = Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("imported");
+
+    let many = conversations.len() > 1;
+    let mut saved = Vec::new();
+    for (i, conversation) in conversations.into_iter().enumerate() {
+        let base = match (name, &conversation.title) {
+            (Some(name), _) if many => format!("{}-{}", name, i + 1),
+            (Some(name), _) => name.to_string(),
+            (None, Some(title)) => slug(title),
+            (None, None) => slug(stem),
+        };
+        let base = if base.is_empty() { "imported" } else { &base };
+        let Some(session) = conversation.into_session(sessions::free_name(base)) else {
+            continue;
+        };
+        session
+            .save()
+            .map_err(|e| format!("Failed to save session {}: {}", session.id, e))?;
+        saved.push(session);
+    }
+    if saved.is_empty() {
+        return Err(format!("{} has no answered questions to import", path));
+    }
+    Ok(saved)
+}
+
+/// A session name from a conversation title, e.g. `fix-the-borrow-checker-error`.
+fn slug(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= 40 {
+            break;
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Picks the format from the shape of the file.
+fn parse(text: &str) -> Result<Vec<Conversation>, String> {
+    let Ok(json) = serde_json::from_str::<Value>(text) else {
+        if text.lines().any(|line| line.starts_with("# CHAT:")) {
+            return Ok(aichat_log(text));
+        }
+        return Err(
+            "Unrecognized transcript, expected OpenAI or ChatGPT JSON, aichat's messages.md, \
+             `llm logs --json` or a VS Code Copilot Chat export"
+                .to_string(),
+        );
+    };
+
+    let first = json.as_array().and_then(|items| items.first());
+    let conversations = match (&json, first) {
+        (Value::Array(items), Some(item)) if item.get("mapping").is_some() => {
+            items.iter().map(chatgpt).collect()
+        }
+        (Value::Array(items), Some(item)) if item.get("prompt").is_some() => llm_logs(items),
+        (Value::Array(items), _) => vec![openai(None, items)],
+        (Value::Object(object), _) if object.contains_key("mapping") => vec![chatgpt(&json)],
+        (Value::Object(object), _) if object.contains_key("requests") => vec![vscode(&json)],
+        (Value::Object(object), _) => match object.get("messages").and_then(|m| m.as_array()) {
+            Some(messages) => vec![openai(json.get("model"), messages)],
+            None => return Err("Unrecognized JSON transcript, found no messages".to_string()),
+        },
+        _ => return Err("Unrecognized JSON transcript, found no messages".to_string()),
+    };
+    Ok(conversations)
+}
+
+/// Message content, either a string or OpenAI's list of `{"type": "text", "text": ...}` parts.
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.as_str().or_else(|| part["text"].as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(|s| s.to_string())
+}
+
+/// `[{"role": "user", "content": ...}, ...]`, as sent to the chat completions API.
+fn openai(model: Option<&Value>, messages: &[Value]) -> Conversation {
+    let model = model.and_then(|m| m.as_str()).map(|m| m.to_string());
+    let mut conversation = Conversation::default();
+    for message in messages {
+        conversation.push(
+            message["role"].as_str().unwrap_or_default(),
+            text_of(&message["content"]),
+            None,
+            model.clone(),
+        );
+    }
+    conversation
+}
+
+/// One conversation of ChatGPT's data export. The messages form a tree of edits and
+/// regenerations, the branch that was shown last ends at `current_node`.
+fn chatgpt(conversation: &Value) -> Conversation {
+    let mapping = &conversation["mapping"];
+    let mut branch = Vec::new();
+    let mut node = conversation["current_node"].as_str();
+    while let Some(id) = node {
+        let Some(entry) = mapping.get(id) else {
+            break;
+        };
+        branch.push(&entry["message"]);
+        node = entry["parent"].as_str();
+    }
+
+    let mut imported = Conversation {
+        title: str_field(conversation, "title"),
+        ..Conversation::default()
+    };
+    for message in branch.into_iter().rev().filter(|m| m.is_object()) {
+        imported.push(
+            message["author"]["role"].as_str().unwrap_or_default(),
+            text_of(&message["content"]["parts"]),
+            message["create_time"].as_f64().map(|t| t as i64),
+            str_field(&message["metadata"], "model_slug"),
+        );
+    }
+    imported
+}
+
+/// `llm logs --json`: one entry per exchange, grouped into conversations by id.
+fn llm_logs(entries: &[Value]) -> Vec<Conversation> {
+    let mut ids: Vec<String> = Vec::new();
+    let mut conversations: Vec<Conversation> = Vec::new();
+    for entry in entries {
+        let id = str_field(entry, "conversation_id").unwrap_or_default();
+        let index = match ids.iter().position(|known| *known == id) {
+            Some(index) => index,
+            None => {
+                ids.push(id);
+                conversations.push(Conversation {
+                    title: str_field(entry, "conversation_name"),
+                    ..Conversation::default()
+                });
+                conversations.len() - 1
+            }
+        };
+
+        let time = entry["datetime_utc"].as_str().and_then(|t| {
+            chrono::NaiveDateTime::parse_from_str(t, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|t| t.and_utc().timestamp())
+        });
+        let conversation = &mut conversations[index];
+        if let Some(system) = str_field(entry, "system") {
+            conversation.push("system", system, time, None);
+        }
+        conversation.push("user", text_of(&entry["prompt"]), time, None);
+        conversation.push(
+            "assistant",
+            text_of(&entry["response"]),
+            time,
+            str_field(entry, "model"),
+        );
+    }
+    conversations
+}
+
+/// VS Code's "Chat: Export Chat...": `requests` with the question in `message.text` and the
+/// answer as a list of parts, of which the markdown ones carry a `value`.
+fn vscode(export: &Value) -> Conversation {
+    let mut conversation = Conversation::default();
+    for request in export["requests"].as_array().into_iter().flatten() {
+        let time = request["timestamp"].as_i64().map(|ms| ms / 1000);
+        conversation.push("user", text_of(&request["message"]["text"]), time, None);
+
+        let answer: String = request["response"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|part| {
+                part["value"]
+                    .as_str()
+                    .or_else(|| part["content"]["value"].as_str())
+            })
+            .collect();
+        conversation.push("assistant", answer, time, str_field(request, "modelId"));
+    }
+    conversation
+}
+
+/// aichat's `messages.md`: each exchange starts with `# CHAT: <time> [<session>]`, followed
+/// by the question and the answer, each ended by a `--------` line. Consecutive exchanges of
+/// the same session make one conversation.
+fn aichat_log(text: &str) -> Vec<Conversation> {
+    let mut conversations: Vec<(String, Conversation)> = Vec::new();
+    for chunk in text.split("# CHAT:").skip(1) {
+        let (header, body) = chunk.split_once('\n').unwrap_or((chunk, ""));
+        let (time, session) = match header.split_once('[') {
+            Some((time, rest)) => (time.trim(), rest.trim_end().trim_end_matches(']')),
+            None => (header.trim(), ""),
+        };
+        let time = chrono::DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|t| t.timestamp());
+
+        let mut parts = body.split("\n--------\n");
+        let question = parts.next().unwrap_or_default().to_string();
+        let answer = parts
+            .next()
+            .unwrap_or_default()
+            .trim_end()
+            .trim_end_matches("--------")
+            .to_string();
+
+        if conversations.last().map(|(s, _)| s.as_str()) != Some(session) || session.is_empty() {
+            let title = (!session.is_empty()).then(|| session.to_string());
+            conversations.push((
+                session.to_string(),
+                Conversation {
+                    title,
+                    ..Conversation::default()
+                },
+            ));
+        }
+        let conversation = &mut conversations.last_mut().unwrap().1;
+        conversation.push("user", question, time, None);
+        conversation.push("assistant", answer, time, None);
+    }
+    conversations.into_iter().map(|(_, c)| c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(conversation: &Conversation) -> Vec<(&str, &str)> {
+        conversation
+            .messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn openai_and_chatgpt() {
+        let api = r#"{"model": "gpt-4o", "messages": [
+            {"role": "system", "content": "Be brief"},
+            {"role": "user", "content": [{"type": "text", "text": "hi"}]},
+            {"role": "assistant", "content": "hello"},
+            {"role": "user", "content": "unanswered"}
+        ]}"#;
+        let conversation = &parse(api).unwrap()[0];
+        assert_eq!(conversation.prompt.as_deref(), Some("Be brief"));
+        assert_eq!(
+            contents(conversation),
+            [
+                ("user", "hi"),
+                ("assistant", "hello"),
+                ("user", "unanswered")
+            ]
+        );
+        assert_eq!(conversation.messages[1].model.as_deref(), Some("gpt-4o"));
+
+        let export = r#"[{"title": "Rust lifetimes", "current_node": "c", "mapping": {
+            "root": {"message": null, "parent": null},
+            "a": {"message": {"author": {"role": "user"}, "content": {"parts": ["why?"]}, "create_time": 1700000000.5}, "parent": "root"},
+            "old": {"message": {"author": {"role": "assistant"}, "content": {"parts": ["regenerated away"]}}, "parent": "a"},
+            "c": {"message": {"author": {"role": "assistant"}, "content": {"parts": ["because"]}, "metadata": {"model_slug": "gpt-4"}}, "parent": "a"}
+        }}]"#;
+        let conversation = &parse(export).unwrap()[0];
+        assert_eq!(conversation.title.as_deref(), Some("Rust lifetimes"));
+        assert_eq!(
+            contents(conversation),
+            [("user", "why?"), ("assistant", "because")]
+        );
+        assert_eq!(conversation.messages[0].time, 1700000000);
+        assert_eq!(slug("Rust lifetimes: why?"), "rust-lifetimes-why");
+    }
+
+    #[test]
+    fn cli_tools_and_vscode() {
+        let llm = r#"[
+            {"conversation_id": "1", "prompt": "a", "response": "b", "model": "gpt-4", "datetime_utc": "2024-01-02T03:04:05.123"},
+            {"conversation_id": "2", "prompt": "c", "response": "d"},
+            {"conversation_id": "1", "prompt": "e", "response": "f"}
+        ]"#;
+        let conversations = parse(llm).unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].messages.len(), 4);
+        assert_eq!(conversations[0].messages[0].time, 1704164645);
+
+        let aichat = "# CHAT: 2024-01-02T03:04:05+00:00 [work]\nquestion one\n--------\nanswer one\n--------\n\n\
+                      # CHAT: 2024-01-02T03:05:00+00:00 [work]\nquestion two\n--------\nanswer two\n--------\n\n";
+        let conversations = parse(aichat).unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(
+            contents(&conversations[0]),
+            [
+                ("user", "question one"),
+                ("assistant", "answer one"),
+                ("user", "question two"),
+                ("assistant", "answer two")
+            ]
+        );
+
+        let vscode = r#"{"requesterUsername": "me", "requests": [
+            {"message": {"text": "explain"}, "response": [{"value": "It "}, {"kind": "inlineReference"}, {"value": "works"}]}
+        ]}"#;
+        assert_eq!(
+            contents(&parse(vscode).unwrap()[0]),
+            [("user", "explain"), ("assistant", "It works")]
+        );
+    }
+}
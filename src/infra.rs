@@ -0,0 +1,111 @@
+use std::process::Command;
+
+use crate::config::InfraHelper;
+
+/// Flags that would keep a command running instead of printing a snapshot.
+const STREAMING_FLAGS: &[&str] = &["-f", "--follow", "-w", "--watch", "--watch-only"];
+
+impl InfraHelper {
+    pub fn name(self) -> &'static str {
+        match self {
+            InfraHelper::K8s => "k8s",
+            InfraHelper::Docker => "docker",
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            InfraHelper::K8s => "kubectl",
+            InfraHelper::Docker => "docker",
+        }
+    }
+
+    /// The read-only views, each with the arguments it runs and what it expects after them.
+    fn views(self) -> &'static [(&'static str, &'static [&'static str], &'static str)] {
+        match self {
+            InfraHelper::K8s => &[
+                ("pods", &["get", "pods", "-o", "wide"], ""),
+                ("deployments", &["get", "deployments", "-o", "wide"], ""),
+                ("services", &["get", "services", "-o", "wide"], ""),
+                ("nodes", &["get", "nodes", "-o", "wide"], ""),
+                ("events", &["get", "events", "--sort-by=.lastTimestamp"], ""),
+                ("describe", &["describe"], " <kind> <name>"),
+                ("logs", &["logs", "--tail=200"], " <pod>"),
+            ],
+            InfraHelper::Docker => &[
+                ("ps", &["ps", "-a"], ""),
+                ("images", &["images"], ""),
+                ("stats", &["stats", "--no-stream"], ""),
+                ("inspect", &["inspect"], " <container>"),
+                ("logs", &["logs", "--tail", "200"], " <container>"),
+            ],
+        }
+    }
+
+    pub fn usage(self) -> String {
+        let views: Vec<String> = self
+            .views()
+            .iter()
+            .map(|(view, _, args)| format!("{}{}", view, args))
+            .collect();
+        format!("Usage: /{} {}", self.name(), views.join(" | "))
+    }
+
+    /// The command line for `/k8s <view> [args]` or `/docker <view> [args]`. Extra arguments
+    /// such as `-n prod` are passed on, but nothing outside the views can be run and flags
+    /// that would stream forever are refused.
+    pub fn command_line(self, arg: &str) -> Result<Vec<String>, String> {
+        let mut words = arg.split_whitespace();
+        let view = words.next().unwrap_or("");
+        let Some((_, base, _)) = self.views().iter().find(|(name, _, _)| *name == view) else {
+            return Err(self.usage());
+        };
+
+        let extra: Vec<&str> = words.collect();
+        if let Some(flag) = extra.iter().find(|w| STREAMING_FLAGS.contains(w)) {
+            return Err(format!(
+                "{} would keep running, use /capture for that",
+                flag
+            ));
+        }
+
+        let mut line = vec![self.program().to_string()];
+        line.extend(base.iter().chain(&extra).map(|w| w.to_string()));
+        Ok(line)
+    }
+}
+
+/// Runs a command line from `command_line` and returns what it printed.
+pub fn run(line: &[String]) -> Result<String, String> {
+    let output = Command::new(&line[0])
+        .args(&line[1..])
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", line[0], e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", line.join(" "), stderr.trim()));
+    }
+    Ok(format!("{}{}", stdout, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_read_only_views_run() {
+        assert_eq!(
+            InfraHelper::K8s.command_line("pods -n prod").unwrap(),
+            ["kubectl", "get", "pods", "-o", "wide", "-n", "prod"]
+        );
+        assert_eq!(
+            InfraHelper::Docker.command_line("logs web").unwrap(),
+            ["docker", "logs", "--tail", "200", "web"]
+        );
+        assert!(InfraHelper::K8s.command_line("delete pod web").is_err());
+        assert!(InfraHelper::K8s.command_line("logs web -f").is_err());
+        assert!(InfraHelper::Docker.command_line("").is_err());
+    }
+}
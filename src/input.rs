@@ -0,0 +1,368 @@
+use rustyline::{history::DefaultHistory, Cmd, Config, Editor, KeyCode, KeyEvent, Modifiers};
+
+pub type LineEditor = Editor<(), DefaultHistory>;
+
+/// What a block of pasted text looks like.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PasteKind {
+    Code,
+    Logs,
+    Text,
+}
+
+/// The keys of the prompt as `/help` lists them: rustyline's Emacs-style defaults, which
+/// `new_editor` keeps, and what the chat does on top.
+pub const KEYBINDINGS: &[(&str, &str)] = &[
+    ("Enter", "Ask the question, or run the command"),
+    ("Up / Down", "Go through earlier input"),
+    ("Ctrl+R", "Search earlier input"),
+    ("Ctrl+A / Ctrl+E", "Move to the start / end of the line"),
+    ("Alt+B / Alt+F", "Move a word back / forward"),
+    ("Ctrl+W", "Delete the word before the cursor"),
+    ("Ctrl+U / Ctrl+K", "Delete to the start / end of the line"),
+    ("Ctrl+L", "Clear the screen"),
+    ("Esc / Ctrl+C", "Stop an answer while it streams"),
+    ("Ctrl+C / Ctrl+D", "Quit at the prompt"),
+    (
+        "Paste",
+        "Multi-line code or logs can be attached as context",
+    ),
+];
+
+pub fn new_editor() -> LineEditor {
+    // with bracketed paste the terminal wraps pasted text in escape codes, so a
+    // multi-line paste arrives as a single insertion instead of one line per Enter
+    let config = Config::builder().bracketed_paste(true).build();
+    Editor::with_config(config).unwrap()
+}
+
+/// Only a bracketed paste can put a newline into the line buffer, since Enter submits the line.
+pub fn is_paste(input: &str) -> bool {
+    input.trim().contains('\n')
+}
+
+pub fn classify_paste(text: &str) -> PasteKind {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() {
+        return PasteKind::Text;
+    }
+
+    let log_lines = lines.iter().filter(|l| looks_like_log_line(l)).count();
+    let code_lines = lines.iter().filter(|l| looks_like_code_line(l)).count();
+
+    // a third of the lines is enough, prose almost never matches either heuristic
+    if log_lines * 3 >= lines.len() {
+        PasteKind::Logs
+    } else if code_lines * 3 >= lines.len() {
+        PasteKind::Code
+    } else {
+        PasteKind::Text
+    }
+}
+
+fn looks_like_log_line(line: &str) -> bool {
+    const MARKERS: [&str; 9] = [
+        "ERROR",
+        "WARN",
+        "INFO",
+        "DEBUG",
+        "TRACE",
+        "panicked at",
+        "Traceback",
+        "Exception",
+        "error[",
+    ];
+    let trimmed = line.trim_start();
+
+    MARKERS.iter().any(|m| line.contains(m))
+        || trimmed.starts_with("at ")
+        || trimmed.starts_with("File \"")
+        // timestamps like 2024-01-31 or 12:34:56
+        || line.chars().take(10).filter(|c| c.is_ascii_digit()).count() >= 6
+}
+
+fn looks_like_code_line(line: &str) -> bool {
+    const KEYWORDS: [&str; 14] = [
+        "fn ",
+        "let ",
+        "pub ",
+        "use ",
+        "impl ",
+        "def ",
+        "class ",
+        "import ",
+        "return ",
+        "const ",
+        "function ",
+        "#include",
+        "if (",
+        "for (",
+    ];
+    let trimmed = line.trim();
+
+    KEYWORDS.iter().any(|k| trimmed.starts_with(k))
+        || trimmed.ends_with(';')
+        || trimmed.ends_with('{')
+        || trimmed.ends_with('}')
+        || (trimmed.ends_with(':') && line.starts_with(char::is_alphabetic))
+        || line.starts_with("    ")
+        || line.starts_with('\t')
+}
+
+/// Asks a yes/no question, `default` is used when the user just presses Enter.
+pub fn confirm(rl: &mut LineEditor, question: &str, default: bool) -> bool {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    let answer = rl
+        .readline(&format!("{} {} ", question, hint))
+        .unwrap_or_default();
+
+    match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        "y" | "yes" => true,
+        _ => false,
+    }
+}
+
+/// Reads a line with `initial` already filled in, so the user can accept or edit it.
+pub fn prefilled(rl: &mut LineEditor, prompt: &str, initial: &str) -> Option<String> {
+    rl.readline_with_initial(prompt, (initial, "")).ok()
+}
+
+/// Asks the user to pick one of `options`, returning the chosen key.
+/// The first option is the default, Esc or Ctrl+C cancels with `None`.
+pub fn choose(rl: &mut LineEditor, question: &str, options: &[(char, &str)]) -> Option<char> {
+    let hint = options
+        .iter()
+        .map(|(key, label)| format!("[{}]{}", key, label))
+        .collect::<Vec<_>>()
+        .join(" / ");
+
+    // only while choosing, at the chat prompt Esc stays the Alt prefix
+    let esc = KeyEvent(KeyCode::Esc, Modifiers::NONE);
+    let previous = rl.bind_sequence(esc, Cmd::Interrupt);
+    let choice = loop {
+        let Ok(answer) = rl.readline(&format!("{} {} ", question, hint)) else {
+            break None;
+        };
+        let answer = answer.trim().to_lowercase();
+
+        if answer.is_empty() {
+            break Some(options[0].0);
+        }
+
+        if let Some(key) = pick(options, &answer) {
+            break Some(key);
+        }
+    };
+    match previous {
+        Some(previous) => rl.bind_sequence(esc, previous),
+        None => rl.unbind_sequence(esc),
+    };
+    choice
+}
+
+/// The option `answer` picks: the one whose key it is, else the one whose whole word, key
+/// and label, it begins. Labels alone aren't matched, "e" mustn't pick "[k]eep".
+fn pick(options: &[(char, &str)], answer: &str) -> Option<char> {
+    options
+        .iter()
+        .find(|(key, _)| answer.chars().eq([*key]))
+        .or_else(|| {
+            options
+                .iter()
+                .find(|(key, label)| format!("{}{}", key, label).starts_with(answer))
+        })
+        .map(|(key, _)| *key)
+}
+
+/// Reads a secret without echoing it. When stdin isn't a terminal (`echo $TOKEN | copilot
+/// auth login --with-token`) the first line is read as-is.
+pub fn read_secret(prompt: &str) -> std::io::Result<String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use std::io::{IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return Ok(line.trim().to_string());
+    }
+
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+
+    crossterm::terminal::enable_raw_mode()?;
+    let mut secret = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    secret.clear();
+                    break Ok(());
+                }
+                KeyCode::Backspace => {
+                    secret.pop();
+                }
+                KeyCode::Char(c) => secret.push(c),
+                _ => {}
+            },
+            // pasting into a terminal without bracketed paste arrives as key events,
+            // with bracketed paste as a single event
+            Ok(Event::Paste(text)) => secret.push_str(&text),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    eprintln!();
+
+    result.map(|_| secret.trim().to_string())
+}
+
+/// Watches the terminal for Esc and Ctrl+C while an answer comes in, see `pressed`. Until
+/// it's dropped, keys arrive without Enter and aren't echoed, and Ctrl+C doesn't raise
+/// SIGINT, so the process keeps its default handler the rest of the time. Anything else
+/// typed meanwhile is dropped.
+#[cfg(unix)]
+pub struct CancelKeys {
+    saved: libc::termios,
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    reader: Option<std::thread::JoinHandle<()>>,
+    pressed: tokio::sync::oneshot::Receiver<()>,
+}
+
+#[cfg(unix)]
+impl CancelKeys {
+    const ESC: u8 = 0x1b;
+    const CTRL_C: u8 = 0x03;
+
+    /// Starts watching, `None` when stdin isn't a terminal.
+    pub fn watch() -> Option<CancelKeys> {
+        use std::{
+            io::IsTerminal,
+            sync::{atomic::AtomicBool, Arc},
+        };
+
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        let fd = libc::STDIN_FILENO;
+        // SAFETY: termios is plain data, filled in by tcgetattr before it's used
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+            return None;
+        }
+        let mut keys = saved;
+        keys.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        // reads give up after a tenth of a second, so the reader notices when to stop
+        keys.c_cc[libc::VMIN] = 0;
+        keys.c_cc[libc::VTIME] = 1;
+        // SAFETY: `keys` is the terminal's own settings with a few flags changed
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &keys) } != 0 {
+            return None;
+        }
+
+        let (sender, pressed) = tokio::sync::oneshot::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut sender = Some(sender);
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    let byte = match read_byte(fd) {
+                        Ok(Some(byte)) => byte,
+                        Ok(None) => continue,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    };
+                    // arrow keys and the like start with Esc as well, but more follows at once
+                    let cancel = byte == Self::CTRL_C
+                        || (byte == Self::ESC && matches!(read_byte(fd), Ok(None)));
+                    if cancel {
+                        if let Some(sender) = sender.take() {
+                            let _ = sender.send(());
+                        }
+                    }
+                }
+            }
+        });
+
+        Some(CancelKeys {
+            saved,
+            stop,
+            reader: Some(reader),
+            pressed,
+        })
+    }
+
+    /// Resolves once Esc or Ctrl+C is pressed.
+    pub async fn pressed(&mut self) {
+        if (&mut self.pressed).await.is_err() {
+            // the terminal can't be read anymore, so nothing will be pressed
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for CancelKeys {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        // SAFETY: these are the settings tcgetattr returned for the same terminal
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+#[cfg(unix)]
+fn read_byte(fd: i32) -> std::io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    // SAFETY: reads at most one byte into `byte`
+    match unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) } {
+        1 => Ok(Some(byte)),
+        0 => Ok(None),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Keys are only watched on unix, elsewhere Ctrl+C is caught as a signal instead.
+#[cfg(not(unix))]
+pub struct CancelKeys;
+
+#[cfg(not(unix))]
+impl CancelKeys {
+    pub fn watch() -> Option<CancelKeys> {
+        None
+    }
+
+    pub async fn pressed(&mut self) {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Removes the echoed multi-line input from the screen so the transcript stays short.
+pub fn clear_echoed_lines(count: usize) {
+    use std::io::Write;
+
+    if !crate::term::Capabilities::detect().alternate_screen {
+        return;
+    }
+    print!("\x1b[{}A\x1b[J", count);
+    std::io::stdout().flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_keys_before_words() {
+        let options = [('k', "eep current"), ('t', "ake patch"), ('e', "dit")];
+        assert_eq!(pick(&options, "e"), Some('e'));
+        assert_eq!(pick(&options, "edit"), Some('e'));
+        assert_eq!(pick(&options, "keep"), Some('k'));
+        assert_eq!(pick(&options, "eep"), None);
+    }
+}
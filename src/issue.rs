@@ -0,0 +1,105 @@
+use serde::Deserialize;
+
+use crate::codeblocks;
+
+/// A GitHub issue drafted from a conversation by `/to-issue`.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct IssueDraft {
+    pub title: String,
+    /// What the problem is, in Markdown.
+    pub summary: String,
+    pub steps: Vec<String>,
+    pub environment: String,
+}
+
+impl IssueDraft {
+    /// Reads the draft out of the model's reply, a JSON object on its own or in a code block.
+    pub fn parse(reply: &str) -> Result<IssueDraft, String> {
+        let draft: IssueDraft = codeblocks::find_json(reply)
+            .and_then(|value| serde_json::from_value(value).ok())
+            .ok_or("The reply wasn't an issue draft")?;
+        if draft.title.trim().is_empty() {
+            return Err("The draft has no title".to_string());
+        }
+        Ok(draft)
+    }
+
+    /// The issue body, with a section for each part that isn't empty.
+    pub fn body(&self) -> String {
+        let mut body = self.summary.trim().to_string();
+        if !self.steps.is_empty() {
+            body.push_str("\n\n## Steps to reproduce\n");
+            for (i, step) in self.steps.iter().enumerate() {
+                body.push_str(&format!("\n{}. {}", i + 1, step.trim()));
+            }
+        }
+        if !self.environment.trim().is_empty() {
+            body.push_str("\n\n## Environment\n\n");
+            body.push_str(self.environment.trim());
+        }
+        body.trim_start().to_string()
+    }
+}
+
+/// `owner/repo` from a GitHub remote URL, SSH or HTTPS.
+pub fn repo_from_remote(url: &str) -> Option<String> {
+    let path = url
+        .trim()
+        .strip_prefix("git@")
+        .and_then(|rest| rest.split_once(':'))
+        .map(|(_, path)| path)
+        .or_else(|| {
+            let rest = url.trim().split_once("://")?.1;
+            Some(rest.split_once('/')?.1)
+        })?;
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    let (owner, repo) = path.split_once('/')?;
+    (!owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+        .then(|| format!("{}/{}", owner, repo))
+}
+
+/// The repository `origin` points at in the current directory, if it's on GitHub.
+pub fn current_repo() -> Option<String> {
+    let output = std::process::Command::new("git")
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| repo_from_remote(&String::from_utf8_lossy(&output.stdout)))
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repos_from_remotes() {
+        for url in [
+            "git@github.com:darkdarcool/copilot.git",
+            "https://github.com/darkdarcool/copilot",
+            "https://github.com/darkdarcool/copilot.git/\n",
+        ] {
+            assert_eq!(
+                repo_from_remote(url).as_deref(),
+                Some("darkdarcool/copilot")
+            );
+        }
+        assert_eq!(repo_from_remote("/srv/git/copilot.git"), None);
+    }
+
+    #[test]
+    fn body_sections() {
+        let draft = IssueDraft::parse(
+            r#"{"title": "Crash on empty config", "summary": "It panics.", "steps": ["Create an empty config", "Run copilot"], "environment": ""}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            draft.body(),
+            "It panics.\n\n## Steps to reproduce\n\n1. Create an empty config\n2. Run copilot"
+        );
+    }
+}
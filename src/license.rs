@@ -0,0 +1,152 @@
+use std::{path::Path, process::Command};
+
+use crate::config::Config;
+
+/// Words that make a file's leading comment its license header.
+const MARKERS: &[&str] = &["license", "copyright", "spdx-license-identifier"];
+
+/// How many files of the same type `"auto"` looks at for a header.
+const DETECT_FILES: usize = 20;
+
+/// `contents` for a new file at `path`, with the `license_header` from the config on top if
+/// one is set and the file type takes comments.
+pub fn apply(config: &Config, path: &Path, contents: &str) -> String {
+    let header = config
+        .license_header
+        .as_deref()
+        .and_then(|configured| header_for(path, configured));
+    match header {
+        Some(header) => prepend(contents, &header),
+        None => contents.to_string(),
+    }
+}
+
+/// The header a new file at `path` should start with: `configured` as a comment in the
+/// file's language, or with `"auto"` the license comment existing files of the same type
+/// start with.
+fn header_for(path: &Path, configured: &str) -> Option<String> {
+    let ext = path.extension()?.to_str()?;
+    let prefix = comment_prefix(ext)?;
+    if configured == "auto" {
+        return detect(ext, prefix);
+    }
+
+    let configured = configured.trim_end();
+    if configured.trim_start().starts_with(prefix) || configured.trim_start().starts_with("/*") {
+        return Some(configured.to_string());
+    }
+    let commented: Vec<String> = configured
+        .lines()
+        .map(|line| match line.trim_end() {
+            "" => prefix.to_string(),
+            line => format!("{} {}", prefix, line),
+        })
+        .collect();
+    Some(commented.join("\n"))
+}
+
+/// The line comment of the languages headers are added to.
+pub fn comment_prefix(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "go" | "java" | "kt" | "swift"
+        | "scala" | "js" | "jsx" | "mjs" | "ts" | "tsx" | "dart" | "php" | "proto" => "//",
+        "py" | "sh" | "bash" | "zsh" | "rb" | "pl" | "r" | "toml" | "yaml" | "yml" | "ex"
+        | "exs" | "nim" | "tf" => "#",
+        "sql" | "lua" | "hs" => "--",
+        _ => return None,
+    })
+}
+
+/// The license comment at the top of the first tracked `.ext` file that has one.
+fn detect(ext: &str, prefix: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["ls-files", "--", &format!("*.{}", ext)])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .take(DETECT_FILES)
+        .filter_map(|file| std::fs::read_to_string(file).ok())
+        .find_map(|contents| leading_header(&contents, prefix))
+}
+
+/// The comment `contents` starts with, after any shebang line, if it mentions a license.
+fn leading_header(contents: &str, prefix: &str) -> Option<String> {
+    let mut lines = contents
+        .lines()
+        .skip_while(|line| line.starts_with("#!"))
+        .peekable();
+
+    let mut header = Vec::new();
+    if lines.peek()?.trim_start().starts_with("/*") {
+        for line in lines {
+            header.push(line);
+            if line.contains("*/") {
+                break;
+            }
+        }
+    } else {
+        header.extend(lines.take_while(|line| line.trim_start().starts_with(prefix)));
+    }
+
+    let header = header.join("\n");
+    let lower = header.to_lowercase();
+    MARKERS
+        .iter()
+        .any(|marker| lower.contains(marker))
+        .then_some(header)
+}
+
+/// `contents` with `header` on top, below a shebang line, unless it already starts with it.
+fn prepend(contents: &str, header: &str) -> String {
+    let (shebang, rest) = match contents.split_once('\n') {
+        Some((first, rest)) if first.starts_with("#!") => (Some(first), rest),
+        _ => (None, contents),
+    };
+    if rest
+        .trim_start()
+        .starts_with(header.lines().next().unwrap_or_default())
+    {
+        return contents.to_string();
+    }
+
+    match shebang {
+        Some(shebang) => format!("{}\n{}\n\n{}", shebang, header, rest),
+        None => format!("{}\n\n{}", header, rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_headers_are_commented() {
+        let header = header_for(Path::new("src/main.rs"), "Copyright Acme\n\nMIT").unwrap();
+        assert_eq!(header, "// Copyright Acme\n//\n// MIT");
+        assert_eq!(
+            prepend("#!/bin/sh\necho hi\n", "# MIT"),
+            "#!/bin/sh\n# MIT\n\necho hi\n"
+        );
+        assert_eq!(
+            prepend("// MIT\nfn main() {}", "// MIT"),
+            "// MIT\nfn main() {}"
+        );
+        assert!(header_for(Path::new("notes.md"), "MIT").is_none());
+    }
+
+    #[test]
+    fn finds_license_comments() {
+        let rust = "// SPDX-License-Identifier: MIT\n// Copyright Acme\n\nuse std::io;";
+        assert_eq!(
+            leading_header(rust, "//").unwrap(),
+            "// SPDX-License-Identifier: MIT\n// Copyright Acme"
+        );
+        let block = "/*\n * Licensed under the Apache License\n */\nint x;";
+        assert_eq!(
+            leading_header(block, "//").unwrap(),
+            "/*\n * Licensed under the Apache License\n */"
+        );
+        assert!(leading_header("// the parser\nfn parse() {}", "//").is_none());
+    }
+}
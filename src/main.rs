@@ -1,59 +1,1391 @@
+mod adr;
+mod backup;
+#[cfg(feature = "slack")]
+mod bridge;
+mod bundle;
+mod capture;
+mod cli;
+mod codeblocks;
+mod commands;
+mod complete;
+mod config;
+mod context;
 mod copilot;
+#[cfg(unix)]
+mod daemon;
+mod edits;
+mod feedback;
+mod filter;
 mod gh;
 mod headers;
+mod html;
+mod http;
+mod http_cache;
+mod import;
+mod infra;
+mod input;
+mod issue;
+mod license;
+mod memory;
+mod metrics;
+mod oauth;
+mod patch;
+mod postprocess;
 mod prompts;
+mod redact;
+mod refactor;
+mod repl;
+mod retrieval;
+mod search;
+mod secrets;
+mod sessions;
+mod shell_check;
+mod sinks;
+mod sse;
+mod subcommands;
+mod tasks;
+mod templates;
+mod term;
+mod timings;
+mod tokens;
+mod toolchain;
+mod tools;
 mod urls;
 mod utils;
-mod term;
-
-use crossterm::{
-    execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
-};
-use std::io::{stdout, Write};
+mod webhooks;
 
-use oxc_allocator;
-use rustyline::DefaultEditor;
+use commands::Command;
+use config::Config;
+use std::io::Write;
 
 fn move_up_one_line() {
+    if !term::Capabilities::detect().alternate_screen {
+        return;
+    }
     print!("\x1b[1A");
     std::io::stdout().flush().unwrap();
 }
 
+/// Runs `copilot daemon` until it fails, returning the process exit code.
+#[cfg(unix)]
+async fn run_daemon(auth_manager: &gh::AuthenticationManager, client: &reqwest::Client) -> i32 {
+    match daemon::run(auth_manager, client).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_daemon(_auth_manager: &gh::AuthenticationManager, _client: &reqwest::Client) -> i32 {
+    eprintln!("The daemon needs unix sockets and isn't supported on this platform");
+    2
+}
+
+/// Runs `copilot bridge slack` until it fails, returning the process exit code.
+#[cfg(feature = "slack")]
+async fn bridge_slack(auth_manager: &gh::AuthenticationManager, client: &reqwest::Client) -> i32 {
+    let Some(slack) = Config::load().slack else {
+        eprintln!(
+            "Add a \"slack\" section with \"app_token\" and \"bot_token\" to the config file"
+        );
+        return 2;
+    };
+    if !auth_manager.is_logged_in() {
+        eprintln!("Not logged in, run `copilot auth login` first");
+        return 1;
+    }
+
+    match bridge::slack(auth_manager, client, &slack).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "slack"))]
+async fn bridge_slack(_auth_manager: &gh::AuthenticationManager, _client: &reqwest::Client) -> i32 {
+    eprintln!("This build doesn't include the Slack bridge, rebuild with `--features slack`");
+    2
+}
+
+/// The sampling parameters the command line asks for, the config filling in the rest.
+fn sampling(
+    args: &cli::Args,
+    config: &Config,
+    output_context: term::OutputContext,
+) -> copilot::Sampling {
+    copilot::Sampling {
+        temperature: args.temperature.unwrap_or(config.temperature),
+        top_p: args.top_p.unwrap_or(config.top_p),
+        max_tokens: args
+            .max_tokens
+            .or(config.max_tokens)
+            .or(output_context.max_tokens(config)),
+        stop: if args.stop.is_empty() {
+            config.stop.clone()
+        } else {
+            args.stop.clone()
+        },
+        choices: args.choices.unwrap_or(1),
+    }
+}
+
+/// Runs `copilot ask "<question>"`: asks once without the chat or the alternate screen and
+/// prints the answer to stdout, highlighted only when that's a terminal. Whatever is piped
+/// in is attached to the question, up to `stdin_max_bytes`. Returns 0 for a complete answer,
+/// 1 when the login or the request failed, 2 for nothing to ask and 3 when the answer was
+/// cut off.
+async fn ask_once(
+    auth_manager: &gh::AuthenticationManager,
+    client: &reqwest::Client,
+    args: &cli::Args,
+    model: &str,
+    system_prompt: &str,
+    output_context: term::OutputContext,
+    question: &str,
+) -> i32 {
+    use std::io::IsTerminal;
+
+    let config = Config::load();
+    let assembling = std::time::Instant::now();
+    let piped = if std::io::stdin().is_terminal() {
+        None
+    } else {
+        match context::read_piped(std::io::stdin().lock(), config.stdin_max_bytes) {
+            Ok(attached) if attached.item.content.trim().is_empty() => None,
+            Ok(attached) => Some(attached),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 2;
+            }
+        }
+    };
+    let question = match (question.trim(), &piped) {
+        ("", None) => {
+            eprintln!("Usage: copilot ask <question>, or pipe something into it");
+            return 2;
+        }
+        ("", Some(_)) => prompts::EXPLAIN_INPUT,
+        (question, _) => question,
+    };
+    let context_time = assembling.elapsed();
+
+    let authenticating = std::time::Instant::now();
+    let auth = match auth_manager.cache_auth().await {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("Authentication failed: {}", e);
+            return 1;
+        }
+    };
+    let mut auth_time = Some(authenticating.elapsed());
+
+    let mut copilot_m = copilot::CopilotManager::new(&auth, client, system_prompt)
+        .with_endpoint(config.endpoint)
+        .with_glossary(&config.glossary)
+        .with_auth_manager(auth_manager)
+        .with_retries(&config)
+        .with_max_time(args.max_time)
+        .with_streaming(!args.no_stream)
+        .with_metrics(config.metrics.clone())
+        // there's nobody to pick another answer, so don't ask for any
+        .with_sampling(copilot::Sampling {
+            choices: 1,
+            ..sampling(args, &config, output_context)
+        });
+    for post in &args.post {
+        copilot_m = copilot_m.with_post_processor(post.processor());
+    }
+    copilot_m.set_model(model);
+    copilot_m.set_style(match output_context {
+        term::OutputContext::Tty => copilot::AnswerStyle::Default,
+        _ => copilot::AnswerStyle::Brief,
+    });
+    copilot_m.set_json_response(args.json_response);
+
+    // --filter and `--json-stream -` already write the answer to stdout
+    let to_stdout = args.filter.is_some() || args.json_stream.as_deref() == Some("-");
+    if !to_stdout {
+        if output_context == term::OutputContext::Tty {
+            copilot_m.subscribe(Box::new(sinks::Coalesce::new(
+                Box::new(term::TerminalSink::default()),
+                std::time::Duration::from_millis(config.render_interval_ms),
+            )));
+        } else {
+            copilot_m.subscribe(Box::new(sinks::StdoutSink::default()));
+        }
+    }
+    let webhooks = webhooks::Webhooks::from_config(client, &config);
+    if let Err(e) = subscribe_sinks(
+        &mut copilot_m,
+        &args.out,
+        &args.json_stream,
+        &args.filter,
+        &config,
+        &webhooks,
+    ) {
+        eprintln!("{}", e);
+        return 2;
+    }
+
+    if let Some(piped) = &piped {
+        if piped.dropped > 0 {
+            eprintln!(
+                "Warning: the input is over the {} byte limit (stdin_max_bytes), only the first {} lines were attached",
+                config.stdin_max_bytes, piped.lines
+            );
+        }
+        copilot_m.attach_context(&piped.item.label, &piped.item.lang, &piped.item.content);
+    }
+
+    let completion = repl::ask_and_save(
+        &mut copilot_m,
+        question,
+        args.timings,
+        &mut auth_time,
+        context_time,
+        None,
+    )
+    .await;
+    match completion.finish_reason.as_str() {
+        copilot::FINISH_ERROR => 1,
+        copilot::FINISH_MAX_TIME | copilot::FINISH_CONTENT_FILTER | copilot::FINISH_LENGTH => {
+            if output_context != term::OutputContext::Tty {
+                eprintln!("The answer was cut off ({})", completion.finish_reason);
+            }
+            3
+        }
+        _ if !completion.filtered.is_empty() => 3,
+        _ => 0,
+    }
+}
+
+/// Adds the `--out`, `--json-stream`, `--filter`, `notify_command` and webhook sinks.
+fn subscribe_sinks(
+    copilot_m: &mut copilot::CopilotManager,
+    out: &Option<String>,
+    json_stream: &Option<String>,
+    filter: &Option<filter::Filter>,
+    config: &Config,
+    webhooks: &webhooks::Webhooks,
+) -> Result<(), String> {
+    if let Some(path) = out {
+        let sink =
+            sinks::FileSink::create(path).map_err(|e| format!("Can't write {}: {}", path, e))?;
+        copilot_m.subscribe(Box::new(sink));
+    }
+    if let Some(path) = json_stream {
+        let sink = sinks::JsonStreamSink::create(path)
+            .map_err(|e| format!("Can't write {}: {}", path, e))?;
+        copilot_m.subscribe(Box::new(sink));
+    }
+    if let Some(filter) = filter {
+        copilot_m.subscribe(Box::new(sinks::FilterSink::new(filter.clone())));
+    }
+    if let Some(command) = &config.notify_command {
+        copilot_m.subscribe(Box::new(sinks::HookSink::new(command)));
+    }
+    if !config.webhooks.is_empty() {
+        copilot_m.subscribe(Box::new(webhooks::WebhookSink::new(webhooks.clone())));
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
-    // enter alternate screen
-    execute!(stdout(), EnterAlternateScreen).unwrap();
+    let mut args = match cli::Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let startup_config = Config::load();
+
+    let mut host = args.host.clone();
+    // replaced by `/system`, which starts a new conversation with it
+    let mut system_prompt = args
+        .system_prompt
+        .clone()
+        .unwrap_or(startup_config.system_prompt().to_string());
+    // kept across profile switches, unlike the conversation
+    let mut model = args
+        .model
+        .clone()
+        .unwrap_or(copilot::DEFAULT_MODEL.to_string());
+    // scripts reading the output get short answers unless they ask otherwise
+    let output_context = term::OutputContext::detect(args.brief);
+    let mut style = match output_context {
+        term::OutputContext::Tty => copilot::AnswerStyle::Default,
+        _ => copilot::AnswerStyle::Brief,
+    };
+    let mut json_response = args.json_response;
+    let mut profile = args
+        .profile
+        .clone()
+        .unwrap_or(startup_config.active_profile_name().to_string());
+
+    if startup_config.tls_insecure {
+        eprintln!("Warning: TLS certificate verification is disabled (tls_insecure)");
+    }
+
+    let client = match http::client(&startup_config) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-    let auth_manager = gh::AuthenticationManager::new();
-    let auth = auth_manager.cache_auth().await.unwrap();
+    let token = match (&args.token_cmd, args.token_fd) {
+        (Some(command), _) => Some(gh::token_from_command(command)),
+        (None, Some(fd)) => Some(gh::token_from_fd(fd)),
+        (None, None) => None,
+    };
+    let mut token = match token.transpose() {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-    let client = reqwest::Client::new();
+    let no_stream = args.no_stream;
+    if let Some(command) = args.command.take() {
+        let mut auth_manager = gh::AuthenticationManager::for_profile(&profile, client.clone());
+        if let Some(host) = &host {
+            auth_manager = auth_manager.with_host(host);
+        }
+        if let Some(token) = token {
+            auth_manager = auth_manager.with_token(token);
+        }
 
-    let allocator = oxc_allocator::Allocator::default();
+        let code = match command {
+            cli::Subcommand::Auth(command) => subcommands::auth(command, &auth_manager).await,
+            cli::Subcommand::Edits(command) => subcommands::edits(command),
+            cli::Subcommand::Backup(command) => subcommands::backup(command),
+            cli::Subcommand::Whoami => subcommands::whoami(&auth_manager).await,
+            cli::Subcommand::Models => subcommands::models(&auth_manager, &client).await,
+            cli::Subcommand::BridgeSlack => bridge_slack(&auth_manager, &client).await,
+            cli::Subcommand::Daemon => run_daemon(&auth_manager, &client).await,
+            cli::Subcommand::Adr { title } => {
+                match adr::run(&auth_manager, &client, &model, &title).await {
+                    Ok(path) => {
+                        println!("Wrote {}", path.display());
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                }
+            }
+            cli::Subcommand::Index => match retrieval::build(&auth_manager, &client).await {
+                Ok((files, chunks)) => {
+                    println!("Indexed {} chunks of {} files, questions now get the most relevant attached", chunks, files);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            },
+            cli::Subcommand::Refactor { instruction } => {
+                match refactor::run(&auth_manager, &client, &model, &instruction).await {
+                    Ok(changed) => {
+                        println!("Changed {} files, `copilot edits log` lists them", changed);
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                }
+            }
+            cli::Subcommand::Complete { file, line, col } => {
+                match complete::run(&auth_manager, &client, &file, line, col).await {
+                    Ok(completion) => {
+                        print!("{}", completion);
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        1
+                    }
+                }
+            }
+            cli::Subcommand::Export { path, format } => {
+                subcommands::export(args.session.as_deref(), path.as_deref(), format)
+            }
+            cli::Subcommand::HistorySearch { query } => subcommands::history_search(&query),
+            cli::Subcommand::Ask { question } => {
+                ask_once(
+                    &auth_manager,
+                    &client,
+                    &args,
+                    &model,
+                    &system_prompt,
+                    output_context,
+                    &question,
+                )
+                .await
+            }
+            cli::Subcommand::Run { template, args } => {
+                subcommands::run_template(
+                    &auth_manager,
+                    &client,
+                    &model,
+                    &system_prompt,
+                    !no_stream,
+                    &template,
+                    &args,
+                )
+                .await
+            }
+            cli::Subcommand::Feedback => {
+                subcommands::print_feedback_stats();
+                0
+            }
+            cli::Subcommand::Import { path, name } => match import::run(&path, name.as_deref()) {
+                Ok(sessions) => {
+                    for session in &sessions {
+                        println!(
+                            "Imported {} messages as session {}",
+                            session.messages.len(),
+                            session.id
+                        );
+                    }
+                    println!("Continue one with --session <name>");
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            },
+            cli::Subcommand::BundleLastRequest { path } => match bundle::write(path.as_deref()) {
+                Ok((path, summary)) => {
+                    if !summary.is_empty() {
+                        println!("Redacted {}", summary);
+                    }
+                    println!(
+                        "Wrote {}, check it before attaching it to an issue",
+                        path.display()
+                    );
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            },
+        };
+        std::process::exit(code);
+    }
 
-    let mut copilot_m = copilot::CopilotManager::new(&auth, &client, &allocator, prompts::COPILOT_INSTRUCTIONS);
+    // enter alternate screen
+    term::enter_alternate_screen();
+
+    let mut rl = input::new_editor();
+
+    // the conversation the next round of the session loop continues, a new one if `None`
+    let mut next_session = None;
+    if let Some(name) = &args.session {
+        match sessions::Session::named(name) {
+            Ok(session) => next_session = Some(session),
+            Err(e) => {
+                term::leave_alternate_screen();
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    } else if args.resume {
+        next_session = sessions::Session::latest();
+        if next_session.is_none() {
+            println!("No earlier conversation to resume, starting a new one");
+        }
+    }
 
-    let mut rl = DefaultEditor::new().unwrap();
+    'session: loop {
+        let mut auth_manager = gh::AuthenticationManager::for_profile(&profile, client.clone());
+        // --host and --token-cmd/--token-fd only apply to the profile selected on the command line
+        if let Some(host) = host.take() {
+            auth_manager = auth_manager.with_host(&host);
+        }
+        if let Some(token) = token.take() {
+            auth_manager = auth_manager.with_token(token);
+        }
+        let authenticating = std::time::Instant::now();
+        let auth = loop {
+            match auth_manager.cache_auth().await {
+                Ok(auth) => break auth,
+                Err(gh::AuthError::Unreachable(e)) => {
+                    println!("Cannot reach {}: {}", auth_manager.host(), e);
+                    let choice = input::choose(
+                        &mut rl,
+                        "What now?",
+                        &[('r', "etry"), ('o', "ffline mode"), ('q', "uit")],
+                    );
+                    let next = match choice {
+                        Some('r') => repl::Offline::Retry,
+                        Some('o') => repl::offline_mode(&mut rl, auth_manager.profile()),
+                        _ => repl::Offline::Exit,
+                    };
+                    match next {
+                        repl::Offline::Retry => continue,
+                        repl::Offline::SwitchProfile(name) => {
+                            profile = name;
+                            continue 'session;
+                        }
+                        repl::Offline::Exit => break 'session,
+                    }
+                }
+                Err(e) => {
+                    term::leave_alternate_screen();
+                    eprintln!("Authentication failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        };
+        let mut auth_time = Some(authenticating.elapsed());
+        let config = Config::load();
 
-    loop {
-        let input = rl.readline("You: ").unwrap();
+        println!(
+            "[{}] {} · profile {}",
+            auth.user.initials(),
+            auth.user.login,
+            auth_manager.profile()
+        );
 
-        move_up_one_line();
+        let mut copilot_m = copilot::CopilotManager::new(&auth, &client, &system_prompt)
+            .with_endpoint(config.endpoint)
+            .with_glossary(&config.glossary)
+            .cancel_on_ctrl_c()
+            .with_auth_manager(&auth_manager)
+            .with_retries(&config)
+            .with_history_budget(config.history_token_budget)
+            .with_history_limit(config.history_max_messages)
+            .with_max_time(args.max_time)
+            .with_streaming(!args.no_stream)
+            .with_tools(tools::Registry::from_config(&config))
+            .with_max_tool_rounds(config.max_tool_rounds)
+            .with_metrics(config.metrics.clone())
+            .with_sampling(sampling(&args, &config, output_context));
+        for post in &args.post {
+            copilot_m = copilot_m.with_post_processor(post.processor());
+        }
+        copilot_m.set_model(&model);
+        copilot_m.set_style(style);
+        copilot_m.set_json_response(json_response);
+        if config.memory {
+            match memory::load() {
+                Ok(memories) => {
+                    let facts: Vec<String> = memories.into_iter().map(|m| m.text).collect();
+                    copilot_m.set_memories(&facts);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+        if config.prewarm {
+            copilot_m.prewarm();
+        }
+        copilot_m.subscribe(Box::new(sinks::Coalesce::new(
+            Box::new(term::TerminalSink::default()),
+            std::time::Duration::from_millis(config.render_interval_ms),
+        )));
+        let webhooks = webhooks::Webhooks::from_config(&client, &config);
+        if let Err(e) = subscribe_sinks(
+            &mut copilot_m,
+            &args.out,
+            &args.json_stream,
+            &args.filter,
+            &config,
+            &webhooks,
+        ) {
+            term::leave_alternate_screen();
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
 
-        if input == "exit" {
-            break;
+        let mut last_question = String::new();
+        let mut last_answer = String::new();
+        let session = next_session.take();
+        if let Some(session) = &session {
+            if let Some(prompt) = &session.prompt {
+                copilot_m.set_prompt(prompt);
+            }
+            for (question, answer) in session.exchanges() {
+                copilot_m.replay(
+                    &question.content,
+                    &answer.content,
+                    answer.model.as_deref(),
+                    answer.usage,
+                );
+                last_question = question.content.clone();
+                last_answer = answer.content.clone();
+            }
+            match &session.name {
+                Some(name) => println!("[session {}, {} messages]", name, session.messages.len()),
+                None => println!(
+                    "[resumed the conversation from {}, {} messages]",
+                    utils::format_time(session.started),
+                    session.messages.len()
+                ),
+            }
         }
+        let mut session = session.unwrap_or_else(sessions::Session::new);
+        let workspace = match patch::Workspace::from_config(&config) {
+            Ok(workspace) => workspace.with_webhooks(webhooks.clone()),
+            Err(e) => {
+                term::leave_alternate_screen();
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        };
+        let index = retrieval::Index::load_current();
+
+        loop {
+            let mut mode = match copilot_m.style() {
+                copilot::AnswerStyle::Default => copilot_m.model().to_string(),
+                copilot::AnswerStyle::Brief => format!("{}, brief", copilot_m.model()),
+                copilot::AnswerStyle::Verbose => format!("{}, verbose", copilot_m.model()),
+            };
+            if let Some(name) = &session.name {
+                mode = format!("{} · {}", name, mode);
+            }
+            let prompt = match copilot_m.pending_context() {
+                0 => format!("You ({}): ", mode),
+                n => format!("You ({}) [+{} context]: ", mode, n),
+            };
+            let Ok(input) = rl.readline(&prompt) else {
+                break 'session;
+            };
+
+            move_up_one_line();
+
+            match Command::parse(&input) {
+                Some(Command::Exit) => break 'session,
+                Some(Command::Profile(None)) => {
+                    repl::print_profiles(auth_manager.profile());
+                    continue;
+                }
+                Some(Command::Profile(Some(name))) => {
+                    let mut config = Config::load();
+                    config.active_profile = Some(name.clone());
+                    config.save();
+
+                    println!("Switching to profile {}", name);
+                    profile = name;
+                    continue 'session;
+                }
+                Some(Command::Web(None)) => {
+                    println!("Usage: /web <query>");
+                    continue;
+                }
+                Some(Command::Web(Some(query))) => {
+                    let Some(provider) = &config.web_search else {
+                        println!("Web search is not configured, add a \"web_search\" provider to the config file");
+                        continue;
+                    };
+
+                    let assembling = std::time::Instant::now();
+                    let results = match search::search(&client, provider, &query).await {
+                        Ok(results) => results,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
 
-        let _msg = copilot_m.ask(&input, true).await;
-        // reset the forground color
-        print!("\033[0m");
-        // syntax highlighting
-        // let highlighted = term::highlight_text(&msg.content);
-        // println!("{}", highlighted);
+                    copilot_m.attach_context(
+                        "Web search results",
+                        "",
+                        &search::format_context(&results),
+                    );
+                    repl::select_context(&mut copilot_m, &config, &webhooks, &query);
+                    let context_time = assembling.elapsed();
+                    if !repl::confirm_large_request(&mut rl, &mut copilot_m, &config, &query) {
+                        continue;
+                    }
+                    let msg = repl::ask_and_save(
+                        &mut copilot_m,
+                        &query,
+                        args.timings,
+                        &mut auth_time,
+                        context_time,
+                        Some((&mut rl, &mut session)),
+                    )
+                    .await;
+                    last_question = query.clone();
+                    last_answer = msg.content.clone();
 
+                    if !results.is_empty() {
+                        println!("\n{}", search::format_citations(&msg.content, &results));
+                    }
+                    continue;
+                }
+                Some(Command::Edit) => {
+                    if last_question.is_empty() {
+                        println!("There is no question to edit yet");
+                        continue;
+                    }
+                    let edited = match input::prefilled(&mut rl, &prompt, &last_question) {
+                        Some(edited) if !edited.trim().is_empty() => edited,
+                        _ => continue,
+                    };
+
+                    let assembling = std::time::Instant::now();
+                    repl::retrieve_context(&mut copilot_m, &config, &index, &edited).await;
+                    repl::select_context(&mut copilot_m, &config, &webhooks, &edited);
+                    let context_time = assembling.elapsed();
+                    // the original exchange stays until the edited question is on its way
+                    if !repl::confirm_large_request(&mut rl, &mut copilot_m, &config, &edited) {
+                        continue;
+                    }
+                    if !copilot_m.forget_last_exchange() {
+                        println!(
+                            "The last question has no answer to replace, ask it again instead"
+                        );
+                        continue;
+                    }
+                    let msg = repl::ask_and_save(
+                        &mut copilot_m,
+                        &edited,
+                        args.timings,
+                        &mut auth_time,
+                        context_time,
+                        Some((&mut rl, &mut session)),
+                    )
+                    .await;
+                    last_question = edited;
+                    last_answer = msg.content;
+                    continue;
+                }
+                Some(Command::Regenerate(arg)) => {
+                    let temperature = match arg.as_deref().map(str::parse::<f64>) {
+                        None => None,
+                        Some(Ok(t)) if (0.0..=2.0).contains(&t) => Some(t),
+                        Some(_) => {
+                            println!("Usage: /regenerate [temperature], from 0 to 2");
+                            continue;
+                        }
+                    };
+                    repl::print_model_badge(&copilot_m);
+                    match copilot_m.regenerate(temperature).await {
+                        Some(msg)
+                            if [copilot::FINISH_ERROR, copilot::FINISH_CANCELLED]
+                                .contains(&msg.finish_reason.as_str()) =>
+                        {
+                            println!("Kept the previous answer");
+                        }
+                        Some(msg) => {
+                            repl::print_timings(
+                                args.timings,
+                                &mut auth_time,
+                                std::time::Duration::ZERO,
+                                &msg,
+                            );
+                            last_answer = msg.content;
+                            repl::save_session(&mut session, &copilot_m);
+                        }
+                        None => println!("There is no answer to regenerate yet"),
+                    }
+                    continue;
+                }
+                Some(Command::Apply(arg)) => {
+                    let written = repl::apply_code_block(
+                        &mut rl,
+                        &workspace,
+                        &config,
+                        &last_answer,
+                        arg.as_deref(),
+                    );
+                    if !written.is_empty() {
+                        for path in &written {
+                            session.record_generated(path, copilot_m.model());
+                        }
+                        repl::save_session(&mut session, &copilot_m);
+                    }
+                    continue;
+                }
+                Some(Command::Open(arg)) => {
+                    repl::open_code_block(
+                        &mut rl,
+                        &mut copilot_m,
+                        &config,
+                        &last_answer,
+                        arg.as_deref(),
+                    );
+                    continue;
+                }
+                Some(Command::Session(arg)) => {
+                    if let Some(switch_to) = repl::session_command(
+                        &mut copilot_m,
+                        &mut session,
+                        &system_prompt,
+                        arg.as_deref(),
+                    ) {
+                        next_session = Some(switch_to);
+                        continue 'session;
+                    }
+                    continue;
+                }
+                Some(Command::Fork(arg)) => {
+                    if let Some(branch) = repl::fork_command(&session, arg.as_deref()) {
+                        next_session = Some(branch);
+                        continue 'session;
+                    }
+                    continue;
+                }
+                Some(Command::Search(None)) => {
+                    println!("Usage: /search <text>, e.g. /search device flow");
+                    continue;
+                }
+                Some(Command::Search(Some(query))) => {
+                    if let Some(found) = repl::search_command(&mut rl, &session, &query) {
+                        next_session = Some(found);
+                        continue 'session;
+                    }
+                    continue;
+                }
+                Some(Command::Scratch) => {
+                    let path = session.scratch_path();
+                    let created = std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| {
+                        std::fs::OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(&path)
+                    });
+                    match created {
+                        Ok(_) => match utils::open_in_editor(config.editor.as_deref(), &path) {
+                            Ok(()) => {
+                                println!("[saved the scratchpad, mention @scratch to attach it]")
+                            }
+                            Err(e) => println!("{}", e),
+                        },
+                        Err(e) => println!("Failed to create {}: {}", path.display(), e),
+                    }
+                    continue;
+                }
+                Some(Command::UndoEdit) => {
+                    let result = match workspace.undo(false) {
+                        Err(e @ patch::PatchError::ChangedSince { .. }) => {
+                            if input::confirm(&mut rl, &format!("{}, undo anyway?", e), false) {
+                                workspace.undo(true)
+                            } else {
+                                continue;
+                            }
+                        }
+                        result => result,
+                    };
+                    match result {
+                        Ok(edit) if edit.before.is_some() => println!("Restored {}", edit.path),
+                        Ok(edit) => println!("Removed {}, the edit created it", edit.path),
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::Retry) => {
+                    println!("Already connected");
+                    continue;
+                }
+                Some(Command::Rate(rating, reason)) => {
+                    if last_answer.is_empty() {
+                        println!("There is no answer to rate yet");
+                        continue;
+                    }
+                    let entry = feedback::Feedback {
+                        time: chrono::Utc::now().timestamp(),
+                        rating,
+                        reason,
+                        model: copilot_m.model().to_string(),
+                        profile: auth_manager.profile().to_string(),
+                        question: last_question.clone(),
+                        answer: last_answer.clone(),
+                    };
+                    match feedback::record(&entry) {
+                        Ok(()) => println!("Thanks, feedback saved"),
+                        Err(e) => println!("Failed to save the feedback: {}", e),
+                    }
+                    if let Some(url) = &config.feedback_webhook {
+                        if let Err(e) = feedback::send_webhook(&client, url, &entry).await {
+                            println!("Failed to send the feedback to the webhook: {}", e);
+                        }
+                    }
+                    continue;
+                }
+                Some(Command::Feedback) => {
+                    subcommands::print_feedback_stats();
+                    continue;
+                }
+                Some(command @ (Command::Remember(_) | Command::Memories(_))) => {
+                    repl::memories_command(&mut rl, &config, &mut copilot_m, command).await;
+                    continue;
+                }
+                Some(Command::Tasks(arg)) => {
+                    repl::tasks_command(
+                        &mut rl,
+                        &workspace,
+                        &mut copilot_m,
+                        &mut session,
+                        &last_answer,
+                        arg.as_deref(),
+                    )
+                    .await;
+                    continue;
+                }
+                Some(Command::Template(None)) => {
+                    match templates::list().as_slice() {
+                        [] => println!(
+                            "No templates yet, add them as <name>.md to {}",
+                            templates::templates_dir().display()
+                        ),
+                        names => println!("Templates: {}", names.join(", ")),
+                    }
+                    continue;
+                }
+                Some(Command::Template(Some(arg))) => {
+                    let (name, args) = arg.split_once(char::is_whitespace).unwrap_or((&arg, ""));
+                    let prompt = templates::load(name).and_then(|template| {
+                        templates::render(
+                            &template,
+                            args,
+                            templates::read_file,
+                            utils::read_clipboard,
+                        )
+                    });
+                    let prompt = match prompt {
+                        Ok(prompt) => prompt,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+
+                    let assembling = std::time::Instant::now();
+                    repl::select_context(&mut copilot_m, &config, &webhooks, &prompt);
+                    let context_time = assembling.elapsed();
+                    if !repl::confirm_large_request(&mut rl, &mut copilot_m, &config, &prompt) {
+                        continue;
+                    }
+                    let msg = repl::ask_and_save(
+                        &mut copilot_m,
+                        &prompt,
+                        args.timings,
+                        &mut auth_time,
+                        context_time,
+                        Some((&mut rl, &mut session)),
+                    )
+                    .await;
+                    last_question = prompt;
+                    last_answer = msg.content;
+                    continue;
+                }
+                Some(Command::ToIssue(repo)) => {
+                    repl::to_issue(&mut rl, &mut copilot_m, &auth_manager, &auth, repo).await;
+                    continue;
+                }
+                Some(Command::Whoami) => {
+                    subcommands::print_whoami(&auth);
+                    continue;
+                }
+                Some(Command::Check(arg)) => {
+                    let tool = match arg.as_deref() {
+                        Some(name) => toolchain::Tool::from_name(name),
+                        None => toolchain::Tool::detect(std::path::Path::new(".")),
+                    };
+                    let Some(tool) = tool else {
+                        let names: Vec<&str> =
+                            toolchain::Tool::ALL.iter().map(|t| t.name()).collect();
+                        println!(
+                            "Usage: /check [{}], no project detected here",
+                            names.join("|")
+                        );
+                        continue;
+                    };
+
+                    println!("Running {}...", tool.name());
+                    match toolchain::run(tool) {
+                        Ok(summary) => {
+                            let text = summary.render();
+                            println!("{}", text);
+                            copilot_m.attach_context(
+                                &format!("Result of {}", tool.name()),
+                                "text",
+                                &text,
+                            );
+                            println!("[attached the {} result, ask your question]", tool.name());
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::File(None)) => {
+                    println!("Usage: /file <path>, e.g. /file src/copilot.rs");
+                    continue;
+                }
+                Some(Command::File(Some(path))) => {
+                    match context::read_file(std::path::Path::new(&path), config.file_max_bytes) {
+                        Ok(file) => {
+                            copilot_m.attach_context(
+                                &file.item.label,
+                                &file.item.lang,
+                                &file.item.content,
+                            );
+                            if file.dropped > 0 {
+                                println!("Warning: {} is over the {} byte limit (file_max_bytes), only the first {} lines were attached", path, config.file_max_bytes, file.lines);
+                            }
+                            println!(
+                                "[attached {} ({} lines), ask your question]",
+                                path, file.lines
+                            );
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::Capture(None)) => {
+                    println!(
+                        "Usage: /capture [duration] <command>, e.g. /capture 30s journalctl -f"
+                    );
+                    continue;
+                }
+                Some(Command::Capture(Some(arg))) => {
+                    let (duration, command) = match arg.split_once(char::is_whitespace) {
+                        Some((first, rest)) => match cli::parse_duration(first) {
+                            Some(duration) => (duration, rest.trim()),
+                            None => (std::time::Duration::from_secs(10), arg.as_str()),
+                        },
+                        None => (std::time::Duration::from_secs(10), arg.as_str()),
+                    };
+                    let question = format!(
+                        "Run `{}` for {:?} and attach its output?",
+                        command, duration
+                    );
+                    if !input::confirm(&mut rl, &question, false) {
+                        continue;
+                    }
+
+                    println!("Capturing for {:?}, Ctrl+C stops early...", duration);
+                    match capture::capture(command, duration, config.capture_max_bytes).await {
+                        Ok(captured) if captured.output.trim().is_empty() => {
+                            println!("{} printed nothing", command)
+                        }
+                        Ok(captured) => {
+                            let title = format!("Output of `{}`", command);
+                            copilot_m.attach_context(&title, "text", &captured.output);
+                            let mut note = format!("{} lines", captured.output.lines().count());
+                            if captured.dropped > 0 {
+                                note.push_str(&format!(
+                                    ", the first {} bytes dropped",
+                                    captured.dropped
+                                ));
+                            }
+                            if captured.exited {
+                                note.push_str(", it exited on its own");
+                            }
+                            println!(
+                                "[attached the output of {} ({}), ask your question]",
+                                command, note
+                            );
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::Infra(helper, arg)) => {
+                    if !config.infra_helpers.contains(&helper) {
+                        println!("/{} is off, add \"{}\" to \"infra_helpers\" in the config file to enable it", helper.name(), helper.name());
+                        continue;
+                    }
+                    let line = match helper.command_line(arg.as_deref().unwrap_or("")) {
+                        Ok(line) => line,
+                        Err(e) => {
+                            println!("{}", e);
+                            continue;
+                        }
+                    };
+
+                    match infra::run(&line) {
+                        Ok(output) => {
+                            let output = tokens::truncate(&output, config.context_budget);
+                            println!("{}", output.trim_end());
+                            copilot_m.attach_context(
+                                &format!("Output of `{}`", line.join(" ")),
+                                "text",
+                                &output,
+                            );
+                            println!(
+                                "[attached the output of {}, ask your question]",
+                                line.join(" ")
+                            );
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::Models) => {
+                    match copilot_m.models().await {
+                        Ok(models) => subcommands::print_models(&models, Some(copilot_m.model())),
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Some(Command::System(None)) => {
+                    println!("{}", copilot_m.prompt());
+                    continue;
+                }
+                Some(Command::System(Some(prompt))) => {
+                    system_prompt = if prompt == "reset" {
+                        config.system_prompt().to_string()
+                    } else {
+                        prompt
+                    };
+                    println!("Replaced the system prompt, starting a new conversation");
+                    continue 'session;
+                }
+                Some(Command::Model(None)) => {
+                    println!("Model: {}", copilot_m.model());
+                    continue;
+                }
+                Some(Command::Model(Some(name))) => {
+                    copilot_m.set_model(&name);
+                    model = name;
+                    println!("Switched to {}", model);
+                    continue;
+                }
+                Some(command @ (Command::Brief | Command::Verbose)) => {
+                    let wanted = match command {
+                        Command::Brief => copilot::AnswerStyle::Brief,
+                        _ => copilot::AnswerStyle::Verbose,
+                    };
+                    style = if style == wanted {
+                        copilot::AnswerStyle::Default
+                    } else {
+                        wanted
+                    };
+                    copilot_m.set_style(style);
+                    match style {
+                        copilot::AnswerStyle::Default => {
+                            println!("Back to the default answer length")
+                        }
+                        copilot::AnswerStyle::Brief => {
+                            println!("Answers will be brief, /brief again to undo")
+                        }
+                        copilot::AnswerStyle::Verbose => {
+                            println!("Answers will be detailed, /verbose again to undo")
+                        }
+                    }
+                    continue;
+                }
+                Some(Command::Json) => {
+                    json_response = !json_response;
+                    copilot_m.set_json_response(json_response);
+                    if json_response {
+                        println!("Answers will be JSON objects, /json again to undo");
+                    } else {
+                        println!("Back to prose answers");
+                    }
+                    continue;
+                }
+                Some(Command::Export(None)) => {
+                    println!("Usage: /export [html] <file> [redaction profile]");
+                    continue;
+                }
+                Some(Command::Export(Some(arg))) => {
+                    repl::export_transcript(
+                        &mut rl,
+                        &workspace,
+                        &config,
+                        &copilot_m,
+                        &auth.user.login,
+                        &arg,
+                    );
+                    continue;
+                }
+                Some(Command::Cache(None)) => {
+                    let (count, size) = http_cache::stats();
+                    println!("{} cached responses ({} KB)", count, size / 1024);
+                    continue;
+                }
+                Some(Command::Cache(Some(arg))) => {
+                    match arg.as_str() {
+                        "clear" => match http_cache::clear() {
+                            Ok(()) => println!("Cleared the HTTP cache"),
+                            Err(e) => println!("Failed to clear the HTTP cache: {}", e),
+                        },
+                        _ => println!("Usage: /cache [clear]"),
+                    }
+                    continue;
+                }
+                Some(Command::Usage) => {
+                    let (last, total) = copilot_m.usage();
+                    let describe = |usage: copilot::Usage| {
+                        format!(
+                            "{} prompt + {} completion = {} tokens{}",
+                            usage.prompt_tokens,
+                            usage.completion_tokens,
+                            usage.total(),
+                            if usage.estimated { " (estimated)" } else { "" }
+                        )
+                    };
+                    match last {
+                        Some(usage) => println!("Last answer: {}", describe(usage)),
+                        None => println!("Nothing answered yet"),
+                    }
+                    println!("Session:     {}", describe(total));
+                    continue;
+                }
+                Some(Command::Help) => {
+                    let sampling = copilot_m.sampling();
+                    let prompt = copilot_m.prompt();
+                    let persona = if prompt == prompts::COPILOT_INSTRUCTIONS {
+                        "default".to_string()
+                    } else {
+                        let first = prompt.lines().next().unwrap_or_default();
+                        match first.char_indices().nth(60) {
+                            Some((end, _)) => format!("{}...", &first[..end]),
+                            None => first.to_string(),
+                        }
+                    };
+                    let settings = [
+                        ("Model", copilot_m.model().to_string()),
+                        ("Temperature", sampling.temperature.to_string()),
+                        ("Top p", sampling.top_p.to_string()),
+                        (
+                            "Max tokens",
+                            sampling
+                                .max_tokens
+                                .map_or("model default".to_string(), |n| n.to_string()),
+                        ),
+                        (
+                            "Stop sequences",
+                            if sampling.stop.is_empty() {
+                                "none".to_string()
+                            } else {
+                                format!("{:?}", sampling.stop)
+                            },
+                        ),
+                        ("Answers", format!("{:?}", copilot_m.style()).to_lowercase()),
+                        (
+                            "JSON answers",
+                            if copilot_m.json_response() {
+                                "on"
+                            } else {
+                                "off"
+                            }
+                            .to_string(),
+                        ),
+                        ("System prompt", persona),
+                        (
+                            "Session",
+                            session.name.clone().unwrap_or("default".to_string()),
+                        ),
+                        ("Attached context", copilot_m.pending_context().to_string()),
+                    ];
+                    print!("{}", commands::help(&settings));
+                    continue;
+                }
+                Some(Command::Unknown(name)) => {
+                    println!("Unknown command: /{}, /help lists them", name);
+                    continue;
+                }
+                None => {}
+            }
+
+            if input::is_paste(&input) {
+                let kind = input::classify_paste(&input);
+                if kind != input::PasteKind::Text {
+                    let lines = input.trim().lines().count();
+                    let (what, lang) = match kind {
+                        input::PasteKind::Logs => ("logs", "text"),
+                        _ => ("code", ""),
+                    };
+
+                    if input::confirm(
+                        &mut rl,
+                        &format!("Pasted {} lines of {}, attach as context?", lines, what),
+                        true,
+                    ) {
+                        input::clear_echoed_lines(lines + 1);
+                        copilot_m.attach_context(&format!("Pasted {}", what), lang, &input);
+                        println!("[attached {} lines of {}, ask your question]", lines, what);
+                        continue;
+                    }
+                }
+            }
+
+            let prompt_tokens = tokens::estimate(&input);
+            if prompt_tokens > config.prompt_token_limit {
+                webhooks.fire(
+                    config::WebhookEvent::BudgetExceeded,
+                    serde_json::json!({
+                        "kind": "prompt_token_limit",
+                        "tokens": prompt_tokens,
+                        "limit": config.prompt_token_limit,
+                    }),
+                );
+                println!(
+                    "This prompt is ~{} tokens, {:.0}% of the {} token context window.",
+                    prompt_tokens,
+                    prompt_tokens as f64 / config.context_window as f64 * 100.0,
+                    config.context_window
+                );
+
+                match input::choose(
+                    &mut rl,
+                    "Send it?",
+                    &[('s', "end"), ('a', "ttach truncated"), ('c', "ancel")],
+                ) {
+                    Some('s') => {}
+                    Some('a') => {
+                        let truncated = tokens::truncate(&input, config.prompt_token_limit);
+                        copilot_m.attach_context("Long input (truncated)", "", &truncated);
+                        println!(
+                            "[attached ~{} tokens, ask your question]",
+                            tokens::estimate(&truncated)
+                        );
+                        continue;
+                    }
+                    _ => continue,
+                }
+            }
+
+            let assembling = std::time::Instant::now();
+            repl::attach_scratch(&mut copilot_m, &session, &input);
+            repl::retrieve_context(&mut copilot_m, &config, &index, &input).await;
+            repl::select_context(&mut copilot_m, &config, &webhooks, &input);
+            let context_time = assembling.elapsed();
+            if !repl::confirm_large_request(&mut rl, &mut copilot_m, &config, &input) {
+                continue;
+            }
+            let msg = repl::ask_and_save(
+                &mut copilot_m,
+                &input,
+                args.timings,
+                &mut auth_time,
+                context_time,
+                Some((&mut rl, &mut session)),
+            )
+            .await;
+            for warning in shell_check::warnings(&msg.content, config.shell_check).await {
+                println!("[warning: {}]", warning);
+            }
+            last_question = input;
+            last_answer = msg.content;
+            // syntax highlighting
+            // let highlighted = term::highlight_text(&msg.content);
+            // println!("{}", highlighted);
+        }
     }
 
     // leave alternate screen
-    execute!(stdout(), LeaveAlternateScreen).unwrap();
+    term::leave_alternate_screen();
 }
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// A fact about the user kept across sessions, e.g. "prefers tabs" or "works on the
+/// billing service".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Memory {
+    pub text: String,
+    pub time: i64,
+    /// `remember` for `/remember`, `extract` for what `/memories extract` found.
+    pub source: String,
+}
+
+fn memories_path() -> PathBuf {
+    PathBuf::from(utils::get_config_path()).join("memories.json")
+}
+
+/// Every stored memory, oldest first. A file that can't be read or parsed is an error
+/// rather than no memories, so saving doesn't overwrite it.
+pub fn load() -> Result<Vec<Memory>, String> {
+    let path = memories_path();
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&text).map_err(|e| {
+        format!(
+            "Failed to parse {}, fix or remove it: {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+pub fn save(memories: &[Memory]) -> Result<(), String> {
+    let path = memories_path();
+    let write = || -> std::io::Result<()> {
+        std::fs::create_dir_all(utils::get_config_path())?;
+        std::fs::write(&path, serde_json::to_string_pretty(memories)?)
+    };
+    write().map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Stores `text` unless the same fact is already there. Returns false if it was.
+pub fn add(memories: &mut Vec<Memory>, text: &str, source: &str) -> bool {
+    let text = text.trim();
+    if memories.iter().any(|m| m.text.eq_ignore_ascii_case(text)) {
+        return false;
+    }
+    memories.push(Memory {
+        text: text.to_string(),
+        time: chrono::Utc::now().timestamp(),
+        source: source.to_string(),
+    });
+    true
+}
+
+/// The facts in the model's reply to `prompts::MEMORY_PROMPT`, one per `- ` line.
+pub fn parse_extracted(reply: &str) -> Vec<String> {
+    reply
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("- "))
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_each_fact_once() {
+        let mut memories = Vec::new();
+        assert!(add(&mut memories, "Prefers Rust 2021 ", "remember"));
+        assert!(!add(&mut memories, "prefers rust 2021", "extract"));
+        assert_eq!(memories[0].text, "Prefers Rust 2021");
+
+        let reply = "Here is what I noted:\n- Works on the billing service\n-  \n- Uses tabs";
+        assert_eq!(
+            parse_extracted(reply),
+            ["Works on the billing service", "Uses tabs"]
+        );
+    }
+}
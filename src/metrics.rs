@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, fmt::Write, net::UdpSocket, sync::Mutex, time::Duration};
+
+use crate::config::MetricsExporter;
+
+/// Upper bounds of the latency histogram, in seconds.
+const BUCKETS: [f64; 7] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// What this process has counted so far, for the textfile exporter which rewrites the whole
+/// file every time.
+struct State {
+    requests: BTreeMap<(String, String), u64>,
+    /// Requests at or below each of `BUCKETS`, the last one being `+Inf`.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl State {
+    const fn new() -> State {
+        State {
+            requests: BTreeMap::new(),
+            buckets: [0; BUCKETS.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, model: &str, outcome: &str, latency: Duration) {
+        *self
+            .requests
+            .entry((model.to_string(), outcome.to_string()))
+            .or_default() += 1;
+
+        let seconds = latency.as_secs_f64();
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.buckets[BUCKETS.len()] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// The Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP copilot_requests_total Chat requests by model and outcome.\n");
+        out.push_str("# TYPE copilot_requests_total counter\n");
+        for ((model, outcome), count) in &self.requests {
+            let _ = writeln!(
+                out,
+                "copilot_requests_total{{model=\"{}\",outcome=\"{}\"}} {}",
+                label(model),
+                outcome,
+                count
+            );
+        }
+
+        out.push_str("# HELP copilot_request_duration_seconds Time from sending a question to the end of its answer.\n");
+        out.push_str("# TYPE copilot_request_duration_seconds histogram\n");
+        let bounds = BUCKETS
+            .iter()
+            .map(|le| le.to_string())
+            .chain(["+Inf".to_string()]);
+        for (le, count) in bounds.zip(self.buckets) {
+            let _ = writeln!(
+                out,
+                "copilot_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, count
+            );
+        }
+        let _ = writeln!(out, "copilot_request_duration_seconds_sum {}", self.sum);
+        let _ = writeln!(out, "copilot_request_duration_seconds_count {}", self.count);
+        out
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State::new());
+
+/// Escapes a Prometheus label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Reports one answered (or failed) request. `outcome` is `ok`, `error`, `cancelled` or
+/// `timeout`. Exporting is best effort, a full disk or an unreachable statsd never fails
+/// the question.
+pub fn record(exporter: &MetricsExporter, model: &str, outcome: &str, latency: Duration) {
+    match exporter {
+        MetricsExporter::Textfile { path } => {
+            let text = {
+                let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+                state.add(model, outcome, latency);
+                state.render()
+            };
+            // node_exporter may read the file at any time, so it's replaced in one step
+            let partial = format!("{}.partial", path);
+            if std::fs::write(&partial, text).is_ok() {
+                let _ = std::fs::rename(&partial, path);
+            }
+        }
+        MetricsExporter::Statsd { address, prefix } => {
+            let lines = [
+                format!("{}.requests.{}:1|c", prefix, outcome),
+                format!("{}.request_duration:{}|ms", prefix, latency.as_millis()),
+            ];
+            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
+                let _ = socket.send_to(lines.join("\n").as_bytes(), address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_histogram() {
+        let mut state = State::new();
+        state.add("gpt-4", "ok", Duration::from_millis(800));
+        state.add("gpt-4", "ok", Duration::from_secs(3));
+        state.add("gpt-4o", "error", Duration::from_secs(90));
+
+        let text = state.render();
+        assert!(text.contains("copilot_requests_total{model=\"gpt-4\",outcome=\"ok\"} 2\n"));
+        assert!(text.contains("copilot_requests_total{model=\"gpt-4o\",outcome=\"error\"} 1\n"));
+        assert!(text.contains("copilot_request_duration_seconds_bucket{le=\"0.5\"} 0\n"));
+        assert!(text.contains("copilot_request_duration_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(text.contains("copilot_request_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("copilot_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("copilot_request_duration_seconds_count 3\n"));
+    }
+}
@@ -0,0 +1,107 @@
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use crate::{gh::AuthError, html};
+
+const CALLBACK_PATH: &str = "/callback";
+
+/// A temporary HTTP listener on localhost that receives the OAuth redirect.
+pub struct RedirectListener {
+    listener: TcpListener,
+}
+
+impl RedirectListener {
+    pub async fn bind() -> Result<RedirectListener, AuthError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| AuthError::Callback(e.to_string()))?;
+
+        Ok(RedirectListener { listener })
+    }
+
+    /// The `redirect_uri` to register the authorization request with.
+    pub fn redirect_uri(&self) -> String {
+        let port = self.listener.local_addr().map(|a| a.port()).unwrap_or(0);
+        format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH)
+    }
+
+    /// Waits for the browser to be redirected back and returns the authorization code.
+    /// Redirects whose `state` isn't the one the request was started with are turned away,
+    /// anything on the machine can send those.
+    pub async fn wait_for_code(&self, state: &str) -> Result<String, AuthError> {
+        loop {
+            let (mut stream, _) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|e| AuthError::Callback(e.to_string()))?;
+
+            let mut request_line = String::new();
+            BufReader::new(&mut stream)
+                .read_line(&mut request_line)
+                .await
+                .map_err(|e| AuthError::Callback(e.to_string()))?;
+
+            // GET /callback?code=...&state=... HTTP/1.1
+            let target = request_line.split_whitespace().nth(1).unwrap_or("");
+            let Ok(url) = reqwest::Url::parse(&format!("http://localhost{}", target)) else {
+                continue;
+            };
+            if url.path() != CALLBACK_PATH {
+                // browsers also ask for /favicon.ico
+                let _ = stream
+                    .write_all(response(404, "Not found").as_bytes())
+                    .await;
+                continue;
+            }
+
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_string())
+            };
+
+            if param("state").as_deref() != Some(state) {
+                let _ = stream
+                    .write_all(response(400, "State mismatch in the redirect").as_bytes())
+                    .await;
+                continue;
+            }
+
+            let result = if let Some(error) = param("error") {
+                Err(AuthError::Callback(
+                    param("error_description").unwrap_or(error),
+                ))
+            } else {
+                param("code").ok_or(AuthError::Callback("No code in the redirect".to_string()))
+            };
+
+            let page = match &result {
+                Ok(_) => response(
+                    200,
+                    "Logged in, you can close this tab and return to the terminal.",
+                ),
+                Err(e) => response(400, &e.to_string()),
+            };
+            let _ = stream.write_all(page.as_bytes()).await;
+
+            return result;
+        }
+    }
+}
+
+fn response(status: u16, message: &str) -> String {
+    let body = format!(
+        "<!doctype html><html><body><p>{}</p></body></html>",
+        html::escape(message)
+    );
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        if status == 200 { "OK" } else { "Error" },
+        body.len(),
+        body
+    )
+}
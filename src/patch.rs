@@ -0,0 +1,776 @@
+use std::path::{Component, Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{
+    config::Config,
+    config::{SecretScan, WebhookEvent},
+    edits::{self, Edit},
+    input::{self, LineEditor},
+    secrets, term, utils,
+    webhooks::Webhooks,
+};
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// The path matches one of the `protect` globs from the config.
+    Protected {
+        path: String,
+        pattern: String,
+    },
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    /// The snapshot of the file couldn't be saved, so it was left alone.
+    Backup {
+        path: String,
+        error: std::io::Error,
+    },
+    /// The new contents add what looks like a credential, e.g. one the model made up.
+    Secret {
+        path: String,
+        findings: Vec<String>,
+    },
+    NothingToUndo,
+    /// The file was modified after the edit that's being undone.
+    ChangedSince {
+        path: String,
+    },
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::Protected { path, pattern } => write!(
+                f,
+                "Refusing to modify {}: it is write-protected by \"{}\" in the config",
+                path, pattern
+            ),
+            PatchError::Io { path, error } => write!(f, "Failed to write {}: {}", path, error),
+            PatchError::Backup { path, error } => write!(
+                f,
+                "Failed to back up {}, leaving it unchanged: {}",
+                path, error
+            ),
+            PatchError::Secret { path, findings } => write!(
+                f,
+                "Refusing to write {}: it would add {}. Take it out, or set \"secret_scan\": \"warn\" in the config if it belongs there",
+                path,
+                findings.join(", ")
+            ),
+            PatchError::NothingToUndo => write!(f, "There are no edits to undo"),
+            PatchError::ChangedSince { path } => {
+                write!(f, "{} has changed since it was edited", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Every file the crate writes on behalf of the model goes through here, so checks like
+/// write protection can't be skipped by a new feature.
+pub struct Workspace {
+    root: PathBuf,
+    patterns: Vec<String>,
+    protected: GlobSet,
+    secret_scan: SecretScan,
+    webhooks: Option<Webhooks>,
+}
+
+impl Workspace {
+    /// A workspace rooted at the current directory, protected by the `protect` globs.
+    pub fn from_config(config: &Config) -> Result<Workspace, String> {
+        let root = std::env::current_dir().map_err(|e| e.to_string())?;
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in &config.protect {
+            // like .gitignore, a pattern without a slash matches at any depth
+            let pattern = if pattern.contains('/') {
+                pattern.trim_start_matches('/').to_string()
+            } else {
+                format!("**/{}", pattern)
+            };
+            let glob = Glob::new(&pattern)
+                .map_err(|e| format!("Invalid protect pattern {}: {}", pattern, e))?;
+            builder.add(glob);
+        }
+
+        Ok(Workspace {
+            root,
+            patterns: config.protect.clone(),
+            protected: builder.build().map_err(|e| e.to_string())?,
+            secret_scan: config.secret_scan,
+            webhooks: None,
+        })
+    }
+
+    /// Reports every write and undo as an `edit_applied` event.
+    pub fn with_webhooks(mut self, webhooks: Webhooks) -> Workspace {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    fn notify(&self, path: &Path, action: &str) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.fire(
+                WebhookEvent::EditApplied,
+                serde_json::json!({
+                    "path": self.relative(path).display().to_string(),
+                    "action": action,
+                }),
+            );
+        }
+    }
+
+    /// The path relative to the workspace root, which is what the globs match against.
+    fn relative(&self, path: &Path) -> PathBuf {
+        let absolute = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.root.join(path)
+        };
+
+        // resolve `..` without touching the filesystem, the file may not exist yet
+        let mut normalized = PathBuf::new();
+        for component in absolute.components() {
+            match component {
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::CurDir => {}
+                other => normalized.push(other),
+            }
+        }
+
+        normalized
+            .strip_prefix(&self.root)
+            .map(|p| p.to_path_buf())
+            .unwrap_or(normalized)
+    }
+
+    /// Fails with `PatchError::Protected` if `path` may not be modified.
+    pub fn check(&self, path: &Path) -> Result<(), PatchError> {
+        let relative = self.relative(path);
+
+        if let Some(index) = self.protected.matches(&relative).first() {
+            return Err(PatchError::Protected {
+                path: relative.display().to_string(),
+                pattern: self.patterns[*index].clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes `contents` to `path`, creating parent directories as needed. The previous
+    /// contents are snapshotted first so `undo` can restore them. Contents that add what
+    /// looks like a secret are refused or warned about, see `secret_scan`.
+    pub fn write(&self, path: &Path, contents: &str) -> Result<(), PatchError> {
+        self.check(path)?;
+
+        let io_error = |error| PatchError::Io {
+            path: path.display().to_string(),
+            error,
+        };
+        let backup_error = |error| PatchError::Backup {
+            path: path.display().to_string(),
+            error,
+        };
+
+        let previous = match std::fs::read_to_string(path) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(backup_error(e)),
+        };
+
+        if self.secret_scan != SecretScan::Off {
+            let findings: Vec<String> =
+                secrets::added(previous.as_deref().unwrap_or_default(), contents)
+                    .iter()
+                    .map(|finding| finding.to_string())
+                    .collect();
+            if !findings.is_empty() {
+                if self.secret_scan == SecretScan::Block {
+                    return Err(PatchError::Secret {
+                        path: path.display().to_string(),
+                        findings,
+                    });
+                }
+                eprintln!(
+                    "Warning: {} now has {}",
+                    path.display(),
+                    findings.join(", ")
+                );
+            }
+        }
+
+        let before = match previous {
+            Some(previous) => Some(edits::snapshot(&previous).map_err(backup_error)?),
+            None => None,
+        };
+        let after = edits::snapshot(contents).map_err(backup_error)?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        std::fs::write(path, contents).map_err(io_error)?;
+
+        edits::record(&self.root.join(path), before, after).map_err(backup_error)?;
+        self.notify(path, "write");
+
+        Ok(())
+    }
+
+    /// Restores the file touched by the most recent edit that hasn't been undone, deleting it
+    /// if the edit created it. Unless `force` is set, a file that was modified since is left alone.
+    pub fn undo(&self, force: bool) -> Result<Edit, PatchError> {
+        let (index, edit) = edits::last_undoable().ok_or(PatchError::NothingToUndo)?;
+        let path = Path::new(&edit.path);
+        self.check(path)?;
+
+        let io_error = |error| PatchError::Io {
+            path: edit.path.clone(),
+            error,
+        };
+
+        let current = std::fs::read_to_string(path).ok();
+        if !force && current.as_deref().map(edits::content_id) != Some(edit.after.clone()) {
+            return Err(PatchError::ChangedSince {
+                path: edit.path.clone(),
+            });
+        }
+
+        match &edit.before {
+            Some(id) => {
+                let previous = edits::load_snapshot(id).map_err(io_error)?;
+                std::fs::write(path, previous).map_err(io_error)?;
+            }
+            None if current.is_some() => std::fs::remove_file(path).map_err(io_error)?,
+            None => {}
+        }
+
+        edits::mark_undone(index).map_err(io_error)?;
+        self.notify(path, "undo");
+
+        Ok(edit)
+    }
+}
+
+/// One file's changes from a unified diff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// The file the diff modifies, without the `a/` / `b/` prefix git adds.
+    pub path: String,
+    /// True for `--- /dev/null`, the file doesn't exist yet.
+    pub new_file: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// 1-based line in the original file, if the `@@` header has one. Models often leave it out.
+    pub old_start: Option<usize>,
+    pub lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    /// The lines the hunk expects to find in the file.
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    /// The lines the hunk leaves behind.
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Add(s) => Some(s.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Parses every file in a unified diff. Until the line counts of an `@@` header are used
+/// up, `--- ` and `+++ ` lines belong to the hunk, e.g. a removed `-- comment` line. Past
+/// them the hunk goes on to the next header, because model-written diffs get the counts
+/// wrong more often than not.
+pub fn parse_diff(diff: &str) -> Vec<FilePatch> {
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut old_path: Option<String> = None;
+    // the old and new lines the current hunk's header still promises
+    let mut remaining = (0, 0);
+
+    for line in diff.lines() {
+        if remaining == (0, 0) {
+            if let Some(path) = line.strip_prefix("--- ") {
+                old_path = Some(diff_path(path));
+                continue;
+            }
+            if let Some(path) = line.strip_prefix("+++ ") {
+                let old = old_path.take().unwrap_or_default();
+                let new = diff_path(path);
+                patches.push(FilePatch {
+                    path: if new == "/dev/null" { old.clone() } else { new },
+                    new_file: old == "/dev/null",
+                    hunks: Vec::new(),
+                });
+                continue;
+            }
+            if line.starts_with("diff ") || line.starts_with("index ") {
+                continue;
+            }
+        }
+
+        let Some(patch) = patches.last_mut() else {
+            continue;
+        };
+
+        if line.starts_with("@@") {
+            let old = hunk_range(line, '-');
+            remaining = (
+                old.map_or(0, |(_, count)| count),
+                hunk_range(line, '+').map_or(0, |(_, count)| count),
+            );
+            patch.hunks.push(Hunk {
+                old_start: old.map(|(start, _)| start),
+                lines: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(hunk) = patch.hunks.last_mut() else {
+            continue;
+        };
+
+        let (old, new) = &mut remaining;
+        match line.chars().next() {
+            Some('+') => {
+                hunk.lines.push(HunkLine::Add(line[1..].to_string()));
+                *new = new.saturating_sub(1);
+            }
+            Some('-') => {
+                hunk.lines.push(HunkLine::Remove(line[1..].to_string()));
+                *old = old.saturating_sub(1);
+            }
+            // editors and models strip the space from empty context lines
+            Some(' ') | None => {
+                hunk.lines
+                    .push(HunkLine::Context(line.get(1..).unwrap_or("").to_string()));
+                *old = old.saturating_sub(1);
+                *new = new.saturating_sub(1);
+            }
+            // "\ No newline at end of file"
+            Some('\\') => {}
+            Some(_) => {}
+        }
+    }
+
+    // trailing blank "context" is usually just the end of the code block
+    for hunk in patches.iter_mut().flat_map(|p| p.hunks.iter_mut()) {
+        while hunk.lines.last() == Some(&HunkLine::Context(String::new())) {
+            hunk.lines.pop();
+        }
+    }
+
+    patches
+}
+
+fn diff_path(path: &str) -> String {
+    // `--- a/src/main.rs\t2024-01-01 ...`
+    let path = path.split('\t').next().unwrap_or("").trim();
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// The start and line count of the `side` (`-` or `+`) of a hunk header, the count is 1
+/// when it's left out: `@@ -12,7 +12 @@` -> `Some((12, 7))` and `Some((12, 1))`.
+fn hunk_range(header: &str, side: char) -> Option<(usize, usize)> {
+    let range = header
+        .split_whitespace()
+        .find_map(|w| w.strip_prefix(side))?;
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    Some((start.parse().ok()?, count.parse().ok()?))
+}
+
+/// A hunk whose lines weren't found in the file.
+pub struct Conflict<'a> {
+    pub path: &'a str,
+    /// 1-based index of the hunk in the file's patch.
+    pub hunk: usize,
+    /// 1-based line where the hunk was supposed to go.
+    pub line: usize,
+    /// What the file currently has there.
+    pub ours: Vec<String>,
+    /// What the hunk wants there.
+    pub theirs: Vec<String>,
+}
+
+pub enum Resolution {
+    /// Leave the file as it is and skip the hunk.
+    KeepOurs,
+    /// Replace what's in the file with the hunk's lines.
+    TakeTheirs,
+    /// Replace what's in the file with these lines.
+    Edited(Vec<String>),
+}
+
+/// The outcome of applying a `FilePatch` to a file's contents.
+pub struct Applied {
+    pub contents: String,
+    /// Hunks that applied cleanly, possibly at a different line than the header says.
+    pub clean: usize,
+    /// Hunks that conflicted and were resolved by `resolve`, and how many of those were skipped.
+    pub resolved: usize,
+    pub skipped: usize,
+}
+
+/// Applies every hunk of `patch` to `original`. A hunk whose old lines can't be found
+/// (even ignoring trailing whitespace) is handed to `resolve` instead of failing the patch.
+pub fn apply(
+    original: &str,
+    patch: &FilePatch,
+    mut resolve: impl FnMut(&Conflict) -> Resolution,
+) -> Applied {
+    let mut lines: Vec<String> = original.lines().map(|l| l.to_string()).collect();
+    // how far earlier hunks moved the rest of the file
+    let mut offset: isize = 0;
+    let mut applied = Applied {
+        contents: String::new(),
+        clean: 0,
+        resolved: 0,
+        skipped: 0,
+    };
+
+    for (index, hunk) in patch.hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        let expected = hunk
+            .old_start
+            .map(|start| (start.saturating_sub(1) as isize + offset).max(0) as usize)
+            .unwrap_or(0)
+            .min(lines.len());
+
+        let (start, len, replacement) = match find_lines(&lines, &old, expected) {
+            Some(start) => {
+                applied.clean += 1;
+                (
+                    start,
+                    old.len(),
+                    new.iter().map(|l| l.to_string()).collect(),
+                )
+            }
+            None => {
+                let len = old.len().min(lines.len() - expected);
+                let conflict = Conflict {
+                    path: &patch.path,
+                    hunk: index + 1,
+                    line: expected + 1,
+                    ours: lines[expected..expected + len].to_vec(),
+                    theirs: new.iter().map(|l| l.to_string()).collect(),
+                };
+                applied.resolved += 1;
+                match resolve(&conflict) {
+                    Resolution::KeepOurs => {
+                        applied.skipped += 1;
+                        continue;
+                    }
+                    Resolution::TakeTheirs => (expected, len, conflict.theirs),
+                    Resolution::Edited(edited) => (expected, len, edited),
+                }
+            }
+        };
+
+        offset += replacement.len() as isize - len as isize;
+        lines.splice(start..start + len, replacement);
+    }
+
+    applied.contents = lines.join("\n");
+    if !lines.is_empty() && (original.is_empty() || original.ends_with('\n')) {
+        applied.contents.push('\n');
+    }
+
+    applied
+}
+
+/// Finds `needle` in `lines`, preferring the match closest to `near`.
+fn find_lines(lines: &[String], needle: &[&str], near: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(near);
+    }
+    if needle.len() > lines.len() {
+        return None;
+    }
+
+    let matches = |start: usize, eq: &dyn Fn(&str, &str) -> bool| {
+        needle
+            .iter()
+            .zip(&lines[start..])
+            .all(|(want, have)| eq(want, have))
+    };
+    let last = lines.len() - needle.len();
+    let mut candidates: Vec<usize> = (0..=last).collect();
+    candidates.sort_by_key(|start| start.abs_diff(near));
+
+    let exact = |a: &str, b: &str| a == b;
+    let loose = |a: &str, b: &str| a.trim_end() == b.trim_end();
+    candidates
+        .iter()
+        .copied()
+        .find(|&start| matches(start, &exact))
+        .or_else(|| {
+            candidates
+                .iter()
+                .copied()
+                .find(|&start| matches(start, &loose))
+        })
+}
+
+/// Prints the hunks of `file` as a diff, removed lines red and added ones green, so they can
+/// be read before deciding whether to apply them.
+pub fn print_patch(file: &FilePatch) {
+    let (red, green, reset) = (
+        term::style("\x1b[31m"),
+        term::style("\x1b[32m"),
+        term::style("\x1b[0m"),
+    );
+    println!("--- a/{}\n+++ b/{}", file.path, file.path);
+    for hunk in &file.hunks {
+        match hunk.old_start {
+            Some(start) => println!("@@ -{} @@", start),
+            None => println!("@@"),
+        }
+        for line in &hunk.lines {
+            match line {
+                HunkLine::Context(text) => println!(" {}", text),
+                HunkLine::Remove(text) => println!("{}-{}{}", red, text, reset),
+                HunkLine::Add(text) => println!("{}+{}{}", green, text, reset),
+            }
+        }
+    }
+}
+
+/// Asks what to do with a conflicting hunk: keep the file as is, take the hunk's
+/// version, or edit the region in `$EDITOR` with conflict markers.
+pub fn resolve_interactively(rl: &mut LineEditor, conflict: &Conflict) -> Resolution {
+    println!(
+        "\nHunk {} of {} doesn't apply at line {}",
+        conflict.hunk, conflict.path, conflict.line
+    );
+    println!("{}<<<<<<< current", term::style("\x1b[31m"));
+    for line in &conflict.ours {
+        println!("{}", line);
+    }
+    println!(
+        "{}======={}",
+        term::style("\x1b[0m"),
+        term::style("\x1b[32m")
+    );
+    for line in &conflict.theirs {
+        println!("{}", line);
+    }
+    println!(">>>>>>> patch{}", term::style("\x1b[0m"));
+
+    loop {
+        let choice = input::choose(
+            rl,
+            "Resolve:",
+            &[('k', "eep current"), ('t', "ake patch"), ('e', "dit")],
+        );
+        match choice {
+            Some('t') => return Resolution::TakeTheirs,
+            Some('e') => match edit_conflict(conflict) {
+                Ok(lines) => return Resolution::Edited(lines),
+                Err(e) => println!("{}", e),
+            },
+            _ => return Resolution::KeepOurs,
+        }
+    }
+}
+
+/// Opens the conflict in `$VISUAL`/`$EDITOR` and returns what the user saved.
+fn edit_conflict(conflict: &Conflict) -> Result<Vec<String>, String> {
+    let path = std::env::temp_dir().join(format!(
+        "copilot-hunk-{}.txt",
+        utils::generate_random_uuid4()
+    ));
+    let contents = format!(
+        "<<<<<<< current\n{}=======\n{}>>>>>>> patch\n",
+        conflict
+            .ours
+            .iter()
+            .map(|l| format!("{}\n", l))
+            .collect::<String>(),
+        conflict
+            .theirs
+            .iter()
+            .map(|l| format!("{}\n", l))
+            .collect::<String>(),
+    );
+    std::fs::write(&path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    let opened = utils::open_in_editor(None, &path);
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    opened?;
+
+    let edited = edited.map_err(|e| e.to_string())?;
+    if edited
+        .lines()
+        .any(|l| l.starts_with("<<<<<<<") || l.starts_with("=======") || l.starts_with(">>>>>>>"))
+    {
+        return Err(
+            "The conflict markers are still there, remove them to resolve the hunk".to_string(),
+        );
+    }
+
+    Ok(edited.lines().map(|l| l.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(patterns: &[&str]) -> Workspace {
+        let config = Config {
+            protect: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Config::default()
+        };
+        Workspace::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn protect_globs() {
+        let workspace = workspace(&["**/prod/**", "Cargo.lock", "/secrets.env"]);
+
+        assert!(workspace.check(Path::new("deploy/prod/app.yml")).is_err());
+        assert!(workspace.check(Path::new("Cargo.lock")).is_err());
+        assert!(workspace.check(Path::new("crates/foo/Cargo.lock")).is_err());
+        assert!(workspace.check(Path::new("src/../secrets.env")).is_err());
+        assert!(workspace.check(Path::new("src/main.rs")).is_ok());
+        assert!(workspace.check(Path::new("nested/secrets.env")).is_ok());
+    }
+
+    const DIFF: &str = "--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -2,3 +2,3 @@
+ fn one() {}
+-fn two() {}
++fn two() -> u8 { 2 }
+ fn three() {}
+@@ -9,2 +9,3 @@
+ fn nine() {}
++fn ten() {}
+
+";
+
+    #[test]
+    fn parse_unified_diff() {
+        let patches = parse_diff(DIFF);
+
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, "src/lib.rs");
+        assert!(!patches[0].new_file);
+        assert_eq!(patches[0].hunks.len(), 2);
+        assert_eq!(patches[0].hunks[0].old_start, Some(2));
+        assert_eq!(patches[0].hunks[1].lines.len(), 2);
+    }
+
+    #[test]
+    fn hunk_counts_default_to_one() {
+        assert_eq!(hunk_range("@@ -12,7 +12 @@ fn main", '-'), Some((12, 7)));
+        assert_eq!(hunk_range("@@ -12,7 +12 @@ fn main", '+'), Some((12, 1)));
+        assert_eq!(hunk_range("@@ -0,0 +1,2 @@", '-'), Some((0, 0)));
+        assert_eq!(hunk_range("@@ @@", '-'), None);
+    }
+
+    #[test]
+    fn header_like_lines_inside_a_counted_hunk() {
+        let diff = "--- a/schema.sql
++++ b/schema.sql
+@@ -1,2 +1,2 @@
+--- users
++++ users and their roles
+ CREATE TABLE users ();
+\\ No newline at end of file
+--- a/README.md
++++ b/README.md
+@@ -1 +1 @@
+-old
++new
+";
+        let patches = parse_diff(diff);
+
+        assert_eq!(patches.len(), 2);
+        assert_eq!(
+            patches[0].hunks[0].lines,
+            [
+                HunkLine::Remove("-- users".to_string()),
+                HunkLine::Add("++ users and their roles".to_string()),
+                HunkLine::Context("CREATE TABLE users ();".to_string()),
+            ]
+        );
+        assert_eq!(patches[1].path, "README.md");
+        assert_eq!(patches[1].hunks[0].old_start, Some(1));
+        assert_eq!(patches[1].hunks[0].lines.len(), 2);
+    }
+
+    #[test]
+    fn apply_moved_hunks_cleanly() {
+        let patch = &parse_diff(DIFF)[0];
+        // two extra lines at the top moved everything down
+        let original =
+            "// a\n// b\nuse x;\nfn one() {}\nfn two() {}\nfn three() {}\n\n\n\n\nfn nine() {}\n";
+
+        let applied = apply(original, patch, |_| panic!("no conflicts expected"));
+
+        assert_eq!(applied.clean, 2);
+        assert!(applied
+            .contents
+            .contains("fn two() -> u8 { 2 }\nfn three() {}"));
+        assert!(applied.contents.ends_with("fn nine() {}\nfn ten() {}\n"));
+    }
+
+    #[test]
+    fn conflicts_go_to_the_resolver() {
+        let patch = &parse_diff(DIFF)[0];
+        let original = "use x;\nfn one() {}\nfn zwei() {}\nfn three() {}\n";
+
+        let mut seen = Vec::new();
+        let applied = apply(original, patch, |conflict| {
+            seen.push((conflict.hunk, conflict.line, conflict.ours.clone()));
+            Resolution::KeepOurs
+        });
+
+        assert_eq!(seen[0].0, 1);
+        assert_eq!(seen[0].1, 2);
+        assert_eq!(seen[0].2[1], "fn zwei() {}");
+        assert_eq!(applied.skipped, 2);
+        assert_eq!(applied.contents, original);
+
+        let applied = apply(original, patch, |conflict| match conflict.hunk {
+            1 => Resolution::Edited(vec!["fn two() {}".to_string()]),
+            _ => Resolution::KeepOurs,
+        });
+        assert_eq!(applied.contents, "use x;\nfn two() {}\n");
+        assert_eq!(applied.skipped, 1);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::codeblocks;
+
+/// Rewrites a finished answer before it's kept in the history and shown, see
+/// `CopilotManager::with_post_processor`.
+pub trait PostProcessor {
+    fn process(&self, answer: &str) -> String;
+}
+
+/// The post-processors `--post` can pick (`--post code-only,lf`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    /// Only the code of the answer's fenced blocks, for piping it into a file (`--code-only`).
+    CodeOnly,
+    /// `\n` line endings throughout.
+    Lf,
+}
+
+impl Builtin {
+    pub fn parse(name: &str) -> Result<Builtin, String> {
+        match name {
+            "code-only" => Ok(Builtin::CodeOnly),
+            "lf" => Ok(Builtin::Lf),
+            _ => Err(format!(
+                "Unknown post-processor: {}, expected code-only or lf",
+                name
+            )),
+        }
+    }
+
+    pub fn processor(self) -> Box<dyn PostProcessor> {
+        match self {
+            Builtin::CodeOnly => Box::new(CodeOnly),
+            Builtin::Lf => Box::new(Lf),
+        }
+    }
+}
+
+/// Drops everything around the fenced code blocks and the fences themselves. Answers
+/// without a code block are left alone, there's nothing better to show of them.
+pub struct CodeOnly;
+
+impl PostProcessor for CodeOnly {
+    fn process(&self, answer: &str) -> String {
+        let blocks = codeblocks::extract(answer);
+        if blocks.is_empty() {
+            return answer.to_string();
+        }
+        let code: Vec<&str> = blocks.iter().map(|block| block.code.trim_end()).collect();
+        code.join("\n\n")
+    }
+}
+
+/// Turns `\r\n` and lone `\r` into `\n`.
+pub struct Lf;
+
+impl PostProcessor for Lf {
+    fn process(&self, answer: &str) -> String {
+        answer.replace("\r\n", "\n").replace('\r', "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_only_keeps_the_code() {
+        let answer =
+            "Use this:\n\n```rust\nfn main() {}\n```\n\nand\n\n```toml\n[dependencies]\n```\nDone.";
+        assert_eq!(CodeOnly.process(answer), "fn main() {}\n\n[dependencies]");
+        assert_eq!(CodeOnly.process("No code here."), "No code here.");
+    }
+
+    #[test]
+    fn line_endings_become_lf() {
+        assert_eq!(Lf.process("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert_eq!(Builtin::parse("lf"), Ok(Builtin::Lf));
+        assert!(Builtin::parse("crlf").is_err());
+    }
+}
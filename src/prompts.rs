@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 pub const COPILOT_INSTRUCTIONS: &str = r#"You are an AI programming assistant.
 When asked for you name, you must respond with "GitHub Copilot".
 Follow the user's requirements carefully & to the letter.
@@ -30,3 +32,127 @@ The active document is the source code the user is looking at right now.
 You can only give one reply for each conversation turn.
 You should always generate short suggestions for the next user turns that are relevant to the conversation and not offensive.
 "#;
+
+/// The question `copilot ask` sends when it only got piped input.
+pub const EXPLAIN_INPUT: &str = "Explain what this shows and point out anything that looks wrong.";
+
+/// Appended to the system prompt by `/brief`.
+pub const BRIEF_DIRECTIVE: &str =
+    "Answer as briefly as possible: a sentence or two of plain prose that reads well aloud, \
+and code only when it was asked for.";
+
+/// Appended to the system prompt by `/verbose`.
+pub const VERBOSE_DIRECTIVE: &str =
+    "Answer in detail: explain the reasoning, the trade-offs and the edge cases, \
+and walk through any code you give.";
+
+/// Appended to the system prompt in JSON mode, which the API only allows when the messages
+/// ask for JSON.
+pub const JSON_DIRECTIVE: &str =
+    "Reply with only a single JSON object, without Markdown or code fences around it.";
+
+/// Sent in JSON mode when an answer didn't parse, with the parser's `error`.
+pub fn json_retry(error: &str) -> String {
+    format!(
+        "Your answer is not valid JSON: {}. Reply with only the corrected JSON object.",
+        error
+    )
+}
+
+/// Sent by `/tasks new` to turn the last answer into a checklist.
+pub const TASKS_PROMPT: &str =
+    "Turn your last answer into a checklist of the concrete steps it describes, in order. \
+Reply with only a JSON array of strings, one short imperative sentence per step.";
+
+/// Sent by `/memories extract` to find facts worth keeping for later sessions.
+pub const MEMORY_PROMPT: &str =
+    "List the lasting facts about me this conversation shows, that would help in future \
+conversations: my preferences for answers and code style, the projects, languages and tools I \
+work with. Leave out anything only about this task. Reply with only one \"- \" line per fact, \
+or nothing if there is none.";
+
+/// Sent by `/to-issue` to draft a GitHub issue from the conversation.
+pub const ISSUE_PROMPT: &str =
+    "Summarize this conversation as a GitHub issue. Reply with only a JSON object with a \
+\"title\", a Markdown \"summary\" of the problem and what was found, the \"steps\" to \
+reproduce it as a list of strings and the \"environment\" (versions, OS) as a string. \
+Leave out what the conversation doesn't say rather than guessing.";
+
+/// The first step of `copilot adr`: what to ask the user before writing the record.
+pub fn adr_questions(title: &str) -> String {
+    format!(
+        "I want to write an Architecture Decision Record about: {}\n\
+Ask me the three to five questions you most need answered to write it well, such as the \
+context, the options considered and the consequences. Reply with only a JSON array of the \
+questions as strings.",
+        title
+    )
+}
+
+/// The last step of `copilot adr`: the record itself from the interview.
+pub fn adr_record(title: &str, date: &str, interview: &str) -> String {
+    format!(
+        "Write the Architecture Decision Record \"{}\" in Markdown from my answers below. Use a \
+\"# <title>\" heading, then \"Date: {}\" and \"Status: Proposed\", then the sections Context, \
+Decision, Alternatives considered and Consequences. Reply with only the record.\n\n{}",
+        title, date, interview
+    )
+}
+
+/// The first step of `copilot refactor`: which files to change and how, from the lines
+/// that mention the identifiers involved.
+pub fn refactor_plan(instruction: &str, matches: &str) -> String {
+    format!(
+        "I want to refactor my project: {}\n\
+These are the lines in it that mention the identifiers involved, as path:line:text:\n\n{}\n\n\
+Plan the change across the files. Reply with only a JSON array with one object per file that \
+needs changing, each with the \"path\" as shown above and the \"change\" to make there in one \
+sentence, in the order they should be changed.",
+        instruction, matches
+    )
+}
+
+/// One file of a `copilot refactor` plan: the diff that makes its part of the change.
+pub fn refactor_file(
+    instruction: &str,
+    plan: &str,
+    path: &str,
+    change: &str,
+    contents: &str,
+) -> String {
+    format!(
+        "We are refactoring: {}\nThe plan:\n{}\n\nNow change only {}: {}\n\
+Reply with only a unified diff of {} in a diff code block, with enough context lines to place \
+every hunk. This is the file:\n\n{}",
+        instruction, plan, path, change, path, contents
+    )
+}
+
+/// Lists the remembered facts about the user for the system prompt, `None` if there are none.
+pub fn memories(facts: &[String]) -> Option<String> {
+    if facts.is_empty() {
+        return None;
+    }
+
+    let mut text =
+        String::from("Keep in mind what you know about the user from earlier conversations:");
+    for fact in facts {
+        text.push_str(&format!("\n- {}", fact));
+    }
+    Some(text)
+}
+
+/// Lists the `glossary` from the config for the system prompt, `None` if it's empty.
+pub fn glossary(terms: &BTreeMap<String, String>) -> Option<String> {
+    if terms.is_empty() {
+        return None;
+    }
+
+    let mut text = String::from(
+        "The user's team uses these terms, read them with these meanings and use them the same way:",
+    );
+    for (term, meaning) in terms {
+        text.push_str(&format!("\n- {}: {}", term, meaning));
+    }
+    Some(text)
+}
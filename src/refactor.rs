@@ -0,0 +1,257 @@
+use std::{path::Path, process::Command};
+
+use reqwest::Client;
+
+use crate::{
+    codeblocks,
+    config::Config,
+    copilot::{self, CopilotManager, Sampling},
+    gh::AuthenticationManager,
+    input, patch, prompts,
+};
+
+/// At most this many matching lines are shown to the model when it plans the refactor.
+const MAX_MATCHES: usize = 200;
+
+/// One file the model plans to change, and how.
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub path: String,
+    pub change: String,
+}
+
+/// Runs `copilot refactor "<instruction>"`: finds the files mentioning the identifiers in
+/// the instruction, has the model plan the change across them, then asks for and applies
+/// a diff file by file, each only after approval. Returns how many files were changed.
+pub async fn run(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    model: &str,
+    instruction: &str,
+) -> Result<usize, String> {
+    let config = Config::load();
+    let workspace = patch::Workspace::from_config(&config)?;
+
+    let symbols = symbols(instruction);
+    if symbols.is_empty() {
+        return Err(
+            "Name the identifiers to change, e.g. copilot refactor \"rename CopilotManager to ChatClient\""
+                .to_string(),
+        );
+    }
+    let matches = search(&symbols)?;
+    if matches.is_empty() {
+        return Err(format!("No tracked file mentions {}", symbols.join(", ")));
+    }
+
+    let auth = auth_manager.cache_auth().await.map_err(|e| e.to_string())?;
+    let mut copilot_m = CopilotManager::new(&auth, client, config.system_prompt())
+        .with_endpoint(config.endpoint)
+        .with_sampling(Sampling::from_config(&config))
+        .with_retries(&config);
+    copilot_m.set_model(model);
+
+    println!("Planning \"{}\"...", instruction);
+    let reply = copilot_m
+        .ask_aside(&prompts::refactor_plan(instruction, &matches))
+        .await;
+    let plan = parse_plan(&reply.content);
+    if plan.is_empty() {
+        return Err("The model didn't come up with a plan, try again".to_string());
+    }
+
+    println!();
+    for (i, step) in plan.iter().enumerate() {
+        println!("{}. {}: {}", i + 1, step.path, step.change);
+    }
+    println!();
+
+    let mut rl = input::new_editor();
+    if !input::confirm(&mut rl, "Go through the plan file by file?", true) {
+        return Err("Nothing changed".to_string());
+    }
+
+    let plan_text = plan
+        .iter()
+        .map(|step| format!("- {}: {}", step.path, step.change))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut changed = 0;
+    for step in &plan {
+        let path = Path::new(&step.path);
+        if let Err(e) = workspace.check(path) {
+            println!("{}", e);
+            continue;
+        }
+        let original = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        println!("\n{}: {}", step.path, step.change);
+        let answer = copilot_m
+            .ask_aside(&prompts::refactor_file(
+                instruction,
+                &plan_text,
+                &step.path,
+                &step.change,
+                &original,
+            ))
+            .await;
+        if answer.finish_reason == copilot::FINISH_ERROR {
+            println!("Skipping {}: no diff", path.display());
+            continue;
+        }
+
+        let Some(file) = patch::parse_diff(&diff_in(&answer.content))
+            .into_iter()
+            .find(|file| file.path == step.path)
+        else {
+            println!("The answer has no diff for {}, skipping it", path.display());
+            continue;
+        };
+
+        // the rewrite is asked aside, so nothing of it was shown yet
+        patch::print_patch(&file);
+        match input::choose(
+            &mut rl,
+            &format!("Apply to {}?", path.display()),
+            &[('a', "pply"), ('s', "kip"), ('q', "uit")],
+        ) {
+            Some('a') => {}
+            Some('q') | None => break,
+            _ => continue,
+        }
+
+        let applied = patch::apply(&original, &file, |conflict| {
+            patch::resolve_interactively(&mut rl, conflict)
+        });
+        if applied.contents == original {
+            println!("{}: nothing changed", path.display());
+            continue;
+        }
+        match workspace.write(path, &applied.contents) {
+            Ok(()) => {
+                println!("Patched {}", path.display());
+                changed += 1;
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    Ok(changed)
+}
+
+/// The identifiers named in an instruction: words in backticks, and words that look like
+/// code because they have an underscore, `::` or a capital after the first letter.
+fn symbols(instruction: &str) -> Vec<String> {
+    let mut symbols: Vec<String> = Vec::new();
+    for (i, part) in instruction.split('`').enumerate() {
+        let words: Vec<&str> = if i % 2 == 1 {
+            vec![part.trim()]
+        } else {
+            part.split(|c: char| !c.is_alphanumeric() && c != '_' && c != ':')
+                .map(|w| w.trim_matches(':'))
+                .filter(|w| {
+                    w.contains('_') || w.contains("::") || w.chars().skip(1).any(char::is_uppercase)
+                })
+                .collect()
+        };
+        for word in words {
+            if !word.is_empty() && !symbols.iter().any(|s| s == word) {
+                symbols.push(word.to_string());
+            }
+        }
+    }
+    symbols
+}
+
+/// The tracked lines mentioning any of `symbols` as a whole word, as `path:line:text`.
+fn search(symbols: &[String]) -> Result<String, String> {
+    let mut git = Command::new("git");
+    git.args(["grep", "-n", "-w", "-F", "-I"]);
+    for symbol in symbols {
+        git.arg("-e").arg(symbol);
+    }
+    let output = git
+        .output()
+        .map_err(|e| format!("Failed to run git grep: {}", e))?;
+
+    // git grep exits with 1 when nothing matched
+    if !output.status.success() && output.status.code() != Some(1) {
+        return Err(format!(
+            "git grep failed, copilot refactor only works inside a git repository: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let total = stdout.lines().count();
+    let mut matches: Vec<&str> = stdout.lines().take(MAX_MATCHES).collect();
+    let more = format!("... and {} more", total.saturating_sub(MAX_MATCHES));
+    if total > MAX_MATCHES {
+        matches.push(&more);
+    }
+    Ok(matches.join("\n"))
+}
+
+/// Reads the plan, a JSON array of `{"path", "change"}` objects.
+fn parse_plan(reply: &str) -> Vec<Step> {
+    let Some(serde_json::Value::Array(items)) = codeblocks::find_json(reply) else {
+        return Vec::new();
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            Some(Step {
+                path: item.get("path")?.as_str()?.trim().to_string(),
+                change: item.get("change")?.as_str()?.trim().to_string(),
+            })
+        })
+        .filter(|step| !step.path.is_empty())
+        .collect()
+}
+
+/// The diff in an answer: its `diff` code block, or the whole answer if it has none.
+fn diff_in(answer: &str) -> String {
+    codeblocks::extract(answer)
+        .into_iter()
+        .find(|block| {
+            block.lang == "diff" || block.lang == "patch" || block.code.starts_with("--- ")
+        })
+        .map(|block| block.code)
+        .unwrap_or_else(|| answer.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_identifiers() {
+        assert_eq!(
+            symbols("rename CopilotManager to ChatClient"),
+            ["CopilotManager", "ChatClient"]
+        );
+        assert_eq!(
+            symbols("inline `ask` and move utils::get_config_path, read_config_file"),
+            ["ask", "utils::get_config_path", "read_config_file"]
+        );
+        assert!(symbols("make it faster").is_empty());
+    }
+
+    #[test]
+    fn reads_plan() {
+        let reply = "```json\n[{\"path\": \"src/copilot.rs\", \"change\": \"rename the struct\"}, {\"change\": \"no path\"}]\n```";
+        assert_eq!(
+            parse_plan(reply),
+            [Step {
+                path: "src/copilot.rs".to_string(),
+                change: "rename the struct".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,975 @@
+use crate::{
+    cli, codeblocks,
+    commands::Command,
+    config::{self, Config},
+    context, copilot, gh, http_cache, input, issue, license, memory, patch, prompts, redact,
+    retrieval, sessions, subcommands, tasks, timings, utils, webhooks,
+};
+
+pub fn print_profiles(active: &str) {
+    let config = Config::load();
+    for (name, profile) in &config.profiles {
+        let marker = if name == active { "*" } else { " " };
+        println!(
+            "{} {} ({}@{})",
+            marker,
+            name,
+            profile.user.as_deref().unwrap_or("?"),
+            profile.host
+        );
+    }
+}
+
+/// Handles `/apply [n] [path]`: writes a code block from the last answer to disk,
+/// suggesting a file name when none is given. Diff blocks are applied to the files they name.
+/// New files get the `license_header`. Returns the files that were written.
+pub fn apply_code_block(
+    rl: &mut input::LineEditor,
+    workspace: &patch::Workspace,
+    config: &Config,
+    answer: &str,
+    arg: Option<&str>,
+) -> Vec<std::path::PathBuf> {
+    let blocks = codeblocks::extract(answer);
+    if blocks.is_empty() {
+        println!("The last answer has no code blocks");
+        return Vec::new();
+    }
+
+    let mut words = arg.unwrap_or("").split_whitespace().peekable();
+    let index = match words.peek().and_then(|w| w.parse::<usize>().ok()) {
+        Some(n) => {
+            words.next();
+            n
+        }
+        None => blocks.len(),
+    };
+    let Some(block) = index.checked_sub(1).and_then(|i| blocks.get(i)) else {
+        println!(
+            "There is no code block {}, the last answer has {}",
+            index,
+            blocks.len()
+        );
+        return Vec::new();
+    };
+
+    if block.lang == "diff" || block.lang == "patch" || block.code.starts_with("--- ") {
+        return apply_diff(rl, workspace, config, &block.code);
+    }
+
+    let path = match words.next() {
+        Some(path) => path.to_string(),
+        None => match input::prefilled(rl, "Save to: ", &codeblocks::infer_filename(block)) {
+            Some(path) if !path.trim().is_empty() => path.trim().to_string(),
+            _ => return Vec::new(),
+        },
+    };
+
+    let path = std::path::Path::new(&path);
+
+    // refuse before asking anything if the file is protected
+    if let Err(e) = workspace.check(path) {
+        println!("{}", e);
+        return Vec::new();
+    }
+
+    let contents = if path.exists() {
+        if !input::confirm(rl, &format!("{} exists, overwrite?", path.display()), false) {
+            return Vec::new();
+        }
+        block.code.clone()
+    } else {
+        license::apply(config, path, &block.code)
+    };
+
+    match workspace.write(path, &contents) {
+        Ok(()) => {
+            println!(
+                "Wrote {} ({} lines)",
+                path.display(),
+                contents.lines().count()
+            );
+            vec![path.to_path_buf()]
+        }
+        Err(e) => {
+            println!("{}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Handles `/open [n]`: writes a code block from the last answer to a temp file named like
+/// `/apply` would name it, opens it in the editor and offers to attach what the user changed.
+pub fn open_code_block(
+    rl: &mut input::LineEditor,
+    copilot_m: &mut copilot::CopilotManager,
+    config: &Config,
+    answer: &str,
+    arg: Option<&str>,
+) {
+    let blocks = codeblocks::extract(answer);
+    let index = match arg.map(|a| a.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("Usage: /open [n]");
+            return;
+        }
+        None => blocks.len(),
+    };
+    let Some(block) = index.checked_sub(1).and_then(|i| blocks.get(i)) else {
+        println!(
+            "There is no code block {}, the last answer has {}",
+            index,
+            blocks.len()
+        );
+        return;
+    };
+
+    // a directory per block keeps the inferred name, and with it the extension editors go by
+    let dir = std::env::temp_dir().join(format!("copilot-{}", utils::generate_random_uuid4()));
+    let name = codeblocks::infer_filename(block);
+    let name = std::path::Path::new(&name)
+        .file_name()
+        .map(|n| n.to_owned())
+        .unwrap_or_else(|| "code.txt".into());
+    let path = dir.join(name);
+    if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, &block.code)) {
+        println!("Failed to write {}: {}", path.display(), e);
+        return;
+    }
+
+    let opened = utils::open_in_editor(config.editor.as_deref(), &path);
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_dir_all(&dir);
+    if let Err(e) = opened {
+        println!("{}", e);
+        return;
+    }
+
+    match edited {
+        Ok(edited) if edited != block.code => {
+            if input::confirm(rl, "Attach your edits to the next question?", true) {
+                let title = format!("Edited code block {}", index);
+                copilot_m.attach_context(&title, &block.lang, &edited);
+                println!("[attached the edited code, ask your question]");
+            }
+        }
+        Ok(_) => println!("No changes"),
+        Err(e) => println!("Failed to read {}: {}", path.display(), e),
+    }
+}
+
+/// Applies a unified diff from an answer, asking how to resolve each hunk that doesn't apply.
+/// Returns the files that were written.
+fn apply_diff(
+    rl: &mut input::LineEditor,
+    workspace: &patch::Workspace,
+    config: &Config,
+    diff: &str,
+) -> Vec<std::path::PathBuf> {
+    let patches = patch::parse_diff(diff);
+    if patches.is_empty() {
+        println!("The diff doesn't name any files");
+        return Vec::new();
+    }
+
+    let mut written = Vec::new();
+    for file in &patches {
+        let path = std::path::Path::new(&file.path);
+        if let Err(e) = workspace.check(path) {
+            println!("{}", e);
+            continue;
+        }
+
+        let original = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) if file.new_file => String::new(),
+            Err(e) => {
+                println!("Failed to read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let mut applied = patch::apply(&original, file, |conflict| {
+            patch::resolve_interactively(rl, conflict)
+        });
+
+        if applied.contents == original {
+            println!("{}: nothing changed", path.display());
+            continue;
+        }
+        if original.is_empty() && file.new_file {
+            applied.contents = license::apply(config, path, &applied.contents);
+        }
+
+        match workspace.write(path, &applied.contents) {
+            Ok(()) => {
+                println!(
+                    "Patched {} ({} hunks applied, {} resolved, {} skipped)",
+                    path.display(),
+                    applied.clean,
+                    applied.resolved - applied.skipped,
+                    applied.skipped
+                );
+                written.push(path.to_path_buf());
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    written
+}
+
+/// Handles `/export [html] <file> [profile]`: writes the conversation as Markdown, or HTML
+/// with `html`, after redacting it, showing what was redacted before anything is written.
+pub fn export_transcript(
+    rl: &mut input::LineEditor,
+    workspace: &patch::Workspace,
+    config: &Config,
+    copilot_m: &copilot::CopilotManager,
+    login: &str,
+    arg: &str,
+) {
+    let mut words = arg.split_whitespace().peekable();
+    let format = match words.peek() {
+        Some(&"html") => {
+            words.next();
+            cli::ExportFormat::Html
+        }
+        _ => cli::ExportFormat::Markdown,
+    };
+    let path = words.next().unwrap_or("conversation.html");
+    let path = std::path::Path::new(path);
+    let profile_name = words.next().unwrap_or("default");
+
+    let redactions = match redact::profile(&config.redaction_profiles, profile_name) {
+        Ok(redactions) => redactions,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = workspace.check(path) {
+        println!("{}", e);
+        return;
+    }
+
+    let mut usernames = vec![login.to_string()];
+    if let Ok(user) = std::env::var("USER") {
+        usernames.push(user);
+    }
+
+    let mut summary = redact::Summary::default();
+    let exported: Vec<(String, String)> = copilot_m
+        .transcript()
+        .map(|turn| {
+            (
+                subcommands::speaker(turn.role, turn.model),
+                redact::redact(turn.content, &redactions, &usernames, &mut summary),
+            )
+        })
+        .collect();
+    let messages = exported.len();
+    if messages == 0 {
+        println!("Nothing to export yet");
+        return;
+    }
+
+    if summary.is_empty() {
+        println!("Redaction profile {}: nothing to redact", profile_name);
+    } else {
+        println!("Redaction profile {}: {}", profile_name, summary);
+    }
+    let question = if path.exists() {
+        format!(
+            "{} exists, overwrite with {} messages?",
+            path.display(),
+            messages
+        )
+    } else {
+        format!("Write {} messages to {}?", messages, path.display())
+    };
+    if !input::confirm(rl, &question, !path.exists()) {
+        return;
+    }
+
+    let contents = subcommands::render_export(format, "Copilot conversation", &exported);
+    match workspace.write(path, &contents) {
+        Ok(()) => println!("Exported to {}", path.display()),
+        Err(e) => println!("{}", e),
+    }
+}
+
+/// How `offline_mode` was left.
+pub enum Offline {
+    Retry,
+    SwitchProfile(String),
+    Exit,
+}
+
+/// A REPL for when GitHub can't be reached: questions can't be sent, but everything
+/// stored locally is still available until `/retry` reconnects.
+pub fn offline_mode(rl: &mut input::LineEditor, profile: &str) -> Offline {
+    println!("Offline. /profile lists the saved profiles, /retry reconnects, exit quits.");
+
+    loop {
+        let Ok(input) = rl.readline("You [offline]: ") else {
+            return Offline::Exit;
+        };
+
+        match Command::parse(&input) {
+            Some(Command::Exit) => return Offline::Exit,
+            Some(Command::Retry) => return Offline::Retry,
+            Some(Command::Profile(None)) => print_profiles(profile),
+            Some(Command::Profile(Some(name))) => {
+                let mut config = Config::load();
+                config.active_profile = Some(name.clone());
+                config.save();
+                return Offline::SwitchProfile(name);
+            }
+            Some(Command::Cache(_)) => {
+                let (count, size) = http_cache::stats();
+                println!("{} cached responses ({} KB)", count, size / 1024);
+            }
+            Some(_) | None => println!("That needs a connection, /retry once you're back online"),
+        }
+    }
+}
+
+/// Attaches the indexed code most relevant to `question`, see `copilot index`.
+pub async fn retrieve_context(
+    copilot_m: &mut copilot::CopilotManager<'_>,
+    config: &Config,
+    index: &Option<retrieval::Index>,
+    question: &str,
+) {
+    let Some(index) = index else {
+        return;
+    };
+    if config.retrieval_chunks == 0 {
+        return;
+    }
+    match retrieval::attach_relevant(copilot_m, index, question, config.retrieval_chunks).await {
+        Ok(labels) if labels.is_empty() => {}
+        Ok(labels) => println!("[retrieved {}]", labels.join(", ")),
+        Err(e) => println!("[retrieval failed: {}]", e),
+    }
+}
+
+/// With `"context_selection": "scored"`, trims the pending context to the budget and
+/// says what was left out, so a missing attachment is never a surprise.
+pub fn select_context(
+    copilot_m: &mut copilot::CopilotManager,
+    config: &Config,
+    webhooks: &webhooks::Webhooks,
+    question: &str,
+) {
+    if config.context_selection != config::ContextSelection::Scored
+        || copilot_m.pending_context() == 0
+    {
+        return;
+    }
+
+    let selection = copilot_m.select_context(question, config.context_budget);
+    let describe = |scored: &context::Scored| {
+        format!(
+            "{} (~{} tokens, {} matching words)",
+            scored.item.label, scored.tokens, scored.relevance
+        )
+    };
+
+    for scored in &selection.included {
+        println!("[context] including {}", describe(scored));
+    }
+    for scored in &selection.excluded {
+        println!("[context] leaving out {}", describe(scored));
+    }
+
+    if !selection.excluded.is_empty() {
+        webhooks.fire(
+            config::WebhookEvent::BudgetExceeded,
+            serde_json::json!({
+                "kind": "context_budget",
+                "budget": config.context_budget,
+                "excluded": selection.excluded.iter().map(|s| &s.item.label).collect::<Vec<_>>(),
+            }),
+        );
+    }
+}
+
+/// Marks answers from a different model than the one the conversation started on, e.g.
+/// after `/model`, so mixed histories are easy to tell apart.
+pub fn print_model_badge(copilot_m: &copilot::CopilotManager) {
+    if let Some(pinned) = copilot_m.conversation_model() {
+        if pinned != copilot_m.model() {
+            println!("[{}]", copilot_m.model());
+        }
+    }
+}
+
+/// With `--choices`, shows the candidates after the streamed first one and asks which the
+/// conversation keeps. Returns the kept answer.
+fn pick_candidate(
+    rl: &mut input::LineEditor,
+    copilot_m: &mut copilot::CopilotManager,
+    completion: &copilot::Completion,
+) -> String {
+    if completion.candidates.len() < 2 {
+        return completion.content.clone();
+    }
+    for (i, candidate) in completion.candidates.iter().enumerate().skip(1) {
+        println!("[candidate {}]\n{}\n", i + 1, candidate.trim_end());
+    }
+
+    let question = format!(
+        "Keep which answer? [1-{}, default 1]: ",
+        completion.candidates.len()
+    );
+    let kept = loop {
+        let answer = rl.readline(&question).unwrap_or_default();
+        match answer.trim() {
+            "" => break 1,
+            n => match n.parse::<usize>() {
+                Ok(n) if (1..=completion.candidates.len()).contains(&n) => break n,
+                _ => continue,
+            },
+        }
+    };
+
+    let content = &completion.candidates[kept - 1];
+    copilot_m.replace_last_answer(content);
+    if kept > 1 {
+        println!("[kept candidate {}]", kept);
+    }
+    content.clone()
+}
+
+/// With `--timings`, prints where the time of the request behind `completion` went. Logging
+/// in only counts towards the first answer after it.
+pub fn print_timings(
+    enabled: bool,
+    auth: &mut Option<std::time::Duration>,
+    context: std::time::Duration,
+    completion: &copilot::Completion,
+) {
+    if !enabled {
+        return;
+    }
+    let timings = timings::Timings {
+        auth: auth.take(),
+        context,
+        ..completion.timings
+    };
+    eprint!("{}", timings.render());
+}
+
+/// Asks before sending a request over `large_request_bytes`, listing what it's made of.
+/// Returns whether to send it; the attached context can also be dropped instead.
+pub fn confirm_large_request(
+    rl: &mut input::LineEditor,
+    copilot_m: &mut copilot::CopilotManager,
+    config: &Config,
+    question: &str,
+) -> bool {
+    let parts = copilot_m.request_breakdown(question);
+    let total: usize = parts.iter().map(|(_, bytes)| bytes).sum();
+    if total <= config.large_request_bytes {
+        return true;
+    }
+
+    println!(
+        "This request is {:.1} KB (the limit is {:.1} KB):",
+        total as f64 / 1024.0,
+        config.large_request_bytes as f64 / 1024.0
+    );
+    for (source, bytes) in &parts {
+        println!("  {:>8.1} KB  {}", *bytes as f64 / 1024.0, source);
+    }
+
+    let mut options = vec![('s', "end")];
+    if copilot_m.pending_context() > 0 {
+        options.push(('d', "rop attached context and send"));
+    }
+    options.push(('c', "ancel"));
+
+    match input::choose(rl, "Send it?", &options) {
+        Some('s') => true,
+        Some('d') => {
+            copilot_m.clear_context();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Handles `/fork [n]`: without a number lists the messages to pick from, with one saves a
+/// branch of the conversation at that message and returns it to switch to.
+pub fn fork_command(session: &sessions::Session, arg: Option<&str>) -> Option<sessions::Session> {
+    if session.messages.is_empty() {
+        println!("There is nothing to fork yet");
+        return None;
+    }
+    let Some(arg) = arg else {
+        for (i, message) in session.messages.iter().enumerate() {
+            let first_line = message.content.lines().next().unwrap_or_default();
+            let mut preview: String = first_line.chars().take(70).collect();
+            if preview.len() < message.content.trim_end().len() {
+                preview.push_str("...");
+            }
+            let who = if message.role == "user" {
+                "You"
+            } else {
+                "Copilot"
+            };
+            println!("{:>3}. {}: {}", i + 1, who, preview);
+        }
+        println!("/fork <n> continues from message n in a new session");
+        return None;
+    };
+
+    let at = match arg.parse::<usize>() {
+        Ok(n) if (1..=session.messages.len()).contains(&n) => n,
+        _ => {
+            println!("Usage: /fork <n>, n from 1 to {}", session.messages.len());
+            return None;
+        }
+    };
+    let branch = session.fork(at, &session.fork_name());
+    if let Err(e) = branch.save() {
+        println!("Failed to save the branch: {}", e);
+        return None;
+    }
+    if branch.messages.len() < at {
+        println!("[forked before message {}, ask it differently]", at);
+    }
+    Some(branch)
+}
+
+/// Handles `/search <text>`: lists the stored messages that mention it and shows the exchange
+/// of the one picked. Returns that conversation if it should be continued.
+pub fn search_command(
+    rl: &mut input::LineEditor,
+    session: &sessions::Session,
+    query: &str,
+) -> Option<sessions::Session> {
+    let hits = sessions::search(query);
+    if hits.is_empty() {
+        println!("No stored conversation mentions \"{}\"", query);
+        return None;
+    }
+    subcommands::print_search_hits(&hits);
+
+    let shown = subcommands::shown_search_hits(&hits);
+    let picked = rl
+        .readline(&format!("Open which? [1-{}, Enter to skip]: ", shown))
+        .unwrap_or_default();
+    let hit = match picked.trim().parse::<usize>() {
+        Ok(n) if (1..=shown).contains(&n) => &hits[n - 1],
+        _ => return None,
+    };
+
+    let found = match sessions::Session::load(&hit.session) {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            println!("The conversation {} is gone", hit.session);
+            return None;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+    for message in found.exchange_at(hit.index) {
+        println!(
+            "{}: {}\n",
+            subcommands::speaker(&message.role, message.model.as_deref()),
+            message.content.trim_end()
+        );
+    }
+
+    if found.id == session.id {
+        return None;
+    }
+    let name = found
+        .name
+        .clone()
+        .unwrap_or(utils::format_time(found.started));
+    input::confirm(rl, &format!("Continue the conversation {}?", name), false).then_some(found)
+}
+
+/// Handles `/session`, returning the session to switch to if there is one.
+pub fn session_command(
+    copilot_m: &mut copilot::CopilotManager,
+    session: &mut sessions::Session,
+    system_prompt: &str,
+    arg: Option<&str>,
+) -> Option<sessions::Session> {
+    let (action, rest) = match arg.map(|a| a.split_once(char::is_whitespace).unwrap_or((a, ""))) {
+        Some((action, rest)) => (action, rest.trim()),
+        None => ("list", ""),
+    };
+
+    match (action, rest) {
+        ("list", _) => {
+            let named = sessions::Session::list_named();
+            if named.is_empty() {
+                println!("No named sessions yet, start one with /session new <name>");
+            }
+            for other in named {
+                let name = other.name.as_deref().unwrap_or_default();
+                let current = if other.id == session.id { "* " } else { "  " };
+                let fork = match (&other.parent, other.forked_at) {
+                    (Some(parent), Some(at)) => format!(" · forked from {} at {}", parent, at),
+                    _ => String::new(),
+                };
+                println!(
+                    "{}{} · {} messages · {} tokens · {}{}",
+                    current,
+                    name,
+                    other.messages.len(),
+                    other.usage().total(),
+                    utils::format_time(other.last_active()),
+                    fork
+                );
+            }
+        }
+        ("new" | "switch" | "delete", "") => println!("Usage: /session {} <name>", action),
+        ("new", name) => match sessions::Session::load(name) {
+            Ok(Some(_)) => println!(
+                "There already is a session named {}, use /session switch {}",
+                name, name
+            ),
+            Ok(None) => match sessions::Session::named(name) {
+                Ok(new) => {
+                    if let Err(e) = new.save() {
+                        println!("Failed to save the session: {}", e);
+                    }
+                    return Some(new);
+                }
+                Err(e) => println!("{}", e),
+            },
+            Err(e) => println!("{}", e),
+        },
+        ("switch", name) => {
+            let found = sessions::check_name(name).and_then(|_| sessions::Session::load(name));
+            match found {
+                Ok(Some(other)) => return Some(other),
+                Ok(None) => println!(
+                    "There is no session named {}, create it with /session new {}",
+                    name, name
+                ),
+                Err(e) => println!("{}", e),
+            }
+        }
+        ("delete", name) if session.name.as_deref() == Some(name) => {
+            println!(
+                "{} is the current session, switch to another one first",
+                name
+            )
+        }
+        ("delete", name) => match sessions::Session::delete(name) {
+            Ok(()) => println!("Deleted session {}", name),
+            Err(e) => println!("{}", e),
+        },
+        ("prompt", "") => match &session.prompt {
+            Some(prompt) => println!("{}", prompt),
+            None => println!("This session uses the default system prompt"),
+        },
+        ("prompt", prompt) => {
+            if prompt == "reset" {
+                session.prompt = None;
+                copilot_m.set_prompt(system_prompt);
+                println!("Back to the default system prompt");
+            } else {
+                session.prompt = Some(prompt.to_string());
+                copilot_m.set_prompt(prompt);
+                println!("Set the system prompt for this session");
+            }
+            if let Err(e) = session.save() {
+                println!("Failed to save the session: {}", e);
+            }
+        }
+        _ => println!(
+            "Usage: /session [list|new <name>|switch <name>|delete <name>|prompt [text|reset]]"
+        ),
+    }
+    None
+}
+
+/// Attaches the session's scratchpad when the question mentions `@scratch`.
+pub fn attach_scratch(
+    copilot_m: &mut copilot::CopilotManager,
+    session: &sessions::Session,
+    question: &str,
+) {
+    let mentioned = question
+        .split_whitespace()
+        .any(|word| word.trim_end_matches(|c: char| c.is_ascii_punctuation()) == "@scratch");
+    if !mentioned {
+        return;
+    }
+
+    match std::fs::read_to_string(session.scratch_path()) {
+        Ok(scratch) if !scratch.trim().is_empty() => {
+            copilot_m.attach_context("Scratchpad", "markdown", &scratch)
+        }
+        _ => println!("[the scratchpad is empty, write to it with /scratch]"),
+    }
+}
+
+/// Handles `/remember` and `/memories`. Changes apply to the current conversation right away.
+pub async fn memories_command(
+    rl: &mut input::LineEditor,
+    config: &Config,
+    copilot_m: &mut copilot::CopilotManager<'_>,
+    command: Command,
+) {
+    if !config.memory {
+        println!("Memory is off, turn it on with \"memory\": true in the config");
+        return;
+    }
+    let mut memories = match memory::load() {
+        Ok(memories) => memories,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let before = memories.clone();
+
+    match command {
+        Command::Remember(None) => println!("Usage: /remember <fact>"),
+        Command::Remember(Some(fact)) => {
+            if memory::add(&mut memories, &fact, "remember") {
+                println!("Remembered");
+            } else {
+                println!("Already remembered");
+            }
+        }
+        Command::Memories(None) if memories.is_empty() => {
+            println!("Nothing remembered yet, add facts with /remember")
+        }
+        Command::Memories(None) => {
+            for (i, memory) in memories.iter().enumerate() {
+                println!(
+                    "{:>3}. {} ({}, {})",
+                    i + 1,
+                    memory.text,
+                    memory.source,
+                    utils::format_time(memory.time)
+                );
+            }
+        }
+        Command::Memories(Some(arg)) => {
+            match arg.split_once(char::is_whitespace).unwrap_or((&arg, "")) {
+                ("forget", n) => match n.trim().parse::<usize>() {
+                    Ok(n) if (1..=memories.len()).contains(&n) => {
+                        let forgotten = memories.remove(n - 1);
+                        println!("Forgot: {}", forgotten.text);
+                    }
+                    _ => println!("Usage: /memories forget <n>, n from /memories"),
+                },
+                ("clear", _) => {
+                    if input::confirm(
+                        rl,
+                        &format!("Forget all {} memories?", memories.len()),
+                        false,
+                    ) {
+                        memories.clear();
+                    }
+                }
+                ("extract", _) => {
+                    if copilot_m.transcript().next().is_none() {
+                        println!("There is no conversation to find facts in yet");
+                        return;
+                    }
+                    println!("Looking for facts worth remembering...");
+                    let reply = copilot_m.ask_aside(prompts::MEMORY_PROMPT).await;
+                    let facts = memory::parse_extracted(&reply.content);
+                    if facts.is_empty() {
+                        println!("Nothing worth remembering found");
+                    }
+                    for fact in facts {
+                        if input::confirm(rl, &format!("Remember \"{}\"?", fact), true) {
+                            memory::add(&mut memories, &fact, "extract");
+                        }
+                    }
+                }
+                _ => println!("Usage: /memories [forget <n>|clear|extract]"),
+            }
+        }
+        _ => unreachable!("only memory commands are handled here"),
+    }
+
+    if memories != before {
+        if let Err(e) = memory::save(&memories) {
+            println!("{}", e);
+            return;
+        }
+        let facts: Vec<String> = memories.into_iter().map(|m| m.text).collect();
+        copilot_m.set_memories(&facts);
+    }
+}
+
+/// Handles `/tasks`: makes a checklist out of the last answer, checks items off and exports
+/// it. The list is kept with the session.
+pub async fn tasks_command(
+    rl: &mut input::LineEditor,
+    workspace: &patch::Workspace,
+    copilot_m: &mut copilot::CopilotManager<'_>,
+    session: &mut sessions::Session,
+    last_answer: &str,
+    arg: Option<&str>,
+) {
+    let (action, rest) = match arg.map(|a| a.split_once(char::is_whitespace).unwrap_or((a, ""))) {
+        Some((action, rest)) => (action, rest.trim()),
+        None => ("", ""),
+    };
+
+    match action {
+        "" if session.tasks.is_empty() => {
+            println!("No tasks yet, make them from the last answer with /tasks new")
+        }
+        "" => println!("{}", tasks::render(&session.tasks)),
+        "new" => {
+            if last_answer.is_empty() {
+                println!("There is no answer to make tasks from yet");
+                return;
+            }
+            println!("Making a checklist from the last answer...");
+            let reply = copilot_m.ask_aside(prompts::TASKS_PROMPT).await;
+            match tasks::parse(&reply.content) {
+                Ok(tasks) => {
+                    session.tasks = tasks;
+                    println!("{}", tasks::render(&session.tasks));
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+        "export" if rest.is_empty() => {
+            println!("Usage: /tasks export <file>");
+            return;
+        }
+        "export" => {
+            let path = std::path::Path::new(rest);
+            if session.tasks.is_empty() {
+                println!("No tasks to export");
+                return;
+            }
+            if let Err(e) = workspace.check(path) {
+                println!("{}", e);
+                return;
+            }
+            if path.exists()
+                && !input::confirm(rl, &format!("{} exists, overwrite?", path.display()), false)
+            {
+                return;
+            }
+            match workspace.write(path, &tasks::markdown(&session.tasks)) {
+                Ok(()) => println!("Wrote {} tasks to {}", session.tasks.len(), path.display()),
+                Err(e) => println!("{}", e),
+            }
+            return;
+        }
+        n => {
+            let task = n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| session.tasks.get_mut(i));
+            let Some(task) = task else {
+                println!(
+                    "Usage: /tasks [new|<n>|export <file>], there are {} tasks",
+                    session.tasks.len()
+                );
+                return;
+            };
+            task.done = !task.done;
+            println!("{}", tasks::render(&session.tasks));
+        }
+    }
+
+    if let Err(e) = session.save() {
+        println!("Failed to save the session: {}", e);
+    }
+}
+
+/// Handles `/to-issue`: drafts an issue from the conversation, shows it and files it in the
+/// repository the user confirms.
+pub async fn to_issue(
+    rl: &mut input::LineEditor,
+    copilot_m: &mut copilot::CopilotManager<'_>,
+    auth_manager: &gh::AuthenticationManager,
+    auth: &gh::GithubAuth,
+    repo: Option<String>,
+) {
+    if copilot_m.transcript().next().is_none() {
+        println!("Nothing to summarize yet");
+        return;
+    }
+
+    println!("Drafting an issue from the conversation...");
+    let reply = copilot_m.ask_aside(prompts::ISSUE_PROMPT).await;
+    let draft = match issue::IssueDraft::parse(&reply.content) {
+        Ok(draft) => draft,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let body = draft.body();
+    println!("\n# {}\n\n{}\n", draft.title.trim(), body);
+
+    let repo = repo.or_else(issue::current_repo).unwrap_or_default();
+    let repo = match input::prefilled(rl, "File in (owner/repo, empty to skip): ", &repo) {
+        Some(repo) if !repo.trim().is_empty() => repo.trim().to_string(),
+        _ => return,
+    };
+    if !input::confirm(rl, &format!("Open this issue in {}?", repo), false) {
+        return;
+    }
+
+    match auth_manager.create_issue(&auth.token, &repo, draft.title.trim(), &body).await {
+        Ok(url) => println!("Opened {}", url),
+        Err(gh::AuthError::Forbidden) => println!(
+            "The token can't open issues in {}, it needs the repo or public_repo scope (log in with a token that has it via auth login --with-token)",
+            repo
+        ),
+        Err(e) => println!("Failed to open the issue: {}", e),
+    }
+}
+
+/// Stores the exchange that just finished so `--resume` can continue the conversation.
+pub fn save_session(session: &mut sessions::Session, copilot_m: &copilot::CopilotManager) {
+    session.update(copilot_m.transcript());
+    if let Err(e) = session.save() {
+        println!("Failed to save the conversation: {}", e);
+    }
+}
+
+/// What every way of asking does once the context is assembled: sends `question` and prints
+/// the `--timings`. In the chat, `chat` has the line editor to pick one of the `--choices`
+/// candidates with and the session the exchange is stored in. The returned answer's
+/// `content` is the candidate that was kept.
+pub async fn ask_and_save(
+    copilot_m: &mut copilot::CopilotManager<'_>,
+    question: &str,
+    timings: bool,
+    auth_time: &mut Option<std::time::Duration>,
+    context_time: std::time::Duration,
+    chat: Option<(&mut input::LineEditor, &mut sessions::Session)>,
+) -> copilot::Completion {
+    if chat.is_some() {
+        print_model_badge(copilot_m);
+    }
+    let mut completion = copilot_m.ask(question).await;
+    print_timings(timings, auth_time, context_time, &completion);
+
+    if let Some((rl, session)) = chat {
+        completion.content = pick_candidate(rl, copilot_m, &completion);
+        save_session(session, copilot_m);
+    }
+    completion
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    codeblocks, config::Config, copilot::CopilotManager, gh::AuthenticationManager, utils,
+};
+
+/// Lines per chunk, and how many of them the next chunk repeats so code that straddles a
+/// boundary is still found whole.
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 8;
+
+/// Larger files are usually generated or data, not code worth asking about.
+const MAX_FILE_BYTES: u64 = 200_000;
+
+/// Chunks sent per embeddings request.
+const BATCH_SIZE: usize = 32;
+
+/// Chunks less similar to the question than this are left out even if there's room.
+const MIN_SCORE: f32 = 0.25;
+
+/// A piece of a file with its embedding.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub path: String,
+    /// 1-based, inclusive.
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct IndexedFile {
+    /// The SHA-256 of the contents the chunks were made from.
+    hash: String,
+    chunks: Vec<Chunk>,
+}
+
+/// The embedded chunks of a repository's tracked files, kept under `index/` in the config
+/// directory and updated by `copilot index`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Index {
+    root: String,
+    model: String,
+    files: BTreeMap<String, IndexedFile>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn index_path(root: &str) -> PathBuf {
+    let key = hex(&Sha256::digest(root.as_bytes())[..8]);
+    PathBuf::from(utils::get_config_path())
+        .join("index")
+        .join(format!("{}.json", key))
+}
+
+/// The top directory of the git repository the current directory is in.
+fn repo_root() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Index {
+    /// The index of the repository the current directory is in, if it has been built.
+    pub fn load_current() -> Option<Index> {
+        let text = std::fs::read_to_string(index_path(&repo_root()?)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = index_path(&self.root);
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            let partial = path.with_extension("json.partial");
+            std::fs::write(&partial, serde_json::to_string(self)?)?;
+            std::fs::rename(partial, &path)
+        };
+        write().map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|f| f.chunks.len()).sum()
+    }
+
+    /// The `limit` chunks most similar to `query`, best first.
+    pub fn search(&self, query: &[f32], limit: usize) -> Vec<&Chunk> {
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .files
+            .values()
+            .flat_map(|file| &file.chunks)
+            .map(|chunk| (cosine(query, &chunk.embedding), chunk))
+            .filter(|(score, _)| *score >= MIN_SCORE)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, chunk)| chunk)
+            .collect()
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Splits `text` into overlapping windows of lines, as (first line, last line, text).
+fn chunk_lines(text: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let chunk = lines[start..end].join("\n");
+        if !chunk.trim().is_empty() {
+            chunks.push((start + 1, end, chunk));
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+    chunks
+}
+
+/// Files `git ls-files` lists that look like text worth indexing.
+fn tracked_files(root: &str) -> Result<Vec<String>, String> {
+    let output = Command::new("git")
+        .args(["ls-files", "-z"])
+        .current_dir(root)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    let files = String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .filter(|path| {
+            let name = Path::new(path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            !name.ends_with(".lock") && name != "package-lock.json"
+        })
+        .filter(|path| {
+            std::fs::metadata(Path::new(root).join(path))
+                .is_ok_and(|m| m.is_file() && m.len() <= MAX_FILE_BYTES)
+        })
+        .map(|path| path.to_string())
+        .collect();
+    Ok(files)
+}
+
+/// Runs `copilot index`: embeds the chunks of every tracked text file in the current
+/// repository. Files that didn't change since the last run keep their embeddings. Returns
+/// the number of files and chunks in the index.
+pub async fn build(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+) -> Result<(usize, usize), String> {
+    let root = repo_root().ok_or("The index is built for a git repository, run this in one")?;
+    let config = Config::load();
+    let auth = auth_manager
+        .stored_auth()
+        .await
+        .map_err(|e| e.to_string())?;
+    let copilot_m =
+        CopilotManager::new(&auth, client, config.system_prompt()).with_endpoint(config.endpoint);
+
+    let mut previous = Index::load_current().unwrap_or_default();
+    if previous.model != config.embedding_model {
+        previous.files.clear();
+    }
+    let mut index = Index {
+        root: root.clone(),
+        model: config.embedding_model.clone(),
+        files: BTreeMap::new(),
+    };
+
+    // what still needs an embedding, as (path, start, end, text)
+    let mut pending = Vec::new();
+    for path in tracked_files(&root)? {
+        let Ok(bytes) = std::fs::read(Path::new(&root).join(&path)) else {
+            continue;
+        };
+        if bytes.contains(&0) {
+            continue;
+        }
+        let Ok(text) = String::from_utf8(bytes) else {
+            continue;
+        };
+        let hash = hex(&Sha256::digest(text.as_bytes()));
+        match previous.files.remove(&path) {
+            Some(file) if file.hash == hash => {
+                index.files.insert(path, file);
+            }
+            _ => {
+                for (start, end, chunk) in chunk_lines(&text) {
+                    pending.push((path.clone(), start, end, chunk));
+                }
+                index.files.insert(
+                    path,
+                    IndexedFile {
+                        hash,
+                        chunks: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+
+    let total = pending.len();
+    for (i, batch) in pending.chunks(BATCH_SIZE).enumerate() {
+        println!(
+            "Embedding chunks {}-{} of {}...",
+            i * BATCH_SIZE + 1,
+            i * BATCH_SIZE + batch.len(),
+            total
+        );
+        // with the path, questions naming a file find its code
+        let inputs: Vec<String> = batch
+            .iter()
+            .map(|(path, _, _, text)| format!("{}\n{}", path, text))
+            .collect();
+        let embeddings = match copilot_m.embed(&config.embedding_model, &inputs).await {
+            Ok(embeddings) => embeddings,
+            Err(e) => {
+                // keep the files that are done, the next run picks up from there
+                for (path, ..) in &pending[i * BATCH_SIZE..] {
+                    index.files.remove(path);
+                }
+                index.save()?;
+                return Err(e);
+            }
+        };
+        for ((path, start, end, text), embedding) in batch.iter().zip(embeddings) {
+            index.files.get_mut(path).unwrap().chunks.push(Chunk {
+                path: path.clone(),
+                start: *start,
+                end: *end,
+                text: text.clone(),
+                embedding,
+            });
+        }
+    }
+
+    index.save()?;
+    Ok((index.files.len(), index.chunk_count()))
+}
+
+/// Attaches the chunks of `index` most relevant to `question` as context and returns their
+/// labels, e.g. `src/gh.rs:41-80`.
+pub async fn attach_relevant(
+    copilot_m: &mut CopilotManager<'_>,
+    index: &Index,
+    question: &str,
+    limit: usize,
+) -> Result<Vec<String>, String> {
+    let embedding = copilot_m
+        .embed(&index.model, &[question.to_string()])
+        .await?
+        .pop()
+        .ok_or("No embedding came back for the question")?;
+
+    let mut labels = Vec::new();
+    for chunk in index.search(&embedding, limit) {
+        let label = format!("{}:{}-{}", chunk.path, chunk.start, chunk.end);
+        let lang = codeblocks::lang_for_path(Path::new(&chunk.path));
+        copilot_m.attach_context(&format!("From {}", label), lang, &chunk.text);
+        labels.push(label);
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_overlap() {
+        let text: String = (1..=100).map(|n| format!("line {}\n", n)).collect();
+        let chunks = chunk_lines(&text);
+        let ranges: Vec<(usize, usize)> = chunks.iter().map(|(s, e, _)| (*s, *e)).collect();
+        assert_eq!(ranges, [(1, 40), (33, 72), (65, 100)]);
+        assert!(chunks[2].2.ends_with("line 100"));
+        assert!(chunk_lines("\n\n").is_empty());
+    }
+
+    #[test]
+    fn finds_the_most_similar_chunks() {
+        let chunk = |path: &str, embedding: Vec<f32>| Chunk {
+            path: path.to_string(),
+            start: 1,
+            end: 1,
+            text: String::new(),
+            embedding,
+        };
+        let mut index = Index::default();
+        index.files.insert(
+            "a".to_string(),
+            IndexedFile {
+                hash: String::new(),
+                chunks: vec![
+                    chunk("auth.rs", vec![1.0, 0.0]),
+                    chunk("cache.rs", vec![0.7, 0.7]),
+                    chunk("unrelated.rs", vec![-1.0, 0.0]),
+                ],
+            },
+        );
+
+        let found: Vec<&str> = index
+            .search(&[1.0, 0.1], 5)
+            .iter()
+            .map(|c| c.path.as_str())
+            .collect();
+        assert_eq!(found, ["auth.rs", "cache.rs"]);
+        assert_eq!(index.search(&[1.0, 0.1], 1).len(), 1);
+    }
+}
@@ -0,0 +1,107 @@
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::{config::WebSearchProvider, http_cache};
+
+const MAX_RESULTS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// Searches the web with the configured provider and returns the top results.
+pub async fn search(
+    client: &Client,
+    provider: &WebSearchProvider,
+    query: &str,
+) -> Result<Vec<SearchResult>, String> {
+    let request = match provider {
+        WebSearchProvider::Searxng { url } => client
+            .get(format!("{}/search", url.trim_end_matches('/')))
+            .query(&[("q", query), ("format", "json")]),
+        WebSearchProvider::Brave { api_key } => client
+            .get("https://api.search.brave.com/res/v1/web/search")
+            .query(&[("q", query)])
+            .header("X-Subscription-Token", api_key)
+            .header("Accept", "application/json"),
+        WebSearchProvider::Bing { api_key } => client
+            .get("https://api.bing.microsoft.com/v7.0/search")
+            .query(&[("q", query)])
+            .header("Ocp-Apim-Subscription-Key", api_key),
+    };
+
+    let body = http_cache::get(client, request).await?;
+    let json = serde_json::from_str::<Value>(&body).map_err(|e| e.to_string())?;
+
+    // (path to the result list, title field, snippet field)
+    let (list, title, snippet) = match provider {
+        WebSearchProvider::Searxng { .. } => ("/results", "title", "content"),
+        WebSearchProvider::Brave { .. } => ("/web/results", "title", "description"),
+        WebSearchProvider::Bing { .. } => ("/webPages/value", "name", "snippet"),
+    };
+
+    let field = |item: &Value, name: &str| item[name].as_str().unwrap_or("").to_string();
+
+    let results = json
+        .pointer(list)
+        .and_then(|v| v.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .take(MAX_RESULTS)
+                .map(|item| SearchResult {
+                    title: field(item, title),
+                    url: field(item, "url"),
+                    snippet: field(item, snippet),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(results)
+}
+
+/// Formats the results as numbered sources for the model to cite as `[n]`.
+pub fn format_context(results: &[SearchResult]) -> String {
+    let sources = results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| format!("[{}] {}\n{}\n{}", i + 1, r.title, r.url, r.snippet))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    format!(
+        "{}\n\nUse these search results to answer and cite them inline as [1], [2], ...",
+        sources
+    )
+}
+
+/// Renders the sources cited in `answer` as a numbered footnote list.
+/// If the answer doesn't cite anything, every source is listed.
+pub fn format_citations(answer: &str, results: &[SearchResult]) -> String {
+    let cited: Vec<(usize, &SearchResult)> = results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (i + 1, r))
+        .filter(|(n, _)| answer.contains(&format!("[{}]", n)))
+        .collect();
+
+    let listed = if cited.is_empty() {
+        results
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (i + 1, r))
+            .collect()
+    } else {
+        cited
+    };
+
+    listed
+        .iter()
+        .map(|(n, r)| format!("[{}] {} - {}", n, r.title, r.url))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,478 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    copilot::{Turn, Usage},
+    tasks::Task,
+    utils,
+};
+
+/// One message of a stored conversation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredMessage {
+    /// `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+    pub time: i64,
+    /// The model that wrote an answer.
+    pub model: Option<String>,
+    /// The tokens an answer took.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// A file `/apply` wrote from one of the session's answers, kept to track what was generated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    pub path: String,
+    pub time: i64,
+    /// The model that was answering when the file was written.
+    pub model: String,
+}
+
+/// A conversation as it is kept under `sessions/` in the config directory, rewritten after
+/// every exchange so `--resume` can pick it up again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    /// The file name, which is the name for named sessions.
+    pub id: String,
+    /// Set for sessions made with `/session new` or `--session`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Replaces the default system prompt for this conversation.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    pub started: i64,
+    pub messages: Vec<StoredMessage>,
+    /// The checklist from `/tasks new`, with what was checked off.
+    #[serde(default)]
+    pub tasks: Vec<Task>,
+    /// For a branch made with `/fork`, the session it came from and how many of its
+    /// messages it started with.
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub forked_at: Option<usize>,
+    /// Files written from answers, see `GeneratedFile`.
+    #[serde(default)]
+    pub generated: Vec<GeneratedFile>,
+}
+
+/// A message of a stored conversation found by `search`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    /// The id of the session, which `--session` and `Session::load` take.
+    pub session: String,
+    pub name: Option<String>,
+    /// Where the message is in the session's `messages`.
+    pub index: usize,
+    pub role: String,
+    pub time: i64,
+    /// The line that matched, shortened around the match.
+    pub snippet: String,
+}
+
+/// Every stored message containing `query`, ignoring case, newest first.
+pub fn search(query: &str) -> Vec<SearchHit> {
+    let mut hits: Vec<SearchHit> = Session::load_all()
+        .iter()
+        .flat_map(|session| {
+            session
+                .messages
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, message)| {
+                    Some(SearchHit {
+                        session: session.id.clone(),
+                        name: session.name.clone(),
+                        index,
+                        role: message.role.clone(),
+                        time: message.time,
+                        snippet: snippet(&message.content, query)?,
+                    })
+                })
+        })
+        .collect();
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.time));
+    hits
+}
+
+/// About `SNIPPET_CHARS` around the first line of `text` containing `query`, ignoring case.
+fn snippet(text: &str, query: &str) -> Option<String> {
+    const SNIPPET_CHARS: usize = 80;
+
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+    let line = text
+        .lines()
+        .find(|line| line.to_lowercase().contains(&query))?
+        .trim();
+
+    let chars: Vec<char> = line.chars().collect();
+    let lowered: Vec<char> = line.to_lowercase().chars().collect();
+    let needle: Vec<char> = query.chars().collect();
+    // lowercasing may change the length, then the line is shown from the start
+    let at = if lowered.len() == chars.len() {
+        lowered
+            .windows(needle.len())
+            .position(|window| window == needle.as_slice())
+            .unwrap_or(0)
+    } else {
+        0
+    };
+
+    let start = at.saturating_sub(SNIPPET_CHARS / 2);
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+    let start = end.saturating_sub(SNIPPET_CHARS);
+    let mut snippet: String = chars[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < chars.len() {
+        snippet.push_str("...");
+    }
+    Some(snippet)
+}
+
+fn sessions_dir() -> PathBuf {
+    PathBuf::from(utils::get_config_path()).join("sessions")
+}
+
+fn session_path(id: &str) -> PathBuf {
+    sessions_dir().join(format!("{}.json", id))
+}
+
+/// `base`, or `base-2`, `base-3` and so on if a session by that name already exists.
+pub fn free_name(base: &str) -> String {
+    (1..)
+        .map(|n| match n {
+            1 => base.to_string(),
+            n => format!("{}-{}", base, n),
+        })
+        .find(|name| !session_path(name).exists())
+        .unwrap()
+}
+
+fn scratch_path(id: &str) -> PathBuf {
+    sessions_dir().join(format!("{}.scratch.md", id))
+}
+
+/// Session names end up as file names, so they're kept to letters, digits, `-`, `_` and `.`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid session name {}, use letters, digits, -, _ and .",
+            name
+        ))
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        let uuid = utils::generate_random_uuid4();
+        Session {
+            // sorts by start time, the uuid keeps two sessions started together apart
+            id: format!(
+                "{}-{}",
+                chrono::Local::now().format("%Y%m%d-%H%M%S"),
+                &uuid[..8]
+            ),
+            name: None,
+            prompt: None,
+            started: chrono::Utc::now().timestamp(),
+            messages: Vec::new(),
+            tasks: Vec::new(),
+            parent: None,
+            forked_at: None,
+            generated: Vec::new(),
+        }
+    }
+
+    /// The named session `name`, or a new empty one if there is none yet.
+    pub fn named(name: &str) -> Result<Session, String> {
+        check_name(name)?;
+        match Session::load(name)? {
+            Some(session) => Ok(session),
+            None => Ok(Session {
+                id: name.to_string(),
+                name: Some(name.to_string()),
+                ..Session::new()
+            }),
+        }
+    }
+
+    /// The session stored as `id`, `None` if there is no such file.
+    pub fn load(id: &str) -> Result<Option<Session>, String> {
+        let path = session_path(id);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    /// Every named session, most recently used first.
+    pub fn list_named() -> Vec<Session> {
+        let mut sessions: Vec<Session> = Session::load_all()
+            .into_iter()
+            .filter(|session| session.name.is_some())
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_active()));
+        sessions
+    }
+
+    /// Every stored session that parses, in no particular order.
+    fn load_all() -> Vec<Session> {
+        std::fs::read_dir(sessions_dir())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "json"))
+            .filter_map(|entry| {
+                let text = std::fs::read_to_string(entry.path()).ok()?;
+                serde_json::from_str::<Session>(&text).ok()
+            })
+            .collect()
+    }
+
+    pub fn delete(name: &str) -> Result<(), String> {
+        check_name(name)?;
+        let path = session_path(name);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                let _ = std::fs::remove_file(scratch_path(name));
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(format!("There is no session named {}", name))
+            }
+            Err(e) => Err(format!("Failed to delete {}: {}", path.display(), e)),
+        }
+    }
+
+    /// The session's scratchpad, which `/scratch` opens and `@scratch` attaches. It may not
+    /// exist yet.
+    pub fn scratch_path(&self) -> PathBuf {
+        scratch_path(&self.id)
+    }
+
+    /// When the last message was stored, or when the session started if it has none.
+    pub fn last_active(&self) -> i64 {
+        self.messages.last().map_or(self.started, |m| m.time)
+    }
+
+    /// The conversation saved most recently, if there is one.
+    pub fn latest() -> Option<Session> {
+        let entries = std::fs::read_dir(sessions_dir()).ok()?;
+        let newest = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "json"))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .max()?;
+        let text = std::fs::read_to_string(newest.1).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Adds the messages of `transcript` that aren't stored yet, timestamped now. Stored
+    /// messages that differ from the transcript, like a regenerated answer, are replaced.
+    pub fn update<'t>(&mut self, transcript: impl Iterator<Item = Turn<'t>>) {
+        let now = chrono::Utc::now().timestamp();
+        let mut transcript = transcript.peekable();
+        let mut kept = 0;
+        while kept < self.messages.len()
+            && transcript
+                .next_if(|turn| turn.content == self.messages[kept].content)
+                .is_some()
+        {
+            kept += 1;
+        }
+        self.messages.truncate(kept);
+
+        for turn in transcript {
+            self.messages.push(StoredMessage {
+                role: turn.role.to_string(),
+                content: turn.content.to_string(),
+                time: now,
+                model: turn.model.map(|m| m.to_string()),
+                usage: turn.usage,
+            });
+        }
+    }
+
+    /// A branch named `name` with the messages up to message `at` (1-based), leaving this
+    /// session as it is. Branching at a question leaves that question out, so it can be asked
+    /// differently.
+    pub fn fork(&self, at: usize, name: &str) -> Session {
+        let at = at.min(self.messages.len());
+        // imported and edited conversations don't always alternate, so go by the role
+        let keep = match at.checked_sub(1).map(|i| &self.messages[i]) {
+            Some(message) if message.role == "user" => at - 1,
+            _ => at,
+        };
+        Session {
+            id: name.to_string(),
+            name: Some(name.to_string()),
+            prompt: self.prompt.clone(),
+            messages: self.messages[..keep].to_vec(),
+            tasks: self.tasks.clone(),
+            parent: Some(self.id.clone()),
+            forked_at: Some(keep),
+            ..Session::new()
+        }
+    }
+
+    /// A free name for the next branch of this session, `<name>-2`, `<name>-3` and so on.
+    pub fn fork_name(&self) -> String {
+        free_name(self.name.as_deref().unwrap_or(&self.id))
+    }
+
+    /// Notes that `path` was written from an answer by `model`.
+    pub fn record_generated(&mut self, path: &std::path::Path, model: &str) {
+        self.generated.push(GeneratedFile {
+            path: path.display().to_string(),
+            time: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+        });
+    }
+
+    /// The tokens of every stored answer together.
+    pub fn usage(&self) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.messages.iter().filter_map(|m| m.usage) {
+            total += usage;
+        }
+        total
+    }
+
+    /// The question and answer message `index` belongs to, as far as they're stored.
+    pub fn exchange_at(&self, index: usize) -> &[StoredMessage] {
+        let start = index - index % 2;
+        &self.messages[start.min(self.messages.len())..(start + 2).min(self.messages.len())]
+    }
+
+    /// Each question with the answer that followed it.
+    pub fn exchanges(&self) -> impl Iterator<Item = (&StoredMessage, &StoredMessage)> {
+        self.messages
+            .chunks_exact(2)
+            .map(|pair| (&pair[0], &pair[1]))
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let dir = sessions_dir();
+        std::fs::create_dir_all(&dir)?;
+        // write and rename, so quitting mid-write doesn't leave half a file to resume from
+        let path = session_path(&self.id);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string(self)?)?;
+        std::fs::rename(partial, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(check_name("rust-refactor").is_ok());
+        assert!(check_name("v1.2_notes").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("../config").is_err());
+        assert!(check_name("a/b").is_err());
+    }
+
+    #[test]
+    fn snippets_center_on_the_match() {
+        assert_eq!(
+            snippet("Sure.\nThe Device Flow polls for a token", "device flow").as_deref(),
+            Some("The Device Flow polls for a token")
+        );
+        assert_eq!(snippet("nothing here", "device flow"), None);
+        assert_eq!(snippet("anything", "  "), None);
+
+        let long = format!("{}device flow{}", "a".repeat(100), "b".repeat(100));
+        let found = snippet(&long, "device flow").unwrap();
+        assert!(found.starts_with("...") && found.ends_with("..."));
+        assert!(found.contains("device flow"));
+        assert_eq!(found.chars().count(), 80 + 6);
+    }
+
+    #[test]
+    fn update_adds_new_turns_only() {
+        let mut session = Session::new();
+        let turn = |role, content, model: Option<&'static str>| Turn {
+            role,
+            content,
+            model,
+            usage: model.map(|_| Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                estimated: false,
+            }),
+        };
+
+        session.update(
+            [
+                turn("user", "hi", None),
+                turn("assistant", "hello", Some("gpt-4")),
+            ]
+            .into_iter(),
+        );
+        session.update(
+            [
+                turn("user", "hi", None),
+                turn("assistant", "hello", Some("gpt-4")),
+                turn("user", "again", None),
+                turn("assistant", "hello again", Some("gpt-4o")),
+            ]
+            .into_iter(),
+        );
+
+        let roles: Vec<&str> = session.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+        let exchanges: Vec<(&str, &str)> = session
+            .exchanges()
+            .map(|(q, a)| (q.content.as_str(), a.content.as_str()))
+            .collect();
+        assert_eq!(exchanges, [("hi", "hello"), ("again", "hello again")]);
+        assert_eq!(session.messages[3].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(session.usage().total(), 30);
+
+        // branching at the second question leaves it out, at its answer keeps it
+        let fork = session.fork(3, "hi-2");
+        assert_eq!(fork.messages.len(), 2);
+        assert_eq!(fork.parent.as_deref(), Some(session.id.as_str()));
+        assert_eq!(session.fork(4, "hi-3").messages.len(), 4);
+        assert_eq!(session.messages.len(), 4);
+        let mut answer_first = session.clone();
+        answer_first.messages.remove(0);
+        assert_eq!(answer_first.fork(2, "hello").messages.len(), 1);
+
+        let time = session.messages[0].time;
+        session.update(
+            [
+                turn("user", "hi", None),
+                turn("assistant", "hello", Some("gpt-4")),
+                turn("user", "again", None),
+                turn("assistant", "another take", Some("gpt-4o")),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(session.messages.len(), 4);
+        assert_eq!(session.messages[3].content, "another take");
+        assert_eq!(session.messages[0].time, time);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use crate::{codeblocks, config::ShellCheck};
+
+/// Shell builtins and keywords, which are never on `PATH`.
+const BUILTINS: &[&str] = &[
+    ".", ":", "[", "[[", "alias", "bg", "break", "case", "cd", "continue", "declare", "do", "done",
+    "echo", "elif", "else", "esac", "eval", "exec", "exit", "export", "false", "fg", "fi", "for",
+    "function", "if", "jobs", "local", "popd", "printf", "pushd", "pwd", "read", "return", "set",
+    "shift", "source", "then", "trap", "true", "type", "ulimit", "umask", "unalias", "unset",
+    "until", "wait", "while", "{", "}",
+];
+
+/// Words that run the command after them.
+const PREFIXES: &[&str] = &["sudo", "env", "time", "nohup", "nice", "command", "xargs"];
+
+/// Well known tools and what to use when they're missing.
+const ALTERNATIVES: &[(&str, &str)] = &[
+    ("exa", "ls"),
+    ("eza", "ls"),
+    ("lsd", "ls"),
+    ("bat", "cat"),
+    ("batcat", "cat"),
+    ("fd", "find"),
+    ("fdfind", "find"),
+    ("rg", "grep"),
+    ("ag", "grep"),
+    ("htop", "top"),
+    ("btop", "top"),
+    ("python", "python3"),
+    ("pip", "pip3"),
+    ("nvim", "vim"),
+    ("gsed", "sed"),
+    ("gawk", "awk"),
+    ("podman", "docker"),
+    ("docker", "podman"),
+    ("dust", "du"),
+    ("duf", "df"),
+    ("http", "curl"),
+    ("wget", "curl"),
+];
+
+/// How long a program's `--help` may take before its flags go unchecked.
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One command of a shell snippet: the program and its arguments.
+#[derive(Debug, PartialEq)]
+struct Invocation {
+    program: String,
+    args: Vec<String>,
+}
+
+/// Splits a line into words, keeping quoted strings together. Good enough for commands in
+/// answers, not a full shell parser.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut in_word = false;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// The commands in a shell code block. In `console` blocks only the lines after a `$`
+/// prompt are commands, the rest is their output. Heredoc bodies are skipped.
+fn invocations(lang: &str, code: &str) -> Vec<Invocation> {
+    let mut lines = Vec::new();
+    let mut continued = String::new();
+    let mut heredoc: Option<String> = None;
+    for line in code.lines() {
+        if let Some(end) = &heredoc {
+            if line.trim() == end {
+                heredoc = None;
+            }
+            continue;
+        }
+        let line = match line.trim_start().strip_prefix("$ ") {
+            Some(command) => command,
+            None if lang == "console" && continued.is_empty() => continue,
+            None => line,
+        };
+        if let Some(start) = line.find("<<") {
+            let end = line[start + 2..]
+                .trim_start_matches('-')
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .trim_matches(|c| c == '\'' || c == '"');
+            if !end.is_empty() {
+                heredoc = Some(end.to_string());
+            }
+        }
+        match line.strip_suffix('\\') {
+            Some(part) => continued.push_str(part),
+            None => {
+                continued.push_str(line);
+                lines.push(std::mem::take(&mut continued));
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    for line in lines {
+        let line = line.split(" #").next().unwrap_or_default();
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        // `2>&1` and `&>` redirect, they don't start another command
+        let line = line.replace(">&", ">").replace("&>", ">");
+        for segment in line.split(['|', ';', '&', '(', ')', '`']) {
+            let mut words = words(segment).into_iter().skip_while(|word| {
+                PREFIXES.contains(&word.as_str())
+                    || word.starts_with('-')
+                    // FOO=bar before the command
+                    || word.split_once('=').is_some_and(|(name, _)| {
+                        !name.is_empty()
+                            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    })
+            });
+            let Some(program) = words.next() else {
+                continue;
+            };
+            let checkable = !BUILTINS.contains(&program.as_str())
+                && !program.contains(['/', '$', '<', '>', '*', '='])
+                && program
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphanumeric());
+            if checkable {
+                found.push(Invocation {
+                    program,
+                    args: words.collect(),
+                });
+            }
+        }
+    }
+    found
+}
+
+/// Whether `program` is an executable file in one of the `PATH` directories.
+fn installed(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+/// What `program --help` prints, `None` if it fails or takes too long.
+fn help_text(program: &str) -> Option<String> {
+    let mut child = Command::new(program)
+        .arg("--help")
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("GIT_PAGER", "cat")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let started = Instant::now();
+    while child.try_wait().ok()?.is_none() {
+        if started.elapsed() > HELP_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let output = child.wait_with_output().ok()?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (!text.trim().is_empty()).then_some(text)
+}
+
+/// Warnings about the commands in `blocks`, with `installed` and `help` to look programs up.
+fn warnings_with(
+    blocks: &[codeblocks::CodeBlock],
+    mode: ShellCheck,
+    installed: impl Fn(&str) -> bool,
+    mut help: impl FnMut(&str) -> Option<String>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut checked: HashMap<String, Option<String>> = HashMap::new();
+    for block in blocks {
+        if !matches!(
+            block.lang.as_str(),
+            "sh" | "bash" | "shell" | "zsh" | "console"
+        ) {
+            continue;
+        }
+        for invocation in invocations(&block.lang, &block.code) {
+            let program = &invocation.program;
+            if !installed(program) {
+                let warning = match ALTERNATIVES
+                    .iter()
+                    .find(|(missing, instead)| missing == program && installed(instead))
+                {
+                    Some((_, instead)) => {
+                        format!("`{}` isn't installed, use `{}`?", program, instead)
+                    }
+                    None => format!("`{}` isn't installed", program),
+                };
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+                continue;
+            }
+
+            // flags after a subcommand, like `git commit --amend`, aren't in the program's
+            // own --help, so only commands that start with a flag are checked
+            let starts_with_flag = invocation
+                .args
+                .first()
+                .is_some_and(|arg| arg.starts_with('-'));
+            if mode != ShellCheck::Flags || !starts_with_flag {
+                continue;
+            }
+            let text = checked
+                .entry(program.clone())
+                .or_insert_with(|| help(program));
+            let Some(text) = text else {
+                continue;
+            };
+            for arg in &invocation.args {
+                let Some(flag) = arg.strip_prefix("--") else {
+                    continue;
+                };
+                let flag = format!("--{}", flag.split('=').next().unwrap_or_default());
+                if flag.len() > 2 && !text.contains(&flag) {
+                    let warning = format!("`{}` doesn't list {} in its --help", program, flag);
+                    if !warnings.contains(&warning) {
+                        warnings.push(warning);
+                    }
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// Checks the shell commands an answer suggests against this machine, with `shell_check`:
+/// that the programs are installed and, with `flags`, that long flags appear in their
+/// `--help`. Runs on the blocking pool, since each `--help` can take up to `HELP_TIMEOUT`.
+pub async fn warnings(answer: &str, mode: ShellCheck) -> Vec<String> {
+    if mode == ShellCheck::Off {
+        return Vec::new();
+    }
+    let blocks = codeblocks::extract(answer);
+    tokio::task::spawn_blocking(move || warnings_with(&blocks, mode, installed, help_text))
+        .await
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_programs_of_commands() {
+        let code = "$ sudo FOO=1 exa -la 2>&1 | rg 'two words' && cd src\nsome output\n$ cat <<EOF\nnot a command\nEOF";
+        let programs: Vec<String> = invocations("console", code)
+            .into_iter()
+            .map(|i| i.program)
+            .collect();
+        assert_eq!(programs, ["exa", "rg", "cat"]);
+        assert_eq!(words("grep -r \"a b\" .").len(), 4);
+
+        let multiline = "docker run \\\n  --rm alpine # comment\n./configure";
+        let found = invocations("sh", multiline);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].args, ["run", "--rm", "alpine"]);
+    }
+
+    #[test]
+    fn warns_about_missing_programs_and_flags() {
+        let answer = "```bash\nexa -l\nls --colour=auto --all\ngit commit --amend\n```";
+        let blocks = codeblocks::extract(answer);
+        let installed = |program: &str| ["ls", "git"].contains(&program);
+        let help = |_: &str| Some("  -a, --all\n      --color[=WHEN]".to_string());
+
+        let warnings = warnings_with(&blocks, ShellCheck::Flags, installed, help);
+        assert_eq!(
+            warnings,
+            [
+                "`exa` isn't installed, use `ls`?",
+                "`ls` doesn't list --colour in its --help"
+            ]
+        );
+        assert_eq!(
+            warnings_with(&blocks, ShellCheck::Path, installed, help).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::{codeblocks, copilot::Completion, filter::Filter};
+
+/// Something that consumes the answer while it streams in: the terminal, a file, a hook...
+pub trait DeltaSink {
+    /// Called for every piece of content as it arrives.
+    fn on_delta(&mut self, content: &str);
+
+    /// Called regularly while the answer streams, also when nothing arrives.
+    fn on_tick(&mut self) {}
+
+    /// Called once the answer is complete.
+    fn on_finish(&mut self, _completion: &Completion) {}
+}
+
+/// Fans every delta out to all subscribed sinks, so `ask` doesn't need to know who listens.
+#[derive(Default)]
+pub struct Fanout {
+    sinks: Vec<Box<dyn DeltaSink>>,
+}
+
+impl Fanout {
+    pub fn subscribe(&mut self, sink: Box<dyn DeltaSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn delta(&mut self, content: &str) {
+        for sink in &mut self.sinks {
+            sink.on_delta(content);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for sink in &mut self.sinks {
+            sink.on_tick();
+        }
+    }
+
+    pub fn finish(&mut self, completion: &Completion) {
+        for sink in &mut self.sinks {
+            sink.on_finish(completion);
+        }
+    }
+}
+
+/// Hands deltas to `sink` at most once per `interval`, joined, so answers streaming in tiny
+/// pieces don't redraw the terminal for each of them (`render_interval_ms`). The first
+/// piece of an answer goes through right away, what's held back goes through on the first
+/// tick after the interval, and whatever is left when it finishes is flushed before
+/// `on_finish`.
+pub struct Coalesce {
+    sink: Box<dyn DeltaSink>,
+    interval: Duration,
+    pending: String,
+    last_flush: Option<Instant>,
+}
+
+impl Coalesce {
+    pub fn new(sink: Box<dyn DeltaSink>, interval: Duration) -> Coalesce {
+        Coalesce {
+            sink,
+            interval,
+            pending: String::new(),
+            last_flush: None,
+        }
+    }
+
+    fn due(&self) -> bool {
+        self.last_flush
+            .is_none_or(|last| last.elapsed() >= self.interval)
+    }
+
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.sink.on_delta(&self.pending);
+            self.pending.clear();
+        }
+        self.last_flush = Some(Instant::now());
+    }
+}
+
+impl DeltaSink for Coalesce {
+    fn on_delta(&mut self, content: &str) {
+        self.pending.push_str(content);
+        if self.due() {
+            self.flush();
+        }
+    }
+
+    fn on_tick(&mut self) {
+        if !self.pending.is_empty() && self.due() {
+            self.flush();
+        }
+        self.sink.on_tick();
+    }
+
+    fn on_finish(&mut self, completion: &Completion) {
+        self.flush();
+        self.last_flush = None;
+        self.sink.on_finish(completion);
+    }
+}
+
+/// Writes the answer to stdout as it arrives, without highlighting, for `copilot ask` when its
+/// output goes to another program.
+#[derive(Default)]
+pub struct StdoutSink {
+    ends_with_newline: bool,
+    written: bool,
+}
+
+impl DeltaSink for StdoutSink {
+    fn on_delta(&mut self, content: &str) {
+        if content.is_empty() {
+            return;
+        }
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(content.as_bytes());
+        let _ = stdout.flush();
+        self.ends_with_newline = content.ends_with('\n');
+        self.written = true;
+    }
+
+    fn on_finish(&mut self, _completion: &Completion) {
+        if self.written && !self.ends_with_newline {
+            println!();
+        }
+        self.written = false;
+    }
+}
+
+/// Writes each answer to a file as plain text (`--out answer.md`).
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: &str) -> std::io::Result<FileSink> {
+        Ok(FileSink {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl DeltaSink for FileSink {
+    fn on_delta(&mut self, content: &str) {
+        let _ = self.writer.write_all(content.as_bytes());
+    }
+
+    fn on_finish(&mut self, _completion: &Completion) {
+        let _ = self.writer.write_all(b"\n");
+        let _ = self.writer.flush();
+    }
+}
+
+/// Writes one JSON object per line for each delta and a final `done` event (`--json-stream`).
+pub struct JsonStreamSink {
+    writer: Box<dyn Write>,
+}
+
+impl JsonStreamSink {
+    /// `-` streams to stdout, anything else is a file path.
+    pub fn create(path: &str) -> std::io::Result<JsonStreamSink> {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        Ok(JsonStreamSink { writer })
+    }
+
+    fn write_event(&mut self, event: serde_json::Value) {
+        let _ = writeln!(self.writer, "{}", event);
+        let _ = self.writer.flush();
+    }
+}
+
+impl DeltaSink for JsonStreamSink {
+    fn on_delta(&mut self, content: &str) {
+        self.write_event(json!({ "type": "delta", "content": content }));
+    }
+
+    fn on_finish(&mut self, completion: &Completion) {
+        self.write_event(json!({
+            "type": "done",
+            "content": completion.content,
+            "finish_reason": completion.finish_reason,
+            "content_filter": completion.filtered,
+        }));
+    }
+}
+
+/// Runs a shell command after every answer with the answer on stdin (`notify_command`),
+/// e.g. `notify-send Copilot "answer ready"`.
+pub struct HookSink {
+    command: String,
+}
+
+impl HookSink {
+    pub fn new(command: &str) -> HookSink {
+        HookSink {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl DeltaSink for HookSink {
+    fn on_delta(&mut self, _content: &str) {}
+
+    fn on_finish(&mut self, completion: &Completion) {
+        let child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("COPILOT_FINISH_REASON", &completion.finish_reason)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+
+        // a hook that doesn't read its stdin would block the write once the pipe is full,
+        // so the answer is handed over on another thread, which also reaps the hook
+        if let Ok(mut child) = child {
+            let content = completion.content.clone();
+            std::thread::spawn(move || {
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = stdin.write_all(content.as_bytes());
+                }
+                let _ = child.wait();
+            });
+        }
+    }
+}
+
+/// Prints what `--filter` picks out of each answer, one JSON value per line like jq. The
+/// answer has to be JSON, on its own or in a code block.
+pub struct FilterSink {
+    filter: Filter,
+}
+
+impl FilterSink {
+    pub fn new(filter: Filter) -> FilterSink {
+        FilterSink { filter }
+    }
+}
+
+impl DeltaSink for FilterSink {
+    fn on_delta(&mut self, _content: &str) {}
+
+    fn on_finish(&mut self, completion: &Completion) {
+        let Some(value) = codeblocks::find_json(&completion.content) else {
+            eprintln!("--filter: the answer isn't JSON");
+            return;
+        };
+
+        match self.filter.apply(&value) {
+            Ok(results) => {
+                let mut stdout = std::io::stdout().lock();
+                for result in results {
+                    let _ = writeln!(stdout, "{}", result);
+                }
+                let _ = stdout.flush();
+            }
+            Err(e) => eprintln!("--filter: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl DeltaSink for Recorder {
+        fn on_delta(&mut self, content: &str) {
+            self.0.borrow_mut().push(content.to_string());
+        }
+    }
+
+    #[test]
+    fn coalesces_deltas_until_the_interval_passes() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut sink = Coalesce::new(Box::new(Recorder(seen.clone())), Duration::from_secs(60));
+        for piece in ["He", "ll", "o", "!"] {
+            sink.on_delta(piece);
+        }
+        assert_eq!(*seen.borrow(), ["He"]);
+
+        let completion = Completion {
+            content: "Hello!".to_string(),
+            finish_reason: "stop".to_string(),
+            usage: Default::default(),
+            filtered: Vec::new(),
+            timings: Default::default(),
+            candidates: Vec::new(),
+        };
+        sink.on_finish(&completion);
+        assert_eq!(*seen.borrow(), ["He", "llo!"]);
+
+        let mut immediate = Coalesce::new(Box::new(Recorder(seen.clone())), Duration::ZERO);
+        immediate.on_delta("a");
+        immediate.on_delta("b");
+        assert_eq!(seen.borrow().len(), 4);
+    }
+
+    #[test]
+    fn ticks_flush_what_a_stall_held_back() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut sink = Coalesce::new(Box::new(Recorder(seen.clone())), Duration::from_millis(20));
+        sink.on_delta("He");
+        sink.on_delta("llo");
+        sink.on_tick();
+        assert_eq!(*seen.borrow(), ["He"]);
+
+        std::thread::sleep(Duration::from_millis(30));
+        sink.on_tick();
+        assert_eq!(*seen.borrow(), ["He", "llo"]);
+        sink.on_tick();
+        assert_eq!(seen.borrow().len(), 2);
+    }
+}
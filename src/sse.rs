@@ -0,0 +1,129 @@
+/// One server-sent event. `data` lines are joined with `\n`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Turns the bytes of a `text/event-stream` body into events, however the body is chunked.
+/// Follows the parts of the spec the completions API uses: `\n`, `\r\n` and `\r` line
+/// endings, `:` comment lines (heartbeats), multi-line `data` and `event` names.
+#[derive(Default)]
+pub struct Decoder {
+    /// Bytes of a line that hasn't ended yet, kept as bytes so a chunk boundary inside a
+    /// multi-byte character doesn't garble it.
+    line: Vec<u8>,
+    /// The last chunk ended in `\r`, so a `\n` at the start of the next one is part of it.
+    after_cr: bool,
+    event: Option<String>,
+    data: Option<String>,
+}
+
+impl Decoder {
+    /// Feeds a chunk and returns the events it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for &byte in chunk {
+            let after_cr = std::mem::replace(&mut self.after_cr, false);
+            match byte {
+                b'\n' if after_cr => {}
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    let line = std::mem::take(&mut self.line);
+                    if let Some(event) = self.line_ended(&String::from_utf8_lossy(&line)) {
+                        events.push(event);
+                    }
+                }
+                _ => self.line.push(byte),
+            }
+        }
+
+        events
+    }
+
+    fn line_ended(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            let data = self.data.take()?;
+            return Some(Event {
+                event: self.event.take(),
+                data,
+            });
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "event" => self.event = Some(value.to_string()),
+            // `id` and `retry` only matter for reconnecting, which a completion never does
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut decoder = Decoder::default();
+        chunks.iter().flat_map(|c| decoder.push(c)).collect()
+    }
+
+    fn data(events: &[Event]) -> Vec<&str> {
+        events.iter().map(|e| e.data.as_str()).collect()
+    }
+
+    #[test]
+    fn events_split_across_chunks() {
+        let body = "data: {\"a\":1}\n\ndata: {\"b\":2}\n\ndata: [DONE]\n\n".as_bytes();
+        let whole = decode(&[body]);
+        assert_eq!(data(&whole), ["{\"a\":1}", "{\"b\":2}", "[DONE]"]);
+
+        // every possible split point, including mid-token and between the two newlines
+        for i in 0..body.len() {
+            assert_eq!(decode(&[&body[..i], &body[i..]]), whole, "split at {}", i);
+        }
+    }
+
+    #[test]
+    fn multibyte_characters_split_across_chunks() {
+        let body = "data: {\"content\":\"héllo ✓\"}\n\n".as_bytes();
+        for i in 0..body.len() {
+            assert_eq!(
+                data(&decode(&[&body[..i], &body[i..]])),
+                ["{\"content\":\"héllo ✓\"}"]
+            );
+        }
+    }
+
+    #[test]
+    fn comments_line_endings_and_fields() {
+        let events = decode(&[
+            b": keep-alive\r\n\r\n",
+            b"event: usage\rdata:first\r",
+            b"\ndata: second\r\n\r\n",
+            b"id: 7\nretry: 100\n\n",
+        ]);
+        assert_eq!(
+            events,
+            [Event {
+                event: Some("usage".to_string()),
+                data: "first\nsecond".to_string(),
+            }]
+        );
+    }
+}
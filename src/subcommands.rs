@@ -0,0 +1,528 @@
+use reqwest::Client;
+
+use crate::{
+    backup,
+    cli::{AuthCommand, BackupCommand, EditsCommand, ExportFormat, LoginMethod},
+    config::Config,
+    copilot::{self, CopilotManager, ModelInfo, Sampling},
+    edits, feedback,
+    gh::{AuthError, AuthenticationManager, GithubAuth},
+    html, input, patch, prompts, redact, sessions, templates, term, utils,
+};
+
+/// Runs `copilot auth <command>`, returning the process exit code.
+pub async fn auth(command: AuthCommand, auth_manager: &AuthenticationManager) -> i32 {
+    match command {
+        AuthCommand::Login(method) => {
+            let result = match method {
+                LoginMethod::DeviceFlow => auth_manager.login().await,
+                LoginMethod::Web => auth_manager.login_web().await,
+                LoginMethod::WithToken => {
+                    let token = match input::read_secret("Paste a personal access token: ") {
+                        Ok(token) if !token.trim().is_empty() => token,
+                        Ok(_) => {
+                            eprintln!("No token given");
+                            return 1;
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to read the token: {}", e);
+                            return 1;
+                        }
+                    };
+                    auth_manager.login_with_token(&token).await
+                }
+            };
+            login_result(result, auth_manager)
+        }
+        AuthCommand::Logout => {
+            if auth_manager.logout() {
+                println!("Logged out of profile {}", auth_manager.profile());
+            } else {
+                println!("Profile {} was not logged in", auth_manager.profile());
+            }
+            0
+        }
+        AuthCommand::Status => {
+            if !auth_manager.is_logged_in() {
+                println!("Not logged in (profile {})", auth_manager.profile());
+                return 1;
+            }
+
+            let auth = match auth_manager.stored_auth().await {
+                Ok(auth) => auth,
+                Err(AuthError::Unauthorized) => {
+                    eprintln!(
+                        "Token rejected for profile {}, log in again with copilot auth login",
+                        auth_manager.profile()
+                    );
+                    return 1;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Stored token for profile {} is invalid: {}",
+                        auth_manager.profile(),
+                        e
+                    );
+                    return 1;
+                }
+            };
+            let scopes = auth_manager
+                .gh_token_scopes(&auth.token)
+                .await
+                .unwrap_or_default();
+            let expires = chrono::DateTime::from_timestamp(auth.copilot_auth.expires_at as i64, 0)
+                .map(|t| t.with_timezone(&chrono::Local).to_rfc2822())
+                .unwrap_or(auth.copilot_auth.expires_at.to_string());
+
+            println!("Profile:     {}", auth_manager.profile());
+            println!("Host:        {}", auth_manager.host());
+            if auth_manager.has_external_token() {
+                println!("Token:       from --token-cmd/--token-fd or GH_TOKEN/GITHUB_TOKEN");
+            }
+            println!(
+                "User:        {} ({}) [{}]",
+                auth.user.login,
+                auth.user.name.as_deref().unwrap_or("no name"),
+                auth.user.initials()
+            );
+            println!(
+                "Scopes:      {}",
+                if scopes.is_empty() { "(none)" } else { &scopes }
+            );
+            println!(
+                "Copilot SKU: {} ({})",
+                auth.copilot_auth.plan_name(),
+                auth.copilot_auth.sku
+            );
+            println!("Expires:     {}", expires);
+
+            if let Some(profile) = auth_manager.stored_profile() {
+                let time = |t: Option<i64>| {
+                    t.and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                        .map(|t| t.with_timezone(&chrono::Local).to_rfc2822())
+                        .unwrap_or("unknown".to_string())
+                };
+                println!("Stored:      {}", time(profile.created_at));
+                println!("Verified:    {}", time(profile.last_verified_at));
+            }
+            0
+        }
+    }
+}
+
+/// Runs `copilot whoami`, returning the process exit code.
+pub async fn whoami(auth_manager: &AuthenticationManager) -> i32 {
+    if !auth_manager.is_logged_in() {
+        println!("Not logged in (profile {})", auth_manager.profile());
+        return 1;
+    }
+
+    match auth_manager.stored_auth().await {
+        Ok(auth) => {
+            print_whoami(&auth);
+            0
+        }
+        Err(AuthError::Unauthorized) => {
+            eprintln!(
+                "Token rejected for profile {}, log in again with copilot auth login",
+                auth_manager.profile()
+            );
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Runs `copilot models`.
+pub async fn models(auth_manager: &AuthenticationManager, client: &Client) -> i32 {
+    let auth = match auth_manager.cache_auth().await {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let copilot_m = CopilotManager::new(&auth, client, prompts::COPILOT_INSTRUCTIONS)
+        .with_endpoint(Config::load().endpoint);
+    match copilot_m.models().await {
+        Ok(models) => {
+            print_models(&models, None);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Runs `copilot run <template> [args]`: asks the filled in template once and prints the
+/// answer.
+pub async fn run_template(
+    auth_manager: &AuthenticationManager,
+    client: &Client,
+    model: &str,
+    system_prompt: &str,
+    streaming: bool,
+    template: &str,
+    args: &str,
+) -> i32 {
+    let prompt = match templates::load(template)
+        .and_then(|t| templates::render(&t, args, templates::read_file, utils::read_clipboard))
+    {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let auth = match auth_manager.cache_auth().await {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let config = Config::load();
+    let mut copilot_m = CopilotManager::new(&auth, client, system_prompt)
+        .with_endpoint(config.endpoint)
+        .with_glossary(&config.glossary)
+        .with_sampling(Sampling::from_config(&config))
+        .with_retries(&config)
+        .with_streaming(streaming);
+    copilot_m.set_model(model);
+    copilot_m.subscribe(Box::new(term::TerminalSink::default()));
+
+    let completion = copilot_m.ask(&prompt).await;
+    if completion.finish_reason == copilot::FINISH_ERROR {
+        1
+    } else {
+        0
+    }
+}
+
+/// Prints one model per line, marking `current` with a `*`.
+pub fn print_models(models: &[ModelInfo], current: Option<&str>) {
+    let width = models.iter().map(|m| m.id.len()).max().unwrap_or(0).max(2);
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    println!(
+        "  {:width$}  {:>8}  {:9}  {:6}  TYPE",
+        "ID", "CONTEXT", "STREAMING", "VISION"
+    );
+    for model in models {
+        let marker = if current == Some(model.id.as_str()) {
+            "*"
+        } else {
+            " "
+        };
+        let context = match model.capabilities.limits.max_context_window_tokens {
+            Some(tokens) => tokens.to_string(),
+            None => "?".to_string(),
+        };
+        println!(
+            "{} {:width$}  {:>8}  {:9}  {:6}  {}",
+            marker,
+            model.id,
+            context,
+            yes_no(model.capabilities.supports.streaming),
+            yes_no(model.capabilities.supports.vision),
+            model.capabilities.kind
+        );
+    }
+}
+
+/// Prints who the token belongs to and what their Copilot plan allows.
+pub fn print_whoami(auth: &GithubAuth) {
+    let features = auth.copilot_auth.enabled_features();
+
+    println!("Login:    {}", auth.user.login);
+    println!(
+        "Name:     {}",
+        auth.user.name.as_deref().unwrap_or("no name")
+    );
+    println!(
+        "Plan:     {} ({})",
+        auth.copilot_auth.plan_name(),
+        auth.copilot_auth.sku
+    );
+    println!(
+        "Features: {}",
+        if features.is_empty() {
+            "(none)".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+}
+
+/// Prints how answers were rated with `/good` and `/bad`, overall and per model.
+pub fn print_feedback_stats() {
+    let stats = feedback::stats(&feedback::load());
+    let total = stats.total.good + stats.total.bad;
+    if total == 0 {
+        println!("No feedback yet, rate answers with /good or /bad <reason>");
+        return;
+    }
+
+    println!(
+        "{} rated answers: {} good, {} bad ({:.0}% good)",
+        total,
+        stats.total.good,
+        stats.total.bad,
+        stats.total.good as f64 / total as f64 * 100.0
+    );
+    for (model, tally) in &stats.by_model {
+        println!("  {}: {} good, {} bad", model, tally.good, tally.bad);
+    }
+    if !stats.bad_reasons.is_empty() {
+        println!("Most common complaints:");
+        for (reason, count) in stats.bad_reasons.iter().take(5) {
+            println!("  {}x {}", count, reason);
+        }
+    }
+}
+
+fn login_result(
+    result: Result<GithubAuth, AuthError>,
+    auth_manager: &AuthenticationManager,
+) -> i32 {
+    match result {
+        Ok(auth) => {
+            println!(
+                "Logged in as {} (profile {})",
+                auth.user.login,
+                auth_manager.profile()
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Login failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Runs `copilot backup <command>`, returning the process exit code.
+pub fn backup(command: BackupCommand) -> i32 {
+    match command {
+        BackupCommand::Create {
+            path,
+            include_credentials,
+        } => match backup::create(path.as_deref(), include_credentials) {
+            Ok((path, left_out)) => {
+                println!("Wrote {}", path.display());
+                if !left_out.is_empty() {
+                    println!(
+                        "Left out {} for holding credentials, add --include-credentials to keep them",
+                        left_out.join(", ")
+                    );
+                } else if include_credentials {
+                    println!("It contains your tokens, keep it somewhere safe");
+                }
+                0
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        },
+        BackupCommand::Restore { path } => {
+            match backup::restore(&path) {
+                Ok(restored) => {
+                    println!("Restored the config and {} files", restored.files);
+                    if !restored.skipped.is_empty() {
+                        println!(
+                            "Kept {} existing files instead of the backup's: {}",
+                            restored.skipped.len(),
+                            restored.skipped.join(", ")
+                        );
+                    }
+                    if !restored.credentials {
+                        println!("The backup has no credentials, log in with `copilot auth login` if needed");
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            }
+        }
+    }
+}
+
+/// Who wrote a message, as exports show it.
+pub fn speaker(role: &str, model: Option<&str>) -> String {
+    match (role, model) {
+        ("user", _) => "You".to_string(),
+        (_, Some(model)) => format!("Copilot ({})", model),
+        (_, None) => "Copilot".to_string(),
+    }
+}
+
+/// A conversation as (who, markdown) pairs in an export `format`.
+pub fn render_export(format: ExportFormat, title: &str, messages: &[(String, String)]) -> String {
+    match format {
+        ExportFormat::Markdown => messages
+            .iter()
+            .map(|(who, content)| format!("## {}\n\n{}\n\n", who, content.trim()))
+            .collect(),
+        ExportFormat::Html => html::render(title, messages),
+    }
+}
+
+/// Runs `copilot export`: writes the `--session` conversation, or the latest one, redacted
+/// with the `default` profile. Returns the process exit code.
+pub fn export(session: Option<&str>, path: Option<&str>, format: ExportFormat) -> i32 {
+    let session = match session {
+        Some(name) => sessions::Session::named(name).map(Some),
+        None => Ok(sessions::Session::latest()),
+    };
+    let session = match session {
+        Ok(Some(session)) if !session.messages.is_empty() => session,
+        Ok(_) => {
+            eprintln!("There is no conversation to export");
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let config = Config::load();
+    let redactions = match redact::profile(&config.redaction_profiles, "default") {
+        Ok(redactions) => redactions,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let usernames: Vec<String> = std::env::var("USER").into_iter().collect();
+    let mut summary = redact::Summary::default();
+    let messages: Vec<(String, String)> = session
+        .messages
+        .iter()
+        .map(|message| {
+            (
+                speaker(&message.role, message.model.as_deref()),
+                redact::redact(&message.content, &redactions, &usernames, &mut summary),
+            )
+        })
+        .collect();
+
+    let title = session
+        .name
+        .clone()
+        .unwrap_or(format!("Conversation {}", session.id));
+    let path = path
+        .map(str::to_string)
+        .unwrap_or(format!("{}.{}", session.id, format.extension()));
+    let written = patch::Workspace::from_config(&config).and_then(|workspace| {
+        workspace
+            .write(
+                std::path::Path::new(&path),
+                &render_export(format, &title, &messages),
+            )
+            .map_err(|e| e.to_string())
+    });
+    match written {
+        Ok(()) => {
+            if !summary.is_empty() {
+                println!("Redacted {}", summary);
+            }
+            println!("Exported {} messages to {}", messages.len(), path);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// `/search` and `history search` list no more hits than this.
+const MAX_SEARCH_HITS: usize = 20;
+
+/// Lists the first `hits`, numbered from 1.
+pub fn print_search_hits(hits: &[sessions::SearchHit]) {
+    for (n, hit) in hits.iter().take(MAX_SEARCH_HITS).enumerate() {
+        println!(
+            "{:>3}. {} · {} · {}: {}",
+            n + 1,
+            hit.name.as_deref().unwrap_or(&hit.session),
+            utils::format_time(hit.time),
+            speaker(&hit.role, None),
+            hit.snippet
+        );
+    }
+    if hits.len() > MAX_SEARCH_HITS {
+        println!(
+            "     ... and {} more, search for something longer",
+            hits.len() - MAX_SEARCH_HITS
+        );
+    }
+}
+
+/// How many of `hits` `print_search_hits` shows.
+pub fn shown_search_hits(hits: &[sessions::SearchHit]) -> usize {
+    hits.len().min(MAX_SEARCH_HITS)
+}
+
+/// Runs `copilot history search`, returning the process exit code: 1 if nothing matched.
+pub fn history_search(query: &str) -> i32 {
+    let hits = sessions::search(query);
+    if hits.is_empty() {
+        println!("No stored conversation mentions \"{}\"", query);
+        return 1;
+    }
+    print_search_hits(&hits);
+    println!("Continue a conversation with --session <name>");
+    0
+}
+
+/// Runs `copilot edits <command>`, returning the process exit code.
+pub fn edits(command: EditsCommand) -> i32 {
+    match command {
+        EditsCommand::Log => {
+            let log = edits::log();
+            if log.is_empty() {
+                println!("No edits yet");
+            }
+
+            for edit in log.iter().rev() {
+                let time = chrono::DateTime::from_timestamp(edit.time, 0)
+                    .map(|t| {
+                        t.with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                let lines = |id: &str| {
+                    edits::load_snapshot(id)
+                        .map(|s| s.lines().count())
+                        .unwrap_or(0)
+                };
+                let change = match &edit.before {
+                    Some(before) => format!("{} -> {} lines", lines(before), lines(&edit.after)),
+                    None => format!("created, {} lines", lines(&edit.after)),
+                };
+
+                println!(
+                    "{}  {}  ({}){}",
+                    time,
+                    edit.path,
+                    change,
+                    if edit.undone { " [undone]" } else { "" }
+                );
+            }
+            0
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::codeblocks;
+
+/// One item of the checklist `/tasks new` makes from an answer.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Task {
+    pub text: String,
+    pub done: bool,
+}
+
+/// Reads the checklist out of the model's reply: a JSON array of strings, or of objects with
+/// a `text`, `task` or `title`, on its own or in a code block.
+pub fn parse(reply: &str) -> Result<Vec<Task>, String> {
+    let Some(Value::Array(items)) = codeblocks::find_json(reply) else {
+        return Err("The reply wasn't a JSON list of tasks".to_string());
+    };
+
+    let tasks: Vec<Task> = items
+        .iter()
+        .filter_map(|item| match item {
+            Value::String(text) => Some(text.as_str()),
+            Value::Object(object) => ["text", "task", "title"]
+                .iter()
+                .find_map(|key| object.get(*key)?.as_str()),
+            _ => None,
+        })
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(|text| Task {
+            text: text.to_string(),
+            done: false,
+        })
+        .collect();
+
+    if tasks.is_empty() {
+        return Err("The reply had no tasks in it".to_string());
+    }
+    Ok(tasks)
+}
+
+/// The list as shown by `/tasks`, numbered for `/tasks <n>`.
+pub fn render(tasks: &[Task]) -> String {
+    let done = tasks.iter().filter(|t| t.done).count();
+    let mut text = format!("Tasks ({}/{} done)", done, tasks.len());
+    for (i, task) in tasks.iter().enumerate() {
+        let mark = if task.done { "x" } else { " " };
+        text.push_str(&format!("\n  [{}] {}. {}", mark, i + 1, task.text));
+    }
+    text
+}
+
+/// A Markdown task list, as GitHub renders it.
+pub fn markdown(tasks: &[Task]) -> String {
+    tasks
+        .iter()
+        .map(|task| format!("- [{}] {}\n", if task.done { "x" } else { " " }, task.text))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_strings_and_objects() {
+        let reply =
+            "Here you go:\n```json\n[\"Add the flag\", {\"task\": \"Write a test\"}, 3]\n```";
+        let mut tasks = parse(reply).unwrap();
+        assert_eq!(tasks.len(), 2);
+
+        tasks[0].done = true;
+        assert_eq!(markdown(&tasks), "- [x] Add the flag\n- [ ] Write a test\n");
+        assert!(parse("{\"tasks\": 1}").is_err());
+    }
+}
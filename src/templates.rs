@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use crate::utils;
+
+/// Where reusable prompts are kept, as `<name>.md`, for `/tpl <name> [args]` and
+/// `copilot run <name> [args]`.
+pub fn templates_dir() -> PathBuf {
+    PathBuf::from(utils::get_config_path()).join("templates")
+}
+
+/// The names of every template, sorted.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(templates_dir())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            (path.extension()? == "md").then(|| path.file_stem()?.to_str().map(String::from))?
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn load(name: &str) -> Result<String, String> {
+    let path = templates_dir().join(format!("{}.md", name));
+    std::fs::read_to_string(&path).map_err(|_| {
+        let names = list();
+        if names.is_empty() {
+            format!("No template named {}, add one as {}", name, path.display())
+        } else {
+            format!(
+                "No template named {}, there are: {}",
+                name,
+                names.join(", ")
+            )
+        }
+    })
+}
+
+/// Fills in the placeholders of `template`:
+///
+/// - `{{selection}}`: the file named by the arguments, or the arguments themselves
+/// - `{{args}}`: the arguments as typed
+/// - `{{file:path}}`: the contents of `path`
+/// - `{{clipboard}}`: the clipboard
+///
+/// Arguments given to a template that uses neither `{{selection}}` nor `{{args}}` are
+/// appended as the selection. Files and the clipboard are read through `read_file` and
+/// `clipboard`.
+pub fn render(
+    template: &str,
+    args: &str,
+    read_file: impl Fn(&Path) -> Result<String, String>,
+    clipboard: impl Fn() -> Result<String, String>,
+) -> Result<String, String> {
+    let args = args.trim();
+    let selection = || -> Result<String, String> {
+        let path = Path::new(args);
+        if !args.is_empty() && path.is_file() {
+            Ok(fenced(path, &read_file(path)?))
+        } else {
+            Ok(args.to_string())
+        }
+    };
+
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    let mut used_args = false;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or("A {{ in the template is never closed")?;
+        let placeholder = after[..end].trim();
+
+        let value = match placeholder {
+            "selection" => {
+                used_args = true;
+                selection()?
+            }
+            "args" => {
+                used_args = true;
+                args.to_string()
+            }
+            "clipboard" => clipboard()?,
+            _ => match placeholder.strip_prefix("file:") {
+                Some(path) => {
+                    let path = Path::new(path.trim());
+                    fenced(path, &read_file(path)?)
+                }
+                None => return Err(format!("Unknown placeholder {{{{{}}}}}", placeholder)),
+            },
+        };
+        out.push_str(&value);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+
+    if !used_args && !args.is_empty() {
+        out = format!("{}\n\n{}", out.trim_end(), selection()?);
+    }
+    Ok(out)
+}
+
+/// `path` and its contents as a Markdown code block.
+fn fenced(path: &Path, contents: &str) -> String {
+    let lang = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    format!(
+        "`{}`:\n```{}\n{}\n```",
+        path.display(),
+        lang,
+        contents.trim_end()
+    )
+}
+
+/// Reads a file for a template, with the path in the error.
+pub fn read_file(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(template: &str, args: &str) -> Result<String, String> {
+        render(
+            template,
+            args,
+            |path| Ok(format!("contents of {}", path.display())),
+            || Ok("copied".to_string()),
+        )
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render_with("Explain {{ args }} using {{clipboard}}", "lifetimes").unwrap(),
+            "Explain lifetimes using copied"
+        );
+        assert_eq!(
+            render_with("Review {{file:src/gh.rs}}", "").unwrap(),
+            "Review `src/gh.rs`:\n```rs\ncontents of src/gh.rs\n```"
+        );
+        assert_eq!(
+            render_with("Write tests for this:", "the parser").unwrap(),
+            "Write tests for this:\n\nthe parser"
+        );
+        assert!(render_with("{{nope}}", "").is_err());
+        assert!(render_with("{{args", "").is_err());
+    }
+}
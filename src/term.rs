@@ -1,19 +1,366 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use crossterm::{
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+};
 use syntect::{self, highlighting::Style};
 
-pub fn highlight_line(text: &String) -> Vec<(Style, &str)> {
+use crate::{
+    config::Config,
+    copilot::{self, Completion},
+    sinks::DeltaSink,
+};
+
+/// Where answers end up, which decides how long they should be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputContext {
+    /// Someone reading along in a terminal.
+    Tty,
+    /// Another program, e.g. `copilot --json-stream - | jq`.
+    Pipe,
+    /// `--brief` was given.
+    Brief,
+}
+
+impl OutputContext {
+    pub fn detect(brief: bool) -> OutputContext {
+        use std::io::IsTerminal;
+
+        if brief {
+            OutputContext::Brief
+        } else if std::io::stdout().is_terminal() {
+            OutputContext::Tty
+        } else {
+            OutputContext::Pipe
+        }
+    }
+
+    /// The answer length cap when none is set explicitly, from `pipe_max_tokens` and
+    /// `brief_max_tokens`. Terminal output isn't capped.
+    pub fn max_tokens(self, config: &Config) -> Option<u32> {
+        match self {
+            OutputContext::Tty => None,
+            OutputContext::Pipe => Some(config.pipe_max_tokens),
+            OutputContext::Brief => Some(config.brief_max_tokens),
+        }
+    }
+}
+
+/// How many colors the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    None,
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// What the terminal the chat runs in can do. Dumb terminals and CI logs get plain
+/// sequential output instead of escapes they'd print literally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// The alternate screen and moving the cursor work.
+    pub alternate_screen: bool,
+    pub colors: ColorSupport,
+}
+
+impl Capabilities {
+    /// Looks at stdout, `TERM`, `COLORTERM` and `NO_COLOR` once per run.
+    pub fn detect() -> Capabilities {
+        use std::io::IsTerminal;
+
+        static DETECTED: OnceLock<Capabilities> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            let var = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+            Capabilities::from_env(
+                var("TERM").as_deref(),
+                var("COLORTERM").as_deref(),
+                var("NO_COLOR").is_some(),
+                std::io::stdout().is_terminal(),
+            )
+        })
+    }
+
+    fn from_env(
+        term: Option<&str>,
+        colorterm: Option<&str>,
+        no_color: bool,
+        is_terminal: bool,
+    ) -> Capabilities {
+        // Windows terminals don't set TERM but handle escapes fine
+        let dumb = match term {
+            Some(term) => term == "dumb",
+            None => !cfg!(windows),
+        };
+        if dumb || !is_terminal {
+            return Capabilities {
+                alternate_screen: false,
+                colors: ColorSupport::None,
+            };
+        }
+
+        let colors = if no_color {
+            ColorSupport::None
+        } else if matches!(colorterm, Some("truecolor" | "24bit")) || cfg!(windows) {
+            ColorSupport::TrueColor
+        } else if term.is_some_and(|term| term.contains("256color")) {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        };
+        Capabilities {
+            alternate_screen: true,
+            colors,
+        }
+    }
+}
+
+static IN_ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+
+/// Switches to the alternate screen if the terminal has one.
+pub fn enter_alternate_screen() {
+    if Capabilities::detect().alternate_screen {
+        execute!(std::io::stdout(), EnterAlternateScreen).unwrap();
+        IN_ALTERNATE_SCREEN.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Goes back to the normal screen, if `enter_alternate_screen` left it.
+pub fn leave_alternate_screen() {
+    if IN_ALTERNATE_SCREEN.swap(false, Ordering::Relaxed) {
+        execute!(std::io::stdout(), LeaveAlternateScreen).unwrap();
+    }
+}
+
+/// `escape` (a color or reset sequence) when the terminal shows colors, nothing otherwise.
+pub fn style(escape: &'static str) -> &'static str {
+    if Capabilities::detect().colors == ColorSupport::None {
+        ""
+    } else {
+        escape
+    }
+}
+
+pub fn highlight_line(text: &str) -> Vec<(Style, &str)> {
     // using syntect, apply markdown syntax highlighting to the text
     let syntax_set = syntect::parsing::SyntaxSet::load_defaults_newlines();
     let syntax = syntax_set.find_syntax_by_extension("md").unwrap();
     let h = syntect::highlighting::ThemeSet::load_defaults();
-    let mut highlighter = syntect::easy::HighlightLines::new(syntax, &h.themes["base16-mocha.dark"]);
+    let mut highlighter =
+        syntect::easy::HighlightLines::new(syntax, &h.themes["base16-mocha.dark"]);
 
-    let highlighted = highlighter.highlight_line(text, &syntax_set).unwrap();
     // let escaped = syntect::util::as_24_bit_terminal_escaped(&highlighted, false);
-    highlighted
+    highlighter.highlight_line(text, &syntax_set).unwrap()
+}
+
+/// `code` as a `<pre>` block with inline styles, in the colors answers get in the terminal.
+/// Languages syntect doesn't know come out as plain text.
+pub fn highlight_html(code: &str, lang: &str) -> String {
+    let syntax_set = syntect::parsing::SyntaxSet::load_defaults_newlines();
+    let syntax = syntax_set
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    let h = syntect::highlighting::ThemeSet::load_defaults();
+    syntect::html::highlighted_html_for_string(
+        code,
+        &syntax_set,
+        syntax,
+        &h.themes["base16-mocha.dark"],
+    )
+    .unwrap_or_else(|_| format!("<pre>{}</pre>\n", crate::html::escape(code)))
 }
 
-pub fn to_terminal_escaped(highlighted: &Vec<(Style, &str)>) -> String {
+pub fn to_terminal_escaped(highlighted: &[(Style, &str)], colors: ColorSupport) -> String {
     // convert the highlighted text to a string with terminal escape sequences
-    let escaped = syntect::util::as_24_bit_terminal_escaped(highlighted, false);
-    escaped
+    if colors == ColorSupport::TrueColor {
+        return syntect::util::as_24_bit_terminal_escaped(highlighted, false);
+    }
+
+    let mut out = String::new();
+    for (style, text) in highlighted {
+        let c = style.foreground;
+        match colors {
+            ColorSupport::Ansi256 => {
+                out.push_str(&format!("\x1b[38;5;{}m", ansi256(c.r, c.g, c.b)))
+            }
+            ColorSupport::Ansi16 => out.push_str(&format!("\x1b[{}m", ansi16(c.r, c.g, c.b))),
+            _ => {}
+        }
+        out.push_str(text);
+    }
+    out
+}
+
+/// The nearest color of the xterm 256 color palette.
+fn ansi256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            249..=255 => 231,
+            v => 232 + ((v - 8) as u16 * 24 / 241) as u8,
+        };
+    }
+    // the 6x6x6 cube's levels are 0, 95, 135, 175, 215 and 255
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        v => (v - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// The SGR foreground code of the nearest of the 16 basic colors, as xterm draws them.
+fn ansi16(r: u8, g: u8, b: u8) -> u8 {
+    const PALETTE: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let distance = |(pr, pg, pb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    let nearest = (0..16u8)
+        .min_by_key(|&i| distance(PALETTE[i as usize]))
+        .unwrap();
+    if nearest < 8 {
+        30 + nearest
+    } else {
+        90 + nearest - 8
+    }
+}
+
+/// Renders `data` as a QR code out of unicode half blocks, two modules per character cell.
+pub fn render_qr_code(data: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(data.as_bytes()).ok()?;
+
+    Some(
+        code.render::<qrcode::render::unicode::Dense1x2>()
+            // filled blocks use the (usually light) foreground color, so invert the
+            // modules to keep the code scannable on dark terminal backgrounds
+            .dark_color(qrcode::render::unicode::Dense1x2::Light)
+            .light_color(qrcode::render::unicode::Dense1x2::Dark)
+            .quiet_zone(true)
+            .build(),
+    )
+}
+
+/// Renders the streamed answer to the terminal with markdown highlighting, one line at a time.
+pub struct TerminalSink {
+    full_message: String,
+    colors: ColorSupport,
+}
+
+impl Default for TerminalSink {
+    fn default() -> Self {
+        TerminalSink {
+            full_message: String::new(),
+            colors: Capabilities::detect().colors,
+        }
+    }
+}
+
+impl DeltaSink for TerminalSink {
+    fn on_delta(&mut self, content: &str) {
+        self.full_message.push_str(content);
+        let line_count = self.full_message.split("\n").count();
+
+        if self.full_message.ends_with("\n") {
+            let highlighted = highlight_line(&self.full_message);
+            let escaped: Vec<String> = to_terminal_escaped(&highlighted, self.colors)
+                .split("\n")
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+
+            let mut escaped_len = escaped.len();
+            while line_count > escaped_len {
+                println!();
+                escaped_len += 1;
+            }
+
+            print!("{}", escaped.last().unwrap());
+            std::io::stdout().flush().unwrap();
+        }
+    }
+
+    fn on_finish(&mut self, completion: &Completion) {
+        // lines are only drawn once they're complete, so flush the last one
+        if !self.full_message.is_empty() && !self.full_message.ends_with('\n') {
+            self.on_delta("\n");
+        }
+
+        // reset the forground color
+        if self.colors == ColorSupport::None {
+            println!();
+        } else {
+            println!("\x1b[0m");
+        }
+        match completion.finish_reason.as_str() {
+            copilot::FINISH_CANCELLED => println!("[cancelled]"),
+            copilot::FINISH_MAX_TIME => println!("[stopped at --max-time]"),
+            copilot::FINISH_TOOL_ROUNDS => {
+                println!("[stopped after too many rounds of tool calls, see max_tool_rounds]")
+            }
+            copilot::FINISH_CONTENT_FILTER if completion.filtered.is_empty() => {
+                println!("[cut off by the content filter]")
+            }
+            _ => {}
+        }
+        if !completion.filtered.is_empty() {
+            let verdicts: Vec<String> = completion.filtered.iter().map(|v| v.to_string()).collect();
+            println!("[content filter: {}]", verdicts.join(", "));
+        }
+        std::io::stdout().flush().unwrap();
+        self.full_message.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_output_for_dumb_terminals() {
+        let dumb = Capabilities::from_env(Some("dumb"), None, false, true);
+        assert!(!dumb.alternate_screen);
+        assert_eq!(dumb.colors, ColorSupport::None);
+        let piped = Capabilities::from_env(Some("xterm-256color"), Some("truecolor"), false, false);
+        assert!(!piped.alternate_screen);
+
+        let xterm = Capabilities::from_env(Some("xterm-256color"), None, false, true);
+        assert!(xterm.alternate_screen);
+        assert_eq!(xterm.colors, ColorSupport::Ansi256);
+        let no_color = Capabilities::from_env(Some("xterm"), Some("truecolor"), true, true);
+        assert_eq!(no_color.colors, ColorSupport::None);
+    }
+
+    #[test]
+    fn colors_fall_back_to_the_nearest() {
+        assert_eq!(ansi256(255, 0, 0), 196);
+        assert_eq!(ansi256(0, 0, 0), 16);
+        assert_eq!(ansi256(128, 128, 128), 243);
+        assert_eq!(ansi16(250, 10, 10), 91);
+        assert_eq!(ansi16(30, 30, 30), 30);
+    }
 }
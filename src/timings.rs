@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Where the time of one request went, printed after each answer with `--timings`.
+/// Measured locally only, nothing of it is sent anywhere.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Timings {
+    /// Logging in and getting the Copilot token, only for the first question after it.
+    pub auth: Option<Duration>,
+    /// Reading, retrieving and selecting the context sent with the question.
+    pub context: Duration,
+    /// Until the response headers arrived, for every round of tool calls together.
+    pub send: Duration,
+    /// From sending the question until the first piece of the answer, if any arrived.
+    pub first_token: Option<Duration>,
+    /// Receiving the answer, without the time spent drawing it.
+    pub streaming: Duration,
+    /// Drawing the answer, highlighting included.
+    pub rendering: Duration,
+}
+
+impl Timings {
+    /// One line per stage, the stages that didn't happen left out.
+    pub fn render(&self) -> String {
+        let stages = [
+            ("auth check", self.auth),
+            ("context assembly", Some(self.context)),
+            ("request send", Some(self.send)),
+            ("first token", self.first_token),
+            ("streaming", Some(self.streaming)),
+            ("rendering", Some(self.rendering)),
+        ];
+
+        let mut report = String::from("Timings:\n");
+        for (stage, took) in stages {
+            if let Some(took) = took {
+                report.push_str(&format!("  {:<17}{:>7} ms\n", stage, took.as_millis()));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_stages_that_did_not_happen() {
+        let timings = Timings {
+            context: Duration::from_millis(3),
+            send: Duration::from_millis(410),
+            streaming: Duration::from_millis(2310),
+            rendering: Duration::from_millis(40),
+            ..Default::default()
+        };
+        assert_eq!(
+            timings.render(),
+            "Timings:\n  \
+             context assembly       3 ms\n  \
+             request send         410 ms\n  \
+             streaming           2310 ms\n  \
+             rendering             40 ms\n"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::utils;
+
+/// Roughly estimates the number of tokens in `text`.
+///
+/// GPT tokenizers average about four characters per token for English and code,
+/// which is close enough for guardrails without shipping a full BPE vocabulary.
+pub fn estimate(text: &str) -> usize {
+    let chars = text.chars().count();
+    let words = text.split_whitespace().count();
+
+    // short words are usually one token each, so never go below the word count
+    chars.div_ceil(4).max(words)
+}
+
+/// Shortens `text` to about `max_tokens` by keeping its beginning and end, which is
+/// where the interesting parts of logs and stack traces usually are.
+pub fn truncate(text: &str, max_tokens: usize) -> String {
+    if estimate(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let budget = max_tokens / 2;
+
+    let mut head = Vec::new();
+    let mut used = 0;
+    for line in &lines {
+        used += estimate(line) + 1;
+        if used > budget {
+            // one huge line, e.g. minified code, still shows how it starts
+            if head.is_empty() {
+                head.push(utils::truncate_bytes(line, budget * 4));
+            }
+            break;
+        }
+        head.push(*line);
+    }
+
+    let mut tail = Vec::new();
+    used = 0;
+    for line in lines[head.len()..].iter().rev() {
+        used += estimate(line) + 1;
+        if used > budget {
+            break;
+        }
+        tail.push(*line);
+    }
+    tail.reverse();
+
+    let skipped = lines.len() - head.len() - tail.len();
+    format!(
+        "{}\n... [{} lines truncated] ...\n{}",
+        head.join("\n"),
+        skipped,
+        tail.join("\n")
+    )
+}
@@ -0,0 +1,204 @@
+use std::{path::Path, process::Command};
+
+/// At most this many problem lines make it into a summary, the rest are only counted.
+const MAX_PROBLEMS: usize = 20;
+
+/// A build or test command whose result can be handed back to the model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    CargoCheck,
+    NpmTest,
+    Pytest,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 3] = [Tool::CargoCheck, Tool::NpmTest, Tool::Pytest];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::CargoCheck => "cargo_check",
+            Tool::NpmTest => "npm_test",
+            Tool::Pytest => "pytest",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Tool> {
+        Tool::ALL.into_iter().find(|tool| tool.name() == name)
+    }
+
+    /// Picks the tool for the project in `dir` from the files it contains.
+    pub fn detect(dir: &Path) -> Option<Tool> {
+        if dir.join("Cargo.toml").exists() {
+            Some(Tool::CargoCheck)
+        } else if dir.join("package.json").exists() {
+            Some(Tool::NpmTest)
+        } else if [
+            "pytest.ini",
+            "pyproject.toml",
+            "setup.cfg",
+            "tox.ini",
+            "conftest.py",
+        ]
+        .iter()
+        .any(|file| dir.join(file).exists())
+        {
+            Some(Tool::Pytest)
+        } else {
+            None
+        }
+    }
+
+    fn command(self) -> Command {
+        let (program, args): (&str, &[&str]) = match self {
+            Tool::CargoCheck => (
+                "cargo",
+                &["check", "--all-targets", "--message-format", "short"],
+            ),
+            Tool::NpmTest => ("npm", &["test", "--silent"]),
+            Tool::Pytest => ("pytest", &["-q", "-rfE", "--tb=no", "--color=no"]),
+        };
+        let mut command = Command::new(program);
+        command.args(args);
+        command
+    }
+}
+
+/// What a run came down to: the verdict, a one line tally and the lines worth reading.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub tool: Tool,
+    pub passed: bool,
+    pub headline: String,
+    pub problems: Vec<String>,
+    /// Problems left out beyond `MAX_PROBLEMS`.
+    pub omitted: usize,
+}
+
+impl Summary {
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "{} {}: {}",
+            self.tool.name(),
+            if self.passed { "passed" } else { "failed" },
+            self.headline
+        );
+        for problem in &self.problems {
+            text.push_str("\n  ");
+            text.push_str(problem);
+        }
+        if self.omitted > 0 {
+            text.push_str(&format!("\n  ... and {} more", self.omitted));
+        }
+        text
+    }
+}
+
+/// Runs `tool` in the current directory and summarizes its output.
+pub fn run(tool: Tool) -> Result<Summary, String> {
+    let output = tool
+        .command()
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", tool.name(), e))?;
+
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(summarize(tool, output.status.success(), &text))
+}
+
+fn summarize(tool: Tool, passed: bool, output: &str) -> Summary {
+    let lines = output.lines().map(str::trim_end);
+
+    let (headline, problems): (String, Vec<&str>) = match tool {
+        Tool::CargoCheck => {
+            // `--message-format short`: `src/main.rs:3:5: error[E0425]: ...`
+            let problems: Vec<&str> = lines
+                .filter(|line| line.contains(": error") || line.contains(": warning"))
+                .collect();
+            let errors = problems.iter().filter(|l| l.contains(": error")).count();
+            let headline = format!("{} errors, {} warnings", errors, problems.len() - errors);
+            (headline, problems)
+        }
+        Tool::NpmTest => {
+            // jest (`Tests: 1 failed, 3 passed`, `● suite › test`), mocha (`3 passing`) and TAP
+            let lines: Vec<&str> = lines.collect();
+            let headline = lines
+                .iter()
+                .map(|l| l.trim())
+                .filter(|l| {
+                    l.starts_with("Tests:") || l.ends_with(" passing") || l.ends_with(" failing")
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let problems = lines
+                .into_iter()
+                .map(str::trim)
+                .filter(|l| l.starts_with("● ") || l.starts_with("✕ ") || l.starts_with("not ok "))
+                .collect();
+            (headline, problems)
+        }
+        Tool::Pytest => {
+            // `-rfE` lists `FAILED path::test - reason`, the last line is `1 failed, 3 passed in 0.1s`
+            let lines: Vec<&str> = lines.collect();
+            let headline = lines
+                .iter()
+                .rev()
+                .map(|l| l.trim().trim_matches('='))
+                .find(|l| l.contains(" passed") || l.contains(" failed") || l.contains(" error"))
+                .unwrap_or("")
+                .trim()
+                .to_string();
+            let problems = lines
+                .into_iter()
+                .filter(|l| l.starts_with("FAILED ") || l.starts_with("ERROR "))
+                .collect();
+            (headline, problems)
+        }
+    };
+
+    let omitted = problems.len().saturating_sub(MAX_PROBLEMS);
+    Summary {
+        tool,
+        passed,
+        headline: if headline.is_empty() {
+            "no summary in the output".to_string()
+        } else {
+            headline
+        },
+        problems: problems
+            .into_iter()
+            .take(MAX_PROBLEMS)
+            .map(|l| l.to_string())
+            .collect(),
+        omitted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_cargo_and_pytest() {
+        let cargo = "    Checking demo v0.1.0\n\
+                     src/main.rs:3:5: error[E0425]: cannot find value `x` in this scope\n\
+                     src/lib.rs:1:5: warning: unused import: `std::fs`\n\
+                     error: could not compile `demo` (bin \"demo\") due to 1 previous error";
+        let summary = summarize(Tool::CargoCheck, false, cargo);
+        assert_eq!(summary.headline, "1 errors, 1 warnings");
+        assert_eq!(summary.problems.len(), 2);
+
+        let pytest = "..F.\n\
+                      ===== short test summary info =====\n\
+                      FAILED tests/test_math.py::test_div - ZeroDivisionError: division by zero\n\
+                      1 failed, 3 passed in 0.05s";
+        let summary = summarize(Tool::Pytest, false, pytest);
+        assert_eq!(
+            summary.render(),
+            "pytest failed: 1 failed, 3 passed in 0.05s\n  \
+             FAILED tests/test_math.py::test_div - ZeroDivisionError: division by zero"
+        );
+    }
+}
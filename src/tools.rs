@@ -0,0 +1,462 @@
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::{
+    capture,
+    config::{BuiltinTool, Config, WebSearchProvider},
+    http_cache, input, search, toolchain, utils,
+};
+
+/// At most this much of a tool's output goes back to the model.
+const MAX_OUTPUT_BYTES: usize = 16_000;
+
+/// How many rounds of tool calls one answer may take unless `max_tool_rounds` says otherwise.
+pub const DEFAULT_MAX_ROUNDS: usize = 8;
+
+/// Shell commands the model runs are stopped after this long.
+const SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A call the model asked for, with the arguments as the JSON it wrote.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Adds a piece of a streamed call. The id and name come once, the arguments in parts.
+    pub fn extend(&mut self, id: Option<&str>, name: Option<&str>, arguments: Option<&str>) {
+        if let Some(id) = id {
+            self.id = id.to_string();
+        }
+        if let Some(name) = name {
+            self.name.push_str(name);
+        }
+        if let Some(arguments) = arguments {
+            self.arguments.push_str(arguments);
+        }
+    }
+}
+
+/// A local function the model can call. Nothing runs without the user's approval.
+pub trait Tool {
+    fn name(&self) -> &'static str;
+    /// Tells the model what the tool is for.
+    fn description(&self) -> &'static str;
+    /// The JSON schema of the arguments.
+    fn parameters(&self) -> Value;
+    /// What the call would do, shown when asking whether to run it.
+    fn preview(&self, args: &Value) -> String;
+    fn run<'a>(
+        &'a self,
+        args: &'a Value,
+        client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>>;
+}
+
+/// The tools offered to the model with every question.
+#[derive(Default)]
+pub struct Registry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl Registry {
+    /// The built-in tools listed in `tools` in the config.
+    pub fn from_config(config: &Config) -> Registry {
+        let mut registry = Registry::default();
+        for tool in &config.tools {
+            registry.register(match tool {
+                BuiltinTool::Shell => Box::new(Shell),
+                BuiltinTool::ReadFile => Box::new(ReadFile),
+                BuiltinTool::WebFetch => Box::new(WebFetch),
+                BuiltinTool::WebSearch => match &config.web_search {
+                    Some(provider) => Box::new(WebSearch {
+                        provider: provider.clone(),
+                    }),
+                    None => {
+                        eprintln!(
+                            "The web_search tool needs a \"web_search\" provider in the config"
+                        );
+                        continue;
+                    }
+                },
+                BuiltinTool::CargoCheck => Box::new(Check(toolchain::Tool::CargoCheck)),
+                BuiltinTool::NpmTest => Box::new(Check(toolchain::Tool::NpmTest)),
+                BuiltinTool::Pytest => Box::new(Check(toolchain::Tool::Pytest)),
+            });
+        }
+        registry
+    }
+
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// The `tools` of a chat request.
+    pub fn schemas(&self) -> Value {
+        self.tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "parameters": tool.parameters(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Asks whether to run `call`, runs it and returns the message with its result. Errors,
+    /// including the user saying no, go back to the model as the result.
+    pub async fn call(&self, call: &ToolCall, client: &Client) -> Value {
+        let content = match self.run(call, client).await {
+            Ok(output) => truncate(output),
+            Err(e) => format!("Error: {}", e),
+        };
+        json!({
+            "role": "tool",
+            "tool_call_id": call.id,
+            "content": content,
+        })
+    }
+
+    async fn run(&self, call: &ToolCall, client: &Client) -> Result<String, String> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.name() == call.name)
+            .ok_or(format!("There is no tool named {}", call.name))?;
+        let arguments = match call.arguments.trim() {
+            "" => "{}",
+            arguments => arguments,
+        };
+        let args: Value = serde_json::from_str(arguments)
+            .map_err(|e| format!("The arguments aren't valid JSON: {}", e))?;
+
+        let mut rl = input::new_editor();
+        println!();
+        if !input::confirm(&mut rl, &format!("Run {}?", tool.preview(&args)), false) {
+            return Err("The user declined to run it".to_string());
+        }
+        tool.run(&args, client).await
+    }
+}
+
+/// The answer that asked for `calls`, which has to come before their results.
+pub fn call_message(content: &str, calls: &[ToolCall]) -> Value {
+    let calls: Vec<Value> = calls
+        .iter()
+        .map(|call| {
+            json!({
+                "id": call.id,
+                "type": "function",
+                "function": { "name": call.name, "arguments": call.arguments },
+            })
+        })
+        .collect();
+    json!({
+        "role": "assistant",
+        "content": content,
+        "tool_calls": calls,
+    })
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() <= MAX_OUTPUT_BYTES {
+        return output;
+    }
+    let end = utils::truncate_bytes(&output, MAX_OUTPUT_BYTES).len();
+    let dropped = output.len() - end;
+    output.truncate(end);
+    format!("{}\n[{} more bytes left out]", output, dropped)
+}
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, String> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or(format!("Missing the \"{}\" argument", name))
+}
+
+fn schema(name: &str, description: &str) -> Value {
+    json!({
+        "type": "object",
+        "properties": { name: { "type": "string", "description": description } },
+        "required": [name],
+    })
+}
+
+/// `shell`: runs a command through `sh -c`.
+struct Shell;
+
+impl Tool for Shell {
+    fn name(&self) -> &'static str {
+        "shell"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run a shell command in the user's current directory and get its output."
+    }
+
+    fn parameters(&self) -> Value {
+        schema("command", "The command line to run with sh -c")
+    }
+
+    fn preview(&self, args: &Value) -> String {
+        format!("`{}`", args["command"].as_str().unwrap_or_default())
+    }
+
+    fn run<'a>(
+        &'a self,
+        args: &'a Value,
+        _client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let command = string_arg(args, "command")?;
+            let capture = capture::capture(command, SHELL_TIMEOUT, MAX_OUTPUT_BYTES).await?;
+            let mut output = capture.output;
+            if !capture.exited {
+                output.push_str("\n[stopped before it finished]");
+            }
+            Ok(output)
+        })
+    }
+}
+
+/// `read_file`: reads a text file.
+struct ReadFile;
+
+impl Tool for ReadFile {
+    fn name(&self) -> &'static str {
+        "read_file"
+    }
+
+    fn description(&self) -> &'static str {
+        "Read a text file, relative to the user's current directory."
+    }
+
+    fn parameters(&self) -> Value {
+        schema("path", "The file to read")
+    }
+
+    fn preview(&self, args: &Value) -> String {
+        format!("read_file {}", args["path"].as_str().unwrap_or_default())
+    }
+
+    fn run<'a>(
+        &'a self,
+        args: &'a Value,
+        _client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let path = string_arg(args, "path")?;
+            std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))
+        })
+    }
+}
+
+/// `web_fetch`: GETs a URL through the HTTP cache.
+struct WebFetch;
+
+impl Tool for WebFetch {
+    fn name(&self) -> &'static str {
+        "web_fetch"
+    }
+
+    fn description(&self) -> &'static str {
+        "Fetch a web page or other URL and get the response body."
+    }
+
+    fn parameters(&self) -> Value {
+        schema("url", "The http or https URL to fetch")
+    }
+
+    fn preview(&self, args: &Value) -> String {
+        format!("web_fetch {}", args["url"].as_str().unwrap_or_default())
+    }
+
+    fn run<'a>(
+        &'a self,
+        args: &'a Value,
+        client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let url = string_arg(args, "url")?;
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "Only http and https URLs can be fetched, not {}",
+                    url
+                ));
+            }
+            http_cache::get(client, client.get(url)).await
+        })
+    }
+}
+
+/// `web_search`: the top results from the configured search provider, numbered for citing.
+struct WebSearch {
+    provider: WebSearchProvider,
+}
+
+impl Tool for WebSearch {
+    fn name(&self) -> &'static str {
+        "web_search"
+    }
+
+    fn description(&self) -> &'static str {
+        "Search the web and get the top results with their URLs, cite them as [1], [2], ..."
+    }
+
+    fn parameters(&self) -> Value {
+        schema("query", "What to search for")
+    }
+
+    fn preview(&self, args: &Value) -> String {
+        format!(
+            "web_search \"{}\"",
+            args["query"].as_str().unwrap_or_default()
+        )
+    }
+
+    fn run<'a>(
+        &'a self,
+        args: &'a Value,
+        client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let query = string_arg(args, "query")?;
+            let results = search::search(client, &self.provider, query).await?;
+            if results.is_empty() {
+                return Ok(format!("No results for {}", query));
+            }
+            Ok(search::format_context(&results))
+        })
+    }
+}
+
+/// `cargo_check`, `npm_test` and `pytest`: runs the project's checks in the current
+/// directory and returns their summary.
+struct Check(toolchain::Tool);
+
+impl Tool for Check {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn description(&self) -> &'static str {
+        match self.0 {
+            toolchain::Tool::CargoCheck => {
+                "Run cargo check on the user's Rust project and get the errors and warnings."
+            }
+            toolchain::Tool::NpmTest => "Run npm test in the user's project and get the failures.",
+            toolchain::Tool::Pytest => "Run pytest in the user's project and get the failures.",
+        }
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    fn preview(&self, _args: &Value) -> String {
+        self.0.name().to_string()
+    }
+
+    fn run<'a>(
+        &'a self,
+        _args: &'a Value,
+        _client: &'a Client,
+    ) -> LocalBoxFuture<'a, Result<String, String>> {
+        let tool = self.0;
+        Box::pin(async move {
+            // builds and test suites take a while, keep the runtime free meanwhile
+            let summary = tokio::task::spawn_blocking(move || toolchain::run(tool))
+                .await
+                .map_err(|e| format!("{} didn't finish: {}", tool.name(), e))??;
+            Ok(summary.render())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_calls_and_schemas() {
+        let mut call = ToolCall::default();
+        call.extend(Some("call_1"), Some("read_file"), Some("{\"pa"));
+        call.extend(None, None, Some("th\": \"Cargo.toml\"}"));
+        assert_eq!(call.arguments, "{\"path\": \"Cargo.toml\"}");
+
+        let mut registry = Registry::default();
+        registry.register(Box::new(ReadFile));
+        registry.register(Box::new(ReadFile));
+        let schemas = registry.schemas();
+        assert_eq!(schemas.as_array().unwrap().len(), 1);
+        assert_eq!(schemas[0]["function"]["name"], "read_file");
+        assert_eq!(schemas[0]["function"]["parameters"]["required"][0], "path");
+
+        let message = call_message("", &[call]);
+        assert_eq!(message["tool_calls"][0]["function"]["name"], "read_file");
+    }
+
+    #[test]
+    fn web_search_needs_a_query() {
+        let search = WebSearch {
+            provider: WebSearchProvider::Searxng {
+                url: "http://localhost:8080".to_string(),
+            },
+        };
+        assert_eq!(search.parameters()["required"][0], "query");
+        assert_eq!(
+            search.preview(&json!({ "query": "tokio select" })),
+            "web_search \"tokio select\""
+        );
+
+        let client = Client::new();
+        let missing = futures::executor::block_on(search.run(&json!({}), &client));
+        assert_eq!(missing, Err("Missing the \"query\" argument".to_string()));
+    }
+
+    #[test]
+    fn checks_are_registered_from_the_config() {
+        let config = Config {
+            tools: vec![
+                BuiltinTool::CargoCheck,
+                BuiltinTool::NpmTest,
+                BuiltinTool::Pytest,
+                // left out without a provider
+                BuiltinTool::WebSearch,
+            ],
+            ..Config::default()
+        };
+        let registry = Registry::from_config(&config);
+        let names: Vec<&Value> = registry
+            .schemas()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|schema| &schema["function"]["name"])
+            .collect();
+        assert_eq!(names, ["cargo_check", "npm_test", "pytest"]);
+        assert_eq!(Check(toolchain::Tool::Pytest).preview(&json!({})), "pytest");
+        assert!(Check(toolchain::Tool::CargoCheck).parameters()["required"].is_null());
+    }
+
+    #[test]
+    fn long_output_is_cut() {
+        let output = truncate("é".repeat(MAX_OUTPUT_BYTES));
+        assert!(output.ends_with(&format!("[{} more bytes left out]", MAX_OUTPUT_BYTES)));
+    }
+}
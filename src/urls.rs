@@ -1,4 +1,90 @@
-pub const DEVICE_CODE_LOGIN_URL: &str = "https://github.com/login/device/code";
-pub const DEVICE_CODE_TOKEN_CHECK_URL: &str = "https://github.com/login/oauth/access_token";
-pub const GH_AUTH_TOKEN_URL: &str = "https://api.github.com/user";
-pub const GH_COPILOT_INTERNAL_AUTH_URL: &str = "https://api.github.com/copilot_internal/v2/token";
+pub const GITHUB_HOST: &str = "github.com";
+
+pub fn device_code_login_url(host: &str) -> String {
+    format!("https://{}/login/device/code", host)
+}
+
+pub fn device_code_token_check_url(host: &str) -> String {
+    format!("https://{}/login/oauth/access_token", host)
+}
+
+pub fn oauth_authorize_url(host: &str) -> String {
+    format!("https://{}/login/oauth/authorize", host)
+}
+
+/// The REST API of `host`: `api.github.com` for github.com, `/api/v3` on a GitHub
+/// Enterprise Server.
+pub fn gh_api_base(host: &str) -> String {
+    if host == GITHUB_HOST {
+        format!("https://api.{}", host)
+    } else {
+        format!("https://{}/api/v3", host)
+    }
+}
+
+pub fn gh_auth_token_url(host: &str) -> String {
+    format!("{}/user", gh_api_base(host))
+}
+
+pub fn gh_issues_url(host: &str, repo: &str) -> String {
+    format!("{}/repos/{}/issues", gh_api_base(host), repo)
+}
+
+pub const COPILOT_API_INDIVIDUAL: &str = "https://api.githubcopilot.com";
+pub const COPILOT_API_BUSINESS: &str = "https://api.business.githubcopilot.com";
+pub const COPILOT_API_ENTERPRISE: &str = "https://api.enterprise.githubcopilot.com";
+
+/// Where inline completions go unless the Copilot token lists another proxy.
+pub const COPILOT_PROXY: &str = "https://copilot-proxy.githubusercontent.com";
+
+pub fn copilot_code_completions_url(proxy_base: &str) -> String {
+    format!(
+        "{}/v1/engines/copilot-codex/completions",
+        proxy_base.trim_end_matches('/')
+    )
+}
+
+pub fn copilot_chat_completions_url(api_base: &str) -> String {
+    format!("{}/chat/completions", api_base.trim_end_matches('/'))
+}
+
+pub fn copilot_embeddings_url(api_base: &str) -> String {
+    format!("{}/embeddings", api_base.trim_end_matches('/'))
+}
+
+pub fn copilot_models_url(api_base: &str) -> String {
+    format!("{}/models", api_base.trim_end_matches('/'))
+}
+
+pub fn gh_copilot_internal_auth_url(host: &str) -> String {
+    format!("{}/copilot_internal/v2/token", gh_api_base(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_base_of_github_and_enterprise_servers() {
+        assert_eq!(
+            gh_auth_token_url("github.com"),
+            "https://api.github.com/user"
+        );
+        assert_eq!(
+            gh_copilot_internal_auth_url("ghe.example.com"),
+            "https://ghe.example.com/api/v3/copilot_internal/v2/token"
+        );
+    }
+
+    #[test]
+    fn issues_url_on_enterprise_servers() {
+        assert_eq!(
+            gh_issues_url("github.com", "octo/repo"),
+            "https://api.github.com/repos/octo/repo/issues"
+        );
+        assert_eq!(
+            gh_issues_url("ghe.example.com", "octo/repo"),
+            "https://ghe.example.com/api/v3/repos/octo/repo/issues"
+        );
+    }
+}
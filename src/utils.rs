@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
-use chrono;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Mutex, OnceLock},
+};
+
 use homedir::get_my_home;
-use rand;
 use rand::Rng;
 use uuid::Uuid;
 
@@ -28,22 +31,143 @@ pub(crate) fn random_hex_string(length: usize) -> String {
     s
 }
 
-fn get_config_path() -> String {
+pub(crate) fn get_config_path() -> String {
     let home = get_my_home().unwrap().unwrap();
     format!("{}/.config/copilot", home.to_str().unwrap())
 }
 
+/// Opens `url` with the platform's default browser.
+pub(crate) fn open_in_browser(url: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/c", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+
+    command
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Opens `path` in `editor`, or `$VISUAL`/`$EDITOR` when it's `None`, and waits for it to
+/// exit. The command may take arguments, e.g. `code --wait`.
+pub(crate) fn open_in_editor(editor: Option<&str>, path: &std::path::Path) -> Result<(), String> {
+    let editor = match editor {
+        Some(editor) => editor.to_string(),
+        None => std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string()),
+    };
+
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", editor, status)),
+        Err(e) => Err(format!("Failed to run {}: {}", editor, e)),
+    }
+}
+
+/// Reads the clipboard with whichever of the platform's clipboard tools is installed.
+pub(crate) fn read_clipboard() -> Result<String, String> {
+    let tools: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbpaste", &[])]
+    } else if cfg!(windows) {
+        &[("powershell", &["-NoProfile", "-Command", "Get-Clipboard"])]
+    } else {
+        &[
+            ("wl-paste", &["--no-newline"]),
+            ("xclip", &["-selection", "clipboard", "-o"]),
+            ("xsel", &["--clipboard", "--output"]),
+        ]
+    };
+
+    for (program, args) in tools {
+        if let Ok(output) = std::process::Command::new(program).args(*args).output() {
+            if output.status.success() {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+        }
+    }
+    let names: Vec<&str> = tools.iter().map(|(program, _)| *program).collect();
+    Err(format!(
+        "Can't read the clipboard, install {}",
+        names.join(" or ")
+    ))
+}
+
+/// Appends `content` to `file_path`. Logging never stops the chat, so failures are ignored.
 pub(crate) fn append_to_file(file_path: &str, content: &str) {
     use std::fs::OpenOptions;
     use std::io::Write;
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file_path)
-        .unwrap();
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(file_path) {
+        let _ = file.write_all(content.as_bytes());
+    }
+}
+
+/// Whether `COPILOT_DEBUG` is set, which keeps logs and the last request for bug reports.
+pub(crate) fn debugging() -> bool {
+    std::env::var_os("COPILOT_DEBUG").is_some()
+}
 
-    file.write_all(content.as_bytes()).unwrap();
+/// Appends a line to `debug.txt` when `COPILOT_DEBUG` is set.
+pub(crate) fn debug_log(message: &str) {
+    if debugging() {
+        append_to_file("debug.txt", &format!("{}\n", message));
+    }
+}
+
+/// A Unix timestamp in local time, the way conversations, memories and search hits list it.
+pub(crate) fn format_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// The longest start of `text` that fits in `max_bytes` without splitting a character.
+pub(crate) fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Logs fields the API sent that our types don't know about yet, once per field, so
+/// maintainers notice new upstream fields without the stream breaking.
+pub(crate) fn log_unknown_fields(type_name: &str, extra: &BTreeMap<String, serde_json::Value>) {
+    static SEEN: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+    if extra.is_empty() {
+        return;
+    }
+
+    let mut seen = SEEN.get_or_init(Default::default).lock().unwrap();
+    for (field, value) in extra {
+        if seen.insert(format!("{}.{}", type_name, field)) {
+            debug_log(&format!("unknown field {}.{}: {}", type_name, field, value));
+        }
+    }
 }
 
 pub(crate) fn read_config_file() -> String {
@@ -51,21 +175,30 @@ pub(crate) fn read_config_file() -> String {
     let cache_path = get_config_path();
     let config_path = format!("{}/config.json", get_config_path());
 
-    println!("Writing token to config file: {}", config_path);
-
     // create if not exists
-    std::fs::create_dir_all(&cache_path).unwrap();
+    std::fs::create_dir_all(cache_path).unwrap();
 
-    let config = std::fs::read_to_string(config_path).unwrap_or("".to_string());
-    config
+    std::fs::read_to_string(config_path).unwrap_or("".to_string())
 }
 
-pub(crate) fn write_token_to_config_file(token: &String) {
+pub(crate) fn write_config_file(contents: &str) {
     let cache_path = get_config_path();
     let config_path = format!("{}/config.json", get_config_path());
 
     // create if not exists
-    std::fs::create_dir_all(&cache_path).unwrap();
+    std::fs::create_dir_all(cache_path).unwrap();
 
-    std::fs::write(config_path, token).unwrap();
+    std::fs::write(config_path, contents).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_at_a_char_boundary() {
+        assert_eq!(truncate_bytes("héllo", 2), "h");
+        assert_eq!(truncate_bytes("héllo", 3), "hé");
+        assert_eq!(truncate_bytes("héllo", 64), "héllo");
+    }
 }
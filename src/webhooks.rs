@@ -0,0 +1,127 @@
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::{
+    config::{Config, Webhook, WebhookEvent},
+    copilot::Completion,
+    sinks::DeltaSink,
+    utils,
+};
+
+/// Sends events to the `webhooks` from the config. Delivery happens in the background and
+/// failures only end up in the debug log, a slow endpoint never holds up the chat.
+#[derive(Clone)]
+pub struct Webhooks {
+    client: Client,
+    hooks: Vec<Webhook>,
+}
+
+impl Webhooks {
+    pub fn from_config(client: &Client, config: &Config) -> Webhooks {
+        Webhooks {
+            client: client.clone(),
+            hooks: config.webhooks.clone(),
+        }
+    }
+
+    /// Posts `{"event": ..., "time": ..., "data": data}` to every hook subscribed to `event`.
+    pub fn fire(&self, event: WebhookEvent, data: Value) {
+        let hooks: Vec<Webhook> = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.events.is_empty() || hook.events.contains(&event))
+            .cloned()
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+
+        let body = json!({
+            "event": event,
+            "time": chrono::Utc::now().timestamp(),
+            "data": data,
+        })
+        .to_string();
+
+        for hook in hooks {
+            let client = self.client.clone();
+            let body = body.clone();
+
+            tokio::spawn(async move {
+                let mut request = client
+                    .post(&hook.url)
+                    .header("Content-Type", "application/json");
+                if let Some(secret) = &hook.secret {
+                    request = request.header("X-Copilot-Signature-256", signature(secret, &body));
+                }
+
+                match request.body(body).send().await {
+                    Ok(response) if response.status().is_success() => {}
+                    Ok(response) => utils::debug_log(&format!(
+                        "webhook {} responded with {}",
+                        hook.url,
+                        response.status()
+                    )),
+                    Err(e) => utils::debug_log(&format!("webhook {} failed: {}", hook.url, e)),
+                }
+            });
+        }
+    }
+}
+
+/// `sha256=<hex HMAC of the body>`, the same scheme GitHub uses for its webhooks, so
+/// existing verification code works unchanged.
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// Fires `answer_completed` after every answer.
+pub struct WebhookSink {
+    webhooks: Webhooks,
+}
+
+impl WebhookSink {
+    pub fn new(webhooks: Webhooks) -> WebhookSink {
+        WebhookSink { webhooks }
+    }
+}
+
+impl DeltaSink for WebhookSink {
+    fn on_delta(&mut self, _delta: &str) {}
+
+    fn on_finish(&mut self, completion: &Completion) {
+        self.webhooks.fire(
+            WebhookEvent::AnswerCompleted,
+            json!({
+                "answer": completion.content,
+                "finish_reason": completion.finish_reason,
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_style_signature() {
+        // the example from GitHub's "Validating webhook deliveries" docs
+        assert_eq!(
+            signature("It's a Secret to Everybody", "Hello, World!"),
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+    }
+}